@@ -0,0 +1,4 @@
+VER=5.2.37
+SRCS="tbl::https://ftp.gnu.org/gnu/bash/bash-$VER.tar.gz"
+CHKSUMS="sha256::9599b22ecd1d5787ad7d3b7bf0c59f312b3396d1e281175dd1f8a4014da621ff"
+CHKUPDATE="anitya::id=166"
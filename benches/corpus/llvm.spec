@@ -0,0 +1,5 @@
+VER=18.1.8
+REL=2
+SRCS="tbl::https://github.com/llvm/llvm-project/releases/download/llvmorg-$VER/llvm-project-$VER.src.tar.xz"
+CHKSUMS="sha256::0b58557a6d32ceee97c8d533a59b9212d87e0fc4d2833924eb6c611247db2f2a"
+CHKUPDATE="anitya::id=1830"
@@ -0,0 +1,54 @@
+use abbs::apf::{self, Context};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const CORPUS: &[(&str, &str, &str)] = &[
+    (
+        "bash",
+        include_str!("corpus/bash.spec"),
+        include_str!("corpus/bash.defines"),
+    ),
+    (
+        "llvm",
+        include_str!("corpus/llvm.spec"),
+        include_str!("corpus/llvm.defines"),
+    ),
+];
+
+/// A defines file far larger than anything in the tree, with values built
+/// from concatenated references to earlier variables.
+fn large_defines() -> String {
+    let mut source = String::from("BASE=\"https://example.com/releases\"\n");
+    for i in 0..2000 {
+        source += &format!(
+            "VAR_{i}=\"$BASE/foo-{i}.tar.xz\"'::'${{BASE:0:5}}\nLIST_{i}=\"${{VAR_{i}}} $BASE\"\n",
+            i = i
+        );
+    }
+    source
+}
+
+fn parse_package(spec: &str, defines: &str) -> Context {
+    let mut context = Context::new();
+    apf::parse(spec, &mut context).unwrap();
+    apf::parse(defines, &mut context).unwrap();
+    context
+}
+
+fn bench_parse(c: &mut Criterion) {
+    for (name, spec, defines) in CORPUS {
+        c.bench_function(&format!("parse/{}", name), |b| {
+            b.iter(|| parse_package(black_box(spec), black_box(defines)))
+        });
+        c.bench_function(&format!("parse_lossless/{}", name), |b| {
+            b.iter(|| apf::parse_lossless(black_box(defines)).unwrap())
+        });
+    }
+
+    let large = large_defines();
+    c.bench_function("parse/large_defines", |b| {
+        b.iter(|| parse_package("", black_box(&large)))
+    });
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+#ifndef ABBS_H
+#define ABBS_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Variables defined by one or more parsed files.
+typedef struct AbbsContext AbbsContext;
+
+// Create an empty context.
+AbbsContext *abbs_context_new(void);
+
+// Parse the file at `path` into a new context.
+// Returns NULL on failure, with a message in `*error` unless `error` is NULL.
+//
+// # Safety
+// `path` must be a valid NUL-terminated string, and `error` NULL or valid
+// for writes.
+AbbsContext *abbs_parse_file(const char *path, char **error);
+
+// Parse the file at `path` on top of `context`, i.e: a defines file on top of
+// the context of its spec. Returns 0 on success, or -1 with a message in
+// `*error` unless `error` is NULL. On failure, `context` keeps the variables
+// defined before the error.
+//
+// # Safety
+// `context` must come from this library, `path` must be a valid
+// NUL-terminated string, and `error` NULL or valid for writes.
+int abbs_parse_file_into(AbbsContext *context, const char *path, char **error);
+
+// Value of the variable `name`, or NULL if it is not set.
+//
+// # Safety
+// `context` must come from this library and `name` must be a valid
+// NUL-terminated string.
+char *abbs_context_get(const AbbsContext *context, const char *name);
+
+// Release a context. NULL is ignored.
+//
+// # Safety
+// `context` must come from this library and not be used afterwards.
+void abbs_free(AbbsContext *context);
+
+// Release a string returned by this library. NULL is ignored.
+//
+// # Safety
+// `s` must come from this library and not be used afterwards.
+void abbs_string_free(char *s);
+
+#endif /* ABBS_H */
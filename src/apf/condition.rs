@@ -0,0 +1,681 @@
+//! Evaluation of `if` and `case` blocks, i.e:
+//! ```bash
+//! if [ "$ARCH" = "amd64" ]; then
+//!     PKGDEP="$PKGDEP foo"
+//! fi
+//! ```
+//! Conditions may only be `[`, `[[` or `test` commands over variables, and
+//! bodies may only contain assignments and further `if`/`case` blocks.
+
+use super::{
+    check_expansion, check_limits, get_args_top_level, get_simple_word_as_string, glob,
+    lowered::{self, WordPart},
+    substitution, Context, Limits, ParseErrorInfo, ParseOptions, ParseWarningInfo,
+};
+use conch_parser::{ast, lexer::Lexer, parse::DefaultParser};
+
+/// An expanded word of a condition or a case pattern.
+struct Operand {
+    value: String,
+    /// `value` as a glob, with the quoted parts escaped.
+    pattern: String,
+}
+
+/// Escape the glob and extglob characters of `s`, so it matches literally.
+fn escape_glob(s: &str, pattern: &mut String) {
+    for c in s.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '?' | '[' | ']' | '!' | '@' | '+' | '(' | ')' | '|'
+        ) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+}
+
+fn expand_word(
+    word: &ast::DefaultWord,
+    context: &Context,
+    options: &ParseOptions,
+    operand: &mut Operand,
+) -> Result<(), ParseErrorInfo> {
+    match word {
+        ast::Word::SingleQuoted(s) => {
+            operand.value += s;
+            escape_glob(s, &mut operand.pattern);
+        }
+        ast::Word::DoubleQuoted(words) => {
+            for w in words {
+                let value = expand_simple_word(w, context, options)?;
+                operand.value += &value;
+                escape_glob(&value, &mut operand.pattern);
+            }
+        }
+        ast::Word::Simple(w) => {
+            let special = match w {
+                ast::SimpleWord::Star => Some('*'),
+                ast::SimpleWord::Question => Some('?'),
+                ast::SimpleWord::SquareOpen => Some('['),
+                ast::SimpleWord::SquareClose => Some(']'),
+                ast::SimpleWord::Tilde => Some('~'),
+                _ => None,
+            };
+            match (special, w) {
+                (Some(c), _) => {
+                    operand.value.push(c);
+                    operand.pattern.push(c);
+                }
+                (None, ast::SimpleWord::Escaped(_)) => {
+                    let value = expand_simple_word(w, context, options)?;
+                    operand.value += &value;
+                    escape_glob(&value, &mut operand.pattern);
+                }
+                (None, _) => {
+                    let value = expand_simple_word(w, context, options)?;
+                    operand.value += &value;
+                    operand.pattern += &value;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unlike in assignments, unset variables are allowed in conditions and
+/// expand to nothing, so `[ -z "$FOO" ]` works.
+fn expand_simple_word(
+    word: &ast::DefaultSimpleWord,
+    context: &Context,
+    options: &ParseOptions,
+) -> Result<String, ParseErrorInfo> {
+    match word {
+        ast::SimpleWord::Param(ast::Parameter::Var(name)) => Ok(context.get(name).cloned().unwrap_or_default()),
+        _ => get_simple_word_as_string(word, context, options).map(|s| s.into_owned()),
+    }
+}
+
+fn expand(
+    word: &ast::DefaultComplexWord,
+    context: &Context,
+    options: &ParseOptions,
+) -> Result<Operand, ParseErrorInfo> {
+    let mut operand = Operand {
+        value: String::new(),
+        pattern: String::new(),
+    };
+    match word {
+        ast::ComplexWord::Single(w) => expand_word(w, context, options, &mut operand)?,
+        ast::ComplexWord::Concat(words) => {
+            for w in words {
+                expand_word(w, context, options, &mut operand)?;
+            }
+        }
+    }
+    operand.value = check_expansion(operand.value, &options.limits)?;
+
+    Ok(operand)
+}
+
+fn glob_matches(value: &str, pattern: &str, limits: &Limits) -> Result<bool, ParseErrorInfo> {
+    // A whole `!(...)` pattern is the only negation a regex can express.
+    if let Some(inner) = pattern.strip_prefix("!(").and_then(|p| p.strip_suffix(')')) {
+        return Ok(!glob_matches(value, &format!("@({})", inner), limits)?);
+    }
+    Ok(glob::translate(pattern, glob::GlobFlags::with_limits(limits))?.is_match(value))
+}
+
+pub(super) fn eval_compound(
+    cmd: &ast::DefaultCompoundCommand,
+    context: &mut Context,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarningInfo>,
+) -> Result<(), ParseErrorInfo> {
+    if !cmd.io.is_empty() {
+        return Err(ParseErrorInfo::InvalidSyntax(
+            "Redirects not allowed.".to_string(),
+        ));
+    }
+
+    let body = match &cmd.kind {
+        ast::CompoundCommandKind::If {
+            conditionals,
+            else_branch,
+        } => {
+            let mut taken = else_branch.as_ref();
+            for pair in conditionals {
+                if eval_guard(&pair.guard, context, options)? {
+                    taken = Some(&pair.body);
+                    break;
+                }
+            }
+            taken
+        }
+        ast::CompoundCommandKind::Case { word, arms } => {
+            let value = expand(word, context, options)?.value;
+            let mut taken = None;
+            'arms: for arm in arms {
+                for pattern in arm.patterns.iter() {
+                    let pattern = expand(pattern, context, options)?.pattern;
+                    if glob_matches(&value, &pattern, &options.limits)? {
+                        taken = Some(&arm.body);
+                        break 'arms;
+                    }
+                }
+            }
+            taken
+        }
+        _ => {
+            return Err(ParseErrorInfo::InvalidSyntax(
+                "Only if and case blocks are allowed.".to_string(),
+            ));
+        }
+    };
+
+    for cmd in body.into_iter().flatten() {
+        get_args_top_level(cmd, context, options, warnings)?;
+    }
+
+    Ok(())
+}
+
+/// Exit status of the condition of an `if`, as in the status of its last
+/// command.
+fn eval_guard(
+    guard: &[ast::TopLevelCommand<String>],
+    context: &Context,
+    options: &ParseOptions,
+) -> Result<bool, ParseErrorInfo> {
+    let mut status = true;
+    for cmd in guard {
+        let list = match &cmd.0 {
+            ast::Command::List(list) => list,
+            ast::Command::Job(_) => {
+                return Err(ParseErrorInfo::InvalidSyntax(
+                    "Syntax error: job not allowed.".to_string(),
+                ));
+            }
+        };
+        status = eval_listable(&list.first, context, options)?;
+        for and_or in list.rest.iter() {
+            match and_or {
+                ast::AndOr::And(cmd) if status => status = eval_listable(cmd, context, options)?,
+                ast::AndOr::Or(cmd) if !status => status = eval_listable(cmd, context, options)?,
+                _ => (),
+            }
+        }
+    }
+
+    Ok(status)
+}
+
+fn eval_listable(
+    cmd: &ast::DefaultListableCommand,
+    context: &Context,
+    options: &ParseOptions,
+) -> Result<bool, ParseErrorInfo> {
+    match cmd {
+        ast::ListableCommand::Single(cmd) => eval_pipeable(cmd, context, options),
+        ast::ListableCommand::Pipe(bang, cmds) if cmds.len() == 1 => {
+            Ok(eval_pipeable(&cmds[0], context, options)? != *bang)
+        }
+        ast::ListableCommand::Pipe(_, _) => Err(ParseErrorInfo::InvalidSyntax(
+            "Pipe not allowed".to_string(),
+        )),
+    }
+}
+
+fn eval_pipeable(
+    cmd: &ast::DefaultPipeableCommand,
+    context: &Context,
+    options: &ParseOptions,
+) -> Result<bool, ParseErrorInfo> {
+    let cmd = match cmd {
+        ast::PipeableCommand::Simple(cmd) => cmd,
+        _ => {
+            return Err(ParseErrorInfo::InvalidSyntax(
+                "Only test commands are allowed in conditions.".to_string(),
+            ));
+        }
+    };
+    if !cmd.redirects_or_env_vars.is_empty() {
+        return Err(ParseErrorInfo::InvalidSyntax(
+            "Assignments and redirects not allowed in conditions.".to_string(),
+        ));
+    }
+    let mut words = Vec::new();
+    for word in cmd.redirects_or_cmd_words.iter() {
+        match word {
+            ast::RedirectOrCmdWord::CmdWord(w) => words.push(expand(&w.0, context, options)?),
+            ast::RedirectOrCmdWord::Redirect(_) => {
+                return Err(ParseErrorInfo::InvalidSyntax(
+                    "Redirects not allowed.".to_string(),
+                ));
+            }
+        }
+    }
+
+    eval_command(&words, &options.limits)
+}
+
+/// Exit status of a command of a condition, from its expanded words.
+fn eval_command(words: &[Operand], limits: &Limits) -> Result<bool, ParseErrorInfo> {
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (name.value.as_str(), args),
+        None => return Ok(true),
+    };
+    let closing = match name {
+        "true" | ":" => return Ok(true),
+        "false" => return Ok(false),
+        "test" => None,
+        "[" => Some("]"),
+        "[[" => Some("]]"),
+        _ => {
+            return Err(ParseErrorInfo::InvalidSyntax(format!(
+                "Command {} not allowed in conditions.",
+                name
+            )));
+        }
+    };
+    let args = match closing {
+        Some(closing) => match args.split_last() {
+            Some((last, args)) if last.value == closing => args,
+            _ => {
+                return Err(ParseErrorInfo::InvalidSyntax(format!(
+                    "Missing {} in test.",
+                    closing
+                )));
+            }
+        },
+        None => args,
+    };
+
+    let mut test = Test {
+        args,
+        pos: 0,
+        extended: name == "[[",
+        limits,
+    };
+    // An empty test is false.
+    if args.is_empty() {
+        return Ok(false);
+    }
+    let result = test.or()?;
+    if test.pos < args.len() {
+        return Err(ParseErrorInfo::InvalidSyntax(format!(
+            "Unexpected {} in test.",
+            args[test.pos].value
+        )));
+    }
+
+    Ok(result)
+}
+
+/// Recursive descent over the arguments of a test command.
+struct Test<'a> {
+    args: &'a [Operand],
+    pos: usize,
+    /// Whether this is a `[[` test, where the right side of `==` and `!=`
+    /// is a pattern unless quoted.
+    extended: bool,
+    limits: &'a Limits,
+}
+
+impl<'a> Test<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).map(|a| a.value.as_str())
+    }
+
+    fn next(&mut self) -> Result<&'a Operand, ParseErrorInfo> {
+        let arg = self.args.get(self.pos).ok_or_else(|| {
+            ParseErrorInfo::InvalidSyntax("Incomplete test expression.".to_string())
+        })?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    fn or(&mut self) -> Result<bool, ParseErrorInfo> {
+        let mut result = self.and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            result |= self.and()?;
+        }
+
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, ParseErrorInfo> {
+        let mut result = self.unary()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            result &= self.unary()?;
+        }
+
+        Ok(result)
+    }
+
+    fn unary(&mut self) -> Result<bool, ParseErrorInfo> {
+        // `[ ! = x ]` compares the string `!`.
+        if self.peek(1).is_some_and(is_binary) {
+            return self.binary();
+        }
+        match self.peek(0) {
+            Some("!") => {
+                self.pos += 1;
+                Ok(!self.unary()?)
+            }
+            Some("(") => {
+                self.pos += 1;
+                let result = self.or()?;
+                if self.next()?.value != ")" {
+                    return Err(ParseErrorInfo::InvalidSyntax(
+                        "Missing ) in test.".to_string(),
+                    ));
+                }
+                Ok(result)
+            }
+            Some("-z") if self.peek(1).is_some() => {
+                self.pos += 1;
+                Ok(self.next()?.value.is_empty())
+            }
+            Some("-n") if self.peek(1).is_some() => {
+                self.pos += 1;
+                Ok(!self.next()?.value.is_empty())
+            }
+            Some(op) if self.peek(1).is_some() && op.len() == 2 && op.starts_with('-') => {
+                Err(ParseErrorInfo::InvalidSyntax(format!(
+                    "Test operator {} not allowed.",
+                    op
+                )))
+            }
+            _ => Ok(!self.next()?.value.is_empty()),
+        }
+    }
+
+    fn binary(&mut self) -> Result<bool, ParseErrorInfo> {
+        let left = self.next()?;
+        let op = self.next()?.value.as_str();
+        let right = self.next()?;
+        match op {
+            "=" | "==" if self.extended => glob_matches(&left.value, &right.pattern, self.limits),
+            "!=" if self.extended => Ok(!glob_matches(&left.value, &right.pattern, self.limits)?),
+            "=" | "==" => Ok(left.value == right.value),
+            "!=" => Ok(left.value != right.value),
+            _ => {
+                let (left, right) = (integer(&left.value)?, integer(&right.value)?);
+                Ok(match op {
+                    "-eq" => left == right,
+                    "-ne" => left != right,
+                    "-lt" => left < right,
+                    "-le" => left <= right,
+                    "-gt" => left > right,
+                    "-ge" => left >= right,
+                    _ => unreachable!(),
+                })
+            }
+        }
+    }
+}
+
+fn is_binary(op: &str) -> bool {
+    matches!(op, "=" | "==" | "!=" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge")
+}
+
+fn integer(s: &str) -> Result<i64, ParseErrorInfo> {
+    s.trim()
+        .parse()
+        .map_err(|_| ParseErrorInfo::InvalidSyntax(format!("Integer expected in test, found {}.", s)))
+}
+
+/// Same as `expand`, for a lowered word. Quoting is not kept in lowered
+/// words, so expanded variables never act as patterns.
+fn expand_lowered(
+    word: &lowered::Word,
+    context: &Context,
+    options: &ParseOptions,
+) -> Result<Operand, ParseErrorInfo> {
+    let mut operand = Operand {
+        value: String::new(),
+        pattern: String::new(),
+    };
+    for part in word.parts.iter() {
+        let origin = |name: &str| context.get(name).map_or("", |v| v.as_str());
+        let value = match part {
+            WordPart::Glob(c) => {
+                operand.value.push(*c);
+                operand.pattern.push(*c);
+                continue;
+            }
+            WordPart::Literal(s) => s.clone(),
+            WordPart::Param(name) => origin(name).to_string(),
+            WordPart::Length(name) => {
+                substitution::get_length(origin(name), options.substring_mode).to_string()
+            }
+            WordPart::Substring { name, argument } => substitution::get_substring_with_mode(
+                origin(name),
+                &expand_lowered(argument, context, options)?.value,
+                options.substring_mode,
+            )?,
+            WordPart::Replace {
+                name,
+                all,
+                argument,
+            } => substitution::get_replace_with_limits(
+                origin(name),
+                &expand_lowered(argument, context, options)?.value,
+                *all,
+                &options.limits,
+            )?,
+        };
+        escape_glob(&value, &mut operand.pattern);
+        operand.value += &value;
+    }
+    operand.value = check_expansion(operand.value, &options.limits)?;
+
+    Ok(operand)
+}
+
+/// Exit status of a lowered condition, as in `eval_guard`.
+pub(super) fn eval_lowered_condition(
+    condition: &[lowered::Command],
+    context: &Context,
+    options: &ParseOptions,
+) -> Result<bool, ParseErrorInfo> {
+    let mut status = true;
+    for cmd in condition {
+        let run = match cmd.connector {
+            lowered::Connector::Sequence => true,
+            lowered::Connector::And => status,
+            lowered::Connector::Or => !status,
+        };
+        if run {
+            let words = cmd
+                .words
+                .iter()
+                .map(|w| expand_lowered(w, context, options))
+                .collect::<Result<Vec<_>, _>>()?;
+            status = eval_command(&words, &options.limits)? != cmd.negated;
+        }
+    }
+
+    Ok(status)
+}
+
+/// Whether the lowered `word` matches the case `pattern`.
+pub(super) fn lowered_case_matches(
+    word: &lowered::Word,
+    pattern: &lowered::Word,
+    context: &Context,
+    options: &ParseOptions,
+) -> Result<bool, ParseErrorInfo> {
+    glob_matches(
+        &expand_lowered(word, context, options)?.value,
+        &expand_lowered(pattern, context, options)?.pattern,
+        &options.limits,
+    )
+}
+
+/// Evaluate a test expression on its own, i.e: `[ "$ARCH" = amd64 ]`,
+/// `[[ $VER == 1.* ]]` or `test -n "$PKGDEP" && [ "$REL" -gt 0 ]`.
+///
+/// Supports string (in)equality, `-z`, `-n`, pattern matching with `==` and
+/// `!=` in `[[ ]]`, and `-eq`, `-ne`, `-lt`, `-le`, `-gt`, `-ge`. Unset
+/// variables are empty. Expressions that are malformed or use anything else,
+/// i.e: file tests, are false.
+pub fn eval_test(expr: &str, context: &Context) -> bool {
+    eval_test_with_options(expr, context, &ParseOptions::default())
+}
+
+/// Same as `eval_test`, within the limits of `options`. Expressions
+/// exceeding them are false.
+pub fn eval_test_with_options(expr: &str, context: &Context, options: &ParseOptions) -> bool {
+    if check_limits(expr, &options.limits).is_err() {
+        return false;
+    }
+    let mut parser = DefaultParser::new(Lexer::new(expr.chars()));
+    let mut cmds = Vec::new();
+    loop {
+        match parser.complete_command() {
+            Ok(Some(cmd)) => cmds.push(cmd),
+            Ok(None) => break,
+            Err(_) => return false,
+        }
+    }
+
+    !cmds.is_empty() && eval_guard(&cmds, context, options).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+
+    #[test]
+    fn test_if() {
+        let mut context = Context::new();
+        context.insert("ARCH".to_string(), "amd64".to_string());
+        let source = r#"
+PKGDEP="foo"
+if [ "$ARCH" = "amd64" ]; then
+    PKGDEP="$PKGDEP bar"
+elif [[ $ARCH == arm64 ]]; then
+    PKGDEP="$PKGDEP baz"
+else
+    PKGDEP=""
+fi
+if [ -z "$UNSET" ] && ! test "$ARCH" != amd64; then
+    A=1
+fi
+if [ -n "$UNSET" -o "$ARCH" = ppc64 ]; then B=1; fi
+"#;
+        parse(source, &mut context).unwrap();
+        assert_eq!(context["PKGDEP"], "foo bar");
+        assert_eq!(context["A"], "1");
+        assert!(!context.contains_key("B"));
+
+        context.insert("ARCH".to_string(), "arm64".to_string());
+        parse(source, &mut context).unwrap();
+        assert_eq!(context["PKGDEP"], "foo baz");
+
+        context.insert("ARCH".to_string(), "riscv64".to_string());
+        parse(source, &mut context).unwrap();
+        assert_eq!(context["PKGDEP"], "");
+    }
+
+    #[test]
+    fn test_case() {
+        let mut context = Context::new();
+        let source = r#"
+case "$ARCH" in
+    amd64|arm64)
+        B=64
+        ;;
+    "i*")
+        B=quoted
+        ;;
+    i*86)
+        B=32
+        ;;
+    *)
+        B=unknown
+        ;;
+esac
+"#;
+        for (arch, bits) in [("amd64", "64"), ("arm64", "64"), ("i486", "32"), ("i*", "quoted"), ("mips64r6el", "unknown")] {
+            context.insert("ARCH".to_string(), arch.to_string());
+            parse(source, &mut context).unwrap();
+            assert_eq!(context["B"], bits, "{}", arch);
+        }
+    }
+
+    #[test]
+    fn test_eval_test() {
+        let mut context = Context::new();
+        context.insert("ARCH".to_string(), "amd64".to_string());
+        context.insert("VER".to_string(), "1.2.3".to_string());
+        context.insert("REL".to_string(), "2".to_string());
+        let cases = vec![
+            ("[ \"$ARCH\" = amd64 ]", true),
+            ("[ \"$ARCH\" == arm64 ]", false),
+            ("[ \"$ARCH\" != arm64 ]", true),
+            ("test -z \"$UNSET\"", true),
+            ("[ -n \"$UNSET\" ]", false),
+            ("[ \"$ARCH\" ]", true),
+            ("[ ]", false),
+            ("[[ $VER == 1.* ]]", true),
+            ("[[ $VER == 2* ]]", false),
+            ("[[ $VER != 1.* ]]", false),
+            ("[[ $VER == \"1.*\" ]]", false),
+            ("[[ \"@(a|b)\" == \"@(a|b)\" ]]", true),
+            ("[[ a == \"@(a|b)\" ]]", false),
+            ("[[ xx == '+(x)' ]]", false),
+            ("[[ \"+(x)\" == '+(x)' ]]", true),
+            ("[ $VER = 1.* ]", false),
+            ("[ $REL -gt 1 ]", true),
+            ("[ $REL -le 1 ]", false),
+            ("[ $REL -eq 2 -a $ARCH = amd64 ]", true),
+            ("[ $REL -ne 2 -o ! -z $ARCH ]", true),
+            ("[ $REL -lt 1 ] || [[ $ARCH == amd* ]]", true),
+            ("[ $REL -ge 3 ] && [ -n $ARCH ]", false),
+            ("[ $ARCH -eq 1 ]", false),
+            ("[ -f /etc/os-release ]", false),
+            ("[ $ARCH = amd64", false),
+            ("rm -rf /", false),
+            ("", false),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(eval_test(expr, &context), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_extglob_matches() {
+        let glob_matches = |value, pattern| glob_matches(value, pattern, &Limits::default());
+        assert!(glob_matches("arm64", "@(amd|arm)64").unwrap());
+        assert!(!glob_matches("ppc64", "@(amd|arm)64").unwrap());
+        assert!(glob_matches("ppc64", "!(amd64|arm64)").unwrap());
+        assert!(!glob_matches("arm64", "!(amd64|arm64)").unwrap());
+        assert!(matches!(
+            glob_matches("arm64", "a!(md64)"),
+            Err(ParseErrorInfo::GlobError(e)) if e.contains("patterns")
+        ));
+    }
+
+    #[test]
+    fn test_rejected() {
+        let bad = vec![
+            "if echo 1; then A=1; fi",
+            "if [ 1 = 1 ]; then echo 1; fi",
+            "if [ -f /etc/os-release ]; then A=1; fi",
+            "if [ 1 = 1; then A=1; fi",
+            "if [ 1 = 1 ] > /dev/null; then A=1; fi",
+            "if [ 1 = 1 ]; then A=1; fi > /dev/null",
+            "while [ 1 = 1 ]; do A=1; done",
+            "for i in 1 2; do A=$i; done",
+        ];
+        for b in bad {
+            assert!(parse(b, &mut Context::new()).is_err(), "{}", b);
+        }
+    }
+}
@@ -1,6 +1,69 @@
-use super::ParseErrorInfo;
+//! Bash glob patterns, as used by `case`, `[[ == ]]` and the `${VAR/pat/rep}`
+//! substitutions. i.e:
+//! ```
+//! use abbs::apf::glob::{translate, GlobFlags};
+//!
+//! let regex = translate("lib@(foo|bar)-[0-9]*", GlobFlags::default()).unwrap();
+//! assert!(regex.is_match("libfoo-2.0"));
+//! assert!(!regex.is_match("libbaz-2.0"));
+//! ```
 
-pub fn get_regex_string_from_glob(glob: &str) -> Result<String, ParseErrorInfo> {
+use super::{Limits, ParseErrorInfo};
+use regex::{Regex, RegexBuilder};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlobFlags {
+    /// Whether `@(a|b)`, `+(..)`, `?(..)` and `*(..)` are patterns, as with
+    /// `shopt -s extglob`, which autobuild enables.
+    pub extglob: bool,
+    /// Whether the pattern must match the whole string, like in `case`,
+    /// rather than any part of it, like in substitutions.
+    pub anchored: bool,
+    /// Like `shopt -s nocasematch`.
+    pub case_insensitive: bool,
+    /// Bytes the compiled regex may take, see `Limits::regex_size_limit`.
+    pub size_limit: usize,
+}
+
+impl GlobFlags {
+    /// The default flags, with the regex size limit of `limits`.
+    pub fn with_limits(limits: &Limits) -> Self {
+        GlobFlags {
+            extglob: true,
+            anchored: true,
+            case_insensitive: false,
+            size_limit: limits.regex_size_limit,
+        }
+    }
+}
+
+impl Default for GlobFlags {
+    fn default() -> Self {
+        GlobFlags::with_limits(&Limits::default())
+    }
+}
+
+/// Translate `pattern` into a regex with the same semantics the parser uses.
+/// `!(..)` is only supported by `case` and `[[ ]]`, as the whole pattern,
+/// since the regex crate has no lookahead.
+pub fn translate(pattern: &str, flags: GlobFlags) -> Result<Regex, ParseErrorInfo> {
+    let regex = translate_to_string(pattern, flags.extglob)?;
+    let regex = match flags.anchored {
+        true => format!("^(?:{})$", regex),
+        false => regex,
+    };
+    Ok(RegexBuilder::new(&regex)
+        .case_insensitive(flags.case_insensitive)
+        .size_limit(flags.size_limit)
+        .build()?)
+}
+
+/// Translate a bash glob into an unanchored regex, with extglob enabled.
+pub(crate) fn get_regex_string_from_glob(glob: &str) -> Result<String, ParseErrorInfo> {
+    translate_to_string(glob, true)
+}
+
+fn translate_to_string(glob: &str, extglob: bool) -> Result<String, ParseErrorInfo> {
     let mut result = String::new();
     let mut idx = 0;
     let chars = glob.chars().collect::<Vec<_>>();
@@ -8,90 +71,164 @@ pub fn get_regex_string_from_glob(glob: &str) -> Result<String, ParseErrorInfo>
     result.reserve(length);
 
     while idx < length {
+        if extglob && idx + 1 < length && chars[idx + 1] == '(' && "@+?*!".contains(chars[idx]) {
+            idx = translate_extglob(&chars, idx, &mut result)?;
+            continue;
+        }
         match chars[idx] {
             '\\' => {
-                result.push('\\');
                 if idx + 1 < length {
                     idx += 1;
-                    result.push(chars[idx]);
+                    push_literal(chars[idx], &mut result);
                 } else {
                     return Err(ParseErrorInfo::GlobError("Incomplete escape sequence".to_string()));
                 }
             }
-            '[' => {
-                if idx + 1 >= length {
-                    result += "\\[";
-                    break;
-                }
-                let mut closing = idx;
-                let mut found = idx;
-                // find the closing bracket (greedy)
-                while closing < length {
-                    let capture = chars[closing];
-                    if capture == ']' {
-                        if closing + 1 >= length {
-                            // this one must be the closing bracket
-                            found = closing;
-                            break;
-                        }
-                        found = closing;
-                    // this is to ensure that the closing bracket is before another opening bracket
-                    } else if capture == '[' && found > idx {
-                        break;
-                    } else if capture == '!' && (chars[closing - 1] != '\\' && chars[closing - 1] != '[') {
-                        return Err(ParseErrorInfo::GlobError("Unescaped `!` symbol in a capturing group".to_string()));
-                    }
-                    closing += 1;
-                }
-                if found <= idx {
-                    // no matching closing bracket found, backtrack and interpret as non-capturing group
-                    result += "\\[";
-                    idx += 1;
-                    continue;
-                }
-                result.push('[');
-                idx += 1;
-                if chars[idx] == '!' {
-                    result.push('^');
-                    idx += 1;
-                }
-                for c in chars[idx..found].into_iter() {
-                    if *c == '[' || *c == ']' {
-                        result.push('\\');
-                    }
-                    result.push(*c);
-                }
-                result.push(']');
-                // advance the cursor to the closing brackets
-                idx = found;
-                // process repeating directives
-                if idx + 1 >= length {
-                    break;
-                }
-                let directive = chars[idx + 1];
-                if directive == '?' || directive == '*'{
-                    result.push(directive);
-                    idx += 2; // +1 for ] and +1 for */?
+            '[' => match translate_bracket(&chars, idx, &mut result) {
+                Some(end) => {
+                    idx = end;
                     continue;
                 }
-            }
+                // No closing bracket, so it is a literal `[`.
+                None => result += "\\[",
+            },
             '*' => {
                 result += ".*";
             }
             '?' => {
-                result += ".?";
+                result.push('.');
+            }
+            c => push_literal(c, &mut result),
+        }
+        idx += 1;
+    }
+
+    Ok(result)
+}
+
+fn push_literal(c: char, result: &mut String) {
+    let mut buf = [0; 4];
+    result.push_str(&regex::escape(c.encode_utf8(&mut buf)));
+}
+
+/// Characters with a meaning inside a regex class, including the `&&`, `--`
+/// and `~~` set operations. `-` is kept for ranges.
+fn push_class_literal(c: char, result: &mut String) {
+    if matches!(c, '[' | ']' | '\\' | '^' | '&' | '~') {
+        result.push('\\');
+    }
+    result.push(c);
+}
+
+/// Translate the bracket expression starting at `start`, i.e: `[!a-z]` or
+/// `[[:alpha:]_]`, into `result`. Returns the index after the closing
+/// bracket, or `None` without touching `result` if there is none.
+fn translate_bracket(chars: &[char], start: usize, result: &mut String) -> Option<usize> {
+    let mut class = String::from("[");
+    let mut idx = start + 1;
+    if idx < chars.len() && (chars[idx] == '!' || chars[idx] == '^') {
+        class.push('^');
+        idx += 1;
+    }
+    // A `]` right after the opening bracket is part of the set.
+    if idx < chars.len() && chars[idx] == ']' {
+        class += "\\]";
+        idx += 1;
+    }
+    while idx < chars.len() {
+        match chars[idx] {
+            ']' => {
+                class.push(']');
+                result.push_str(&class);
+                return Some(idx + 1);
             }
-            '!' => {
-                return Err(ParseErrorInfo::GlobError("Unescaped `!` symbol".to_string()));
+            '[' if chars.get(idx + 1) == Some(&':') => {
+                let name_start = idx + 2;
+                let name_end = (name_start..chars.len().saturating_sub(1))
+                    .find(|&i| chars[i] == ':' && chars[i + 1] == ']');
+                match name_end {
+                    Some(end) => {
+                        class.push_str("[:");
+                        class.extend(&chars[name_start..end]);
+                        class.push_str(":]");
+                        idx = end + 2;
+                        continue;
+                    }
+                    None => push_class_literal('[', &mut class),
+                }
             }
-            _ => {
-                result.push(chars[idx]);
+            '\\' if idx + 1 < chars.len() => {
+                idx += 1;
+                push_class_literal(chars[idx], &mut class);
             }
+            c => push_class_literal(c, &mut class),
         }
         idx += 1;
     }
 
-    Ok(result)
+    None
+}
+
+/// Translate the extglob `@(a|b)`, `+(..)`, `?(..)` or `*(..)` starting at
+/// `start` into `result`. Returns the index after the closing parenthesis.
+fn translate_extglob(chars: &[char], start: usize, result: &mut String) -> Result<usize, ParseErrorInfo> {
+    let mut depth = 0;
+    let mut alternatives = Vec::new();
+    let mut current = String::new();
+    let mut idx = start + 2;
+    while idx < chars.len() {
+        let c = chars[idx];
+        match c {
+            '\\' if idx + 1 < chars.len() => {
+                current.push(c);
+                idx += 1;
+                current.push(chars[idx]);
+            }
+            '(' => {
+                depth += 1;
+                current.push(c);
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                current.push(c);
+            }
+            ')' => break,
+            '|' if depth == 0 => alternatives.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+        idx += 1;
+    }
+    if idx >= chars.len() {
+        return Err(ParseErrorInfo::GlobError(format!(
+            "Unterminated `{}(` pattern",
+            chars[start]
+        )));
+    }
+    alternatives.push(current);
+
+    let suffix = match chars[start] {
+        '@' => "",
+        '+' => "+",
+        '?' => "?",
+        '*' => "*",
+        _ => {
+            // Would need a lookahead, which the regex crate does not have.
+            return Err(ParseErrorInfo::GlobError(
+                "`!(...)` is only supported as the whole of `case` or `[[ ]]` patterns".to_string(),
+            ));
+        }
+    };
+    result.push_str("(?:");
+    for (i, alternative) in alternatives.iter().enumerate() {
+        if i > 0 {
+            result.push('|');
+        }
+        result.push_str(&get_regex_string_from_glob(alternative)?);
+    }
+    result.push(')');
+    result.push_str(suffix);
+
+    Ok(idx + 1)
 }
 
 #[cfg(test)]
@@ -103,27 +240,147 @@ mod tests {
         let cases = vec![
             ("1234", "1234"),
             ("1234*", "1234.*"),
+            ("a?.c", "a.\\.c"),
             ("[!x?*]", "[^x?*]"),
-            ("[!abcd+]?", "[^abcd+]?"),
-            ("[abcd+][!123]*", "[abcd+][^123]*"),
-            ("[abcd+]?[!123]*", "[abcd+]?[^123]*"),
+            ("[!abcd+]?", "[^abcd+]."),
+            ("[abcd+][!123]*", "[abcd+][^123].*"),
+            ("[abcd+]?[!123]*", "[abcd+].[^123].*"),
             ("[a][b]", "[a][b]"),
             ("[!a][!b]", "[^a][^b]"),
-            ("[abc]]", "[abc\\]]"),
-            ("[abc]][0[]]", "[abc\\]][0\\[\\]]"),
+            ("[abc]]", "[abc]\\]"),
+            ("[abc]][0[]]", "[abc]\\][0\\[]\\]"),
+            ("[]a]", "[\\]a]"),
+            ("[[:alpha:]_]", "[[:alpha:]_]"),
+            ("[a&&b]", "[a\\&\\&b]"),
             ("[abc", "\\[abc"),
             ("[abc[p", "\\[abc\\[p"),
             ("[abc[", "\\[abc\\["),
+            ("\\*(", "\\*\\("),
         ];
 
         for i in cases {
-            assert_eq!(get_regex_string_from_glob(i.0).unwrap(), i.1);
+            assert_eq!(get_regex_string_from_glob(i.0).unwrap(), i.1, "{}", i.0);
         }
     }
 
+    /// Expected results are from `[[ $text == $glob ]]` in bash with
+    /// extglob enabled.
+    #[test]
+    fn test_matches_bash() {
+        let cases = vec![
+            ("1234", "1234", true),
+            ("1234*", "12345", true),
+            ("1234*", "1234", true),
+            ("1234*", "123", false),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("a?c", "abbc", false),
+            ("a.c", "a.c", true),
+            ("a.c", "abc", false),
+            ("a+", "a+", true),
+            ("a+", "aa", false),
+            ("^a$", "^a$", true),
+            ("{a,b}", "{a,b}", true),
+            ("[abc]", "b", true),
+            ("[abc]", "d", false),
+            ("[!abc]", "d", true),
+            ("[!abc]", "a", false),
+            ("[^abc]", "d", true),
+            ("[^abc]", "a", false),
+            ("[a-z]x", "qx", true),
+            ("[a-z]x", "Qx", false),
+            ("[]a]", "]", true),
+            ("[]a]", "a", true),
+            ("[!]a]", "]", false),
+            ("[!]a]", "b", true),
+            ("[*?]", "*", true),
+            ("[*?]", "?", true),
+            ("[*?]", "a", false),
+            ("[[:alpha:]]", "q", true),
+            ("[[:alpha:]]", "1", false),
+            ("[[:digit:][:upper:]]", "7", true),
+            ("[[:digit:][:upper:]]", "Q", true),
+            ("[[:digit:][:upper:]]", "q", false),
+            ("[![:space:]]", "a", true),
+            ("[![:space:]]", " ", false),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("\\?", "?", true),
+            ("\\?", "a", false),
+            ("\\[a]", "[a]", true),
+            ("\\[a]", "a", false),
+            ("a\\.b", "a.b", true),
+            ("a\\.b", "axb", false),
+            ("[abc", "[abc", true),
+            ("[abc", "a", false),
+            ("[a-]", "-", true),
+            ("[\\]]", "]", true),
+            ("[a&&b]", "&", true),
+            ("[a~~b]", "~", true),
+            ("[.]", ".", true),
+            ("[.]", "a", false),
+            ("*.tar.*", "foo.tar.xz", true),
+            ("*.tar.*", "foo.tarxz", false),
+            ("[!x!]", "!", false),
+            ("[!x!]", "y", true),
+            ("[!x!]", "x", false),
+            ("_!", "_!", true),
+        ];
+
+        for (glob, text, expected) in cases {
+            let regex = format!("^(?:{})$", get_regex_string_from_glob(glob).unwrap());
+            let regex = regex::Regex::new(&regex).unwrap();
+            assert_eq!(regex.is_match(text), expected, "{} {}", glob, text);
+        }
+    }
+
+    #[test]
+    fn test_extglob() {
+        let cases = vec![
+            ("@(foo|bar)", "(?:foo|bar)"),
+            ("lib+(a|b)z", "lib(?:a|b)+z"),
+            ("x?(-dev)", "x(?:\\-dev)?"),
+            ("*(ab)c*", "(?:ab)*c.*"),
+            ("@(a|+(b|c))", "(?:a|(?:b|c)+)"),
+            ("@(a\\)|b)", "(?:a\\)|b)"),
+            ("@()", "(?:)"),
+        ];
+        for (glob, regex) in cases {
+            assert_eq!(get_regex_string_from_glob(glob).unwrap(), regex, "{}", glob);
+        }
+    }
+
+    #[test]
+    fn test_translate() {
+        let regex = translate("*.tar.*", GlobFlags::default()).unwrap();
+        assert!(regex.is_match("foo.tar.xz"));
+        assert!(!regex.is_match("foo.tar"));
+
+        let flags = GlobFlags {
+            anchored: false,
+            ..Default::default()
+        };
+        assert!(translate("tar", flags).unwrap().is_match("foo.tar.xz"));
+        assert!(!translate("TAR", flags).unwrap().is_match("foo.tar.xz"));
+
+        let flags = GlobFlags {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert!(translate("*.TAR.*", flags).unwrap().is_match("foo.tar.xz"));
+
+        let flags = GlobFlags {
+            extglob: false,
+            ..Default::default()
+        };
+        assert!(translate("@(a|b)", flags).unwrap().is_match("@(a|b)"));
+        assert!(!translate("@(a|b)", flags).unwrap().is_match("a"));
+        assert!(translate("@(a|b)", GlobFlags::default()).unwrap().is_match("a"));
+    }
+
     #[test]
     fn test_bad_glob() {
-        let cases = vec!["[!x!]", "_!"];
+        let cases = vec!["abc\\", "@(a|b", "!(a)"];
         for i in cases {
             assert_eq!(get_regex_string_from_glob(i).is_ok(), false);
         }
@@ -0,0 +1,417 @@
+//! Incremental reparsing for editors.
+//! A `Document` keeps the source split into top-level statements, each with
+//! the context after evaluating it. An edit only re-evaluates statements from
+//! the first one it touches, up to the point where both the text and the
+//! variables in scope match the previous parse again.
+//!
+//! Unlike `parse`, evaluation does not stop at the first error: every
+//! statement is evaluated on top of whatever the statements before it defined,
+//! so a typo on one line does not hide everything below it.
+
+use super::{parse_lossless, parse_with_options, Context, Node, ParseError, ParseOptions};
+use std::{fmt, ops::Range};
+
+/// Replace the bytes in `range` with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditError {
+    /// The range ends before it starts or past the end of the source.
+    OutOfBounds(Range<usize>),
+    /// The range splits a multi-byte character.
+    NotCharBoundary(usize),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::OutOfBounds(r) => {
+                write!(f, "Edit range {}..{} is out of bounds", r.start, r.end)
+            }
+            EditError::NotCharBoundary(pos) => {
+                write!(f, "Edit position {} is not on a character boundary", pos)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// A top-level statement, i.e: an assignment or an `if` block.
+#[derive(Debug)]
+pub struct Statement {
+    range: Range<usize>,
+    line: usize,
+    col: usize,
+    error: Option<ParseError>,
+    /// Variables defined once this statement has been evaluated.
+    context: Context,
+}
+
+impl Statement {
+    /// Byte range of the statement in the source.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Line of its first character, starting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column of its first character, starting from 1.
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    /// Why the statement could not be evaluated, positioned in the whole
+    /// source.
+    pub fn error(&self) -> Option<&ParseError> {
+        self.error.as_ref()
+    }
+
+    /// Variables defined once this statement has been evaluated.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+#[derive(Debug)]
+pub struct Document {
+    source: String,
+    initial: Context,
+    options: ParseOptions,
+    statements: Vec<Statement>,
+}
+
+/// Line and column of byte `pos` in `source`, starting from 1.
+fn position(source: &str, pos: usize) -> (usize, usize) {
+    let before = &source[..pos];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, col)
+}
+
+/// Move an error found in a snippet starting at `line`:`col` to its position
+/// in the whole source.
+fn offset_error(mut error: ParseError, line: usize, col: usize) -> ParseError {
+    if error.line <= 1 {
+        error.col += col - 1;
+    }
+    error.line += line - 1;
+    error
+}
+
+fn shift(pos: usize, delta: isize) -> usize {
+    (pos as isize + delta) as usize
+}
+
+impl Document {
+    /// Parse `source` with `context` in scope, i.e: the spec variables when
+    /// `source` is a defines file.
+    pub fn new(source: &str, context: Context) -> Self {
+        Document::with_options(source, context, ParseOptions::default())
+    }
+
+    /// Same as `new`, evaluating each statement with `options`.
+    pub fn with_options(source: &str, context: Context, options: ParseOptions) -> Self {
+        let mut document = Document {
+            source: source.to_string(),
+            initial: context,
+            options,
+            statements: Vec::new(),
+        };
+        document.statements = document.scan(0);
+        document.evaluate(0, usize::MAX, Vec::new());
+        document
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    /// Variables defined by the whole document.
+    pub fn context(&self) -> &Context {
+        self.statements.last().map_or(&self.initial, |s| &s.context)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ParseError> {
+        self.statements.iter().filter_map(|s| s.error.as_ref())
+    }
+
+    /// The statement containing byte `pos`, if any.
+    pub fn statement_at(&self, pos: usize) -> Option<&Statement> {
+        self.statements.iter().find(|s| s.range.contains(&pos))
+    }
+
+    /// Split the source from byte `start`, which is the start of a statement,
+    /// into statements. They are not evaluated yet.
+    fn scan(&self, start: usize) -> Vec<Statement> {
+        let (mut line, mut col) = position(&self.source, start);
+        let tail = &self.source[start..];
+        let (nodes, broken) = match parse_lossless(tail) {
+            Ok(tree) => (tree.into_nodes(), None),
+            Err(e) => {
+                // Keep what comes before the line of the error if it scans on
+                // its own. The rest has no structure to go on, so it becomes a
+                // single broken statement.
+                let cut = tail
+                    .match_indices('\n')
+                    .nth(e.line.saturating_sub(2))
+                    .filter(|_| e.line > 1)
+                    .map_or(0, |(i, _)| i + 1);
+                let (nodes, cut) = match parse_lossless(&tail[..cut]) {
+                    Ok(tree) => (tree.into_nodes(), cut),
+                    Err(_) => (Vec::new(), 0),
+                };
+                (nodes, Some((cut, offset_error(e, line, col))))
+            }
+        };
+
+        let mut statements = Vec::new();
+        let mut pos = start;
+        for node in nodes {
+            let text = node.to_string();
+            if let Node::Assignment(_) | Node::Command(_) = node {
+                statements.push(Statement {
+                    range: pos..pos + text.len(),
+                    line,
+                    col,
+                    error: None,
+                    context: Context::new(),
+                });
+            }
+            pos += text.len();
+            match text.rfind('\n') {
+                Some(i) => {
+                    line += text.matches('\n').count();
+                    col = text.len() - i;
+                }
+                None => col += text.len(),
+            }
+        }
+
+        if let Some((cut, error)) = broken {
+            let rest = &tail[cut..];
+            let begin = start + cut + rest.len() - rest.trim_start().len();
+            let (line, col) = position(&self.source, begin);
+            statements.push(Statement {
+                range: begin..self.source.len(),
+                line,
+                col,
+                error: Some(error),
+                context: Context::new(),
+            });
+        }
+
+        statements
+    }
+
+    /// Evaluate statements from `index` on.
+    /// `previous` holds the results of the last parse for statements starting
+    /// at or after byte `reuse_from`, each with the context before it. Once a
+    /// statement lines up with one of them in the same context, the remaining
+    /// results are taken over as they are. Returns the index after the last
+    /// statement evaluated.
+    fn evaluate(
+        &mut self,
+        index: usize,
+        reuse_from: usize,
+        previous: Vec<(Context, Statement)>,
+    ) -> usize {
+        let mut context = match index {
+            0 => self.initial.clone(),
+            i => self.statements[i - 1].context.clone(),
+        };
+        for i in index..self.statements.len() {
+            let statement = &self.statements[i];
+            if statement.range.start >= reuse_from {
+                let found = previous.iter().position(|(_, p)| {
+                    p.range == statement.range && (p.line, p.col) == (statement.line, statement.col)
+                });
+                if let Some(j) = found {
+                    let rest = &previous[j..];
+                    if rest[0].0 == context && rest.len() == self.statements.len() - i {
+                        let reused = previous.into_iter().skip(j).map(|(_, p)| p);
+                        self.statements.truncate(i);
+                        self.statements.extend(reused);
+                        return i;
+                    }
+                }
+            }
+
+            let statement = &mut self.statements[i];
+            // Statements that failed to scan are not evaluated at all.
+            if statement.error.is_none() {
+                let source = &self.source[statement.range.clone()];
+                if let Err(e) = parse_with_options(source, &mut context, &self.options) {
+                    statement.error = Some(offset_error(e, statement.line, statement.col));
+                }
+            }
+            statement.context = context.clone();
+        }
+
+        self.statements.len()
+    }
+
+    /// Apply `edit` and reparse what it affects. Returns the indices of the
+    /// statements that were re-evaluated; the others kept their previous
+    /// results with updated positions.
+    pub fn apply_edit(&mut self, edit: &TextEdit) -> Result<Range<usize>, EditError> {
+        let range = edit.range.clone();
+        if range.start > range.end || range.end > self.source.len() {
+            return Err(EditError::OutOfBounds(range));
+        }
+        for pos in [range.start, range.end] {
+            if !self.source.is_char_boundary(pos) {
+                return Err(EditError::NotCharBoundary(pos));
+            }
+        }
+
+        // The statement before the edit is rescanned as well, since the edit
+        // may continue it, i.e: by adding `&& \` to its end.
+        let first = self
+            .statements
+            .iter()
+            .position(|s| s.range.end >= range.start)
+            .unwrap_or(self.statements.len())
+            .saturating_sub(1);
+        // The first statement may start after the edit, i.e: one in a leading
+        // comment, so it is scanned from the very start instead.
+        let scan_start = match first {
+            0 => 0,
+            i => self.statements[i].range.start,
+        };
+
+        let removed = &self.source[range.clone()];
+        let delta = edit.replacement.len() as isize - removed.len() as isize;
+        let line_delta = edit.replacement.matches('\n').count() as isize
+            - removed.matches('\n').count() as isize;
+        let edit_end = range.start + edit.replacement.len();
+
+        // Statements after the edit are unchanged, only moved.
+        let mut previous = Vec::new();
+        let mut context = match first {
+            0 => self.initial.clone(),
+            i => self.statements[i - 1].context.clone(),
+        };
+        for mut s in self.statements.drain(first..) {
+            let after = s.context.clone();
+            if s.range.start >= range.end {
+                s.range = shift(s.range.start, delta)..shift(s.range.end, delta);
+                s.line = shift(s.line, line_delta);
+                if let Some(e) = s.error.as_mut() {
+                    e.line = shift(e.line, line_delta);
+                }
+                previous.push((context, s));
+            }
+            context = after;
+        }
+        self.source.replace_range(range, &edit.replacement);
+
+        self.statements = {
+            let mut statements = std::mem::take(&mut self.statements);
+            statements.extend(self.scan(scan_start));
+            statements
+        };
+        let end = self.evaluate(first, edit_end, previous);
+
+        Ok(first..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(start: usize, end: usize, replacement: &str) -> TextEdit {
+        TextEdit {
+            range: start..end,
+            replacement: replacement.to_string(),
+        }
+    }
+
+    fn assert_consistent(doc: &Document) {
+        let fresh = Document::new(doc.source(), doc.initial.clone());
+        assert_eq!(doc.context(), fresh.context());
+        assert_eq!(doc.statements.len(), fresh.statements.len());
+        for (a, b) in doc.statements.iter().zip(fresh.statements.iter()) {
+            assert_eq!((a.range(), a.line(), a.col()), (b.range(), b.line(), b.col()));
+            assert_eq!(a.context(), b.context());
+            assert_eq!(a.error().map(|e| e.to_string()), b.error().map(|e| e.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_incremental() {
+        let source = "VER=1.0\nREL=1\nPKGDES=\"Foo $VER\"\nA=x\nB=2; C=3\n";
+        let mut doc = Document::new(source, Context::new());
+        assert_eq!(doc.statements().len(), 6);
+        assert_eq!(doc.context()["PKGDES"], "Foo 1.0");
+        assert_eq!(doc.errors().count(), 0);
+
+        // Every context after VER contains it, so all later statements are
+        // evaluated again.
+        let reparsed = doc.apply_edit(&edit(4, 7, "2.0")).unwrap();
+        assert_eq!(reparsed, 0..6);
+        assert_eq!(doc.context()["PKGDES"], "Foo 2.0");
+        assert_consistent(&doc);
+
+        // Nothing changes after the edit, so the previous results are reused.
+        let reparsed = doc.apply_edit(&edit(7, 7, " # bumped")).unwrap();
+        assert_eq!(reparsed, 0..1);
+        assert_consistent(&doc);
+
+        let pos = doc.source().find("C=3").unwrap() + 2;
+        let reparsed = doc.apply_edit(&edit(pos, pos + 1, "33")).unwrap();
+        assert_eq!(reparsed, 4..6);
+        assert_eq!(doc.context()["C"], "33");
+        assert_consistent(&doc);
+
+        // A new line moves everything below it.
+        let reparsed = doc.apply_edit(&edit(0, 0, "# comment\nX=1\n")).unwrap();
+        assert_eq!(reparsed, 0..7);
+        assert_eq!(doc.statements()[1].line(), 3);
+        assert_eq!(doc.statement_at(doc.source().find("B=2").unwrap()).unwrap().line(), 7);
+        assert_consistent(&doc);
+
+        // Only a comment went away, nothing needs to be evaluated again.
+        let reparsed = doc.apply_edit(&edit(0, 10, "")).unwrap();
+        assert_eq!(reparsed, 0..0);
+        assert_eq!(doc.statements()[1].line(), 2);
+        assert_consistent(&doc);
+    }
+
+    #[test]
+    fn test_incremental_errors() {
+        let mut doc = Document::new("A=1\nB=$(\nC=3\n", Context::new());
+        assert_eq!(doc.errors().count(), 1);
+        assert_eq!(doc.context()["A"], "1");
+        assert_consistent(&doc);
+
+        let pos = doc.source().find("$(").unwrap();
+        doc.apply_edit(&edit(pos, pos + 2, "2")).unwrap();
+        assert_eq!(doc.errors().count(), 0);
+        assert_eq!(doc.context()["C"], "3");
+        assert_consistent(&doc);
+
+        // Evaluation goes on after an error.
+        doc.apply_edit(&edit(0, 3, "A=$UNSET")).unwrap();
+        let error = doc.errors().next().unwrap();
+        assert!(error.to_string().contains("line 1"), "{}", error);
+        assert_eq!(doc.context()["C"], "3");
+        assert_consistent(&doc);
+
+        assert_eq!(doc.apply_edit(&edit(0, 100, "")), Err(EditError::OutOfBounds(0..100)));
+        let mut doc = Document::new("A=\"\u{e9}\"\n", Context::new());
+        assert_eq!(doc.apply_edit(&edit(4, 4, "x")), Err(EditError::NotCharBoundary(4)));
+    }
+}
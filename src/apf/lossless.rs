@@ -0,0 +1,713 @@
+//! Lossless view of a spec or defines file.
+//! Every byte of the input ends up in exactly one node, so the file can be
+//! edited and written back without touching formatting or comments.
+//! Only the top level is split up: anything that is not a plain assignment,
+//! i.e: a conditional or a function, is kept verbatim as a single `Command`.
+
+use super::{ParseError, ParseErrorInfo};
+use std::{fmt, ops::Range};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// Spaces, tabs, newlines and line continuations.
+    Whitespace(String),
+    /// A comment, from `#` to the end of the line, excluding the newline.
+    Comment(String),
+    /// `;` between two statements.
+    Separator(String),
+    Assignment(Assignment),
+    /// Any other statement, kept verbatim.
+    Command(String),
+}
+
+/// `NAME=VALUE` with the value as written, quotes and all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    name: String,
+    value: String,
+    line: usize,
+}
+
+impl Assignment {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value as written, i.e: `"foo-$VER"` including the quotes.
+    pub fn raw_value(&self) -> &str {
+        &self.value
+    }
+
+    /// Line of the assignment in the source, starting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub(crate) fn set_raw_value(&mut self, raw: String) {
+        self.value = raw;
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Whitespace(s) | Node::Comment(s) | Node::Separator(s) | Node::Command(s) => {
+                f.write_str(s)
+            }
+            Node::Assignment(a) => a.fmt(f),
+        }
+    }
+}
+
+/// Result of `parse_lossless`. Displaying it gives back the exact source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyntaxTree {
+    nodes: Vec<Node>,
+}
+
+impl SyntaxTree {
+    pub(crate) fn from_nodes(nodes: Vec<Node>) -> Self {
+        SyntaxTree { nodes }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub(crate) fn into_nodes(self) -> Vec<Node> {
+        self.nodes
+    }
+
+    pub fn assignments(&self) -> impl Iterator<Item = &Assignment> {
+        self.nodes.iter().filter_map(|n| match n {
+            Node::Assignment(a) => Some(a),
+            _ => None,
+        })
+    }
+
+    /// Name and raw value of every assignment, in order, including those
+    /// nested in a `Command`, i.e: in the branches of an `if` or a `case`.
+    pub fn all_assignments(&self) -> Vec<(&str, &str)> {
+        let mut result = Vec::new();
+        for node in self.nodes.iter() {
+            match node {
+                Node::Assignment(a) => result.push((a.name.as_str(), a.value.as_str())),
+                Node::Command(c) => {
+                    result.extend(nested_assignments(c).into_iter().map(|(n, r)| (n, &c[r])))
+                }
+                _ => (),
+            }
+        }
+        result
+    }
+
+    /// Set the raw value of the `index`th assignment of `name`, counted as in
+    /// `all_assignments`. Returns whether there is one.
+    pub fn set_nth_raw(&mut self, name: &str, index: usize, raw: &str) -> bool {
+        let mut seen = 0;
+        for node in self.nodes.iter_mut() {
+            match node {
+                Node::Assignment(a) if a.name == name => {
+                    if seen == index {
+                        a.value = raw.to_string();
+                        return true;
+                    }
+                    seen += 1;
+                }
+                Node::Command(c) => {
+                    let ranges: Vec<_> = nested_assignments(c)
+                        .into_iter()
+                        .filter(|(n, _)| *n == name)
+                        .map(|(_, r)| r)
+                        .collect();
+                    if let Some(range) = ranges.get(index - seen) {
+                        c.replace_range(range.clone(), raw);
+                        return true;
+                    }
+                    seen += ranges.len();
+                }
+                _ => (),
+            }
+        }
+        false
+    }
+
+    /// The last top-level assignment of `name`, which is the one that counts.
+    pub fn find(&self, name: &str) -> Option<&Assignment> {
+        self.assignments().filter(|a| a.name == name).last()
+    }
+
+    /// Set the raw value of the last top-level assignment of `name`, leaving
+    /// everything else untouched. If there is none, `NAME=RAW` is appended
+    /// on a line of its own.
+    pub fn set_raw(&mut self, name: &str, raw: &str) {
+        let last = self.nodes.iter_mut().rev().find_map(|n| match n {
+            Node::Assignment(a) if a.name == name => Some(a),
+            _ => None,
+        });
+        if let Some(a) = last {
+            a.value = raw.to_string();
+            return;
+        }
+
+        let mut source = self.to_string();
+        if !source.is_empty() && !source.ends_with('\n') {
+            self.nodes.push(Node::Whitespace("\n".to_string()));
+            source.push('\n');
+        }
+        self.nodes.push(Node::Assignment(Assignment {
+            name: name.to_string(),
+            value: raw.to_string(),
+            line: source.matches('\n').count() + 1,
+        }));
+        self.nodes.push(Node::Whitespace("\n".to_string()));
+    }
+
+    /// Remove every top-level assignment of `name`, together with a comment
+    /// trailing it and the line itself if nothing else is left on it.
+    /// Returns whether anything was removed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let is_blank = |n: Option<&Node>| matches!(n, Some(Node::Whitespace(ws)) if !ws.contains('\n'));
+        let mut removed = false;
+        let mut i = 0;
+        while i < self.nodes.len() {
+            if !matches!(&self.nodes[i], Node::Assignment(a) if a.name == name) {
+                i += 1;
+                continue;
+            }
+            removed = true;
+
+            let (mut start, mut end) = (i, i + 1);
+            match self.nodes.get(end) {
+                // `A=1; B=2`
+                Some(Node::Separator(_)) => {
+                    end += 1;
+                    if is_blank(self.nodes.get(end)) {
+                        end += 1;
+                    }
+                }
+                // `A=1 # comment` or `A=1 B=2`
+                Some(Node::Whitespace(ws)) if !ws.contains('\n') => {
+                    end += 1;
+                    if let Some(Node::Comment(_)) = self.nodes.get(end) {
+                        end += 1;
+                    }
+                }
+                _ => (),
+            }
+            // `B=2 A=1` leaves no trailing blanks behind.
+            let line_end = match self.nodes.get(end) {
+                None => true,
+                Some(Node::Whitespace(ws)) => ws.starts_with('\n'),
+                _ => false,
+            };
+            if line_end && start > 0 && is_blank(self.nodes.get(start - 1)) {
+                start -= 1;
+            }
+            let line_start = start == 0
+                || matches!(&self.nodes[start - 1], Node::Whitespace(ws) if ws.ends_with('\n'));
+
+            self.nodes.drain(start..end);
+            if line_start && line_end {
+                if let Some(Node::Whitespace(ws)) = self.nodes.get_mut(start) {
+                    ws.remove(0);
+                    if ws.is_empty() {
+                        self.nodes.remove(start);
+                    }
+                }
+            }
+            i = start;
+        }
+
+        removed
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in self.nodes.iter() {
+            node.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Words that start a block spanning several statements.
+const BLOCK_OPENERS: &[&str] = &["if", "case", "for", "while", "until", "select", "{"];
+const BLOCK_CLOSERS: &[&str] = &["fi", "esac", "done", "}"];
+/// Words after which the next word is a command again.
+const COMMAND_PREFIXES: &[&str] = &["if", "then", "else", "elif", "do", "while", "until", "{", "!"];
+/// Words that continue a statement onto the next line.
+const CONTINUATIONS: &[&str] = &["&&", "||", "|"];
+
+struct Word {
+    start: usize,
+    end: usize,
+    /// Contains an unquoted `|&<>()`, so it cannot be a plain assignment.
+    has_operator: bool,
+}
+
+struct Scanner<'a> {
+    src: &'a str,
+    bytes: &'a [u8],
+}
+
+impl<'a> Scanner<'a> {
+    fn error(&self, pos: usize, reason: &str) -> ParseError {
+        let before = &self.src[..pos.min(self.src.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        ParseError {
+            line,
+            col,
+            error: ParseErrorInfo::InvalidSyntax(reason.to_string()),
+        }
+    }
+
+    fn at(&self, pos: usize) -> Option<u8> {
+        self.bytes.get(pos).copied()
+    }
+
+    fn is_continuation(&self, pos: usize) -> bool {
+        self.at(pos) == Some(b'\\') && self.at(pos + 1) == Some(b'\n')
+    }
+
+    /// Skip spaces, tabs and line continuations, but not newlines.
+    fn skip_blanks(&self, mut pos: usize) -> usize {
+        loop {
+            match self.at(pos) {
+                Some(b' ') | Some(b'\t') | Some(b'\r') => pos += 1,
+                _ if self.is_continuation(pos) => pos += 2,
+                _ => return pos,
+            }
+        }
+    }
+
+    fn line_end(&self, pos: usize) -> usize {
+        self.src[pos..].find('\n').map_or(self.src.len(), |i| pos + i)
+    }
+
+    /// `pos` is right after the opening quote. Returns the position after the
+    /// closing quote.
+    fn single_quoted(&self, pos: usize) -> Result<usize, ParseError> {
+        match self.src[pos..].find('\'') {
+            Some(i) => Ok(pos + i + 1),
+            None => Err(self.error(pos - 1, "Unterminated single quote.")),
+        }
+    }
+
+    fn double_quoted(&self, mut pos: usize) -> Result<usize, ParseError> {
+        let start = pos - 1;
+        loop {
+            match self.at(pos) {
+                None => return Err(self.error(start, "Unterminated double quote.")),
+                Some(b'"') => return Ok(pos + 1),
+                Some(b'\\') => pos += 2,
+                Some(b'$') => pos = self.dollar(pos)?,
+                Some(b'`') => pos = self.backquoted(pos + 1)?,
+                Some(_) => pos += 1,
+            }
+        }
+    }
+
+    fn backquoted(&self, mut pos: usize) -> Result<usize, ParseError> {
+        let start = pos - 1;
+        loop {
+            match self.at(pos) {
+                None => return Err(self.error(start, "Unterminated backquote.")),
+                Some(b'`') => return Ok(pos + 1),
+                Some(b'\\') => pos += 2,
+                Some(_) => pos += 1,
+            }
+        }
+    }
+
+    /// `pos` is at a `$`. Skips `$(...)`, `${...}` or just the `$`.
+    fn dollar(&self, pos: usize) -> Result<usize, ParseError> {
+        match self.at(pos + 1) {
+            Some(b'(') => self.nested(pos + 2, b'(', b')', pos),
+            Some(b'{') => self.nested(pos + 2, b'{', b'}', pos),
+            _ => Ok(pos + 1),
+        }
+    }
+
+    /// Skip to the `close` matching an already consumed `open`.
+    fn nested(&self, mut pos: usize, open: u8, close: u8, start: usize) -> Result<usize, ParseError> {
+        let mut depth = 1;
+        loop {
+            match self.at(pos) {
+                None => return Err(self.error(start, "Unterminated substitution.")),
+                Some(b'\\') => pos += 2,
+                Some(b'\'') => pos = self.single_quoted(pos + 1)?,
+                Some(b'"') => pos = self.double_quoted(pos + 1)?,
+                Some(b'`') => pos = self.backquoted(pos + 1)?,
+                Some(b'$') => pos = self.dollar(pos)?,
+                Some(c) if c == close => {
+                    depth -= 1;
+                    pos += 1;
+                    if depth == 0 {
+                        return Ok(pos);
+                    }
+                }
+                Some(c) => {
+                    if c == open {
+                        depth += 1;
+                    }
+                    pos += 1;
+                }
+            }
+        }
+    }
+
+    fn word(&self, start: usize) -> Result<Word, ParseError> {
+        let mut pos = start;
+        let mut has_operator = false;
+        loop {
+            match self.at(pos) {
+                None | Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') | Some(b';') => break,
+                _ if self.is_continuation(pos) => break,
+                Some(b'\\') => pos += 2,
+                Some(b'\'') => pos = self.single_quoted(pos + 1)?,
+                Some(b'"') => pos = self.double_quoted(pos + 1)?,
+                Some(b'`') => pos = self.backquoted(pos + 1)?,
+                Some(b'$') => pos = self.dollar(pos)?,
+                Some(b'|') | Some(b'&') | Some(b'<') | Some(b'>') | Some(b'(') | Some(b')') => {
+                    has_operator = true;
+                    pos += 1;
+                }
+                Some(_) => pos += 1,
+            }
+        }
+
+        Ok(Word {
+            start,
+            end: pos.min(self.src.len()),
+            has_operator,
+        })
+    }
+
+    /// Scan a block starting at `start` up to the end of its closing word.
+    fn block(&self, start: usize) -> Result<usize, ParseError> {
+        let mut pos = start;
+        let mut depth = 0;
+        let mut command_position = true;
+        loop {
+            pos = self.skip_blanks(pos);
+            match self.at(pos) {
+                None => return Err(self.error(start, "Unterminated block.")),
+                Some(b'\n') => {
+                    pos += 1;
+                    command_position = true;
+                    continue;
+                }
+                Some(b';') => {
+                    pos += 1;
+                    command_position = true;
+                    continue;
+                }
+                Some(b'#') => {
+                    pos = self.line_end(pos);
+                    continue;
+                }
+                _ => (),
+            }
+            let word = self.word(pos)?;
+            let text = &self.src[word.start..word.end];
+            pos = word.end;
+            if command_position {
+                if BLOCK_OPENERS.contains(&text) {
+                    depth += 1;
+                } else if BLOCK_CLOSERS.contains(&text) {
+                    depth -= 1;
+                    if depth <= 0 {
+                        return Ok(pos);
+                    }
+                }
+            }
+            // The body of a function, i.e: `foo() {`, starts with a command too.
+            command_position = COMMAND_PREFIXES.contains(&text)
+                || CONTINUATIONS.contains(&text)
+                || text == "&"
+                || text.ends_with("()");
+        }
+    }
+
+    /// Names and value ranges of the assignments in commands, i.e: in the
+    /// branches of a block. Words ending with `)`, like `case` patterns, are
+    /// followed by a command.
+    fn assignments(&self) -> Result<Vec<(&'a str, Range<usize>)>, ParseError> {
+        let mut result = Vec::new();
+        let mut pos = 0;
+        let mut command_position = true;
+        loop {
+            pos = self.skip_blanks(pos);
+            match self.at(pos) {
+                None => return Ok(result),
+                Some(b'\n') | Some(b';') => {
+                    pos += 1;
+                    command_position = true;
+                    continue;
+                }
+                Some(b'#') => {
+                    pos = self.line_end(pos);
+                    continue;
+                }
+                _ => (),
+            }
+            let word = self.word(pos)?;
+            let text = &self.src[word.start..word.end];
+            pos = word.end;
+            match text.split_once('=') {
+                Some((name, _)) if command_position && !word.has_operator && is_name(name) => {
+                    result.push((name, word.start + name.len() + 1..word.end));
+                }
+                _ => {
+                    command_position = COMMAND_PREFIXES.contains(&text)
+                        || CONTINUATIONS.contains(&text)
+                        || text == "&"
+                        || text.ends_with(')');
+                }
+            }
+        }
+    }
+
+    /// Scan the statement at `start`, returning the nodes it consists of and
+    /// the position right after its last word.
+    fn statement(&self, start: usize, line: usize) -> Result<(Vec<Node>, usize), ParseError> {
+        let first = self.word(start)?;
+        let first_text = &self.src[first.start..first.end];
+        let after_first = self.skip_blanks(first.end);
+        let is_function = first_text.ends_with("()")
+            || self.src[after_first..].starts_with("()")
+            || self.src[after_first..].starts_with("( )");
+        if BLOCK_OPENERS.contains(&first_text) || is_function {
+            let end = self.block(start)?;
+            return Ok((vec![Node::Command(self.src[start..end].to_string())], end));
+        }
+
+        let mut words = vec![first];
+        loop {
+            let last = words.last().expect("at least one word");
+            let mut pos = self.skip_blanks(last.end);
+            match self.at(pos) {
+                None | Some(b';') | Some(b'#') => break,
+                Some(b'\n') => {
+                    let text = &self.src[last.start..last.end];
+                    if !CONTINUATIONS.iter().any(|c| text.ends_with(c)) {
+                        break;
+                    }
+                    pos += 1;
+                    while let Some(b'\n') = self.at(self.skip_blanks(pos)) {
+                        pos = self.skip_blanks(pos) + 1;
+                    }
+                    pos = self.skip_blanks(pos);
+                    if self.at(pos).is_none() {
+                        break;
+                    }
+                }
+                _ => (),
+            }
+            words.push(self.word(pos)?);
+        }
+        let end = words.last().expect("at least one word").end;
+
+        let assignments: Option<Vec<_>> = words
+            .iter()
+            .map(|w| {
+                if w.has_operator {
+                    return None;
+                }
+                let (name, value) = self.src[w.start..w.end].split_once('=')?;
+                if is_name(name) {
+                    Some((name, value))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let assignments = match assignments {
+            Some(a) => a,
+            None => return Ok((vec![Node::Command(self.src[start..end].to_string())], end)),
+        };
+
+        let mut nodes = Vec::new();
+        for (i, (name, value)) in assignments.into_iter().enumerate() {
+            if i > 0 {
+                let gap = &self.src[words[i - 1].end..words[i].start];
+                nodes.push(Node::Whitespace(gap.to_string()));
+            }
+            nodes.push(Node::Assignment(Assignment {
+                name: name.to_string(),
+                value: value.to_string(),
+                line: line + self.src[start..words[i].start].matches('\n').count(),
+            }));
+        }
+
+        Ok((nodes, end))
+    }
+}
+
+pub(super) fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Assignments nested in the `Command` `c`, see `SyntaxTree::all_assignments`.
+fn nested_assignments(c: &str) -> Vec<(&str, Range<usize>)> {
+    let scanner = Scanner {
+        src: c,
+        bytes: c.as_bytes(),
+    };
+    // Commands were scanned once already, they cannot fail.
+    scanner.assignments().unwrap_or_default()
+}
+
+/// Split `c` into a lossless syntax tree.
+/// Only quoting and block structure are checked; use `parse` to evaluate.
+pub fn parse_lossless(c: &str) -> Result<SyntaxTree, ParseError> {
+    let scanner = Scanner {
+        src: c,
+        bytes: c.as_bytes(),
+    };
+    let mut nodes = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+
+    while pos < c.len() {
+        let start = pos;
+        match scanner.at(pos) {
+            Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') => {
+                while let Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') = scanner.at(pos) {
+                    pos += 1;
+                }
+                pos = scanner.skip_blanks(pos);
+                nodes.push(Node::Whitespace(c[start..pos].to_string()));
+            }
+            _ if scanner.is_continuation(pos) => {
+                pos = scanner.skip_blanks(pos);
+                nodes.push(Node::Whitespace(c[start..pos].to_string()));
+            }
+            Some(b'#') => {
+                pos = scanner.line_end(pos);
+                nodes.push(Node::Comment(c[start..pos].to_string()));
+            }
+            Some(b';') => {
+                while scanner.at(pos) == Some(b';') {
+                    pos += 1;
+                }
+                nodes.push(Node::Separator(c[start..pos].to_string()));
+            }
+            _ => {
+                let (statement, end) = scanner.statement(pos, line)?;
+                nodes.extend(statement);
+                pos = end;
+            }
+        }
+        line += c[start..pos].matches('\n').count();
+    }
+
+    // Merge adjacent whitespace, i.e: a newline followed by a continuation.
+    let mut merged: Vec<Node> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match (merged.last_mut(), node) {
+            (Some(Node::Whitespace(prev)), Node::Whitespace(ws)) => prev.push_str(&ws),
+            (_, node) => merged.push(node),
+        }
+    }
+
+    Ok(SyntaxTree { nodes: merged })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = "# Maintainer: someone\nVER=1.2.3   # upstream\n\nSRCS=\"tbl::https://example.com/foo-$VER.tar.xz \\\n      file::https://example.com/bar\"\nCHKSUMS='sha256::abc'; REL=2\nif [[ \"$ARCH\" = amd64 ]]; then\n    PKGDEP=\"foo\" # fi\nfi\nfoo() {\n    echo }\n}\nA=1 B=$(echo \"a b\")\nmake && \\\n  make install\necho a |\n  cat\n";
+
+    #[test]
+    fn test_roundtrip() {
+        let tree = parse_lossless(SPEC).unwrap();
+        assert_eq!(tree.to_string(), SPEC);
+        let names: Vec<_> = tree.assignments().map(|a| (a.name(), a.line())).collect();
+        assert_eq!(
+            names,
+            vec![("VER", 2), ("SRCS", 4), ("CHKSUMS", 6), ("REL", 6), ("A", 13), ("B", 13)]
+        );
+        let srcs = tree.assignments().nth(1).unwrap();
+        assert!(srcs.raw_value().starts_with("\"tbl::"));
+        assert!(srcs.raw_value().ends_with("bar\""));
+
+        let commands: Vec<_> = tree
+            .nodes()
+            .iter()
+            .filter_map(|n| match n {
+                Node::Command(c) => Some(c.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(commands.len(), 4);
+        assert!(commands[0].starts_with("if") && commands[0].ends_with("fi"));
+        assert!(commands[1].starts_with("foo()") && commands[1].ends_with("}"));
+        assert_eq!(commands[2], "make && \\\n  make install");
+        assert_eq!(commands[3], "echo a |\n  cat");
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases = vec!["A=\"foo\n", "A='foo", "A=$(foo", "A=${foo", "if true; then\nA=1\n"];
+        for c in cases {
+            assert!(parse_lossless(c).is_err(), "{}", c);
+        }
+        let err = parse_lossless("A=1\nB=\"x").unwrap_err();
+        assert_eq!((err.line, err.col), (2, 3));
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut tree = parse_lossless("# c\nVER=1.0 # bump me\nA=1 B=2\n\nC=3; D=4\nE=5").unwrap();
+        tree.set_raw("VER", "1.1");
+        tree.set_raw("B", "\"x y\"");
+        assert_eq!(tree.find("B").unwrap().raw_value(), "\"x y\"");
+        tree.set_raw("F", "6");
+        assert_eq!(tree.find("F").unwrap().line(), 7);
+        assert_eq!(
+            tree.to_string(),
+            "# c\nVER=1.1 # bump me\nA=1 B=\"x y\"\n\nC=3; D=4\nE=5\nF=6\n"
+        );
+
+        assert!(tree.remove("VER"));
+        assert!(tree.remove("B"));
+        assert!(tree.remove("C"));
+        assert!(tree.remove("F"));
+        assert!(!tree.remove("G"));
+        assert_eq!(tree.to_string(), "# c\nA=1\n\nD=4\nE=5\n");
+        assert!(tree.remove("A"));
+        assert!(tree.remove("D"));
+        assert_eq!(tree.to_string(), "# c\n\nE=5\n");
+
+        let source = "A=1\nif true; then\n    A=\"$A 2\" B=3\nfi\ncase $ARCH in\n    amd64) A=4 ;;\n    *)\n        echo A=5\nesac\nA+=6\n";
+        let mut tree = parse_lossless(source).unwrap();
+        assert_eq!(
+            tree.all_assignments(),
+            vec![("A", "1"), ("A", "\"$A 2\""), ("B", "3"), ("A", "4")]
+        );
+        assert!(tree.set_nth_raw("A", 1, "\"$A x\""));
+        assert!(tree.set_nth_raw("A", 2, "y"));
+        assert!(!tree.set_nth_raw("A", 3, "z"));
+        assert_eq!(
+            tree.to_string(),
+            source.replace("$A 2", "$A x").replace("A=4", "A=y")
+        );
+
+        let mut tree = parse_lossless("").unwrap();
+        tree.set_raw("A", "1");
+        assert_eq!(tree.find("A").unwrap().line(), 1);
+        assert_eq!(tree.to_string(), "A=1\n");
+    }
+}
@@ -0,0 +1,504 @@
+//! Statements of a spec or defines file as the evaluator sees them, before
+//! evaluation: assignments with the parts of their values, and the `if` and
+//! `case` blocks around them. Quotes and line continuations are resolved,
+//! variables and substitutions are kept.
+//!
+//! Useful to evaluate files differently, i.e: finding which variables
+//! influence `PKGDEP`, without parsing them again.
+
+use super::ParseErrorInfo;
+use conch_parser::ast;
+
+/// A part of a word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    /// Text, quoted or not.
+    Literal(String),
+    /// `$NAME` or `${NAME}`.
+    Param(String),
+    /// `${#NAME}`.
+    Length(String),
+    /// `${NAME:ARGUMENT}`, i.e: `${VER:0:3}`.
+    Substring { name: String, argument: Word },
+    /// `${NAME/ARGUMENT}`, or `${NAME//ARGUMENT}` if `all`.
+    Replace {
+        name: String,
+        all: bool,
+        argument: Word,
+    },
+    /// An unquoted `*`, `?`, `[`, `]` or `~`, special in patterns.
+    Glob(char),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    fn push_literal(&mut self, s: &str) {
+        match self.parts.last_mut() {
+            Some(WordPart::Literal(last)) => last.push_str(s),
+            _ => self.parts.push(WordPart::Literal(s.to_string())),
+        }
+    }
+
+    /// The word if it contains no variables, i.e: `"1.0"`.
+    pub fn as_literal(&self) -> Option<String> {
+        let mut literal = String::new();
+        for part in self.parts.iter() {
+            match part {
+                WordPart::Literal(s) => literal.push_str(s),
+                WordPart::Glob(c) => literal.push(*c),
+                _ => return None,
+            }
+        }
+        Some(literal)
+    }
+
+    /// Variables the word refers to, in order, with duplicates.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut result = Vec::new();
+        for part in self.parts.iter() {
+            match part {
+                WordPart::Param(name) | WordPart::Length(name) => result.push(name.as_str()),
+                WordPart::Substring { name, argument }
+                | WordPart::Replace { name, argument, .. } => {
+                    result.push(name.as_str());
+                    result.extend(argument.variables());
+                }
+                WordPart::Literal(_) | WordPart::Glob(_) => (),
+            }
+        }
+        result
+    }
+}
+
+/// How a command of a condition is chained to the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// First command, or after `;` or a newline.
+    Sequence,
+    And,
+    Or,
+}
+
+/// A command of a condition, i.e: `[ "$ARCH" = amd64 ]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub connector: Connector,
+    /// Whether the status is inverted with `!`.
+    pub negated: bool,
+    /// Words of the command, the command name included.
+    pub words: Vec<Word>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub condition: Vec<Command>,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Assignment {
+        name: String,
+        value: Word,
+    },
+    If {
+        branches: Vec<Branch>,
+        else_body: Option<Vec<Statement>>,
+    },
+    Case {
+        word: Word,
+        arms: Vec<CaseArm>,
+    },
+}
+
+/// Every assignment in `statements`, nested ones included, in source order.
+pub fn assignments(statements: &[Statement]) -> Vec<(&str, &Word)> {
+    let mut result = Vec::new();
+    for statement in statements {
+        match statement {
+            Statement::Assignment { name, value } => result.push((name.as_str(), value)),
+            Statement::If {
+                branches,
+                else_body,
+            } => {
+                for branch in branches {
+                    result.extend(assignments(&branch.body));
+                }
+                result.extend(assignments(else_body.as_deref().unwrap_or_default()));
+            }
+            Statement::Case { arms, .. } => {
+                for arm in arms {
+                    result.extend(assignments(&arm.body));
+                }
+            }
+        }
+    }
+    result
+}
+
+fn not_allowed(what: &str) -> ParseErrorInfo {
+    ParseErrorInfo::InvalidSyntax(format!("{} not allowed.", what))
+}
+
+fn lower_param(param: &ast::DefaultParameter) -> Result<String, ParseErrorInfo> {
+    match param {
+        ast::Parameter::Var(name) => Ok(name.clone()),
+        _ => Err(ParseErrorInfo::InvalidSyntax(
+            "Unsupported parameter.".to_string(),
+        )),
+    }
+}
+
+fn lower_argument(argument: &Option<ast::TopLevelWord<String>>) -> Result<Word, ParseErrorInfo> {
+    match argument {
+        Some(word) => lower_word(word),
+        None => Err(ParseErrorInfo::InvalidSyntax(
+            "No substring command provided".to_string(),
+        )),
+    }
+}
+
+fn lower_simple_word(
+    word: &ast::DefaultSimpleWord,
+    quoted: bool,
+    result: &mut Word,
+) -> Result<(), ParseErrorInfo> {
+    let glob = match word {
+        ast::SimpleWord::Literal(s) => {
+            result.push_literal(s);
+            return Ok(());
+        }
+        ast::SimpleWord::Escaped(s) => {
+            if s != "\n" {
+                result.push_literal(s);
+            }
+            return Ok(());
+        }
+        ast::SimpleWord::Colon => {
+            result.push_literal(":");
+            return Ok(());
+        }
+        ast::SimpleWord::Param(p) => {
+            result.parts.push(WordPart::Param(lower_param(p)?));
+            return Ok(());
+        }
+        ast::SimpleWord::Subst(s) => {
+            let part = match s.as_ref() {
+                ast::ParameterSubstitution::Len(p) => WordPart::Length(lower_param(p)?),
+                ast::ParameterSubstitution::Substring(p, argument) => WordPart::Substring {
+                    name: lower_param(p)?,
+                    argument: lower_argument(argument)?,
+                },
+                ast::ParameterSubstitution::ReplaceString(p, argument) => WordPart::Replace {
+                    name: lower_param(p)?,
+                    all: false,
+                    argument: lower_argument(argument)?,
+                },
+                ast::ParameterSubstitution::ReplaceStringAll(p, argument) => WordPart::Replace {
+                    name: lower_param(p)?,
+                    all: true,
+                    argument: lower_argument(argument)?,
+                },
+                _ => {
+                    return Err(ParseErrorInfo::SubstitutionError(
+                        "Unsupported parameter substitution.".to_string(),
+                    ))
+                }
+            };
+            result.parts.push(part);
+            return Ok(());
+        }
+        ast::SimpleWord::Star => '*',
+        ast::SimpleWord::Question => '?',
+        ast::SimpleWord::SquareOpen => '[',
+        ast::SimpleWord::SquareClose => ']',
+        ast::SimpleWord::Tilde => '~',
+    };
+    match quoted {
+        true => result.push_literal(&glob.to_string()),
+        false => result.parts.push(WordPart::Glob(glob)),
+    }
+
+    Ok(())
+}
+
+pub(super) fn lower_word(word: &ast::DefaultComplexWord) -> Result<Word, ParseErrorInfo> {
+    let words = match word {
+        ast::ComplexWord::Single(w) => std::slice::from_ref(w),
+        ast::ComplexWord::Concat(words) => words.as_slice(),
+    };
+    let mut result = Word::default();
+    for word in words {
+        match word {
+            ast::Word::SingleQuoted(s) => result.push_literal(s),
+            ast::Word::Simple(w) => lower_simple_word(w, false, &mut result)?,
+            ast::Word::DoubleQuoted(words) => {
+                for w in words {
+                    lower_simple_word(w, true, &mut result)?;
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn lower_simple(
+    cmd: &ast::DefaultSimpleCommand,
+    result: &mut Vec<Statement>,
+) -> Result<(), ParseErrorInfo> {
+    if !cmd.redirects_or_cmd_words.is_empty() {
+        return Err(not_allowed("Commands"));
+    }
+    for redirect_or_env_var in cmd.redirects_or_env_vars.iter() {
+        match redirect_or_env_var {
+            ast::RedirectOrEnvVar::EnvVar(name, Some(word)) => result.push(Statement::Assignment {
+                name: name.clone(),
+                value: lower_word(word)?,
+            }),
+            ast::RedirectOrEnvVar::EnvVar(name, None) => {
+                return Err(ParseErrorInfo::InvalidSyntax(format!(
+                    "Variable {} without value.",
+                    name
+                )))
+            }
+            ast::RedirectOrEnvVar::Redirect(_) => return Err(not_allowed("Redirects")),
+        }
+    }
+
+    Ok(())
+}
+
+fn lower_condition(guard: &[ast::TopLevelCommand<String>]) -> Result<Vec<Command>, ParseErrorInfo> {
+    let mut result = Vec::new();
+    for cmd in guard {
+        let list = match &cmd.0 {
+            ast::Command::List(list) => list,
+            ast::Command::Job(_) => return Err(not_allowed("Syntax error: job")),
+        };
+        let rest = list.rest.iter().map(|and_or| match and_or {
+            ast::AndOr::And(cmd) => (Connector::And, cmd),
+            ast::AndOr::Or(cmd) => (Connector::Or, cmd),
+        });
+        for (connector, cmd) in std::iter::once((Connector::Sequence, &list.first)).chain(rest) {
+            let (negated, cmd) = match cmd {
+                ast::ListableCommand::Single(cmd) => (false, cmd),
+                ast::ListableCommand::Pipe(bang, cmds) if cmds.len() == 1 => (*bang, &cmds[0]),
+                ast::ListableCommand::Pipe(_, _) => return Err(not_allowed("Pipe")),
+            };
+            let cmd = match cmd {
+                ast::PipeableCommand::Simple(cmd) if cmd.redirects_or_env_vars.is_empty() => cmd,
+                _ => {
+                    return Err(ParseErrorInfo::InvalidSyntax(
+                        "Only test commands are allowed in conditions.".to_string(),
+                    ))
+                }
+            };
+            let mut words = Vec::new();
+            for word in cmd.redirects_or_cmd_words.iter() {
+                match word {
+                    ast::RedirectOrCmdWord::CmdWord(w) => words.push(lower_word(w)?),
+                    ast::RedirectOrCmdWord::Redirect(_) => return Err(not_allowed("Redirects")),
+                }
+            }
+            result.push(Command {
+                connector,
+                negated,
+                words,
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+fn lower_body(body: &[ast::TopLevelCommand<String>]) -> Result<Vec<Statement>, ParseErrorInfo> {
+    let mut result = Vec::new();
+    for cmd in body {
+        lower_top_level(cmd, &mut result)?;
+    }
+    Ok(result)
+}
+
+fn lower_compound(cmd: &ast::DefaultCompoundCommand) -> Result<Statement, ParseErrorInfo> {
+    if !cmd.io.is_empty() {
+        return Err(not_allowed("Redirects"));
+    }
+    match &cmd.kind {
+        ast::CompoundCommandKind::If {
+            conditionals,
+            else_branch,
+        } => Ok(Statement::If {
+            branches: conditionals
+                .iter()
+                .map(|pair| {
+                    Ok(Branch {
+                        condition: lower_condition(&pair.guard)?,
+                        body: lower_body(&pair.body)?,
+                    })
+                })
+                .collect::<Result<_, ParseErrorInfo>>()?,
+            else_body: else_branch.as_deref().map(lower_body).transpose()?,
+        }),
+        ast::CompoundCommandKind::Case { word, arms } => Ok(Statement::Case {
+            word: lower_word(word)?,
+            arms: arms
+                .iter()
+                .map(|arm| {
+                    Ok(CaseArm {
+                        patterns: arm
+                            .patterns
+                            .iter()
+                            .map(|p| lower_word(p))
+                            .collect::<Result<_, _>>()?,
+                        body: lower_body(&arm.body)?,
+                    })
+                })
+                .collect::<Result<_, ParseErrorInfo>>()?,
+        }),
+        _ => Err(ParseErrorInfo::InvalidSyntax(
+            "Only if and case blocks are allowed.".to_string(),
+        )),
+    }
+}
+
+pub(super) fn lower_top_level(
+    cmd: &ast::TopLevelCommand<String>,
+    result: &mut Vec<Statement>,
+) -> Result<(), ParseErrorInfo> {
+    let list = match &cmd.0 {
+        ast::Command::List(list) => list,
+        ast::Command::Job(_) => return Err(not_allowed("Syntax error: job")),
+    };
+    let rest = list.rest.iter().map(|and_or| match and_or {
+        ast::AndOr::And(cmd) | ast::AndOr::Or(cmd) => cmd,
+    });
+    for cmd in std::iter::once(&list.first).chain(rest) {
+        match cmd {
+            ast::ListableCommand::Single(ast::PipeableCommand::Simple(cmd)) => {
+                lower_simple(cmd, result)?
+            }
+            ast::ListableCommand::Single(ast::PipeableCommand::Compound(cmd)) => {
+                result.push(lower_compound(cmd)?)
+            }
+            ast::ListableCommand::Single(ast::PipeableCommand::FunctionDef(_, _)) => {
+                return Err(not_allowed("Function definition"))
+            }
+            ast::ListableCommand::Pipe(_, _) => return Err(not_allowed("Pipe")),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apf::{lower, parse_lowered, Context};
+
+    fn literal(s: &str) -> WordPart {
+        WordPart::Literal(s.to_string())
+    }
+
+    #[test]
+    fn test_lower() {
+        let statements = lower(
+            "VER=1.0\nSRCS=\"tbl::https://example.com/foo-${VER/./_}.tar.xz\" \\\n  A='$B'\n\
+             if [[ \"$ARCH\" == arm* ]] && ! false; then\n    PKGDEP=\"$PKGDEP ${#VER}\"\nelse\n    B=\"*\"\nfi\n\
+             case $ARCH in\n    amd64|i486) C=x86 ;;\nesac\n",
+        )
+        .unwrap();
+        assert_eq!(statements.len(), 5);
+        assert_eq!(
+            statements[1],
+            Statement::Assignment {
+                name: "SRCS".to_string(),
+                value: Word {
+                    parts: vec![
+                        literal("tbl::https://example.com/foo-"),
+                        WordPart::Replace {
+                            name: "VER".to_string(),
+                            all: false,
+                            argument: Word {
+                                parts: vec![literal("./_")]
+                            },
+                        },
+                        literal(".tar.xz"),
+                    ]
+                },
+            }
+        );
+        assert_eq!(
+            statements[2],
+            Statement::Assignment {
+                name: "A".to_string(),
+                value: Word {
+                    parts: vec![literal("$B")]
+                },
+            }
+        );
+        match &statements[3] {
+            Statement::If {
+                branches,
+                else_body,
+            } => {
+                let condition = &branches[0].condition;
+                assert_eq!(condition.len(), 2);
+                assert_eq!(
+                    condition[0].words[3].parts,
+                    vec![literal("arm"), WordPart::Glob('*')]
+                );
+                assert_eq!(condition[1].connector, Connector::And);
+                assert!(condition[1].negated);
+                assert_eq!(
+                    else_body.as_ref().unwrap()[0],
+                    Statement::Assignment {
+                        name: "B".to_string(),
+                        value: Word {
+                            parts: vec![literal("*")]
+                        },
+                    }
+                );
+            }
+            s => panic!("Unexpected {:?}", s),
+        }
+
+        let names: Vec<_> = assignments(&statements).iter().map(|(n, _)| *n).collect();
+        assert_eq!(names, vec!["VER", "SRCS", "A", "PKGDEP", "B", "C"]);
+        let (_, pkgdep) = assignments(&statements)[3];
+        assert_eq!(pkgdep.variables(), vec!["PKGDEP", "VER"]);
+        assert_eq!(pkgdep.as_literal(), None);
+        assert_eq!(
+            statements_value(&statements, 0).as_literal().as_deref(),
+            Some("1.0")
+        );
+
+        assert!(lower("echo foo\n").is_err());
+        assert!(lower("A=$(echo foo)\n").is_err());
+    }
+
+    fn statements_value(statements: &[Statement], i: usize) -> &Word {
+        match &statements[i] {
+            Statement::Assignment { value, .. } => value,
+            s => panic!("Unexpected {:?}", s),
+        }
+    }
+
+    #[test]
+    fn test_parse_lowered() {
+        let mut context = Context::new();
+        let statements = parse_lowered("VER=1.0\nPKGVER=\"$VER\"\n", &mut context).unwrap();
+        assert_eq!(context["PKGVER"], "1.0");
+        assert_eq!(statements.len(), 2);
+    }
+}
@@ -1,12 +1,31 @@
-mod glob;
-mod substitution;
+mod condition;
+pub mod glob;
+mod incremental;
+mod lossless;
+pub mod lowered;
+mod serialize;
+pub mod substitution;
+pub mod symbolic;
+pub mod taint;
 
+pub use condition::{eval_test, eval_test_with_options};
+pub use incremental::{Document, EditError, Statement, TextEdit};
+pub use lossless::{parse_lossless, Assignment, Node, SyntaxTree};
+pub use serialize::{quote, quote_template, quote_with, serialize, unquote, SerializeError, Style};
+
+use crate::autobuild::is_builtin_variable;
+use substitution::SubstringMode;
 use conch_parser::ast;
 use conch_parser::lexer::Lexer;
-use conch_parser::parse::DefaultParser;
-use std::{collections::HashMap, fmt};
+use conch_parser::parse::{DefaultParser, SourcePos};
+use std::{borrow::Cow, collections::HashMap, fmt, ops::Range};
+#[cfg(feature = "std")]
+use std::{
+    cell::Cell,
+    io::{self, BufReader, Read},
+};
 
-type Context = HashMap<String, String>;
+pub type Context = HashMap<String, String>;
 
 #[derive(Debug)]
 pub struct ParseError {
@@ -18,20 +37,29 @@ pub struct ParseError {
 #[derive(Debug)]
 pub enum ParseErrorInfo {
     InvalidSyntax(String),
-    ContextError(String),
+    ContextError {
+        reason: String,
+        /// The variable of the context most likely meant, if one was not
+        /// found, i.e: `PKGVER` for `${PKGVRE}`.
+        suggestion: Option<String>,
+    },
     SubstitutionError(String),
     GlobError(String),
     RegexError(String),
+    IoError(String),
+    /// Input beyond one of the `Limits` of the evaluation.
+    LimitExceeded(String),
 }
 
 impl From<regex::Error> for ParseErrorInfo {
     fn from(err: regex::Error) -> Self {
         match err {
             regex::Error::Syntax(s) => ParseErrorInfo::RegexError(format!("Syntax error: {}", s)),
-            regex::Error::CompiledTooBig(_size) => {
-                ParseErrorInfo::RegexError(format!("Compiled syntax too big."))
-            }
-            _ => ParseErrorInfo::RegexError(format!("Internal regex error.")),
+            regex::Error::CompiledTooBig(size) => ParseErrorInfo::LimitExceeded(format!(
+                "Regex compiled to more than {} bytes.",
+                size
+            )),
+            _ => ParseErrorInfo::RegexError("Internal regex error.".to_string()),
         }
     }
 }
@@ -40,27 +68,468 @@ impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let (err_type, reason) = match &self.error {
             ParseErrorInfo::InvalidSyntax(r) => ("Invalid syntax", r),
-            ParseErrorInfo::ContextError(r) => ("Context error", r),
+            ParseErrorInfo::ContextError { reason, .. } => ("Context error", reason),
             ParseErrorInfo::SubstitutionError(r) => ("Substitution error", r),
             ParseErrorInfo::GlobError(r) => ("Glob translation error", r),
             ParseErrorInfo::RegexError(r) => ("Regex error", r),
+            ParseErrorInfo::IoError(r) => ("I/O error", r),
+            ParseErrorInfo::LimitExceeded(r) => ("Limit exceeded", r),
         };
 
         write!(
             f,
             "{} at line {}, col {}. Reason: {}",
             err_type, self.line, self.col, reason
-        )
+        )?;
+        if let Some(suggestion) = self.suggestion() {
+            write!(f, " Did you mean {}?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl ParseError {
+    /// The variable likely meant where one was not found, see
+    /// `ParseErrorInfo::ContextError`.
+    pub fn suggestion(&self) -> Option<&str> {
+        match &self.error {
+            ParseErrorInfo::ContextError { suggestion, .. } => suggestion.as_deref(),
+            _ => None,
+        }
     }
 }
 
 impl std::error::Error for ParseError {}
 
+fn context_error(reason: String) -> ParseErrorInfo {
+    ParseErrorInfo::ContextError {
+        reason,
+        suggestion: None,
+    }
+}
+
+/// `param` is not set in `context`; suggest a variable of `context` with a
+/// close name, if any.
+fn not_found(param: &ast::DefaultParameter, context: &Context) -> ParseErrorInfo {
+    let suggestion = match param {
+        ast::Parameter::Var(name) => {
+            crate::fields::closest(name, context.keys().map(|k| k.as_str()))
+        }
+        _ => None,
+    };
+    ParseErrorInfo::ContextError {
+        reason: format!("Param {} not found.", param),
+        suggestion: suggestion.map(|s| s.to_string()),
+    }
+}
+
+/// Something suspicious that does not stop parsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    pub line: usize,
+    pub col: usize,
+    pub warning: ParseWarningInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseWarningInfo {
+    /// Assignment to a variable autobuild defines itself, which is silently
+    /// overwritten at build time.
+    ShadowsBuiltin(String),
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match &self.warning {
+            ParseWarningInfo::ShadowsBuiltin(name) => format!(
+                "{} is defined by autobuild, this assignment will be ignored",
+                name
+            ),
+        };
+
+        write!(f, "Warning at line {}, col {}: {}", self.line, self.col, reason)
+    }
+}
+
+/// A function definition recorded by `parse_with_functions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellFunction {
+    name: String,
+    source_span: Range<usize>,
+    raw_body: String,
+}
+
+impl ShellFunction {
+    fn new(name: &str, source: &str, span: Range<usize>) -> Self {
+        let text = source[span.clone()].trim_end();
+        let source_span = span.start..span.start + text.len();
+        // Skip `function NAME` or `NAME()` to get to the body.
+        let rest = text
+            .strip_prefix("function")
+            .filter(|r| r.starts_with(char::is_whitespace))
+            .unwrap_or(text)
+            .trim_start();
+        let rest = rest[name.len().min(rest.len())..].trim_start();
+        let rest = rest.strip_prefix('(').map_or(rest, |r| {
+            r.trim_start().strip_prefix(')').unwrap_or(r)
+        });
+
+        ShellFunction {
+            name: name.to_string(),
+            source_span,
+            raw_body: rest.trim_start().to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Byte range of the whole definition in the parsed source.
+    pub fn source_span(&self) -> Range<usize> {
+        self.source_span.clone()
+    }
+
+    /// Source of the body, i.e: `{ echo 1; }`, unevaluated.
+    pub fn raw_body(&self) -> &str {
+        &self.raw_body
+    }
+}
+
+fn function_name(cmd: &ast::TopLevelCommand<String>) -> Option<&str> {
+    let list = match &cmd.0 {
+        ast::Command::List(list) if list.rest.is_empty() => list,
+        _ => return None,
+    };
+    match &list.first {
+        ast::ListableCommand::Single(ast::PipeableCommand::FunctionDef(name, _)) => Some(name),
+        _ => None,
+    }
+}
+
 pub fn parse(c: &str, context: &mut Context) -> Result<(), ParseError> {
+    parse_with_warnings(c, context, &mut Vec::new())
+}
+
+/// Same as `parse`, but also collects warnings into `warnings`.
+pub fn parse_with_warnings(
+    c: &str,
+    context: &mut Context,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<(), ParseError> {
+    parse_inner(
+        c,
+        Some(context),
+        warnings,
+        None,
+        None,
+        None,
+        &Evaluation::default(),
+    )
+}
+
+/// Same as `parse`, but function definitions, i.e: `PKGEPOCH() { ... }`, are
+/// recorded into `functions` instead of being rejected. Their bodies are not
+/// evaluated.
+pub fn parse_with_functions(
+    c: &str,
+    context: &mut Context,
+    functions: &mut Vec<ShellFunction>,
+) -> Result<(), ParseError> {
+    parse_inner(
+        c,
+        Some(context),
+        &mut Vec::new(),
+        Some(functions),
+        None,
+        None,
+        &Evaluation::default(),
+    )
+}
+
+/// Statements of `c` without evaluating them, see `lowered`.
+pub fn lower(c: &str) -> Result<Vec<lowered::Statement>, ParseError> {
+    let mut statements = Vec::new();
+    parse_inner(
+        c,
+        None,
+        &mut Vec::new(),
+        None,
+        Some(&mut statements),
+        None,
+        &Evaluation::default(),
+    )?;
+    Ok(statements)
+}
+
+/// Same as `parse`, also returning the statements of `c` as `lower` does.
+pub fn parse_lowered(
+    c: &str,
+    context: &mut Context,
+) -> Result<Vec<lowered::Statement>, ParseError> {
+    let mut statements = Vec::new();
+    parse_inner(
+        c,
+        Some(context),
+        &mut Vec::new(),
+        None,
+        Some(&mut statements),
+        None,
+        &Evaluation::default(),
+    )?;
+    Ok(statements)
+}
+
+/// Evaluate `c` without requiring every variable to be known, see
+/// `symbolic`.
+pub fn parse_symbolic(c: &str, context: &mut symbolic::SymbolicContext) -> Result<(), ParseError> {
+    parse_symbolic_with_options(c, context, DEFAULT_OPTIONS)
+}
+
+/// Same as `parse_symbolic`, with `options`.
+pub fn parse_symbolic_with_options(
+    c: &str,
+    context: &mut symbolic::SymbolicContext,
+    options: &ParseOptions,
+) -> Result<(), ParseError> {
+    let eval = Evaluation {
+        options,
+        sources: None,
+    };
+    parse_inner(
+        c,
+        None,
+        &mut Vec::new(),
+        None,
+        Some(&mut Vec::new()),
+        Some(context),
+        &eval,
+    )
+}
+
+/// Default limit of nested sourcing for `parse_with_sources`.
+pub const MAX_SOURCE_DEPTH: usize = 4;
+
+/// Bounds on the input of an evaluation, for services parsing untrusted
+/// files, i.e: the contents of pull requests. Exceeding any of them is a
+/// `ParseErrorInfo::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes of a file, checked for sourced files one by one.
+    pub max_file_size: usize,
+    /// Nesting of `${...}` and `$(...)`, i.e: 2 for `${A/${B}/c}`.
+    pub max_substitution_depth: usize,
+    /// Bytes of a word once expanded, i.e: the value of an assignment.
+    pub max_expansion_length: usize,
+    /// Bytes a regex compiled from a pattern may take, i.e: for
+    /// `${VAR/pat/rep}` or `case`.
+    pub regex_size_limit: usize,
+}
+
+const DEFAULT_LIMITS: Limits = Limits {
+    max_file_size: 4 << 20,
+    max_substitution_depth: 32,
+    max_expansion_length: 1 << 20,
+    // The default of the regex crate.
+    regex_size_limit: 10 << 20,
+};
+
+impl Default for Limits {
+    fn default() -> Self {
+        DEFAULT_LIMITS
+    }
+}
+
+/// Options of `parse_with_options`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Commands skipped instead of rejected, see `allow_commands`.
+    pub allowed_commands: Vec<String>,
+    pub limits: Limits,
+    /// What `${VAR:BEGIN:LENGTH}` and `${#VAR}` count in.
+    pub substring_mode: SubstringMode,
+}
+
+impl ParseOptions {
+    /// Skip simple commands named one of `names` instead of rejecting them,
+    /// i.e: `&["true", ":"]` for the no-ops left in some legacy defines. They
+    /// are never run and their arguments are not evaluated, so
+    /// `: ${FOO:=bar}` does not assign `FOO`. Commands with redirects or
+    /// assignments in front are still rejected.
+    pub fn allow_commands(mut self, names: &[&str]) -> Self {
+        self.allowed_commands
+            .extend(names.iter().map(|n| n.to_string()));
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn substring_mode(mut self, mode: SubstringMode) -> Self {
+        self.substring_mode = mode;
+        self
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        self.allowed_commands.iter().any(|c| c == name)
+    }
+}
+
+const DEFAULT_OPTIONS: &ParseOptions = &ParseOptions {
+    allowed_commands: Vec::new(),
+    limits: DEFAULT_LIMITS,
+    substring_mode: SubstringMode::Chars,
+};
+
+/// How `.` and `source` are followed while evaluating a file.
+#[derive(Clone, Copy)]
+struct Sources<'a> {
+    /// Contents of a sourced file, or why it cannot be read.
+    read: &'a dyn Fn(&str) -> Result<String, String>,
+    /// Files sourced from here may source others as long as this is not 0.
+    depth: usize,
+    /// Whether this is a sourced file, which may only contain assignments.
+    nested: bool,
+}
+
+/// What evaluating a file may do besides assignments and conditions.
+#[derive(Clone, Copy)]
+struct Evaluation<'a> {
+    options: &'a ParseOptions,
+    sources: Option<Sources<'a>>,
+}
+
+impl Default for Evaluation<'_> {
+    fn default() -> Self {
+        Evaluation {
+            options: DEFAULT_OPTIONS,
+            sources: None,
+        }
+    }
+}
+
+/// Same as `parse`, with `options`, i.e:
+/// `parse_with_options(c, context, &ParseOptions::default().allow_commands(&["true"]))`.
+pub fn parse_with_options(
+    c: &str,
+    context: &mut Context,
+    options: &ParseOptions,
+) -> Result<(), ParseError> {
+    let eval = Evaluation {
+        options,
+        sources: None,
+    };
+    parse_inner(c, Some(context), &mut Vec::new(), None, None, None, &eval)
+}
+
+/// Same as `parse`, but `. FILE` and `source FILE` are followed for relative
+/// paths, i.e: `. ../common.sh` in the defines of a sub-package sharing
+/// variables with its siblings. `read` gives the contents of `FILE`, as
+/// written in the directive. Sourced files may only contain assignments and
+/// directives themselves, nested at most `max_depth` deep.
+#[cfg(feature = "std")]
+pub fn parse_with_sources(
+    c: &str,
+    context: &mut Context,
+    read: &dyn Fn(&str) -> io::Result<String>,
+    max_depth: usize,
+) -> Result<(), ParseError> {
+    let read = |file: &str| read(file).map_err(|e| e.to_string());
+    let eval = Evaluation {
+        sources: Some(Sources {
+            read: &read,
+            depth: max_depth,
+            nested: false,
+        }),
+        ..Default::default()
+    };
+    parse_inner(c, Some(context), &mut Vec::new(), None, None, None, &eval)
+}
+
+/// The file of a `. FILE` or `source FILE` directive, if `cmd` is one.
+fn source_directive(
+    cmd: &ast::TopLevelCommand<String>,
+) -> Option<Result<&ast::DefaultComplexWord, ParseErrorInfo>> {
+    let simple = single_simple_command(cmd)?;
+    let words: Vec<_> = simple
+        .redirects_or_cmd_words
+        .iter()
+        .map(|w| match w {
+            ast::RedirectOrCmdWord::CmdWord(w) => Some(&w.0),
+            ast::RedirectOrCmdWord::Redirect(_) => None,
+        })
+        .collect();
+    let is_directive = |w: &Option<&ast::DefaultComplexWord>| {
+        matches!(
+            w,
+            Some(ast::ComplexWord::Single(ast::Word::Simple(ast::SimpleWord::Literal(l))))
+                if l == "." || l == "source"
+        )
+    };
+    if !words.first().is_some_and(is_directive) {
+        return None;
+    }
+    Some(match words.as_slice() {
+        [_, Some(file)] if simple.redirects_or_env_vars.is_empty() => Ok(*file),
+        _ => Err(ParseErrorInfo::InvalidSyntax(
+            "Only `. FILE` or `source FILE` may be sourced.".to_string(),
+        )),
+    })
+}
+
+/// Evaluate the file sourced by `file` into `context`.
+fn include(
+    file: &ast::DefaultComplexWord,
+    eval: &Evaluation,
+    sources: &Sources,
+    context: &mut Context,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<(), ParseErrorInfo> {
+    let path = get_complex_word_as_string(file, context, eval.options)?;
+    if path.is_empty() || path.starts_with('/') {
+        return Err(context_error(format!(
+            "Only relative paths may be sourced, not `{}`.",
+            path
+        )));
+    }
+    if sources.depth == 0 {
+        return Err(context_error(format!(
+            "Sourcing {} is nested too deep.",
+            path
+        )));
+    }
+    let content = (sources.read)(&path)
+        .map_err(|e| ParseErrorInfo::IoError(format!("Failed to read {}: {}", path, e)))?;
+    let nested = Evaluation {
+        sources: Some(Sources {
+            read: sources.read,
+            depth: sources.depth - 1,
+            nested: true,
+        }),
+        ..*eval
+    };
+    parse_inner(&content, Some(context), warnings, None, None, None, &nested)
+        .map_err(|e| context_error(format!("In {}: {}", path, e)))
+}
+
+/// Parse `c`, evaluating it into `context` if there is one.
+fn parse_inner(
+    c: &str,
+    mut context: Option<&mut Context>,
+    warnings: &mut Vec<ParseWarning>,
+    mut functions: Option<&mut Vec<ShellFunction>>,
+    mut lowered: Option<&mut Vec<lowered::Statement>>,
+    mut symbolic: Option<&mut symbolic::SymbolicContext>,
+    eval: &Evaluation,
+) -> Result<(), ParseError> {
+    check_limits(c, &eval.options.limits)?;
     let lex = Lexer::new(c.chars());
     let mut parser = DefaultParser::new(lex);
 
     loop {
+        let start = skip_blanks_and_comments(c, parser.pos().byte);
         let cmd = match parser.complete_command() {
             Ok(x) => x,
             Err(e) => {
@@ -75,17 +544,47 @@ pub fn parse(c: &str, context: &mut Context) -> Result<(), ParseError> {
 
         match cmd {
             Some(cmd) => {
-                match get_args_top_level(&cmd, context) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        let pos = parser.pos();
-                        return Err(ParseError {
+                if let (Some(functions), Some(name)) = (functions.as_mut(), function_name(&cmd)) {
+                    functions.push(ShellFunction::new(name, c, start..parser.pos().byte));
+                    continue;
+                }
+                if let (Some(sources), Some(context)) = (&eval.sources, context.as_mut()) {
+                    let pos = parser.pos();
+                    let error = |error| ParseError {
+                        line: pos.line,
+                        col: pos.col,
+                        error,
+                    };
+                    if let Some(file) = source_directive(&cmd) {
+                        include(file.map_err(error)?, eval, sources, context, warnings)
+                            .map_err(error)?;
+                        continue;
+                    }
+                    if sources.nested && !is_assignment(&cmd) {
+                        return Err(error(ParseErrorInfo::InvalidSyntax(
+                            "Only assignments allowed in sourced files.".to_string(),
+                        )));
+                    }
+                }
+                if let Some(lowered) = lowered.as_mut() {
+                    let pos = parser.pos();
+                    let first = lowered.len();
+                    lowered::lower_top_level(&cmd, lowered)
+                        .and_then(|_| match symbolic.as_mut() {
+                            Some(symbolic) => {
+                                symbolic.eval(&lowered[first..], eval.options)
+                            }
+                            None => Ok(()),
+                        })
+                        .map_err(|e| ParseError {
                             line: pos.line,
                             col: pos.col,
                             error: e,
-                        });
-                    }
-                };
+                        })?;
+                }
+                if let Some(context) = context.as_mut() {
+                    eval_top_level(&cmd, parser.pos(), context, eval.options, warnings)?;
+                }
             }
             None => {
                 break;
@@ -96,9 +595,398 @@ pub fn parse(c: &str, context: &mut Context) -> Result<(), ParseError> {
     Ok(())
 }
 
+/// The limits known before parsing, checked over the text as it is read.
+/// The nesting of substitutions is checked on the text, as the parser
+/// recurses into them. Quoting is ignored, which only ever counts too deep.
+struct LimitCheck<'a> {
+    limits: &'a Limits,
+    size: usize,
+    closing: Vec<char>,
+    escaped: bool,
+    dollar: bool,
+}
+
+impl<'a> LimitCheck<'a> {
+    fn new(limits: &'a Limits) -> Self {
+        LimitCheck {
+            limits,
+            size: 0,
+            closing: Vec::new(),
+            escaped: false,
+            dollar: false,
+        }
+    }
+
+    /// Account for the next character, returning the reason if a limit is
+    /// exceeded.
+    fn push(&mut self, ch: char) -> Result<(), String> {
+        self.size += ch.len_utf8();
+        if self.size > self.limits.max_file_size {
+            return Err(format!(
+                "File larger than {} bytes.",
+                self.limits.max_file_size
+            ));
+        }
+        if std::mem::take(&mut self.escaped) {
+            return Ok(());
+        }
+        let dollar = std::mem::take(&mut self.dollar);
+        match ch {
+            '\\' => self.escaped = true,
+            '$' => self.dollar = true,
+            '{' | '(' if dollar => {
+                self.closing.push(if ch == '{' { '}' } else { ')' });
+                if self.closing.len() > self.limits.max_substitution_depth {
+                    return Err(format!(
+                        "Substitutions nested more than {} deep.",
+                        self.limits.max_substitution_depth
+                    ));
+                }
+            }
+            '}' | ')' if self.closing.last() == Some(&ch) => {
+                self.closing.pop();
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Check `c` against the limits known before parsing it.
+fn check_limits(c: &str, limits: &Limits) -> Result<(), ParseError> {
+    let error = |offset: usize, reason: String| {
+        let line_start = c[..offset].rfind('\n').map_or(0, |i| i + 1);
+        ParseError {
+            line: c[..offset].matches('\n').count() + 1,
+            col: c[line_start..offset].chars().count() + 1,
+            error: ParseErrorInfo::LimitExceeded(reason),
+        }
+    };
+    if c.len() > limits.max_file_size {
+        return Err(error(
+            0,
+            format!("File larger than {} bytes.", limits.max_file_size),
+        ));
+    }
+
+    let mut check = LimitCheck::new(limits);
+    for (offset, ch) in c.char_indices() {
+        // The `$` starting the substitution is reported.
+        let start = if check.dollar { offset - 1 } else { offset };
+        check.push(ch).map_err(|reason| error(start, reason))?;
+    }
+
+    Ok(())
+}
+
+fn eval_top_level(
+    cmd: &ast::TopLevelCommand<String>,
+    pos: SourcePos,
+    context: &mut Context,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<(), ParseError> {
+    let mut cmd_warnings = Vec::new();
+    let result = get_args_top_level(cmd, context, options, &mut cmd_warnings);
+    warnings.extend(cmd_warnings.into_iter().map(|w| ParseWarning {
+        line: pos.line,
+        col: pos.col,
+        warning: w,
+    }));
+
+    result.map_err(|e| ParseError {
+        line: pos.line,
+        col: pos.col,
+        error: e,
+    })
+}
+
+/// Characters decoded from a reader as UTF-8, checked against the limits of
+/// the evaluation. The first I/O or decoding error, or exceeded limit, ends
+/// the stream and is kept in `error`.
+#[cfg(feature = "std")]
+struct ReadChars<'a, R> {
+    bytes: io::Bytes<BufReader<R>>,
+    check: LimitCheck<'a>,
+    error: &'a Cell<Option<ParseErrorInfo>>,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> ReadChars<'_, R> {
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        self.bytes.next().transpose()
+    }
+
+    fn next_char(&mut self) -> io::Result<Option<char>> {
+        let first = match self.next_byte()? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let width = match first.leading_ones() {
+            0 => 1,
+            n @ 2..=4 => n as usize,
+            _ => 0,
+        };
+        let mut buf = [first, 0, 0, 0];
+        for b in buf.iter_mut().take(width).skip(1) {
+            *b = self.next_byte()?.unwrap_or(0);
+        }
+        std::str::from_utf8(&buf[..width.max(1)])
+            .ok()
+            .filter(|_| width > 0)
+            .and_then(|s| s.chars().next())
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for ReadChars<'_, R> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let result = match self.next_char() {
+            Ok(Some(c)) => self
+                .check
+                .push(c)
+                .map(|_| Some(c))
+                .map_err(ParseErrorInfo::LimitExceeded),
+            Ok(None) => Ok(None),
+            Err(e) => Err(ParseErrorInfo::IoError(e.to_string())),
+        };
+        result.unwrap_or_else(|e| {
+            self.error.set(Some(e));
+            None
+        })
+    }
+}
+
+/// Same as `parse`, but reads the content from `reader` as it is lexed
+/// instead of taking it all at once.
+#[cfg(feature = "std")]
+pub fn parse_reader<R: Read>(reader: R, context: &mut Context) -> Result<(), ParseError> {
+    parse_reader_with_options(reader, context, DEFAULT_OPTIONS)
+}
+
+/// Same as `parse_reader`, with `options`. The limits on the file are
+/// checked as it is read, so reading stops once it is too large.
+#[cfg(feature = "std")]
+pub fn parse_reader_with_options<R: Read>(
+    reader: R,
+    context: &mut Context,
+    options: &ParseOptions,
+) -> Result<(), ParseError> {
+    let error = Cell::new(None);
+    let chars = ReadChars {
+        bytes: BufReader::new(reader).bytes(),
+        check: LimitCheck::new(&options.limits),
+        error: &error,
+    };
+    let mut parser = DefaultParser::new(Lexer::new(chars));
+
+    loop {
+        let cmd = parser.complete_command();
+        // A truncated stream usually fails to parse too, report the cause.
+        if let Some(e) = error.take() {
+            let pos = parser.pos();
+            return Err(ParseError {
+                line: pos.line,
+                col: pos.col,
+                error: e,
+            });
+        }
+        match cmd {
+            Ok(Some(cmd)) => eval_top_level(&cmd, parser.pos(), context, options, &mut Vec::new())?,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                let pos = parser.pos();
+                return Err(ParseError {
+                    line: pos.line,
+                    col: pos.col,
+                    error: ParseErrorInfo::InvalidSyntax(e.to_string()),
+                });
+            }
+        }
+    }
+}
+
+/// Parse the leading metadata of `c` into `context`, stopping at the first
+/// statement that is not an assignment or an `if`/`case` block of
+/// assignments, i.e: a build-stage function after the metadata of a defines
+/// file. Returns the byte offset of that statement, or `c.len()` if the whole
+/// file was evaluated.
+///
+/// A syntax error also ends the prelude, so here-docs and other constructs
+/// the parser does not understand in the rest of the file are tolerated.
+/// Errors while evaluating the prelude itself are returned.
+pub fn parse_prelude(c: &str, context: &mut Context) -> Result<usize, ParseError> {
+    parse_prelude_with_options(c, context, DEFAULT_OPTIONS)
+}
+
+/// Same as `parse_prelude`, with `options`.
+pub fn parse_prelude_with_options(
+    c: &str,
+    context: &mut Context,
+    options: &ParseOptions,
+) -> Result<usize, ParseError> {
+    check_limits(c, &options.limits)?;
+    let lex = Lexer::new(c.chars());
+    let mut parser = DefaultParser::new(lex);
+
+    loop {
+        let start = skip_blanks_and_comments(c, parser.pos().byte);
+        let cmd = match parser.complete_command() {
+            Ok(Some(cmd)) if is_prelude_statement(&cmd) => cmd,
+            Ok(None) => return Ok(c.len()),
+            Ok(Some(_)) | Err(_) => return Ok(start),
+        };
+        get_args_top_level(&cmd, context, options, &mut Vec::new()).map_err(|e| {
+            let pos = parser.pos();
+            ParseError {
+                line: pos.line,
+                col: pos.col,
+                error: e,
+            }
+        })?;
+    }
+}
+
+fn skip_blanks_and_comments(c: &str, mut pos: usize) -> usize {
+    loop {
+        let rest = &c[pos..];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+        if !trimmed.starts_with('#') {
+            return pos;
+        }
+        pos += trimmed.find('\n').unwrap_or(trimmed.len());
+    }
+}
+
+fn is_prelude_statement(cmd: &ast::TopLevelCommand<String>) -> bool {
+    let list = match &cmd.0 {
+        ast::Command::List(list) => list,
+        ast::Command::Job(_) => return false,
+    };
+    std::iter::once(&list.first)
+        .chain(list.rest.iter().map(|and_or| match and_or {
+            ast::AndOr::And(cmd) | ast::AndOr::Or(cmd) => cmd,
+        }))
+        .all(|cmd| match cmd {
+            ast::ListableCommand::Single(ast::PipeableCommand::Simple(simple)) => {
+                simple.redirects_or_cmd_words.is_empty()
+            }
+            ast::ListableCommand::Single(ast::PipeableCommand::Compound(compound)) => {
+                let bodies: Vec<_> = match &compound.kind {
+                    ast::CompoundCommandKind::If {
+                        conditionals,
+                        else_branch,
+                    } => conditionals
+                        .iter()
+                        .map(|pair| &pair.body)
+                        .chain(else_branch)
+                        .collect(),
+                    ast::CompoundCommandKind::Case { arms, .. } => {
+                        arms.iter().map(|arm| &arm.body).collect()
+                    }
+                    _ => return false,
+                };
+                bodies.into_iter().flatten().all(is_prelude_statement)
+            }
+            _ => false,
+        })
+}
+
+/// Parse exactly one assignment, i.e: `KEY=${VAL:-x}`, into `context`.
+/// Returns the name of the assigned variable. Anything else, including a
+/// second assignment, is an error.
+pub fn parse_assignment(c: &str, context: &mut Context) -> Result<String, ParseError> {
+    parse_assignment_with_options(c, context, DEFAULT_OPTIONS)
+}
+
+/// Same as `parse_assignment`, with `options`.
+pub fn parse_assignment_with_options(
+    c: &str,
+    context: &mut Context,
+    options: &ParseOptions,
+) -> Result<String, ParseError> {
+    check_limits(c, &options.limits)?;
+    let lex = Lexer::new(c.chars());
+    let mut parser = DefaultParser::new(lex);
+    let syntax_error = |parser: &DefaultParser<_>, reason: String| {
+        let pos = parser.pos();
+        ParseError {
+            line: pos.line,
+            col: pos.col,
+            error: ParseErrorInfo::InvalidSyntax(reason),
+        }
+    };
+
+    let cmd = match parser.complete_command() {
+        Ok(Some(cmd)) => cmd,
+        Ok(None) => {
+            return Err(syntax_error(&parser, "No assignment found.".to_string()));
+        }
+        Err(e) => return Err(syntax_error(&parser, e.to_string())),
+    };
+    let simple = match single_simple_command(&cmd) {
+        Some(simple) if simple.redirects_or_env_vars.len() == 1 => simple,
+        _ => {
+            return Err(syntax_error(
+                &parser,
+                "Expected exactly one assignment.".to_string(),
+            ));
+        }
+    };
+    let name = match &simple.redirects_or_env_vars[0] {
+        ast::RedirectOrEnvVar::EnvVar(name, _) => name.clone(),
+        ast::RedirectOrEnvVar::Redirect(_) => {
+            return Err(syntax_error(&parser, "Redirects not allowed.".to_string()));
+        }
+    };
+    match parser.complete_command() {
+        Ok(None) => (),
+        Ok(Some(_)) => {
+            return Err(syntax_error(
+                &parser,
+                "Expected exactly one assignment.".to_string(),
+            ));
+        }
+        Err(e) => return Err(syntax_error(&parser, e.to_string())),
+    }
+
+    get_args_simple(simple, context, options, &mut Vec::new()).map_err(|e| {
+        let pos = parser.pos();
+        ParseError {
+            line: pos.line,
+            col: pos.col,
+            error: e,
+        }
+    })?;
+
+    Ok(name)
+}
+
+fn is_assignment(cmd: &ast::TopLevelCommand<String>) -> bool {
+    single_simple_command(cmd).is_some_and(|simple| simple.redirects_or_cmd_words.is_empty())
+}
+
+fn single_simple_command(cmd: &ast::TopLevelCommand<String>) -> Option<&ast::DefaultSimpleCommand> {
+    let list = match &cmd.0 {
+        ast::Command::List(list) if list.rest.is_empty() => list,
+        _ => return None,
+    };
+    match &list.first {
+        ast::ListableCommand::Single(ast::PipeableCommand::Simple(simple)) => Some(simple),
+        _ => None,
+    }
+}
+
 fn get_args_top_level(
     cmd: &ast::TopLevelCommand<String>,
     context: &mut Context,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarningInfo>,
 ) -> Result<(), ParseErrorInfo> {
     match &cmd.0 {
         ast::Command::List(list) => {
@@ -106,9 +994,8 @@ fn get_args_top_level(
                 .chain(list.rest.iter().map(|and_or| match and_or {
                     ast::AndOr::And(cmd) | ast::AndOr::Or(cmd) => cmd,
                 }))
-                .map(|cmd| get_args_listable(&cmd, context))
+                .map(|cmd| get_args_listable(cmd, context, options, warnings))
                 .collect();
-            println!("{:?}", results);
             for r in results {
                 match r {
                     Ok(_) => (),
@@ -128,9 +1015,11 @@ fn get_args_top_level(
 fn get_args_listable(
     cmd: &ast::DefaultListableCommand,
     context: &mut Context,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarningInfo>,
 ) -> Result<(), ParseErrorInfo> {
     match cmd {
-        ast::ListableCommand::Single(cmd) => get_args_pipeable(cmd, context),
+        ast::ListableCommand::Single(cmd) => get_args_pipeable(cmd, context, options, warnings),
         ast::ListableCommand::Pipe(_, _cmds) => Err(ParseErrorInfo::InvalidSyntax(
             "Pipe not allowed".to_string(),
         )),
@@ -140,22 +1029,48 @@ fn get_args_listable(
 fn get_args_pipeable(
     cmd: &ast::DefaultPipeableCommand,
     context: &mut Context,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarningInfo>,
 ) -> Result<(), ParseErrorInfo> {
     match cmd {
-        ast::PipeableCommand::Simple(cmd) => get_args_simple(cmd, context),
-        ast::PipeableCommand::Compound(_cmd) => Err(ParseErrorInfo::InvalidSyntax(
-            "Redirection not allowed.".to_string(),
-        )),
+        ast::PipeableCommand::Simple(cmd) => get_args_simple(cmd, context, options, warnings),
+        ast::PipeableCommand::Compound(cmd) => {
+            condition::eval_compound(cmd, context, options, warnings)
+        }
         ast::PipeableCommand::FunctionDef(_, _cmd) => Err(ParseErrorInfo::InvalidSyntax(
             "Function definition not allowed.".to_string(),
         )),
     }
 }
 
+/// Whether `cmd` is a command `options` allows, without redirects or
+/// assignments.
+fn is_allowed_command(cmd: &ast::DefaultSimpleCommand, options: &ParseOptions) -> bool {
+    let name = match cmd.redirects_or_cmd_words.first() {
+        Some(ast::RedirectOrCmdWord::CmdWord(w)) => match &w.0 {
+            ast::ComplexWord::Single(ast::Word::Simple(ast::SimpleWord::Literal(name))) => name,
+            ast::ComplexWord::Single(ast::Word::Simple(ast::SimpleWord::Colon)) => ":",
+            _ => return false,
+        },
+        _ => return false,
+    };
+    options.allows(name)
+        && cmd.redirects_or_env_vars.is_empty()
+        && cmd
+            .redirects_or_cmd_words
+            .iter()
+            .all(|w| matches!(w, ast::RedirectOrCmdWord::CmdWord(_)))
+}
+
 fn get_args_simple(
     cmd: &ast::DefaultSimpleCommand,
     context: &mut Context,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarningInfo>,
 ) -> Result<(), ParseErrorInfo> {
+    if is_allowed_command(cmd, options) {
+        return Ok(());
+    }
     if !cmd.redirects_or_cmd_words.is_empty() {
         return Err(ParseErrorInfo::InvalidSyntax(
             "Commands not allowed.".to_string(),
@@ -190,7 +1105,10 @@ fn get_args_simple(
                     }
                 };
 
-                let value = get_complex_word_as_string(word, context)?;
+                let value = get_complex_word_as_string(word, context, options)?;
+                if is_builtin_variable(name) {
+                    warnings.push(ParseWarningInfo::ShadowsBuiltin(name.to_string()));
+                }
                 context.insert(name.to_string(), value);
             }
             ast::RedirectOrEnvVar::Redirect(_) => {
@@ -206,95 +1124,100 @@ fn get_args_simple(
 fn get_complex_word_as_string(
     word: &ast::DefaultComplexWord,
     context: &Context,
+    options: &ParseOptions,
 ) -> Result<String, ParseErrorInfo> {
-    let word = match word {
-        ast::ComplexWord::Single(word) => word.clone(),
+    let value = match word {
+        ast::ComplexWord::Single(word) => get_word_as_string(word, context, options)?.into_owned(),
         ast::ComplexWord::Concat(words) => {
             let mut word_content = String::new();
             for w in words {
-                word_content += &get_word_as_string(w, context)?;
+                word_content += &get_word_as_string(w, context, options)?;
             }
-            ast::Word::Simple(ast::SimpleWord::Literal(word_content))
+            word_content
         }
     };
+    check_expansion(value, &options.limits)
+}
 
-    get_word_as_string(&word, context)
+/// `value` if it is not longer than the limits allow.
+fn check_expansion(value: String, limits: &Limits) -> Result<String, ParseErrorInfo> {
+    if value.len() > limits.max_expansion_length {
+        return Err(ParseErrorInfo::LimitExceeded(format!(
+            "Expansion longer than {} bytes.",
+            limits.max_expansion_length
+        )));
+    }
+    Ok(value)
 }
 
-fn get_word_as_string(
-    word: &ast::DefaultWord,
-    context: &Context,
-) -> Result<String, ParseErrorInfo> {
+fn get_word_as_string<'a>(
+    word: &'a ast::DefaultWord,
+    context: &'a Context,
+    options: &ParseOptions,
+) -> Result<Cow<'a, str>, ParseErrorInfo> {
     let result = match word {
-        ast::Word::SingleQuoted(w) => w.to_string(),
-        ast::Word::Simple(w) => get_simple_word_as_string(w, context)?.to_string(),
-        ast::Word::DoubleQuoted(words) => {
-            let mut value = String::new();
-            for w in words {
-                value += &get_simple_word_as_string(w, context)?;
+        ast::Word::SingleQuoted(w) => Cow::Borrowed(w.as_str()),
+        ast::Word::Simple(w) => get_simple_word_as_string(w, context, options)?,
+        ast::Word::DoubleQuoted(words) => match words.as_slice() {
+            [w] => get_simple_word_as_string(w, context, options)?,
+            _ => {
+                let mut value = String::new();
+                for w in words {
+                    value += &get_simple_word_as_string(w, context, options)?;
+                }
+                Cow::Owned(value)
             }
-            value
-        }
+        },
     };
 
     Ok(result)
 }
 
-fn get_simple_word_as_string(
-    word: &ast::DefaultSimpleWord,
-    context: &Context,
-) -> Result<String, ParseErrorInfo> {
-    println!("{:?}", word);
+fn get_simple_word_as_string<'a>(
+    word: &'a ast::DefaultSimpleWord,
+    context: &'a Context,
+    options: &ParseOptions,
+) -> Result<Cow<'a, str>, ParseErrorInfo> {
     match word {
-        ast::SimpleWord::Literal(w) => Ok(w.to_string()),
+        ast::SimpleWord::Literal(w) => Ok(Cow::Borrowed(w)),
         ast::SimpleWord::Escaped(w) => {
             let res = match w.as_str() {
-                "\n" => "".to_string(),
-                _ => w.to_string(),
+                "\n" => "",
+                _ => w,
             };
-            Ok(res)
+            Ok(Cow::Borrowed(res))
         }
-        ast::SimpleWord::Colon => Ok(":".to_string()),
+        ast::SimpleWord::Colon => Ok(Cow::Borrowed(":")),
         ast::SimpleWord::Param(p) => match get_parameter_as_string(p, context)? {
-            Some(p) => Ok(p),
-            None => Err(ParseErrorInfo::ContextError(
-                "Param variable not found.".to_string(),
-            )),
+            Some(p) => Ok(Cow::Borrowed(p)),
+            None => Err(not_found(p, context)),
         },
-        ast::SimpleWord::Subst(s) => get_subst_result(s, context),
+        ast::SimpleWord::Subst(s) => get_subst_result(s, context, options).map(Cow::Owned),
         _ => Err(ParseErrorInfo::InvalidSyntax(
             "Encountered star, square, tide, or other unsupported chatacters.".to_string(),
         )),
     }
 }
 
-fn get_parameter_as_string(
+fn get_parameter_as_string<'a>(
     parameter: &ast::DefaultParameter,
-    context: &Context,
-) -> Result<Option<String>, ParseErrorInfo> {
+    context: &'a Context,
+) -> Result<Option<&'a str>, ParseErrorInfo> {
     match parameter {
-        ast::Parameter::Var(name) => match context.get(name) {
-            Some(value) => Ok(Some(value.clone())),
-            None => Ok(None),
-        },
+        ast::Parameter::Var(name) => Ok(context.get(name).map(|value| value.as_str())),
         _ => Err(ParseErrorInfo::InvalidSyntax(
             "Unsupported parameter.".to_string(),
         )),
     }
 }
 
-fn get_subst_origin(
+fn get_subst_origin<'a>(
     param: &ast::DefaultParameter,
-    context: &Context,
-) -> Result<String, ParseErrorInfo> {
+    context: &'a Context,
+) -> Result<&'a str, ParseErrorInfo> {
     let origin = match get_parameter_as_string(param, context)? {
         Some(p) => p,
-        None => {
-            return Err(ParseErrorInfo::ContextError(format!(
-                "Param {} not found.",
-                param
-            )));
-        }
+        None => return Err(not_found(param, context)),
     };
     Ok(origin)
 }
@@ -302,13 +1225,13 @@ fn get_subst_origin(
 fn get_subst_result(
     subst: &ast::DefaultParameterSubstitution,
     context: &Context,
+    options: &ParseOptions,
 ) -> Result<String, ParseErrorInfo> {
-    println!("{:?}", subst);
     match subst {
         ast::ParameterSubstitution::ReplaceString(param, command) => {
             let origin = get_subst_origin(param, context)?;
             let command = match command {
-                Some(c) => get_complex_word_as_string(c, context)?,
+                Some(c) => get_complex_word_as_string(c, context, options)?,
                 None => {
                     return Err(ParseErrorInfo::InvalidSyntax(
                         "No substring command provided".to_string(),
@@ -316,24 +1239,24 @@ fn get_subst_result(
                 }
             };
 
-            substitution::get_replace(&origin, &command, false)
+            substitution::get_replace_with_limits(origin, &command, false, &options.limits)
         }
         ast::ParameterSubstitution::ReplaceStringAll(param, command) => {
             let origin = get_subst_origin(param, context)?;
             let command = match command {
-                Some(c) => get_complex_word_as_string(c, context)?,
+                Some(c) => get_complex_word_as_string(c, context, options)?,
                 None => {
                     return Err(ParseErrorInfo::InvalidSyntax(
                         "No substring command provided".to_string(),
                     ));
                 }
             };
-            substitution::get_replace(&origin, &command, true)
+            substitution::get_replace_with_limits(origin, &command, true, &options.limits)
         }
         ast::ParameterSubstitution::Substring(param, command) => {
             let origin = get_subst_origin(param, context)?;
             let command = match command {
-                Some(c) => get_complex_word_as_string(c, context)?,
+                Some(c) => get_complex_word_as_string(c, context, options)?,
                 None => {
                     return Err(ParseErrorInfo::InvalidSyntax(
                         "No substring command provided".to_string(),
@@ -341,10 +1264,275 @@ fn get_subst_result(
                 }
             };
 
-            substitution::get_substring(&origin, &command)
+            substitution::get_substring_with_mode(origin, &command, options.substring_mode)
         }
-        _ => {
-            todo!()
+        ast::ParameterSubstitution::Len(param) => {
+            let origin = get_subst_origin(param, context)?;
+            Ok(substitution::get_length(origin, options.substring_mode).to_string())
+        }
+        _ => Err(ParseErrorInfo::SubstitutionError(
+            "Unsupported parameter substitution.".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shadowing_warnings() {
+        let mut context = Context::new();
+        let mut warnings = Vec::new();
+        parse_with_warnings("VER=1.0\nSRCDIR=/tmp\nPKGDIR=/tmp\n", &mut context, &mut warnings).unwrap();
+        let names: Vec<_> = warnings
+            .iter()
+            .map(|w| match &w.warning {
+                ParseWarningInfo::ShadowsBuiltin(n) => n.as_str(),
+            })
+            .collect();
+        assert_eq!(names, vec!["SRCDIR", "PKGDIR"]);
+        assert_eq!(context["SRCDIR"], "/tmp");
+    }
+
+    #[test]
+    fn test_parse_assignment() {
+        let mut context = Context::new();
+        context.insert("VER".to_string(), "1.0".to_string());
+        assert_eq!(parse_assignment("SRC=\"foo-$VER\"", &mut context).unwrap(), "SRC");
+        assert_eq!(context["SRC"], "foo-1.0");
+        assert_eq!(parse_assignment("A=1\n", &mut context).unwrap(), "A");
+
+        let bad = vec!["", "A=1 B=2", "A=1\nB=2", "A=1; B=2", "A=1 && B=2", "echo 1", "A=1 > x"];
+        for b in bad {
+            assert!(parse_assignment(b, &mut context).is_err(), "{}", b);
         }
+        assert!(!context.contains_key("B"));
+    }
+
+    #[test]
+    fn test_parse_with_functions() {
+        let source = "\
+VER=1.0
+PKGEPOCH() {
+    echo 1
+}
+
+function prepare { make clean; }
+function_x() { :; }
+PKGDES=\"Foo $VER\"
+";
+        let mut context = Context::new();
+        let mut functions = Vec::new();
+        parse_with_functions(source, &mut context, &mut functions).unwrap();
+        assert_eq!(context["PKGDES"], "Foo 1.0");
+        let names: Vec<_> = functions.iter().map(|f| f.name()).collect();
+        assert_eq!(names, vec!["PKGEPOCH", "prepare", "function_x"]);
+        assert_eq!(&source[functions[0].source_span()], "PKGEPOCH() {\n    echo 1\n}");
+        assert_eq!(functions[0].raw_body(), "{\n    echo 1\n}");
+        assert_eq!(functions[1].raw_body(), "{ make clean; }");
+        assert_eq!(functions[2].raw_body(), "{ :; }");
+
+        assert!(parse(source, &mut Context::new()).is_err());
+        assert!(parse_with_functions("f() { A=1; } && B=1", &mut context, &mut functions).is_err());
+    }
+
+    #[test]
+    fn test_unicode_substitution() {
+        let mut context = Context::new();
+        parse("PKGDES=\"安同 OS 的软件包\"\nA=${PKGDES:0:2}\nB=${#PKGDES}\n", &mut context).unwrap();
+        assert_eq!(context["A"], "安同");
+        assert_eq!(context["B"], "10");
+
+        parse("C=${PKGDES: -3}\nD=${PKGDES:3:-4}\n", &mut context).unwrap();
+        assert_eq!(context["C"], "软件包");
+        assert_eq!(context["D"], "OS ");
+
+        let source = "V=\"cafe\u{301}s\"\nA=${V:3:1}\nB=${#V}\nif [ ${#V} = 5 ]; then C=1; fi\n";
+        let options = ParseOptions::default().substring_mode(SubstringMode::Graphemes);
+        let mut context = Context::new();
+        parse_with_options(source, &mut context, &options).unwrap();
+        assert_eq!(context["A"], "e\u{301}");
+        assert_eq!(context["B"], "5");
+        assert_eq!(context["C"], "1");
+        let mut context = Context::new();
+        parse(source, &mut context).unwrap();
+        assert_eq!(context["B"], "6");
+        assert!(!context.contains_key("C"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_parse_reader() {
+        let source = "VER=1.0\nPKGDES=\"Foo $VER – 日本語\"\n";
+        let mut context = Context::new();
+        parse_reader(source.as_bytes(), &mut context).unwrap();
+        assert_eq!(context["PKGDES"], "Foo 1.0 – 日本語");
+
+        let mut context = Context::new();
+        let err = parse_reader(&b"A=1\nB=\"\xff\"\n"[..], &mut context).unwrap_err();
+        assert!(matches!(err.error, ParseErrorInfo::IoError(_)));
+        assert!(parse_reader("A=$(".as_bytes(), &mut context).is_err());
+    }
+
+    #[test]
+    fn test_parse_prelude() {
+        let source = "\
+VER=1.0
+# Build options.
+if [ \"$VER\" = 1.0 ]; then
+    OPT=old
+fi
+
+build() {
+    cat > foo << EOF
+$VER
+EOF
+}
+A=1
+";
+        let mut context = Context::new();
+        let offset = parse_prelude(source, &mut context).unwrap();
+        assert!(source[offset..].starts_with("build() {"));
+        assert_eq!(context["OPT"], "old");
+        assert!(!context.contains_key("A"));
+
+        let mut context = Context::new();
+        assert_eq!(parse_prelude("A=1\nB=2\n", &mut context).unwrap(), 8);
+        assert_eq!(parse_prelude("A=1\necho $A\n", &mut context).unwrap(), 4);
+        assert_eq!(parse_prelude("A=1\nB=$(\n", &mut context).unwrap(), 4);
+        assert_eq!(parse_prelude("A=1 && make\n", &mut context).unwrap(), 0);
+        assert!(parse_prelude("A=1\nB=$UNSET\n", &mut context).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_parse_with_sources() {
+        let read = |path: &str| match path {
+            "../common.sh" => Ok("_MAJOR=2\n. ./version.sh\n".to_string()),
+            "./version.sh" => Ok("VER=\"$_MAJOR.1\"\n".to_string()),
+            "loop.sh" => Ok("source loop.sh\n".to_string()),
+            "command.sh" => Ok("A=1\necho $A\n".to_string()),
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+        };
+        let parse = |c: &str| {
+            let mut context = Context::new();
+            parse_with_sources(c, &mut context, &read, MAX_SOURCE_DEPTH).map(|_| context)
+        };
+        let context = parse(". ../common.sh\nPKGVER=\"$VER\"\n").unwrap();
+        assert_eq!(context["VER"], "2.1");
+        assert_eq!(context["PKGVER"], "2.1");
+
+        assert!(parse("source loop.sh\n").is_err());
+        assert!(parse(". command.sh\n").is_err());
+        assert!(parse(". missing.sh\n").is_err());
+        assert!(parse(". /etc/profile\n").is_err());
+        assert!(parse(". ../common.sh extra\n").is_err());
+        // Not followed without `parse_with_sources`.
+        assert!(super::parse(". ../common.sh\n", &mut Context::new()).is_err());
+    }
+
+    #[test]
+    fn test_allowed_commands() {
+        let source = "A=1\ntrue\nif [ \"$A\" = 1 ]; then\n    :\n    B=2\nfi\n: ${C:=3}\n";
+        assert!(super::parse(source, &mut Context::new()).is_err());
+        let options = ParseOptions::default().allow_commands(&["true", ":"]);
+        let mut context = Context::new();
+        parse_with_options(source, &mut context, &options).unwrap();
+        assert_eq!(context["B"], "2");
+        assert!(!context.contains_key("C"));
+
+        let parse = |c: &str| parse_with_options(c, &mut Context::new(), &options);
+        assert!(parse("echo foo\n").is_err());
+        assert!(parse("true > /tmp/foo\n").is_err());
+        assert!(parse("A=1 true\n").is_err());
+        assert!(parse("\"true\"\n").is_err());
+    }
+
+    #[test]
+    fn test_suggestion() {
+        let mut context = Context::new();
+        let err = parse("PKGVER=1.0\nPKGDES=\"Foo ${PKGVRE}\"\n", &mut context).unwrap_err();
+        assert_eq!(err.suggestion(), Some("PKGVER"));
+        assert!(err.to_string().ends_with("Did you mean PKGVER?"));
+
+        let err = parse("A=${PKGVRE:0:2}\n", &mut context).unwrap_err();
+        assert_eq!(err.suggestion(), Some("PKGVER"));
+        let err = parse("A=$FOOBAR\n", &mut context).unwrap_err();
+        assert_eq!(err.suggestion(), None);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_file_size: 64,
+            max_substitution_depth: 2,
+            max_expansion_length: 8,
+            regex_size_limit: 1 << 20,
+        };
+        let options = ParseOptions::default().limits(limits);
+        let parse = |c: &str| parse_with_options(c, &mut Context::new(), &options);
+        let limit_exceeded = |c: &str| {
+            matches!(
+                parse(c).unwrap_err().error,
+                ParseErrorInfo::LimitExceeded(_)
+            )
+        };
+        parse("A=foo\nB=\"${A/o/${A}}\"\n").unwrap();
+        assert!(limit_exceeded(&format!("A=\"{}\"\n", "#".repeat(64))));
+        assert!(limit_exceeded("A=foo\nB=\"${A/o/${A/f/${A}}}\"\n"));
+        assert!(limit_exceeded("A=foo\nB=$A$A$A\n"));
+        assert!(limit_exceeded(
+            "A=foo\nif [ \"$A$A$A\" = x ]; then B=1; fi\n"
+        ));
+        // Each `x` becomes 4 bytes, only the first is replaced.
+        assert!(limit_exceeded("A=xxx\nB=yyyy\nC=\"${A//x/$B}\"\n"));
+        parse("A=xxx\nB=yyyy\nC=\"${A/x/$B}\"\n").unwrap();
+
+        fn is_limit<T>(result: Result<T, ParseError>) -> bool {
+            matches!(result, Err(e) if matches!(e.error, ParseErrorInfo::LimitExceeded(_)))
+        }
+        let long = format!("A=\"{}\"\n", "#".repeat(64));
+        let nested = "B=\"${A/o/${A/f/${A}}}\"\n";
+        for c in [long.as_str(), nested] {
+            let mut context = Context::new();
+            context.insert("A".to_string(), "foo".to_string());
+            #[cfg(feature = "std")]
+            {
+                let reader =
+                    parse_reader_with_options(c.as_bytes(), &mut context.clone(), &options);
+                assert!(is_limit(reader), "{}", c);
+            }
+            let prelude = parse_prelude_with_options(c, &mut context.clone(), &options);
+            assert!(is_limit(prelude), "{}", c);
+            let assignment = parse_assignment_with_options(c, &mut context.clone(), &options);
+            assert!(is_limit(assignment), "{}", c);
+            let mut symbolic = symbolic::SymbolicContext::new(context.clone());
+            let symbolic = parse_symbolic_with_options(c, &mut symbolic, &options);
+            assert!(is_limit(symbolic), "{}", c);
+            let document = Document::with_options(c, context, options.clone());
+            assert!(matches!(
+                document.errors().next().map(|e| &e.error),
+                Some(ParseErrorInfo::LimitExceeded(_))
+            ));
+        }
+        let mut context = Context::new();
+        context.insert("A".to_string(), "foo".to_string());
+        let test = "[ \"${A/o/${A}}\" = ffooo ]";
+        assert!(eval_test_with_options(test, &context, &options));
+        let test = "[ \"${A/o/${A/f/${A}}}\" = ffooooo ]";
+        assert!(eval_test(test, &context));
+        assert!(!eval_test_with_options(test, &context, &options));
+
+        let options = ParseOptions::default().limits(Limits {
+            regex_size_limit: 16,
+            ..limits
+        });
+        let source = "A=foo\nif [[ $A == ??? ]]; then B=1; fi\n";
+        let err = parse_with_options(source, &mut Context::new(), &options);
+        assert!(matches!(
+            err.unwrap_err().error,
+            ParseErrorInfo::LimitExceeded(_)
+        ));
     }
 }
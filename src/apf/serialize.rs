@@ -0,0 +1,172 @@
+//! Quoting values, and writing a context back as an assignment-only file
+//! which evaluates to the same context with this crate and with bash.
+
+use super::{lossless::is_name, parse_assignment, Context, ParseError};
+use crate::fmt::FIELD_ORDER;
+use std::fmt;
+
+/// How values are quoted by `serialize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Style {
+    /// Unquoted where possible, otherwise in single quotes, or in double
+    /// quotes if the value contains a single quote.
+    #[default]
+    Minimal,
+    /// Always in single quotes, unless the value contains one.
+    Single,
+    /// Always in double quotes.
+    Double,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerializeError {
+    /// A variable name which cannot be assigned, i.e: `FOO-BAR`.
+    InvalidName(String),
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::InvalidName(name) => write!(f, "Invalid variable name `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
+/// Characters that never need quoting. `~` is left out as it is expanded at
+/// the start of a value.
+fn is_bare_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "._+-:/@%,=".contains(c)
+}
+
+/// Double-quote `value`, escaping what bash would expand. `$` is left as it
+/// is with `keep_expansions`.
+fn double_quote(value: &str, keep_expansions: bool) -> String {
+    let special = if keep_expansions { "\\\"`" } else { "\\\"$`" };
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if special.contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quote `value` in `style` so it evaluates to itself.
+pub fn quote_with(value: &str, style: Style) -> String {
+    match style {
+        _ if value.contains('\'') => double_quote(value, false),
+        Style::Minimal if !value.is_empty() && value.chars().all(is_bare_char) => value.to_string(),
+        Style::Minimal | Style::Single => format!("'{}'", value),
+        Style::Double => double_quote(value, false),
+    }
+}
+
+/// Quote `value` as `serialize` does with `Style::Minimal`, i.e: `foo bar`
+/// becomes `'foo bar'`.
+pub fn quote(value: &str) -> String {
+    quote_with(value, Style::Minimal)
+}
+
+/// Double-quote `value` with its variables left to expand, i.e:
+/// `foo-$VER` becomes `"foo-$VER"`.
+pub fn quote_template(value: &str) -> String {
+    double_quote(value, true)
+}
+
+/// Value of `raw` as written on the right of an assignment, i.e: `'foo bar'`
+/// or `"foo\$"`. Values referring to variables are an error.
+pub fn unquote(raw: &str) -> Result<String, ParseError> {
+    let mut context = Context::new();
+    let name = parse_assignment(&format!("V={}", raw), &mut context)?;
+    Ok(context.remove(&name).unwrap_or_default())
+}
+
+/// Assignments of every variable of `context`, one per line. Fields of
+/// `fmt::FIELD_ORDER` come first in that order, other variables after them
+/// sorted by name.
+pub fn serialize(context: &Context, style: Style) -> Result<String, SerializeError> {
+    let mut names: Vec<_> = context.keys().collect();
+    if let Some(name) = names.iter().find(|n| !is_name(n)) {
+        return Err(SerializeError::InvalidName(name.to_string()));
+    }
+    names.sort_by_key(|n| {
+        let position = FIELD_ORDER.iter().position(|f| f == n);
+        (position.unwrap_or(FIELD_ORDER.len()), n.as_str())
+    });
+
+    let mut result = String::new();
+    for name in names {
+        result.push_str(name);
+        result.push('=');
+        result.push_str(&quote_with(&context[name], style));
+        result.push('\n');
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse;
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        let values = ["", "1.0", "foo bar", "it's", "$VER \\ `x`", "~", "a\nb"];
+        for value in values.iter() {
+            assert_eq!(unquote(&quote(value)).unwrap(), *value, "{}", value);
+        }
+        assert_eq!(quote("foo bar"), "'foo bar'");
+        assert_eq!(quote("it's"), "\"it's\"");
+        assert_eq!(quote("~/foo"), "'~/foo'");
+        assert_eq!(quote_template("a-$VER \"b\""), "\"a-$VER \\\"b\\\"\"");
+        assert_eq!(unquote("\"a\\$b\"'c'd").unwrap(), "a$bcd");
+        assert!(unquote("$VER").is_err());
+        assert!(unquote("'foo").is_err());
+        assert!(unquote("foo; B=1").is_err());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut context = Context::new();
+        let values = [
+            ("VER", "1.0"),
+            ("PKGDES", "Foo's \"bar\" for $5 `now`\\"),
+            ("PKGDEP", "foo bar>=1.0"),
+            ("EMPTY", ""),
+            ("HOME_DIR", "~/foo"),
+            ("MULTI", "a\nb"),
+            ("A_DOLLAR", "$VER"),
+        ];
+        for (k, v) in values.iter() {
+            context.insert(k.to_string(), v.to_string());
+        }
+
+        let minimal = serialize(&context, Style::Minimal).unwrap();
+        assert_eq!(
+            minimal,
+            "VER=1.0\nPKGDEP='foo bar>=1.0'\nPKGDES=\"Foo's \\\"bar\\\" for \\$5 \\`now\\`\\\\\"\n\
+             A_DOLLAR='$VER'\nEMPTY=''\nHOME_DIR='~/foo'\nMULTI='a\nb'\n"
+        );
+        for style in [Style::Minimal, Style::Single, Style::Double].iter() {
+            let serialized = serialize(&context, *style).unwrap();
+            let mut parsed = Context::new();
+            parse(&serialized, &mut parsed).unwrap();
+            assert_eq!(parsed, context, "{:?}", style);
+        }
+        assert!(serialize(&context, Style::Double)
+            .unwrap()
+            .starts_with("VER=\"1.0\"\n"));
+
+        context.insert("FOO-BAR".to_string(), "1".to_string());
+        assert_eq!(
+            serialize(&context, Style::Minimal),
+            Err(SerializeError::InvalidName("FOO-BAR".to_string()))
+        );
+    }
+}
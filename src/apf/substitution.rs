@@ -1,11 +1,47 @@
-use super::{glob::get_regex_string_from_glob, ParseErrorInfo};
+use super::{glob::get_regex_string_from_glob, Limits, ParseErrorInfo};
 
-use regex::Regex;
+use regex::RegexBuilder;
 use std::cmp;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// What `${VAR:BEGIN:LENGTH}` and `${#VAR}` count in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SubstringMode {
+    /// Unicode scalar values, like bash in a UTF-8 locale.
+    #[default]
+    Chars,
+    /// Extended grapheme clusters, so combining marks stay with their base.
+    Graphemes,
+}
+
+/// Byte offsets of the boundaries between units of `s`, including 0 and
+/// `s.len()`.
+fn boundaries(s: &str, mode: SubstringMode) -> Vec<usize> {
+    let mut result: Vec<usize> = match mode {
+        SubstringMode::Chars => s.char_indices().map(|(i, _)| i).collect(),
+        SubstringMode::Graphemes => s.grapheme_indices(true).map(|(i, _)| i).collect(),
+    };
+    result.push(s.len());
+    result
+}
+
+/// Length of `origin` as in `${#VAR}`.
+pub fn get_length(origin: &str, mode: SubstringMode) -> usize {
+    boundaries(origin, mode).len() - 1
+}
 
 /// Substring in bash subsitution.
 /// i.e: ${variable:BEGIN:LENGTH}
 pub fn get_substring(origin: &str, command: &str) -> Result<String, ParseErrorInfo> {
+    get_substring_with_mode(origin, command, SubstringMode::default())
+}
+
+/// Same as `get_substring`, counting in `mode`.
+pub fn get_substring_with_mode(
+    origin: &str,
+    command: &str,
+    mode: SubstringMode,
+) -> Result<String, ParseErrorInfo> {
     let (begin, length) = match command.chars().filter(|c| c == &':').count() {
         0 => (parse_number(command)?, None),
         1 => {
@@ -19,31 +55,41 @@ pub fn get_substring(origin: &str, command: &str) -> Result<String, ParseErrorIn
         }
     };
 
+    let bounds = boundaries(origin, mode);
+    let count = bounds.len() - 1;
+    // Negative offsets count from the end. Out of range, bash expands to
+    // nothing rather than clamping.
     let real_begin = if begin >= 0 {
-        cmp::min(origin.len(), begin as usize)
+        cmp::min(count, begin as usize)
     } else {
-        cmp::max(origin.len() - (begin.abs() as usize), 0)
+        match count.checked_sub(begin.unsigned_abs()) {
+            Some(b) => b,
+            None => return Ok(String::new()),
+        }
     };
 
-    match length {
-        Some(length) => {
-            if length >= 0 {
-                let real_end = cmp::min(origin.len(), real_begin + length as usize);
-                Ok(origin[real_begin..real_end].to_string())
-            } else {
-                let max_len = origin.len() - real_begin;
-                let real_length = cmp::max(0, max_len - length.abs() as usize);
-                let real_end = cmp::min(origin.len(), real_begin + real_length);
-                Ok(origin[real_begin..real_end].to_string())
+    // Negative lengths are an offset from the end to stop at.
+    let real_end = match length {
+        Some(length) if length >= 0 => cmp::min(count, real_begin.saturating_add(length as usize)),
+        Some(length) => match count.checked_sub(length.unsigned_abs()) {
+            Some(end) if end >= real_begin => end,
+            _ => {
+                return Err(ParseErrorInfo::SubstitutionError(format!(
+                    "Substring expression {} < 0.",
+                    length
+                )));
             }
-        }
-        None => Ok(origin[real_begin..].to_string()),
-    }
+        },
+        None => count,
+    };
+
+    Ok(origin[bounds[real_begin]..bounds[real_end]].to_string())
 }
 
 fn parse_number(s: &str) -> Result<isize, ParseErrorInfo> {
-    // Bash magic!
-    if s.len() == 0 {
+    // Bash magic! `${VAR: -1}` needs the space to not be `${VAR:-1}`.
+    let s = s.trim();
+    if s.is_empty() {
         return Ok(0);
     }
     let left_bracket_count = s.chars().filter(|c| c == &'(').count();
@@ -85,6 +131,19 @@ fn get_chars_without_escape(c: &char, s: &str) -> usize {
 }
 
 pub fn get_replace(origin: &str, command: &str, all: bool) -> Result<String, ParseErrorInfo> {
+    get_replace_with_limits(origin, command, all, &Limits::default())
+}
+
+/// Same as `get_replace`, with the pattern compiled to at most
+/// `limits.regex_size_limit` bytes and the result at most
+/// `limits.max_expansion_length` bytes long. The result is checked while
+/// it is built, so a large replacement repeated many times fails early.
+pub fn get_replace_with_limits(
+    origin: &str,
+    command: &str,
+    all: bool,
+    limits: &Limits,
+) -> Result<String, ParseErrorInfo> {
     let (from, to) = match get_chars_without_escape(&'/', command) {
         1 => {
             let commands: Vec<&str> = command.split("/").collect();
@@ -97,13 +156,38 @@ pub fn get_replace(origin: &str, command: &str, all: bool) -> Result<String, Par
         }
     };
 
-    let re = Regex::new(&get_regex_string_from_glob(&from)?)?;
-    let result = match all {
-        true => re.replace_all(origin, to.as_str()),
-        false => re.replace(origin, to.as_str()),
+    // `${VAR/#pat/rep}` and `${VAR/%pat/rep}` only match at the beginning or
+    // the end. In `${VAR//#pat/rep}` the `#` is literal, as in bash.
+    let regex = match (all, from.chars().next()) {
+        (false, Some('#')) => format!("^(?:{})", get_regex_string_from_glob(&from[1..])?),
+        (false, Some('%')) => format!("(?:{})$", get_regex_string_from_glob(&from[1..])?),
+        _ => get_regex_string_from_glob(&from)?,
     };
+    let re = RegexBuilder::new(&regex)
+        .size_limit(limits.regex_size_limit)
+        .build()?;
+    let too_long = || {
+        ParseErrorInfo::LimitExceeded(format!(
+            "Expansion longer than {} bytes.",
+            limits.max_expansion_length
+        ))
+    };
+    let mut result = String::new();
+    let mut last = 0;
+    for m in re.find_iter(origin).take(if all { usize::MAX } else { 1 }) {
+        if result.len() + (m.start() - last) + to.len() > limits.max_expansion_length {
+            return Err(too_long());
+        }
+        result.push_str(&origin[last..m.start()]);
+        result.push_str(&to);
+        last = m.end();
+    }
+    if result.len() + (origin.len() - last) > limits.max_expansion_length {
+        return Err(too_long());
+    }
+    result.push_str(&origin[last..]);
 
-    Ok(result.to_string())
+    Ok(result)
 }
 
 #[cfg(test)]
@@ -122,8 +206,16 @@ mod tests {
             ("0", "1234567890"),
             ("(-1):(-1)", ""),
             ("(0):(-1)", "123456789"),
+            (" -3", "890"),
+            (" -3:2", "89"),
+            ("2:-3", "34567"),
+            ("-3: -3", ""),
+            (" -11", ""),
+            (" -11:2", ""),
+            ("11", ""),
+            ("3:100", "4567890"),
         ];
-        let err_cases = vec!["(:1", "(:1)"];
+        let err_cases = vec!["(:1", "(:1)", "5:-6", " -2:-3", "0:-11", "a"];
 
         for c in ok_cases {
             assert_eq!(get_substring(origin, c.0).unwrap(), c.1);
@@ -132,4 +224,70 @@ mod tests {
             assert_eq!(get_substring(origin, c).is_ok(), false);
         }
     }
+
+    #[test]
+    fn test_anchored_replace() {
+        let origin = "https://example.com/https/foo";
+        let cases = vec![
+            ("#https:/", false, "//example.com/https/foo"),
+            ("#example/", false, origin),
+            ("%foo/bar", false, "https://example.com/https/bar"),
+            ("%https/bar", false, origin),
+            ("#/git+", false, "git+https://example.com/https/foo"),
+            ("https/x", false, "x://example.com/https/foo"),
+            ("https/x", true, "x://example.com/x/foo"),
+        ];
+        for (command, all, expected) in cases {
+            assert_eq!(get_replace(origin, command, all).unwrap(), expected, "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_extglob_replace() {
+        assert_eq!(get_replace("libfoo-dev", "-@(dev|dbg)/", false).unwrap(), "libfoo");
+        assert_eq!(get_replace("aaab", "+(a)/x", false).unwrap(), "xb");
+        assert_eq!(get_replace("v1.2", "#?(v)/", false).unwrap(), "1.2");
+    }
+
+    #[test]
+    fn test_replace_limit() {
+        let limits = Limits {
+            max_expansion_length: 64,
+            ..Default::default()
+        };
+        let origin = "x".repeat(32);
+        let command = format!("x/{}", "y".repeat(32));
+        assert_eq!(
+            get_replace_with_limits(&origin, &command, false, &limits)
+                .unwrap()
+                .len(),
+            63
+        );
+        assert!(matches!(
+            get_replace_with_limits(&origin, &command, true, &limits),
+            Err(ParseErrorInfo::LimitExceeded(_))
+        ));
+        assert_eq!(get_replace("a.b.c", "./_", true).unwrap(), "a_b_c");
+        // The replacement is literal, as in bash.
+        assert_eq!(get_replace("ab", "a/$0", false).unwrap(), "$0b");
+    }
+
+    #[test]
+    fn test_unicode_substring() {
+        let origin = "安同 OS";
+        assert_eq!(get_substring(origin, "0:2").unwrap(), "安同");
+        assert_eq!(get_substring(origin, "1:3").unwrap(), "同 O");
+        assert_eq!(get_substring(origin, "(-2)").unwrap(), "OS");
+        assert_eq!(get_length(origin, SubstringMode::Chars), 5);
+
+        // e followed by a combining acute accent.
+        let origin = "cafe\u{301}s";
+        assert_eq!(get_length(origin, SubstringMode::Chars), 6);
+        assert_eq!(get_length(origin, SubstringMode::Graphemes), 5);
+        assert_eq!(get_substring(origin, "3:1").unwrap(), "e");
+        assert_eq!(
+            get_substring_with_mode(origin, "3:1", SubstringMode::Graphemes).unwrap(),
+            "e\u{301}"
+        );
+    }
 }
@@ -0,0 +1,290 @@
+//! Evaluation without every variable known, i.e: to document a template or
+//! to see what a field is made of.
+//!
+//! Variables which are neither known beforehand nor assigned are kept as
+//! placeholders: with `VER` unknown, `SRCS="tbl::https://example.com/foo-$VER.tar.xz"`
+//! evaluates to `tbl::https://example.com/foo-${VER}.tar.xz`.
+
+use super::{
+    check_expansion, condition, lowered, substitution, Context, ParseErrorInfo, ParseOptions,
+};
+use lowered::{Statement, Word, WordPart};
+use std::collections::BTreeSet;
+
+/// Partially evaluated variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolicContext {
+    /// Values of the variables, with placeholders for the unresolved ones.
+    pub values: Context,
+    /// Variables whose values contain placeholders, or may have been
+    /// changed by an undecided block.
+    pub symbolic: BTreeSet<String>,
+    /// Variables referred to but never known, kept as placeholders.
+    pub unresolved: BTreeSet<String>,
+    /// Variables assigned in an `if` or `case` block whose condition
+    /// depends on unresolved variables. They keep their value from before
+    /// the block, but are symbolic as it may be wrong.
+    pub undecided: BTreeSet<String>,
+}
+
+impl SymbolicContext {
+    /// Start from known values, i.e: an architecture preset.
+    pub fn new(values: Context) -> Self {
+        SymbolicContext {
+            values,
+            ..Default::default()
+        }
+    }
+
+    /// Whether `name` has a value without placeholders.
+    pub fn is_resolved(&self, name: &str) -> bool {
+        self.values.contains_key(name) && !self.symbolic.contains(name)
+    }
+
+    fn word_resolved(&self, word: &Word) -> bool {
+        word.variables().iter().all(|v| self.is_resolved(v))
+    }
+
+    /// Evaluate `word`, keeping what cannot be evaluated as written, i.e:
+    /// `${VER/./_}` with `VER` unknown.
+    pub fn render(
+        &mut self,
+        word: &Word,
+        options: &ParseOptions,
+    ) -> Result<String, ParseErrorInfo> {
+        let mut result = String::new();
+        for part in word.parts.iter() {
+            match part {
+                WordPart::Literal(s) => result.push_str(s),
+                WordPart::Glob(c) => result.push(*c),
+                WordPart::Param(name) => match self.values.get(name) {
+                    Some(value) => result.push_str(value),
+                    None => {
+                        self.unresolved.insert(name.clone());
+                        result.push_str(&format!("${{{}}}", name));
+                    }
+                },
+                WordPart::Length(name) if self.is_resolved(name) => result.push_str(
+                    &substitution::get_length(&self.values[name], options.substring_mode)
+                        .to_string(),
+                ),
+                WordPart::Length(name) => {
+                    self.note_unresolved(name);
+                    result.push_str(&format!("${{#{}}}", name));
+                }
+                WordPart::Substring { name, argument } => {
+                    let resolved = self.is_resolved(name) && self.word_resolved(argument);
+                    let argument = self.render(argument, options)?;
+                    if resolved {
+                        result += &substitution::get_substring_with_mode(
+                            &self.values[name],
+                            &argument,
+                            options.substring_mode,
+                        )?;
+                    } else {
+                        self.note_unresolved(name);
+                        result.push_str(&format!("${{{}:{}}}", name, argument));
+                    }
+                }
+                WordPart::Replace {
+                    name,
+                    all,
+                    argument,
+                } => {
+                    let resolved = self.is_resolved(name) && self.word_resolved(argument);
+                    let argument = self.render(argument, options)?;
+                    if resolved {
+                        result += &substitution::get_replace_with_limits(
+                            &self.values[name],
+                            &argument,
+                            *all,
+                            &options.limits,
+                        )?;
+                    } else {
+                        self.note_unresolved(name);
+                        let slashes = if *all { "//" } else { "/" };
+                        result.push_str(&format!("${{{}{}{}}}", name, slashes, argument));
+                    }
+                }
+            }
+        }
+
+        check_expansion(result, &options.limits)
+    }
+
+    fn note_unresolved(&mut self, name: &str) {
+        if !self.values.contains_key(name) {
+            self.unresolved.insert(name.to_string());
+        }
+    }
+
+    /// Evaluate `statements`. Blocks are only entered if their conditions
+    /// can be decided, otherwise what they assign is recorded in
+    /// `undecided`.
+    pub fn eval(
+        &mut self,
+        statements: &[Statement],
+        options: &ParseOptions,
+    ) -> Result<(), ParseErrorInfo> {
+        for statement in statements {
+            match statement {
+                Statement::Assignment { name, value } => {
+                    let resolved = self.word_resolved(value);
+                    let value = self.render(value, options)?;
+                    match resolved {
+                        true => self.symbolic.remove(name),
+                        false => self.symbolic.insert(name.clone()),
+                    };
+                    self.values.insert(name.clone(), value);
+                }
+                Statement::If {
+                    branches,
+                    else_body,
+                } => {
+                    let words = branches
+                        .iter()
+                        .flat_map(|b| b.condition.iter())
+                        .flat_map(|c| c.words.iter());
+                    if !self.decidable(words) {
+                        let bodies = branches.iter().map(|b| b.body.as_slice());
+                        self.skip(bodies.chain(else_body.as_deref()));
+                        continue;
+                    }
+                    let mut taken = else_body.as_deref();
+                    for branch in branches {
+                        if condition::eval_lowered_condition(
+                            &branch.condition,
+                            &self.values,
+                            options,
+                        )? {
+                            taken = Some(&branch.body);
+                            break;
+                        }
+                    }
+                    self.eval(taken.unwrap_or_default(), options)?;
+                }
+                Statement::Case { word, arms } => {
+                    let patterns = arms.iter().flat_map(|a| a.patterns.iter());
+                    if !self.decidable(std::iter::once(word).chain(patterns)) {
+                        self.skip(arms.iter().map(|a| a.body.as_slice()));
+                        continue;
+                    }
+                    let mut taken = None;
+                    'arms: for arm in arms {
+                        for pattern in arm.patterns.iter() {
+                            if condition::lowered_case_matches(
+                                word,
+                                pattern,
+                                &self.values,
+                                options,
+                            )? {
+                                taken = Some(&arm.body);
+                                break 'arms;
+                            }
+                        }
+                    }
+                    self.eval(taken.map(|b| b.as_slice()).unwrap_or_default(), options)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a condition made of `words` can be evaluated. If not, its
+    /// unknown variables are recorded.
+    fn decidable<'a, I: Iterator<Item = &'a Word>>(&mut self, words: I) -> bool {
+        let mut result = true;
+        for word in words {
+            for variable in word.variables() {
+                if !self.is_resolved(variable) {
+                    self.note_unresolved(variable);
+                    result = false;
+                }
+            }
+        }
+        result
+    }
+
+    fn skip<'a, I: Iterator<Item = &'a [Statement]>>(&mut self, bodies: I) {
+        for body in bodies {
+            for (name, value) in lowered::assignments(body) {
+                self.undecided.insert(name.to_string());
+                self.symbolic.insert(name.to_string());
+                for variable in value.variables() {
+                    self.note_unresolved(variable);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_symbolic;
+    use super::*;
+
+    #[test]
+    fn test_symbolic() {
+        let source = r#"
+PKGVER="$VER"
+SRCS="tbl::https://example.com/foo-${VER/./_}.tar.xz"
+LEN="${#NAME}"
+PKGDEP="bar"
+if [[ "$ARCH" == arm* ]]; then
+    PKGDEP="$PKGDEP baz"
+fi
+case "$KIND" in
+    lib) PKGSEC=libs ;;
+esac
+DEPS="$PKGDEP"
+if [ "$PKGDEP" = bar ]; then
+    ONLY_BAR=1
+fi
+"#;
+        let mut context = SymbolicContext::default();
+        parse_symbolic(source, &mut context).unwrap();
+        assert_eq!(context.values["PKGVER"], "${VER}");
+        assert_eq!(
+            context.values["SRCS"],
+            "tbl::https://example.com/foo-${VER/./_}.tar.xz"
+        );
+        assert_eq!(context.values["LEN"], "${#NAME}");
+        assert_eq!(context.values["PKGDEP"], "bar");
+        assert!(!context.values.contains_key("PKGSEC"));
+        // `PKGDEP` may have changed in the `if` block, so neither `DEPS` nor
+        // the condition on it are taken as resolved.
+        assert_eq!(context.values["DEPS"], "bar");
+        assert!(!context.values.contains_key("ONLY_BAR"));
+        let names = |s: &BTreeSet<String>| s.iter().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            names(&context.symbolic),
+            vec!["DEPS", "LEN", "ONLY_BAR", "PKGDEP", "PKGSEC", "PKGVER", "SRCS"]
+        );
+        assert_eq!(
+            names(&context.unresolved),
+            vec!["ARCH", "KIND", "NAME", "VER"]
+        );
+        assert_eq!(
+            names(&context.undecided),
+            vec!["ONLY_BAR", "PKGDEP", "PKGSEC"]
+        );
+
+        let mut known = Context::new();
+        known.insert("VER".to_string(), "1.2".to_string());
+        known.insert("ARCH".to_string(), "arm64".to_string());
+        let mut context = SymbolicContext::new(known);
+        parse_symbolic(source, &mut context).unwrap();
+        assert_eq!(context.values["PKGVER"], "1.2");
+        assert_eq!(
+            context.values["SRCS"],
+            "tbl::https://example.com/foo-1_2.tar.xz"
+        );
+        assert_eq!(context.values["PKGDEP"], "bar baz");
+        assert_eq!(context.values["DEPS"], "bar baz");
+        assert!(!context.values.contains_key("ONLY_BAR"));
+        assert!(!context.symbolic.contains("SRCS"));
+        assert!(!context.symbolic.contains("DEPS"));
+        assert_eq!(names(&context.undecided), vec!["PKGSEC"]);
+    }
+}
@@ -0,0 +1,172 @@
+//! Which input variables the value of each assigned variable depends on,
+//! i.e: to tell which fields may change with `ARCH` without evaluating
+//! every architecture.
+//!
+//! Inputs are the variables used before being assigned. A value depends on
+//! the inputs it is built from, through other variables too, and on the
+//! inputs of the conditions of the blocks assigning it.
+
+use super::lowered::{Statement, Word};
+use std::collections::{BTreeMap, BTreeSet};
+
+type Dependencies = BTreeMap<String, BTreeSet<String>>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Taint {
+    dependencies: Dependencies,
+}
+
+/// Inputs `name` depends on, itself if it is not assigned yet.
+fn inputs(dependencies: &Dependencies, name: &str) -> BTreeSet<String> {
+    match dependencies.get(name) {
+        Some(inputs) => inputs.clone(),
+        None => std::iter::once(name.to_string()).collect(),
+    }
+}
+
+fn word_inputs(dependencies: &Dependencies, word: &Word, result: &mut BTreeSet<String>) {
+    for variable in word.variables() {
+        result.extend(inputs(dependencies, variable));
+    }
+}
+
+/// Walk `statements`, with `control` the inputs of the conditions around.
+fn walk(statements: &[Statement], control: &BTreeSet<String>, dependencies: &mut Dependencies) {
+    for statement in statements {
+        let (conditions, bodies, exhaustive): (Vec<&Word>, Vec<&[Statement]>, bool) =
+            match statement {
+                Statement::Assignment { name, value } => {
+                    let mut result = control.clone();
+                    word_inputs(dependencies, value, &mut result);
+                    dependencies.insert(name.clone(), result);
+                    continue;
+                }
+                Statement::If {
+                    branches,
+                    else_body,
+                } => {
+                    let words = branches
+                        .iter()
+                        .flat_map(|b| b.condition.iter())
+                        .flat_map(|c| c.words.iter());
+                    let mut bodies: Vec<_> = branches.iter().map(|b| b.body.as_slice()).collect();
+                    bodies.extend(else_body.as_deref());
+                    (words.collect(), bodies, else_body.is_some())
+                }
+                Statement::Case { word, arms } => {
+                    let patterns = arms.iter().flat_map(|a| a.patterns.iter());
+                    let bodies = arms.iter().map(|a| a.body.as_slice()).collect();
+                    (
+                        std::iter::once(word).chain(patterns).collect(),
+                        bodies,
+                        false,
+                    )
+                }
+            };
+
+        let mut control = control.clone();
+        for word in conditions {
+            word_inputs(dependencies, word, &mut control);
+        }
+        // Each variable assigned in a branch may also keep the value of any
+        // other branch, or its value from before the block if no branch
+        // may be taken.
+        let before = dependencies.clone();
+        let branches: Vec<_> = bodies
+            .into_iter()
+            .map(|body| {
+                let mut branch = before.clone();
+                walk(body, &control, &mut branch);
+                branch
+            })
+            .collect();
+        for branch in branches.iter() {
+            for (name, result) in branch.iter() {
+                if before.get(name) == Some(result) {
+                    continue;
+                }
+                let mut merged = dependencies.remove(name).unwrap_or_default();
+                merged.extend(result.iter().cloned());
+                if !exhaustive {
+                    merged.extend(inputs(&before, name));
+                }
+                for other in branches.iter() {
+                    merged.extend(inputs(other, name));
+                }
+                dependencies.insert(name.clone(), merged);
+            }
+        }
+    }
+}
+
+impl Taint {
+    pub fn of(statements: &[Statement]) -> Self {
+        let mut dependencies = Dependencies::new();
+        walk(statements, &BTreeSet::new(), &mut dependencies);
+        Taint { dependencies }
+    }
+
+    /// Inputs the value of `name` depends on, if it is assigned.
+    pub fn inputs_of(&self, name: &str) -> Option<&BTreeSet<String>> {
+        self.dependencies.get(name)
+    }
+
+    /// Assigned variables whose values depend on the input `input`, sorted.
+    pub fn affected_by(&self, input: &str) -> Vec<&str> {
+        self.dependencies
+            .iter()
+            .filter(|(_, inputs)| inputs.contains(input))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Every assigned variable with its inputs, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &BTreeSet<String>)> {
+        self.dependencies.iter().map(|(k, v)| (k.as_str(), v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lower;
+    use super::*;
+
+    #[test]
+    fn test_taint() {
+        let statements = lower(
+            r#"
+PKGVER="${VER/./_}"
+SRCS="tbl::https://example.com/foo-$PKGVER.tar.xz"
+PKGDEP="bar"
+if [[ "$ARCH" == arm* ]]; then
+    PKGDEP="$PKGDEP baz"
+else
+    PKGDES="Foo"
+fi
+case "$KIND" in
+    lib) PKGSEC=libs ;;
+esac
+VER=2.0
+A="$VER"
+"#,
+        )
+        .unwrap();
+        let taint = Taint::of(&statements);
+        let inputs = |name| {
+            taint
+                .inputs_of(name)
+                .unwrap()
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(inputs("SRCS"), vec!["VER"]);
+        assert_eq!(inputs("PKGDEP"), vec!["ARCH"]);
+        assert_eq!(inputs("PKGDES"), vec!["ARCH", "PKGDES"]);
+        assert_eq!(inputs("PKGSEC"), vec!["KIND", "PKGSEC"]);
+        assert!(inputs("A").is_empty());
+        assert_eq!(taint.inputs_of("ARCH"), None);
+        assert_eq!(taint.affected_by("ARCH"), vec!["PKGDEP", "PKGDES"]);
+        assert_eq!(taint.affected_by("VER"), vec!["PKGVER", "SRCS"]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Architectures supported by AOSC OS, and the variables autobuild sets for
+//! them before evaluating spec and defines files.
+
+use crate::apf::Context;
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Arch {
+    Amd64,
+    Arm64,
+    Armv4,
+    Armv6hf,
+    Armv7hf,
+    I486,
+    Loongarch64,
+    Loongson3,
+    Mips64r6el,
+    Powerpc,
+    Ppc64,
+    Ppc64el,
+    Riscv64,
+}
+
+impl Arch {
+    /// Every architecture, in the order of `autobuild::ARCHITECTURES`.
+    pub const ALL: &'static [Arch] = &[
+        Arch::Amd64,
+        Arch::Arm64,
+        Arch::Armv4,
+        Arch::Armv6hf,
+        Arch::Armv7hf,
+        Arch::I486,
+        Arch::Loongarch64,
+        Arch::Loongson3,
+        Arch::Mips64r6el,
+        Arch::Powerpc,
+        Arch::Ppc64,
+        Arch::Ppc64el,
+        Arch::Riscv64,
+    ];
+
+    /// Name used by autobuild, i.e: `amd64`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Arch::Amd64 => "amd64",
+            Arch::Arm64 => "arm64",
+            Arch::Armv4 => "armv4",
+            Arch::Armv6hf => "armv6hf",
+            Arch::Armv7hf => "armv7hf",
+            Arch::I486 => "i486",
+            Arch::Loongarch64 => "loongarch64",
+            Arch::Loongson3 => "loongson3",
+            Arch::Mips64r6el => "mips64r6el",
+            Arch::Powerpc => "powerpc",
+            Arch::Ppc64 => "ppc64",
+            Arch::Ppc64el => "ppc64el",
+            Arch::Riscv64 => "riscv64",
+        }
+    }
+
+    /// GNU target triplet, i.e: `x86_64-aosc-linux-gnu`.
+    pub fn triplet(&self) -> &'static str {
+        match self {
+            Arch::Amd64 => "x86_64-aosc-linux-gnu",
+            Arch::Arm64 => "aarch64-aosc-linux-gnu",
+            Arch::Armv4 => "arm-aosc-linux-gnueabi",
+            Arch::Armv6hf | Arch::Armv7hf => "arm-aosc-linux-gnueabihf",
+            Arch::I486 => "i486-aosc-linux-gnu",
+            Arch::Loongarch64 => "loongarch64-aosc-linux-gnu",
+            Arch::Loongson3 => "mips64el-aosc-linux-gnuabi64",
+            Arch::Mips64r6el => "mipsisa64r6el-aosc-linux-gnuabi64",
+            Arch::Powerpc => "powerpc-aosc-linux-gnu",
+            Arch::Ppc64 => "powerpc64-aosc-linux-gnu",
+            Arch::Ppc64el => "powerpc64le-aosc-linux-gnu",
+            Arch::Riscv64 => "riscv64-aosc-linux-gnu",
+        }
+    }
+
+    /// Variables set by autobuild when building natively for this
+    /// architecture.
+    pub fn preset(&self) -> Context {
+        self.cross_preset(*self)
+    }
+
+    /// Variables set by autobuild when building for this architecture on
+    /// `build`:
+    /// - `ARCH` and `ABHOST`, the architecture built for,
+    /// - `ABBUILD`, the architecture built on,
+    /// - `HOST` and `BUILD`, their triplets,
+    /// - `CROSS`, the architecture built for when cross-compiling, empty
+    ///   otherwise.
+    pub fn cross_preset(&self, build: Arch) -> Context {
+        let cross = if build == *self { "" } else { self.as_str() };
+        [
+            ("ARCH", self.as_str()),
+            ("ABHOST", self.as_str()),
+            ("ABBUILD", build.as_str()),
+            ("HOST", self.triplet()),
+            ("BUILD", build.triplet()),
+            ("CROSS", cross),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+}
+
+impl FromStr for Arch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Arch::ALL
+            .iter()
+            .find(|a| a.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("Unknown architecture `{}`", s))
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autobuild::ARCHITECTURES;
+
+    #[test]
+    fn test_arch() {
+        let names: Vec<_> = Arch::ALL.iter().map(|a| a.as_str()).collect();
+        assert_eq!(names, ARCHITECTURES);
+        assert_eq!("riscv64".parse(), Ok(Arch::Riscv64));
+        assert!("x86_64".parse::<Arch>().is_err());
+
+        let preset = Arch::Riscv64.preset();
+        assert_eq!(preset["ARCH"], "riscv64");
+        assert_eq!(preset["HOST"], "riscv64-aosc-linux-gnu");
+        assert_eq!(preset["CROSS"], "");
+        let preset = Arch::Riscv64.cross_preset(Arch::Amd64);
+        assert_eq!(preset["ABBUILD"], "amd64");
+        assert_eq!(preset["BUILD"], "x86_64-aosc-linux-gnu");
+        assert_eq!(preset["CROSS"], "riscv64");
+    }
+}
@@ -0,0 +1,394 @@
+//! Knowledge about autobuild, the build system consuming ABBS trees.
+
+use crate::{
+    apf::Context,
+    package::{split_arch_suffix, Package},
+    validate::KNOWN_SECTIONS,
+};
+use regex::Regex;
+use std::fmt;
+
+/// Variables autobuild defines itself and overwrites before running the
+/// build, so assigning them in spec or defines files has no effect.
+pub const BUILTIN_VARIABLES: &[&str] = &[
+    "AB",
+    "ABBLPREFIX",
+    "ABBUILD",
+    "ABTARGET",
+    "ARCH",
+    "BINDIR",
+    "BLDDIR",
+    "BOOTDIR",
+    "DOCDIR",
+    "INCLUDE",
+    "LIBDIR",
+    "LIBEXEC",
+    "MANDIR",
+    "PKGDIR",
+    "PREFIX",
+    "SRCDIR",
+    "SYMDIR",
+    "SYSCONF",
+];
+
+pub fn is_builtin_variable(name: &str) -> bool {
+    BUILTIN_VARIABLES.binary_search(&name).is_ok()
+}
+
+/// Build templates selectable with `ABTYPE`.
+pub const BUILD_TYPES: &[&str] = &[
+    "autotools",
+    "cmake",
+    "cmakeninja",
+    "dummy",
+    "gomod",
+    "haskell",
+    "meson",
+    "npm",
+    "pep517",
+    "perl",
+    "plainmake",
+    "python",
+    "qtproj",
+    "ruby",
+    "rust",
+    "self",
+    "waf",
+];
+
+/// Architectures accepted in `ABHOST`, besides `noarch`.
+pub const ARCHITECTURES: &[&str] = &[
+    "amd64",
+    "arm64",
+    "armv4",
+    "armv6hf",
+    "armv7hf",
+    "i486",
+    "loongarch64",
+    "loongson3",
+    "mips64r6el",
+    "powerpc",
+    "ppc64",
+    "ppc64el",
+    "riscv64",
+];
+
+/// Switches autobuild only understands as `0` or `1`.
+pub const BOOLEAN_FIELDS: &[&str] = &[
+    "ABELFDEP",
+    "ABSHADOW",
+    "ABSPLITDBG",
+    "ABSTRIP",
+    "NOLTO",
+    "NOPARALLEL",
+    "NOSTATIC",
+    "USECLANG",
+];
+
+/// Fields every defines file needs.
+pub const REQUIRED_FIELDS: &[&str] = &["PKGNAME", "PKGSEC", "PKGDES"];
+
+/// Fields needed on top of `REQUIRED_FIELDS` for an `ABTYPE`.
+pub fn required_fields(abtype: &str) -> &'static [&'static str] {
+    match abtype {
+        // Nothing is built, so a dummy package is only worth its dependencies.
+        "dummy" => &["PKGDEP"],
+        _ => &[],
+    }
+}
+
+/// A defines file breaking the rules of autobuild.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// A field required for this `ABTYPE` is missing.
+    MissingField { field: String, abtype: Option<String> },
+    UnknownBuildType(String),
+    /// `ABHOST` (or an override of it) is neither `noarch` nor an architecture.
+    BadHost { key: String, value: String },
+    NotBoolean { key: String, value: String },
+    UnknownSection { key: String, value: String },
+    /// `FAIL_ARCH` (or an override of it) is not a valid pattern.
+    BadFailArch { key: String, value: String },
+}
+
+impl Violation {
+    /// The key at fault.
+    pub fn key(&self) -> &str {
+        match self {
+            Violation::MissingField { field, .. } => field,
+            Violation::UnknownBuildType(_) => "ABTYPE",
+            Violation::BadHost { key, .. }
+            | Violation::NotBoolean { key, .. }
+            | Violation::UnknownSection { key, .. }
+            | Violation::BadFailArch { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::MissingField { field, abtype: None } => write!(f, "{} is required", field),
+            Violation::MissingField {
+                field,
+                abtype: Some(abtype),
+            } => write!(f, "{} is required with ABTYPE={}", field, abtype),
+            Violation::UnknownBuildType(t) => write!(f, "Unknown ABTYPE `{}`", t),
+            Violation::BadHost { key, value } => write!(
+                f,
+                "{} must be `noarch` or an architecture, not `{}`",
+                key, value
+            ),
+            Violation::NotBoolean { key, value } => {
+                write!(f, "{} must be 0 or 1, not `{}`", key, value)
+            }
+            Violation::UnknownSection { key, value } => write!(f, "Unknown section `{}` in {}", value, key),
+            Violation::BadFailArch { key, value } => {
+                write!(f, "{} is not a valid architecture pattern: `{}`", key, value)
+            }
+        }
+    }
+}
+
+/// Check a defines context against the rules of autobuild: required fields
+/// (per `ABTYPE`), the value of `ABTYPE`, `ABHOST`, boolean switches and
+/// `PKGSEC`, overrides like `NOSTATIC__AMD64` included.
+/// Violations are sorted by key.
+pub fn check_defines(context: &Context) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let abtype = context.get("ABTYPE").filter(|t| !t.is_empty());
+    if let Some(abtype) = abtype {
+        if !BUILD_TYPES.contains(&abtype.as_str()) {
+            violations.push(Violation::UnknownBuildType(abtype.clone()));
+        }
+    }
+    for field in REQUIRED_FIELDS {
+        if context.get(*field).is_none_or(|v| v.is_empty()) {
+            violations.push(Violation::MissingField {
+                field: field.to_string(),
+                abtype: None,
+            });
+        }
+    }
+    for field in abtype.map_or(&[][..], |t| required_fields(t)) {
+        if context.get(*field).is_none_or(|v| v.is_empty()) {
+            violations.push(Violation::MissingField {
+                field: field.to_string(),
+                abtype: abtype.cloned(),
+            });
+        }
+    }
+
+    for (key, value) in context.iter() {
+        let field = split_arch_suffix(key).map_or(key.as_str(), |(field, _)| field);
+        let (key, value) = (key.clone(), value.clone());
+        match field {
+            "ABHOST" if value != "noarch" && !ARCHITECTURES.contains(&value.as_str()) => {
+                violations.push(Violation::BadHost { key, value })
+            }
+            "FAIL_ARCH" if FailArch::parse(&value).is_err() => {
+                violations.push(Violation::BadFailArch { key, value })
+            }
+            "PKGSEC" if !KNOWN_SECTIONS.contains(&value.as_str()) => {
+                violations.push(Violation::UnknownSection { key, value })
+            }
+            _ if BOOLEAN_FIELDS.contains(&field) && value != "0" && value != "1" => {
+                violations.push(Violation::NotBoolean { key, value })
+            }
+            _ => (),
+        }
+    }
+
+    violations.sort_by(|a, b| a.key().cmp(b.key()));
+    violations
+}
+
+/// Architectures a package fails to build on, from `FAIL_ARCH`. Following
+/// autobuild, the value is a regex matching the whole architecture name, or
+/// everything but the matching ones if prefixed by `!`, i.e:
+/// `(i486|loongson3)` or `!(amd64|arm64)`. A plain list like
+/// `i486 loongson3` and the extglob form `@(i486|loongson3)` are accepted
+/// too.
+#[derive(Debug, Clone)]
+pub struct FailArch {
+    negated: bool,
+    regex: Regex,
+}
+
+impl FailArch {
+    pub fn parse(value: &str) -> Result<Self, regex::Error> {
+        let value = value.trim();
+        let (negated, pattern) = match value.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, value),
+        };
+        let pattern = match pattern.strip_prefix('@') {
+            Some(p) if p.starts_with('(') => p,
+            _ => pattern,
+        };
+        let regex = if pattern.contains(|c: char| "()|[]*?+.^$\\".contains(c)) {
+            format!("^(?:{})$", pattern)
+        } else {
+            let archs: Vec<_> = pattern
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|a| !a.is_empty())
+                .map(regex::escape)
+                .collect();
+            format!("^(?:{})$", archs.join("|"))
+        };
+        // An empty list excludes nothing, not the empty name.
+        let regex = match regex.as_str() {
+            "^(?:)$" => "[^\\s\\S]".to_string(),
+            _ => regex,
+        };
+
+        Ok(FailArch {
+            negated,
+            regex: Regex::new(&regex)?,
+        })
+    }
+
+    /// Whether building for `arch` is expected to fail.
+    pub fn excludes(&self, arch: &str) -> bool {
+        self.regex.is_match(arch) != self.negated
+    }
+}
+
+/// Whether `FAIL_ARCH` in `context` excludes `arch`. An invalid `FAIL_ARCH`
+/// excludes nothing, `check_defines` reports it.
+pub(crate) fn fail_arch_excludes(context: &Context, arch: &str) -> bool {
+    context
+        .get("FAIL_ARCH")
+        .and_then(|v| FailArch::parse(v).ok())
+        .is_some_and(|f| f.excludes(arch))
+}
+
+/// Packages which do not build on `arch`, see `Package::builds_on`.
+pub fn excluded_from<'a>(packages: &'a [Package], arch: &str) -> Vec<&'a Package> {
+    packages.iter().filter(|p| !p.builds_on(arch)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_variables_sorted() {
+        assert!(BUILTIN_VARIABLES.windows(2).all(|w| w[0] < w[1]));
+        assert!(is_builtin_variable("SRCDIR"));
+        assert!(!is_builtin_variable("PKGDEP"));
+        for list in [BUILD_TYPES, ARCHITECTURES, BOOLEAN_FIELDS] {
+            assert!(list.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn test_check_defines() {
+        let context = |fields: &[(&str, &str)]| -> Context {
+            fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let base = [("PKGNAME", "foo"), ("PKGSEC", "utils"), ("PKGDES", "Foo")];
+        assert!(check_defines(&context(&base)).is_empty());
+
+        let mut fields = base.to_vec();
+        fields.extend_from_slice(&[
+            ("ABTYPE", "cmake"),
+            ("ABHOST", "noarch"),
+            ("ABHOST__AMD64", "x86_64"),
+            ("NOSTATIC", "1"),
+            ("ABSPLITDBG__ARM64", "yes"),
+            ("PKGSEC__AMD64", "utilities"),
+        ]);
+        let violations = check_defines(&context(&fields));
+        assert_eq!(
+            violations,
+            vec![
+                Violation::BadHost {
+                    key: "ABHOST__AMD64".to_string(),
+                    value: "x86_64".to_string()
+                },
+                Violation::NotBoolean {
+                    key: "ABSPLITDBG__ARM64".to_string(),
+                    value: "yes".to_string()
+                },
+                Violation::UnknownSection {
+                    key: "PKGSEC__AMD64".to_string(),
+                    value: "utilities".to_string()
+                },
+            ]
+        );
+
+        let violations = check_defines(&context(&[("PKGNAME", "foo"), ("PKGDES", ""), ("ABTYPE", "dummy")]));
+        let messages: Vec<_> = violations.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "PKGDEP is required with ABTYPE=dummy",
+                "PKGDES is required",
+                "PKGSEC is required"
+            ]
+        );
+        let violations = check_defines(&context(&[("ABTYPE", "scons")]));
+        assert_eq!(violations[0], Violation::UnknownBuildType("scons".to_string()));
+    }
+
+    #[test]
+    fn test_fail_arch() {
+        let excluded = |value: &str| -> Vec<&str> {
+            let fail_arch = FailArch::parse(value).unwrap();
+            ARCHITECTURES
+                .iter()
+                .copied()
+                .filter(|a| fail_arch.excludes(a))
+                .collect()
+        };
+        assert_eq!(excluded("(i486|loongson3)"), vec!["i486", "loongson3"]);
+        assert_eq!(excluded("@(i486|loongson3)"), vec!["i486", "loongson3"]);
+        assert_eq!(excluded("i486 loongson3"), vec!["i486", "loongson3"]);
+        assert_eq!(
+            excluded("arm.*"),
+            vec!["arm64", "armv4", "armv6hf", "armv7hf"]
+        );
+        assert_eq!(excluded("!(amd64|arm64)").len(), ARCHITECTURES.len() - 2);
+        assert!(excluded("").is_empty());
+        assert!(FailArch::parse("(amd64").is_err());
+
+        let package = |fail_arch: Option<&str>| {
+            let mut fields = Context::new();
+            if let Some(v) = fail_arch {
+                fields.insert("FAIL_ARCH".to_string(), v.to_string());
+            }
+            Package::new("foo", fields)
+        };
+        let packages = vec![
+            package(None),
+            package(Some("!(amd64)")),
+            package(Some("(amd64")),
+        ];
+        assert!(packages[0].builds_on("riscv64"));
+        assert!(!packages[1].builds_on("riscv64"));
+        assert!(packages[2].builds_on("riscv64"));
+        assert_eq!(excluded_from(&packages, "riscv64").len(), 1);
+        assert!(excluded_from(&packages, "amd64").is_empty());
+
+        let fields = [
+            ("PKGNAME", "foo"),
+            ("PKGSEC", "utils"),
+            ("PKGDES", "Foo"),
+            ("FAIL_ARCH", "(amd64"),
+        ];
+        let violations = check_defines(
+            &fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+        assert_eq!(
+            violations.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+            vec!["FAIL_ARCH is not a valid architecture pattern: `(amd64`"]
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Query and dump tree metadata from the command line.
+//! i.e: `abbs-meta -C TREE dump bash` or `abbs-meta search PKGSEC=utils`.
+
+use abbs::{
+    apf::Context,
+    deps::DependencyGraph,
+    lint::Linter,
+    package::{resolve_arch_fields, Package},
+    package_set::{PackageSet, Reason},
+    query::Query,
+    tree::Tree,
+};
+use std::{collections::BTreeMap, env, path::PathBuf, process};
+
+const USAGE: &str = "\
+Usage: abbs-meta [-C TREE] [--arch ARCH] COMMAND
+
+Commands:
+  dump PACKAGE                   print the fields of PACKAGE as JSON
+  lint [TREE]                    lint every package in TREE
+  depgraph [--reverse] PACKAGE   list what PACKAGE depends on, or what depends on it
+  plan REASON PACKAGE...         print a staged rebuild plan of PACKAGE... for --arch as JSON
+  search KEY=VALUE               list packages where KEY is VALUE
+  query EXPRESSION               list packages matching EXPRESSION, i.e:
+                                 'PKGDEP contains \"python-3\" && SECTION == \"app-devel\"'
+  stats                          time the evaluation of every file, slowest first
+
+Options:
+  -C TREE       tree to work on, the current directory by default
+  --arch ARCH   apply architecture-specific overrides for ARCH, i.e: amd64";
+
+struct Options {
+    tree: PathBuf,
+    arch: Option<String>,
+    reverse: bool,
+    args: Vec<String>,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        tree: PathBuf::from("."),
+        arch: None,
+        reverse: false,
+        args: Vec::new(),
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-C" => match args.next() {
+                Some(tree) => options.tree = PathBuf::from(tree),
+                None => usage_error("-C needs a directory"),
+            },
+            "--arch" => match args.next() {
+                Some(arch) => options.arch = Some(arch),
+                None => usage_error("--arch needs an architecture"),
+            },
+            "--reverse" => options.reverse = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            a if a.starts_with('-') => usage_error(&format!("Unknown option {}", a)),
+            _ => options.args.push(arg),
+        }
+    }
+
+    options
+}
+
+fn scan(tree: &Tree) -> Vec<Package> {
+    let scan = tree
+        .scan()
+        .unwrap_or_else(|e| fail(format!("Cannot read {}: {}", tree.root().display(), e)));
+    for e in scan.errors.iter() {
+        eprintln!("Warning: {}", e);
+    }
+    scan.packages
+}
+
+fn fields_of(packages: &[Package], name: &str) -> Option<Context> {
+    for package in packages {
+        if package.name() == name {
+            return Some(package.fields().clone());
+        }
+        if let Some(sub) = package.subpackages().iter().find(|s| s.name() == name) {
+            return Some(sub.fields().clone());
+        }
+    }
+
+    None
+}
+
+/// Fields of the package or sub-package called `name`.
+fn find_fields(tree: &Tree, name: &str) -> Option<Context> {
+    // Most packages are named after their directory, which avoids a full scan.
+    let dirs = tree
+        .package_dirs()
+        .unwrap_or_else(|e| fail(format!("Cannot read {}: {}", tree.root().display(), e)));
+    let by_dir = dirs.iter().find(|d| d.file_name().is_some_and(|n| n == name));
+    if let Some(Ok(package)) = by_dir.map(Package::from_dir) {
+        if let Some(fields) = fields_of(&[package], name) {
+            return Some(fields);
+        }
+    }
+
+    fields_of(&scan(tree), name)
+}
+
+fn dump(tree: &Tree, arch: Option<&str>, name: &str) {
+    let fields = find_fields(tree, name).unwrap_or_else(|| fail(format!("No package called {}", name)));
+    let fields = match arch {
+        Some(arch) => resolve_arch_fields(&fields, arch),
+        None => fields,
+    };
+    let sorted: BTreeMap<_, _> = fields.iter().collect();
+    match serde_json::to_string_pretty(&sorted) {
+        Ok(json) => println!("{}", json),
+        Err(e) => fail(e.to_string()),
+    }
+}
+
+/// Returns whether any error was found.
+fn lint(tree: &Tree) -> bool {
+    let linter = Linter::default();
+    let dirs = tree
+        .package_dirs()
+        .unwrap_or_else(|e| fail(format!("Cannot read {}: {}", tree.root().display(), e)));
+    let mut has_errors = false;
+    for dir in dirs {
+        match linter.lint_package(&dir) {
+            Ok(reports) => {
+                for report in reports {
+                    has_errors |= report.has_errors();
+                    print!("{}", report);
+                }
+            }
+            Err(e) => {
+                has_errors = true;
+                eprintln!("{}: {}", dir.display(), e);
+            }
+        }
+    }
+
+    has_errors
+}
+
+fn stats(tree: &Tree) {
+    let (_, metrics) = tree
+        .scan_with_metrics()
+        .unwrap_or_else(|e| fail(format!("Cannot read {}: {}", tree.root().display(), e)));
+    print!("{}", metrics);
+}
+
+fn depgraph(tree: &Tree, arch: Option<&str>, reverse: bool, name: &str) {
+    let packages = scan(tree);
+    let graph = DependencyGraph::from_packages(&packages, arch).unwrap_or_else(|e| fail(e.to_string()));
+    if !graph.contains(name) {
+        fail(format!("No package called {}", name));
+    }
+    if reverse {
+        for dependent in graph.reverse_dependencies(name) {
+            println!("{}", dependent);
+        }
+    } else {
+        for (dependency, kind) in graph.dependencies(name) {
+            println!("{} ({})", dependency, kind.field());
+        }
+    }
+}
+
+fn plan(tree: &Tree, arch: Option<&str>, reason: &str, targets: &[&str]) {
+    let arch = arch.unwrap_or_else(|| usage_error("plan needs --arch"));
+    let packages = scan(tree);
+    let graph = DependencyGraph::from_packages(&packages, Some(arch)).unwrap_or_else(|e| fail(e.to_string()));
+    let targets = PackageSet::from_names(targets.iter().copied(), Reason::Explicit);
+    let plan = graph
+        .rebuild_plan(&targets, &[arch], reason)
+        .unwrap_or_else(|e| fail(e.to_string()));
+    match plan.to_json() {
+        Ok(json) => println!("{}", json),
+        Err(e) => fail(e.to_string()),
+    }
+}
+
+fn search(tree: &Tree, arch: Option<&str>, query: &str) {
+    let (key, value) = query
+        .split_once('=')
+        .unwrap_or_else(|| usage_error("search needs KEY=VALUE"));
+    for package in scan(tree) {
+        let mut units = vec![(package.name(), package.fields())];
+        units.extend(package.subpackages().iter().map(|s| (s.name(), s.fields())));
+        for (name, fields) in units {
+            let resolved;
+            let fields = match arch {
+                Some(arch) => {
+                    resolved = resolve_arch_fields(fields, arch);
+                    &resolved
+                }
+                None => fields,
+            };
+            if fields.get(key).map(|v| v.as_str()) == Some(value) {
+                let dir = package.path().and_then(|p| p.strip_prefix(tree.root()).ok());
+                match dir {
+                    Some(dir) => println!("{}\t{}", name, dir.display()),
+                    None => println!("{}", name),
+                }
+            }
+        }
+    }
+}
+
+fn query(tree: &Tree, expression: &str) {
+    let query = Query::parse(expression).unwrap_or_else(|e| fail(e.to_string()));
+    for package in query.filter(&scan(tree)) {
+        let dir = package.path().and_then(|p| p.strip_prefix(tree.root()).ok());
+        match dir {
+            Some(dir) => println!("{}\t{}", package.name(), dir.display()),
+            None => println!("{}", package.name()),
+        }
+    }
+}
+
+fn main() {
+    let options = parse_args();
+    let args: Vec<&str> = options.args.iter().map(|a| a.as_str()).collect();
+    let tree = Tree::open(&options.tree);
+    let arch = options.arch.as_deref();
+    match args.as_slice() {
+        ["dump", name] => dump(&tree, arch, name),
+        ["lint"] => process::exit(lint(&tree) as i32),
+        ["lint", root] => process::exit(lint(&Tree::open(root)) as i32),
+        ["depgraph", name] => depgraph(&tree, arch, options.reverse, name),
+        ["plan", reason, targets @ ..] if !targets.is_empty() => plan(&tree, arch, reason, targets),
+        ["search", query] => search(&tree, arch, query),
+        ["query", expression] => query(&tree, expression),
+        ["stats"] => stats(&tree),
+        [] => usage_error("No command given"),
+        [command, ..] => usage_error(&format!("Bad arguments for {}", command)),
+    }
+}
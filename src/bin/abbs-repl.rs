@@ -0,0 +1,133 @@
+//! Interactive playground for spec authors.
+//! Assignments update the context, anything else is expanded as a shell word,
+//! i.e: `VER=1.2.3` then `${VER:0:3}` prints `1.2`.
+
+use abbs::apf::{self, Context};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, BufRead, Write},
+};
+
+/// Variable used to evaluate bare expressions.
+const EXPR_VAR: &str = "__ABBS_REPL_EXPR";
+
+const HELP: &str = "\
+NAME=VALUE     assign a variable
+WORD           expand WORD, i.e: ${VER:0:3} or \"$PKGNAME-$VER\"
+:load FILE     parse FILE into the context
+:vars          list all variables
+:unset NAME    remove a variable
+:reset         clear the context
+:trace         toggle printing of context changes
+:help          show this message
+:quit          exit";
+
+fn is_assignment(line: &str) -> bool {
+    match line.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+/// Print what changed between `before` and `after`, sorted by name.
+fn print_trace(before: &Context, after: &Context) {
+    let names: BTreeSet<_> = before.keys().chain(after.keys()).collect();
+    for name in names {
+        match (before.get(name), after.get(name)) {
+            (None, Some(new)) => println!("  + {}={}", name, new),
+            (Some(old), Some(new)) if old != new => println!("  ~ {}: {} -> {}", name, old, new),
+            (Some(_), None) => println!("  - {}", name),
+            _ => (),
+        }
+    }
+}
+
+fn print_vars(context: &Context) {
+    let sorted: BTreeMap<_, _> = context.iter().collect();
+    for (name, value) in sorted {
+        println!("{}={}", name, value);
+    }
+}
+
+fn load(path: &str, context: &mut Context) {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return;
+        }
+    };
+    let mut warnings = Vec::new();
+    let result = apf::parse_with_warnings(&content, context, &mut warnings);
+    for w in warnings {
+        eprintln!("{}", w);
+    }
+    if let Err(e) = result {
+        eprintln!("{}", e);
+    }
+}
+
+fn eval(line: &str, context: &mut Context) {
+    if is_assignment(line) {
+        match apf::parse_assignment(line, context) {
+            Ok(name) => println!("{}={}", name, context[&name]),
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
+
+    let mut scratch = context.clone();
+    match apf::parse_assignment(&format!("{}={}", EXPR_VAR, line), &mut scratch) {
+        Ok(_) => println!("{}", scratch[EXPR_VAR]),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut context = Context::new();
+    let mut trace = false;
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("abbs> ");
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let before = if trace { Some(context.clone()) } else { None };
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            ":quit" | ":q" => break,
+            ":help" => println!("{}", HELP),
+            ":vars" => print_vars(&context),
+            ":reset" => context.clear(),
+            ":unset" => {
+                context.remove(arg.trim());
+            }
+            ":load" => load(arg.trim(), &mut context),
+            ":trace" => {
+                trace = !trace;
+                println!("Trace {}", if trace { "on" } else { "off" });
+            }
+            c if c.starts_with(':') => eprintln!("Unknown command {}, try :help", c),
+            _ => eval(line, &mut context),
+        }
+        if let Some(before) = before {
+            print_trace(&before, &context);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+//! Persistent cache of parsed spec and defines files.
+//! Contexts are stored in a flat CBOR file, keyed by a hash of the file content
+//! and the variables in scope before it, so repeated tree scans only reparse
+//! files that changed.
+
+use crate::apf::{self, Context, ParseError};
+use blake2::{Blake2s256, Digest};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// Version of the cache file layout.
+/// Bumped on any incompatible change to it.
+pub const CACHE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    format_version: u32,
+    /// Version of this crate, as a parser change may change the results.
+    parser_version: String,
+    entries: HashMap<String, Context>,
+}
+
+#[derive(Debug, Default)]
+pub struct ParseCache {
+    path: Option<PathBuf>,
+    entries: Mutex<HashMap<String, Context>>,
+    /// Keys looked up since the cache was opened.
+    used: Mutex<HashSet<String>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+/// Hash of `content` evaluated on top of `context`.
+fn cache_key(content: &str, context: &Context) -> String {
+    let mut vars: Vec<_> = context.iter().collect();
+    vars.sort();
+    let mut hasher = Blake2s256::new();
+    for (name, value) in vars {
+        hasher.update(name.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\0");
+    }
+    // Names cannot be empty, so this cannot be mistaken for a variable.
+    hasher.update(b"\0");
+    hasher.update(content.as_bytes());
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl ParseCache {
+    /// An in-memory cache, not backed by any file.
+    pub fn new() -> Self {
+        ParseCache::default()
+    }
+
+    /// Load the cache at `path`.
+    /// A missing, corrupted or outdated file gives an empty cache, which is
+    /// written to `path` on `save`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut cache = ParseCache {
+            path: Some(path.to_path_buf()),
+            ..Default::default()
+        };
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e),
+        };
+        if let Ok(loaded) = ciborium::from_reader::<CacheFile, _>(BufReader::new(file)) {
+            if loaded.format_version == CACHE_FORMAT_VERSION
+                && loaded.parser_version == env!("CARGO_PKG_VERSION")
+            {
+                cache.entries = Mutex::new(loaded.entries);
+            }
+        }
+
+        Ok(cache)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of lookups answered from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of lookups that had to parse.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Same as `apf::parse`, but answered from the cache if `content` was
+    /// already parsed on top of the same `context`.
+    /// Only successful parses are cached.
+    pub fn parse(&self, content: &str, context: &mut Context) -> Result<(), ParseError> {
+        let key = cache_key(content, context);
+        self.used.lock().unwrap().insert(key.clone());
+        if let Some(cached) = self.entries.lock().unwrap().get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            *context = cached.clone();
+            return Ok(());
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        apf::parse(content, context)?;
+        self.entries.lock().unwrap().insert(key, context.clone());
+
+        Ok(())
+    }
+
+    /// Drop entries not looked up since the cache was opened, i.e: files that
+    /// changed or were removed from the tree since the last scan.
+    pub fn prune(&mut self) {
+        let used = self.used.get_mut().unwrap();
+        self.entries
+            .get_mut()
+            .unwrap()
+            .retain(|key, _| used.contains(key));
+    }
+
+    /// Write the cache back to where it was opened from.
+    pub fn save(&self) -> io::Result<()> {
+        let path = self.path.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "Cache was not opened from a path")
+        })?;
+        let file = CacheFile {
+            format_version: CACHE_FORMAT_VERSION,
+            parser_version: env!("CARGO_PKG_VERSION").to_string(),
+            entries: self.entries.lock().unwrap().clone(),
+        };
+        // Write to a temporary file first so an interrupted save cannot leave a
+        // truncated cache behind.
+        let tmp = path.with_extension("tmp");
+        let writer = BufWriter::new(fs::File::create(&tmp)?);
+        ciborium::into_writer(&file, writer).map_err(io::Error::other)?;
+
+        fs::rename(tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.cbor");
+        let cache = ParseCache::open(&path).unwrap();
+        assert!(cache.is_empty());
+
+        let mut spec = Context::new();
+        cache.parse("VER=1.0\n", &mut spec).unwrap();
+        let mut defines = spec.clone();
+        cache.parse("PKGDES=\"Foo $VER\"\n", &mut defines).unwrap();
+        assert!(cache.parse("A=1 | cat\n", &mut Context::new()).is_err());
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (0, 3, 2));
+        cache.save().unwrap();
+
+        let mut cache = ParseCache::open(&path).unwrap();
+        assert_eq!(cache.len(), 2);
+        let mut context = Context::new();
+        cache.parse("VER=1.0\n", &mut context).unwrap();
+        cache.parse("PKGDES=\"Foo $VER\"\n", &mut context).unwrap();
+        assert_eq!(context, defines);
+        // Same content, different variables in scope.
+        let mut context = Context::new();
+        context.insert("VER".to_string(), "2.0".to_string());
+        cache.parse("PKGDES=\"Foo $VER\"\n", &mut context).unwrap();
+        assert_eq!(context["PKGDES"], "Foo 2.0");
+        assert_eq!((cache.hits(), cache.misses()), (2, 1));
+
+        cache.prune();
+        assert_eq!(cache.len(), 3);
+        fs::write(&path, b"garbage").unwrap();
+        assert!(ParseCache::open(&path).unwrap().is_empty());
+        assert!(ParseCache::new().save().is_err());
+    }
+}
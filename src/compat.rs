@@ -0,0 +1,314 @@
+//! Cross-checking the parser against bash.
+//! A file is evaluated both by `apf` and by a restricted bash; any variable the
+//! two disagree on is reported, so metadata extracted from complex specs can be
+//! trusted (or not).
+
+use crate::apf::{self, Context, ParseError};
+use std::{
+    collections::{BTreeSet, HashSet},
+    env, fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long bash may run before it is killed.
+const BASH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Separates whatever the evaluated file printed from the variable dump.
+/// Bash strings cannot contain NUL, so the file cannot fake it by accident.
+const DUMP_MARKER: &[u8] = b"\0ABBS-DUMP\0";
+
+/// The file is read from stdin and evaluated in restricted mode: no `cd`, no
+/// commands with `/` in their names, no changes to `PATH`, and stdout of the
+/// file itself is discarded. Only the variables it defines are then dumped as
+/// NUL-separated `name value` pairs to the original stdout.
+const BASH_SCRIPT: &str = r#"
+IFS= read -r -d '' __abbs_src
+declare -A __abbs_seen
+# Collected inside a function so that FUNCNAME and friends count as seen.
+__abbs_init() {
+    for __abbs_v in $(compgen -v); do __abbs_seen[$__abbs_v]=1; done
+}
+__abbs_init
+__abbs_eval() { eval "$__abbs_src"; } 3>&-
+__abbs_dump() {
+    printf '\0ABBS-DUMP\0'
+    for __abbs_v in $(compgen -v); do
+        [[ -n ${__abbs_seen[$__abbs_v]} || $__abbs_v == __abbs_* ]] && continue
+        printf '%s\0%s\0' "$__abbs_v" "${!__abbs_v}"
+    done
+} >&3
+exec 3>&1 >/dev/null
+set -r
+__abbs_eval
+__abbs_dump
+"#;
+
+/// A variable the parser and bash disagree on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// Defined by the parser only.
+    OnlyInParser { name: String, value: String },
+    /// Defined by bash only.
+    OnlyInBash { name: String, value: String },
+    ValueMismatch {
+        name: String,
+        parser_value: String,
+        bash_value: String,
+    },
+}
+
+impl Divergence {
+    pub fn name(&self) -> &str {
+        match self {
+            Divergence::OnlyInParser { name, .. }
+            | Divergence::OnlyInBash { name, .. }
+            | Divergence::ValueMismatch { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::OnlyInParser { name, value } => {
+                write!(f, "{} is only defined by the parser, as `{}`", name, value)
+            }
+            Divergence::OnlyInBash { name, value } => {
+                write!(f, "{} is only defined by bash, as `{}`", name, value)
+            }
+            Divergence::ValueMismatch {
+                name,
+                parser_value,
+                bash_value,
+            } => write!(
+                f,
+                "{} is `{}` according to the parser, but `{}` according to bash",
+                name, parser_value, bash_value
+            ),
+        }
+    }
+}
+
+/// Outcome of `compat_check`.
+#[derive(Debug)]
+pub struct CompatReport {
+    pub path: PathBuf,
+    /// Set if the parser rejected the file. Variables defined before the error
+    /// are still compared.
+    pub parse_error: Option<ParseError>,
+    /// Why bash could not be run, i.e: it is not installed or timed out.
+    /// No divergences are reported in this case.
+    pub bash_error: Option<String>,
+    /// Anything bash printed to stderr, i.e: `command not found`.
+    pub bash_messages: Vec<String>,
+    /// Sorted by variable name.
+    pub divergences: Vec<Divergence>,
+}
+
+impl CompatReport {
+    /// Whether the parser and bash fully agree on the file.
+    pub fn is_compatible(&self) -> bool {
+        self.parse_error.is_none() && self.bash_error.is_none() && self.divergences.is_empty()
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        if let Some(e) = &self.parse_error {
+            writeln!(f, "{}: {}", path, e)?;
+        }
+        if let Some(e) = &self.bash_error {
+            writeln!(f, "{}: bash not run: {}", path, e)?;
+        }
+        for m in self.bash_messages.iter() {
+            writeln!(f, "{}: bash: {}", path, m)?;
+        }
+        for d in self.divergences.iter() {
+            writeln!(f, "{}: {}", path, d)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare two sets of variables, sorted by name.
+pub fn diff_contexts(parser: &Context, bash: &Context) -> Vec<Divergence> {
+    let names: BTreeSet<_> = parser.keys().chain(bash.keys()).collect();
+    names
+        .into_iter()
+        .filter_map(|name| match (parser.get(name), bash.get(name)) {
+            (Some(p), Some(b)) if p != b => Some(Divergence::ValueMismatch {
+                name: name.clone(),
+                parser_value: p.clone(),
+                bash_value: b.clone(),
+            }),
+            (Some(value), None) => Some(Divergence::OnlyInParser {
+                name: name.clone(),
+                value: value.clone(),
+            }),
+            (None, Some(value)) => Some(Divergence::OnlyInBash {
+                name: name.clone(),
+                value: value.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn find_bash() -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join("bash"))
+        .find(|p| p.is_file())
+}
+
+/// Evaluate `content` with bash, returning the variables it defines and
+/// whatever it printed to stderr.
+fn eval_bash(content: &str) -> Result<(Context, Vec<String>), String> {
+    let bash = find_bash().ok_or_else(|| "bash not found in PATH".to_string())?;
+    let workdir = env::temp_dir();
+    let mut child = Command::new(bash)
+        .args(["--noprofile", "--norc", "-c", BASH_SCRIPT])
+        .env_clear()
+        .env("PATH", "/nonexistent")
+        .current_dir(workdir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let out_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+    let err_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+    {
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // bash may exit before reading everything, which is not our problem.
+        let _ = stdin.write_all(content.as_bytes());
+    }
+
+    let deadline = Instant::now() + BASH_TIMEOUT;
+    loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(_) => break,
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {}s", BASH_TIMEOUT.as_secs()));
+            }
+            None => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+
+    let out = out_reader
+        .join()
+        .map_err(|_| "stdout reader panicked".to_string())?
+        .map_err(|e| e.to_string())?;
+    let err = err_reader
+        .join()
+        .map_err(|_| "stderr reader panicked".to_string())?
+        .map_err(|e| e.to_string())?;
+
+    let start = out
+        .windows(DUMP_MARKER.len())
+        .rposition(|w| w == DUMP_MARKER)
+        .ok_or_else(|| "bash exited before defining anything".to_string())?;
+    let mut fields = out[start + DUMP_MARKER.len()..]
+        .split(|b| *b == 0)
+        .map(|s| String::from_utf8_lossy(s).into_owned());
+    let mut context = Context::new();
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        context.insert(name, value);
+    }
+    let messages = String::from_utf8_lossy(&err)
+        .lines()
+        .map(|l| l.to_string())
+        .collect();
+
+    Ok((context, messages))
+}
+
+/// Evaluate the file at `path` with both the parser and bash and report where
+/// they disagree. Only fails if the file cannot be read.
+pub fn compat_check<P: AsRef<Path>>(path: P) -> io::Result<CompatReport> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)?;
+
+    let mut parsed = Context::new();
+    let parse_error = apf::parse(&content, &mut parsed).err();
+
+    let mut report = CompatReport {
+        path: path.to_path_buf(),
+        parse_error,
+        bash_error: None,
+        bash_messages: Vec::new(),
+        divergences: Vec::new(),
+    };
+    match eval_bash(&content) {
+        Ok((evaluated, messages)) => {
+            report.bash_messages = messages;
+            report.divergences = diff_contexts(&parsed, &evaluated);
+            // A parse error already says the parser gave up half-way; variables
+            // it never reached are not worth repeating.
+            if report.parse_error.is_some() {
+                let reached: HashSet<_> = parsed.keys().map(|k| k.as_str()).collect();
+                report.divergences.retain(|d| reached.contains(d.name()));
+            }
+        }
+        Err(e) => report.bash_error = Some(e),
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(pairs: &[(&str, &str)]) -> Context {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let parser = context(&[("A", "1"), ("B", "2"), ("C", "3")]);
+        let bash = context(&[("A", "1"), ("B", "two"), ("D", "4")]);
+        let diff = diff_contexts(&parser, &bash);
+        let names: Vec<_> = diff.iter().map(|d| d.name()).collect();
+        assert_eq!(names, vec!["B", "C", "D"]);
+        assert!(matches!(diff[0], Divergence::ValueMismatch { .. }));
+        assert!(matches!(diff[1], Divergence::OnlyInParser { .. }));
+        assert!(matches!(diff[2], Divergence::OnlyInBash { .. }));
+    }
+
+    #[test]
+    fn test_compat_check() {
+        if find_bash().is_none() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("defines");
+        fs::write(&path, "VER=1.2.3\nPKGDES=\"Foo ${VER:0:3}\"\n").unwrap();
+        let report = compat_check(&path).unwrap();
+        assert!(report.is_compatible(), "{}", report);
+
+        fs::write(&path, "VER=1.2.3\nPKGDES=\"Foo $VER\"\necho hi\n/bin/true\nB=1\n").unwrap();
+        let report = compat_check(&path).unwrap();
+        assert!(report.parse_error.is_some());
+        assert!(report.divergences.is_empty());
+        assert!(!report.bash_messages.is_empty());
+    }
+}
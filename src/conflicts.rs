@@ -0,0 +1,258 @@
+//! Breaks and replaces between packages of a tree.
+//! `PKGBREAK` lists packages which stop working once this one is installed,
+//! `PKGREP` packages whose files this one takes over. Both are checked
+//! against the dependencies declared by the rest of the tree.
+
+use crate::{
+    apf::Context,
+    dependency::{parse_dependencies, Dependency, DependencyError},
+    deps::GraphError,
+    package::{resolve_arch_fields, Package},
+    version::Version,
+};
+use std::{collections::HashMap, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Relation {
+    /// From PKGBREAK.
+    Breaks,
+    /// From PKGREP.
+    Replaces,
+}
+
+impl Relation {
+    pub fn field(&self) -> &'static str {
+        match self {
+            Relation::Breaks => "PKGBREAK",
+            Relation::Replaces => "PKGREP",
+        }
+    }
+}
+
+/// Relationship fields of a package.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Relationships {
+    /// From PKGDEP.
+    pub depends: Vec<Dependency>,
+    /// From PKGBREAK.
+    pub breaks: Vec<Dependency>,
+    /// From PKGREP.
+    pub replaces: Vec<Dependency>,
+}
+
+impl Relationships {
+    /// Parse the relationship fields of a (resolved) context. Missing fields
+    /// are empty.
+    pub fn from_context(context: &Context) -> Result<Self, DependencyError> {
+        let field = |key: &str| match context.get(key) {
+            Some(value) => parse_dependencies(value),
+            None => Ok(Vec::new()),
+        };
+        Ok(Relationships {
+            depends: field("PKGDEP")?,
+            breaks: field(Relation::Breaks.field())?,
+            replaces: field(Relation::Replaces.field())?,
+        })
+    }
+
+    pub fn get(&self, relation: Relation) -> &[Dependency] {
+        match relation {
+            Relation::Breaks => &self.breaks,
+            Relation::Replaces => &self.replaces,
+        }
+    }
+
+    fn depends_on(&self, name: &str) -> bool {
+        self.depends.iter().any(|d| d.name == name)
+    }
+}
+
+/// A break or replace contradicting a dependency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Package declaring the relationship.
+    pub package: String,
+    pub relation: Relation,
+    /// The entry of `relation` at fault, i.e: `foo<=1.0`.
+    pub target: Dependency,
+    /// Package whose dependencies contradict it, possibly `package` itself.
+    pub dependent: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.relation {
+            Relation::Breaks if self.dependent == self.package => write!(
+                f,
+                "{} depends on {}, but breaks {}",
+                self.package, self.target.name, self.target
+            ),
+            Relation::Breaks => write!(
+                f,
+                "{} depends on both {} and {}, but {} breaks {}",
+                self.dependent, self.package, self.target.name, self.package, self.target
+            ),
+            Relation::Replaces => write!(
+                f,
+                "{} depends on {}, but {} replaces every version of it",
+                self.dependent, self.target.name, self.package
+            ),
+        }
+    }
+}
+
+struct Unit<'a> {
+    name: &'a str,
+    version: Option<Version>,
+    relationships: Relationships,
+}
+
+/// Check the breaks and replaces of `packages` against their dependencies,
+/// with the overrides for `arch` applied if given:
+/// - If `a` breaks the version of `b` in the tree, nothing may depend on both
+///   `a` and `b`, `a` included. Breaks of packages outside of `packages`
+///   are ignored.
+/// - If `a` replaces every version of `b`, nothing but `b` itself may depend
+///   on `b` anymore.
+///
+/// Conflicts are sorted by package, then dependent.
+pub fn find_conflicts(packages: &[Package], arch: Option<&str>) -> Result<Vec<Conflict>, GraphError> {
+    let mut units = Vec::new();
+    for package in packages {
+        let mut contexts = Vec::new();
+        if package.subpackages().is_empty() {
+            contexts.push((package.name(), package.fields()));
+        }
+        for sub in package.subpackages() {
+            contexts.push((sub.name(), sub.fields()));
+        }
+        for (name, fields) in contexts {
+            let resolved;
+            let fields = match arch {
+                Some(arch) => {
+                    resolved = resolve_arch_fields(fields, arch);
+                    &resolved
+                }
+                None => fields,
+            };
+            let relationships = Relationships::from_context(fields)
+                .map_err(|e| GraphError::BadDependency(name.to_string(), e))?;
+            units.push(Unit {
+                name,
+                version: Version::from_context(fields).ok(),
+                relationships,
+            });
+        }
+    }
+    let by_name: HashMap<_, _> = units.iter().map(|u| (u.name, u)).collect();
+
+    let mut conflicts = Vec::new();
+    for unit in units.iter() {
+        for target in unit.relationships.breaks.iter() {
+            let broken = match (by_name.get(target.name.as_str()), &target.version_req) {
+                (None, _) => false,
+                (Some(_), None) => true,
+                (Some(other), Some(req)) => other.version.as_ref().is_some_and(|v| req.matches(v)),
+            };
+            if !broken {
+                continue;
+            }
+            for dependent in units.iter() {
+                let deps = &dependent.relationships;
+                if (dependent.name == unit.name || deps.depends_on(unit.name))
+                    && deps.depends_on(&target.name)
+                {
+                    conflicts.push(Conflict {
+                        package: unit.name.to_string(),
+                        relation: Relation::Breaks,
+                        target: target.clone(),
+                        dependent: dependent.name.to_string(),
+                    });
+                }
+            }
+        }
+
+        for target in unit.relationships.replaces.iter() {
+            if target.version_req.is_some() {
+                continue;
+            }
+            for dependent in units.iter() {
+                if dependent.name != target.name && dependent.relationships.depends_on(&target.name) {
+                    conflicts.push(Conflict {
+                        package: unit.name.to_string(),
+                        relation: Relation::Replaces,
+                        target: target.clone(),
+                        dependent: dependent.name.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    conflicts.sort_by(|a, b| (&a.package, &a.dependent).cmp(&(&b.package, &b.dependent)));
+    Ok(conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::package;
+
+    #[test]
+    fn test_relationships() {
+        let fields = [("PKGBREAK", "foo<=1.0 bar"), ("PKGREP", "foo<=1.0")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let relationships = Relationships::from_context(&fields).unwrap();
+        assert!(relationships.depends.is_empty());
+        assert_eq!(relationships.get(Relation::Breaks).len(), 2);
+        assert_eq!(relationships.replaces[0].to_string(), "foo<=1.0");
+
+        let fields = vec![("PKGREP".to_string(), "foo<1.0".to_string())].into_iter().collect();
+        assert!(Relationships::from_context(&fields).is_err());
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let packages = [
+            // Split package: foo-data takes over files of older foo.
+            package("foo", &[("VER", "2.0"), ("PKGDEP", "foo-data")]),
+            package(
+                "foo-data",
+                &[("VER", "2.0"), ("PKGBREAK", "foo<=1.0"), ("PKGREP", "foo<=1.0")],
+            ),
+            // Breaks the bar in the tree, yet baz needs both.
+            package("bar", &[("VER", "1.0")]),
+            package("newbar", &[("VER", "1.0"), ("PKGBREAK", "bar<=1.0")]),
+            package("baz", &[("VER", "1.0"), ("PKGDEP", "newbar bar>=1.0")]),
+            // Replaces every oldtool, which qux still needs.
+            package("oldtool", &[("VER", "1.0")]),
+            package("tool", &[("VER", "1.0"), ("PKGREP", "oldtool"), ("PKGDEP", "oldtool")]),
+            package("qux", &[("VER", "1.0"), ("PKGDEP", "oldtool")]),
+        ];
+        let conflicts = find_conflicts(&packages, None).unwrap();
+        let messages: Vec<_> = conflicts.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "baz depends on both newbar and bar, but newbar breaks bar<=1.0",
+                "qux depends on oldtool, but tool replaces every version of it",
+                "tool depends on oldtool, but tool replaces every version of it",
+            ]
+        );
+
+        let selfish = package("selfish", &[("PKGDEP", "bar"), ("PKGBREAK", "bar")]);
+        assert!(find_conflicts(std::slice::from_ref(&selfish), None).unwrap().is_empty());
+        let conflicts = find_conflicts(&[selfish, package("bar", &[])], None).unwrap();
+        assert_eq!(conflicts[0].to_string(), "selfish depends on bar, but breaks bar");
+
+        let packages = [
+            package("newbar", &[("PKGBREAK__AMD64", "bar")]),
+            package("baz", &[("PKGDEP", "newbar bar")]),
+            package("bar", &[]),
+        ];
+        assert!(find_conflicts(&packages, None).unwrap().is_empty());
+        assert_eq!(find_conflicts(&packages, Some("amd64")).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,183 @@
+//! Typed model of ABBS relationship fields (PKGDEP, BUILDDEP, PKGBREAK, ...).
+//! i.e: `PKGDEP="glibc>=2.31 gcc-runtime:amd64 python-3==3.8.2"`
+
+pub use crate::version::{Comparator, VersionReq};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// A single entry of a relationship field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dependency {
+    pub name: String,
+    pub version_req: Option<VersionReq>,
+    pub arch_qualifier: Option<String>,
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if let Some(arch) = &self.arch_qualifier {
+            write!(f, ":{}", arch)?;
+        }
+        if let Some(req) = &self.version_req {
+            write!(f, "{}", req)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DependencyError {
+    InvalidName(String),
+    InvalidArch(String),
+    InvalidVersion(String),
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (err_type, entry) = match self {
+            DependencyError::InvalidName(e) => ("Invalid package name", e),
+            DependencyError::InvalidArch(e) => ("Invalid architecture qualifier", e),
+            DependencyError::InvalidVersion(e) => ("Invalid version requirement", e),
+        };
+
+        write!(f, "{} in dependency `{}`.", err_type, entry)
+    }
+}
+
+impl std::error::Error for DependencyError {}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-._".contains(c))
+}
+
+impl FromStr for Dependency {
+    type Err = DependencyError;
+
+    /// Parse one entry in the form of `name[:arch][(>=|<=|==)version]`.
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        let (spec, version_req) = match entry.find(['>', '<', '=']) {
+            Some(idx) => {
+                let (spec, req) = entry.split_at(idx);
+                let op = match req.get(..2) {
+                    Some(">=") => Comparator::GreaterOrEqual,
+                    Some("<=") => Comparator::LessOrEqual,
+                    Some("==") => Comparator::Equal,
+                    _ => return Err(DependencyError::InvalidVersion(entry.to_string())),
+                };
+                let version = req[2..]
+                    .parse()
+                    .map_err(|_| DependencyError::InvalidVersion(entry.to_string()))?;
+                (spec, Some(VersionReq { op, version }))
+            }
+            None => (entry, None),
+        };
+
+        let (name, arch_qualifier) = match spec.split_once(':') {
+            Some((name, arch)) => {
+                if arch.is_empty() || !arch.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err(DependencyError::InvalidArch(entry.to_string()));
+                }
+                (name, Some(arch.to_string()))
+            }
+            None => (spec, None),
+        };
+
+        if !is_valid_name(name) {
+            return Err(DependencyError::InvalidName(entry.to_string()));
+        }
+
+        Ok(Dependency {
+            name: name.to_string(),
+            version_req,
+            arch_qualifier,
+        })
+    }
+}
+
+/// Parse the value of a relationship field into a list of dependencies.
+pub fn parse_dependencies(value: &str) -> Result<Vec<Dependency>, DependencyError> {
+    value.split_whitespace().map(str::parse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ok() {
+        let deps = parse_dependencies("  glibc>=2.31 gcc-runtime:amd64\n python-3==3.8.2 libfoo+bar<=1:2.0-1 ").unwrap();
+        assert_eq!(
+            deps,
+            vec![
+                Dependency {
+                    name: "glibc".to_string(),
+                    version_req: Some(VersionReq {
+                        op: Comparator::GreaterOrEqual,
+                        version: "2.31".parse().unwrap()
+                    }),
+                    arch_qualifier: None,
+                },
+                Dependency {
+                    name: "gcc-runtime".to_string(),
+                    version_req: None,
+                    arch_qualifier: Some("amd64".to_string()),
+                },
+                Dependency {
+                    name: "python-3".to_string(),
+                    version_req: Some(VersionReq {
+                        op: Comparator::Equal,
+                        version: "3.8.2".parse().unwrap()
+                    }),
+                    arch_qualifier: None,
+                },
+                Dependency {
+                    name: "libfoo+bar".to_string(),
+                    version_req: Some(VersionReq {
+                        op: Comparator::LessOrEqual,
+                        version: "1:2.0-1".parse().unwrap()
+                    }),
+                    arch_qualifier: None,
+                },
+            ]
+        );
+        assert_eq!(parse_dependencies("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cases = vec!["glibc>=2.31", "gcc-runtime:amd64", "foo:arm64==1.0"];
+        for c in cases {
+            assert_eq!(c.parse::<Dependency>().unwrap().to_string(), c);
+        }
+    }
+
+    #[test]
+    fn test_parse_bad() {
+        let cases = vec!["foo>1.0", "foo=1.0", "foo>=", ">=1.0", "foo:>=1.0", "foo>=1<=2", "f$o"];
+        for c in cases {
+            assert_eq!(c.parse::<Dependency>().is_ok(), false);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let dep: Dependency = "glibc:amd64>=1:2.31-1".parse().unwrap();
+        let json = serde_json::to_string(&dep).unwrap();
+        assert_eq!(
+            json,
+            r#"{"name":"glibc","version_req":{"op":">=","version":"1:2.31-1"},"arch_qualifier":"amd64"}"#
+        );
+        assert_eq!(serde_json::from_str::<Dependency>(&json).unwrap(), dep);
+        assert!(serde_json::from_str::<Dependency>(
+            r#"{"name":"a","version_req":{"op":">=","version":"x"},"arch_qualifier":null}"#
+        )
+        .is_err());
+    }
+}
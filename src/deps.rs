@@ -0,0 +1,1326 @@
+//! Dependency graph over the packages of a tree, built from PKGDEP and BUILDDEP.
+//! An edge `a -> b` means `a` depends on `b`.
+
+use crate::{
+    dependency::{parse_dependencies, DependencyError},
+    package::{resolve_arch_fields, Package},
+    package_set::PackageSet,
+    plan::RebuildPlan,
+    section::Section,
+};
+use petgraph::{
+    algo,
+    graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+    Direction,
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt, io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum DepKind {
+    /// From PKGDEP.
+    Runtime,
+    /// From BUILDDEP.
+    Build,
+}
+
+impl DepKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DepKind::Runtime => "runtime",
+            DepKind::Build => "build",
+        }
+    }
+
+    pub fn field(&self) -> &'static str {
+        match self {
+            DepKind::Runtime => "PKGDEP",
+            DepKind::Build => "BUILDDEP",
+        }
+    }
+}
+
+/// Components with more dependencies than this get a greedy, possibly
+/// larger, set of dependencies to break instead of an exhaustive search.
+const MAX_EXACT_CYCLE_EDGES: usize = 16;
+
+/// A dependency inside a cycle, `from` depending on `to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CycleEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: DepKind,
+}
+
+impl fmt::Display for CycleEdge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} ({})", self.from, self.to, self.kind.field())
+    }
+}
+
+/// A set of packages depending on each other, returned by
+/// `DependencyGraph::explain_cycles`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CycleExplanation {
+    /// Members of the strongly-connected component, sorted by name.
+    pub packages: Vec<String>,
+    /// Every dependency between members, sorted.
+    pub edges: Vec<CycleEdge>,
+    /// Fewest dependencies to drop so that no cycle is left. Among equally
+    /// small sets, the one with the most BUILDDEP is picked, as those can be
+    /// dropped for a bootstrap build.
+    pub break_edges: Vec<CycleEdge>,
+}
+
+/// A build of a bootstrap plan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct BootstrapStep {
+    pub package: String,
+    /// Whether this is a build without the dependencies in
+    /// `BootstrapPlan::broken`.
+    pub stage1: bool,
+}
+
+/// Returned by `DependencyGraph::bootstrap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct BootstrapPlan {
+    /// Every package to build, seeds included, sorted.
+    pub packages: Vec<String>,
+    /// Packages built twice, first without some of their dependencies.
+    pub stage1: Vec<String>,
+    /// Dependencies left out of stage-1 builds.
+    pub broken: Vec<CycleEdge>,
+    /// Builds in order.
+    pub steps: Vec<BootstrapStep>,
+}
+
+/// Whether the graph of `n` nodes and the `edges` not `removed` has no cycle.
+fn is_acyclic(n: usize, edges: &[(usize, usize, DepKind)], removed: &[bool]) -> bool {
+    let mut in_degree = vec![0; n];
+    for (i, (_, to, _)) in edges.iter().enumerate() {
+        if !removed[i] {
+            in_degree[*to] += 1;
+        }
+    }
+    let mut ready: Vec<_> = (0..n).filter(|&v| in_degree[v] == 0).collect();
+    let mut visited = 0;
+    while let Some(v) = ready.pop() {
+        visited += 1;
+        for (i, (from, to, _)) in edges.iter().enumerate() {
+            if !removed[i] && *from == v {
+                in_degree[*to] -= 1;
+                if in_degree[*to] == 0 {
+                    ready.push(*to);
+                }
+            }
+        }
+    }
+    visited == n
+}
+
+/// Indices of the edges of some cycle among the `edges` not `removed`.
+fn find_local_cycle(n: usize, edges: &[(usize, usize, DepKind)], removed: &[bool]) -> Option<Vec<usize>> {
+    // 0: unvisited, 1: on the current path, 2: done.
+    let mut state = vec![0u8; n];
+    let mut path: Vec<usize> = Vec::new();
+
+    fn visit(
+        v: usize,
+        edges: &[(usize, usize, DepKind)],
+        removed: &[bool],
+        state: &mut [u8],
+        path: &mut Vec<usize>,
+    ) -> Option<Vec<usize>> {
+        state[v] = 1;
+        for (i, (from, to, _)) in edges.iter().enumerate() {
+            if removed[i] || *from != v {
+                continue;
+            }
+            path.push(i);
+            if state[*to] == 1 {
+                let start = path.iter().position(|&e| edges[e].0 == *to).unwrap();
+                return Some(path[start..].to_vec());
+            }
+            if state[*to] == 0 {
+                if let Some(cycle) = visit(*to, edges, removed, state, path) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+        }
+        state[v] = 2;
+        None
+    }
+
+    (0..n).find_map(|v| {
+        if state[v] == 0 {
+            visit(v, edges, removed, &mut state, &mut path)
+        } else {
+            None
+        }
+    })
+}
+
+/// Edges to remove so that no cycle is left, see `CycleExplanation`. `None`
+/// if `cancel` was set before it was found.
+fn break_set(
+    n: usize,
+    edges: &[(usize, usize, DepKind)],
+    cancel: &AtomicBool,
+) -> Option<Vec<usize>> {
+    let count_build = |set: &[usize]| set.iter().filter(|&&i| edges[i].2 == DepKind::Build).count();
+    if edges.len() <= MAX_EXACT_CYCLE_EDGES {
+        for k in 1..=edges.len() {
+            let mut best: Option<Vec<usize>> = None;
+            // Every combination of `k` edges, in lexicographic order.
+            let mut set: Vec<usize> = (0..k).collect();
+            loop {
+                if cancel.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let mut removed = vec![false; edges.len()];
+                for &i in set.iter() {
+                    removed[i] = true;
+                }
+                if is_acyclic(n, edges, &removed)
+                    && best.as_ref().is_none_or(|b| count_build(&set) > count_build(b))
+                {
+                    best = Some(set.clone());
+                }
+                match (0..k).rev().find(|&i| set[i] < edges.len() - k + i) {
+                    Some(i) => {
+                        set[i] += 1;
+                        for j in i + 1..k {
+                            set[j] = set[j - 1] + 1;
+                        }
+                    }
+                    None => break,
+                }
+            }
+            if best.is_some() {
+                return best;
+            }
+        }
+        return Some(Vec::new());
+    }
+
+    let mut removed = vec![false; edges.len()];
+    while let Some(cycle) = find_local_cycle(n, edges, &removed) {
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        let edge = cycle
+            .iter()
+            .copied()
+            .find(|&i| edges[i].2 == DepKind::Build)
+            .unwrap_or(cycle[0]);
+        removed[edge] = true;
+    }
+    Some((0..edges.len()).filter(|&i| removed[i]).collect())
+}
+
+/// Which dependencies `DependencyGraph::rebuild_set` follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Follow {
+    /// Only PKGDEP.
+    Runtime,
+    /// Only BUILDDEP.
+    Build,
+    #[default]
+    Both,
+}
+
+impl Follow {
+    pub(crate) fn allows(&self, kind: DepKind) -> bool {
+        match self {
+            Follow::Runtime => kind == DepKind::Runtime,
+            Follow::Build => kind == DepKind::Build,
+            Follow::Both => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GraphError {
+    BadDependency(String, DependencyError),
+    /// A dependency cycle, as a path starting and ending with the same package.
+    Cycle(Vec<String>),
+    /// The cancellation flag was set before the analysis finished.
+    Cancelled,
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::BadDependency(p, e) => write!(f, "Bad dependency in {}: {}", p, e),
+            GraphError::Cycle(path) => write!(f, "Dependency cycle: {}", path.join(" -> ")),
+            GraphError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    graph: DiGraph<String, DepKind>,
+    nodes: HashMap<String, NodeIndex>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        DependencyGraph::default()
+    }
+
+    /// Build the graph of `packages`.
+    /// If `arch` is given, architecture-specific overrides for it are applied
+    /// first. Packages with sub-packages contribute one node per sub-package.
+    /// Dependencies outside of `packages` are kept as leaf nodes.
+    pub fn from_packages(packages: &[Package], arch: Option<&str>) -> Result<Self, GraphError> {
+        let mut units = Vec::new();
+        for package in packages {
+            if package.subpackages().is_empty() {
+                units.push((package.name(), package.fields()));
+            }
+            for sub in package.subpackages() {
+                units.push((sub.name(), sub.fields()));
+            }
+        }
+
+        let mut graph = DependencyGraph::new();
+        for (name, _) in units.iter() {
+            graph.node(name);
+        }
+        for (name, fields) in units {
+            let resolved;
+            let fields = match arch {
+                Some(arch) => {
+                    resolved = resolve_arch_fields(fields, arch);
+                    &resolved
+                }
+                None => fields,
+            };
+            for kind in [DepKind::Runtime, DepKind::Build].iter() {
+                let value = match fields.get(kind.field()) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let deps = parse_dependencies(value)
+                    .map_err(|e| GraphError::BadDependency(name.to_string(), e))?;
+                for dep in deps {
+                    graph.add_dependency(name, &dep.name, *kind);
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+
+    fn node(&mut self, name: &str) -> NodeIndex {
+        if let Some(idx) = self.nodes.get(name) {
+            return *idx;
+        }
+        let idx = self.graph.add_node(name.to_string());
+        self.nodes.insert(name.to_string(), idx);
+        idx
+    }
+
+    /// Record that `from` depends on `to`.
+    pub fn add_dependency(&mut self, from: &str, to: &str, kind: DepKind) {
+        let from = self.node(from);
+        let to = self.node(to);
+        if !self
+            .graph
+            .edges_connecting(from, to)
+            .any(|e| *e.weight() == kind)
+        {
+            self.graph.add_edge(from, to, kind);
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.nodes.contains_key(name)
+    }
+
+    pub fn packages(&self) -> impl Iterator<Item = &str> {
+        self.graph.node_weights().map(|n| n.as_str())
+    }
+
+    /// Direct dependencies of `name`, sorted by name.
+    pub fn dependencies(&self, name: &str) -> Vec<(&str, DepKind)> {
+        let idx = match self.nodes.get(name) {
+            Some(idx) => *idx,
+            None => return Vec::new(),
+        };
+        let mut deps: Vec<_> = self
+            .graph
+            .edges_directed(idx, Direction::Outgoing)
+            .map(|e| (self.graph[e.target()].as_str(), *e.weight()))
+            .collect();
+        deps.sort();
+        deps
+    }
+
+    /// Packages in build order: every package comes after its dependencies.
+    pub fn build_order(&self) -> Result<Vec<&str>, GraphError> {
+        match algo::toposort(&self.graph, None) {
+            Ok(order) => Ok(order
+                .into_iter()
+                .rev()
+                .map(|idx| self.graph[idx].as_str())
+                .collect()),
+            Err(_) => Err(GraphError::Cycle(self.find_cycle().unwrap_or_default())),
+        }
+    }
+
+    /// Find a dependency cycle, returned as a path starting and ending with the
+    /// same package.
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        for scc in algo::tarjan_scc(&self.graph) {
+            let start = scc[0];
+            if scc.len() == 1 && self.graph.find_edge(start, start).is_none() {
+                continue;
+            }
+            let members: BTreeSet<_> = scc.iter().copied().collect();
+            // Shortest path from start back to itself, staying inside the component.
+            let mut prev = HashMap::new();
+            let mut queue = VecDeque::new();
+            queue.push_back(start);
+            while let Some(idx) = queue.pop_front() {
+                for next in self.graph.neighbors_directed(idx, Direction::Outgoing) {
+                    if !members.contains(&next) || prev.contains_key(&next) {
+                        continue;
+                    }
+                    prev.insert(next, idx);
+                    if next == start {
+                        queue.clear();
+                        break;
+                    }
+                    queue.push_back(next);
+                }
+            }
+            let mut path = vec![self.graph[start].clone()];
+            let mut cur = prev[&start];
+            while cur != start {
+                path.push(self.graph[cur].clone());
+                cur = prev[&cur];
+            }
+            path.push(self.graph[start].clone());
+            path.reverse();
+            return Some(path);
+        }
+
+        None
+    }
+
+    /// Every dependency cycle, one per strongly-connected component, along with
+    /// the dependencies to drop to untangle it. Sorted by first package.
+    pub fn explain_cycles(&self) -> Vec<CycleExplanation> {
+        self.explain_cycles_cancellable(&AtomicBool::new(false))
+            .expect("Cancelled without a way to cancel")
+    }
+
+    /// Same as `explain_cycles`, giving up with `GraphError::Cancelled` once
+    /// `cancel` is set, i.e: by the request handler of a web service when the
+    /// client goes away.
+    pub fn explain_cycles_cancellable(
+        &self,
+        cancel: &AtomicBool,
+    ) -> Result<Vec<CycleExplanation>, GraphError> {
+        let mut result = Vec::new();
+        for scc in algo::tarjan_scc(&self.graph) {
+            let mut members = scc;
+            members.sort_by(|a, b| self.graph[*a].cmp(&self.graph[*b]));
+            let local: HashMap<_, _> = members.iter().enumerate().map(|(i, idx)| (*idx, i)).collect();
+            let mut edges = Vec::new();
+            for (i, idx) in members.iter().enumerate() {
+                for edge in self.graph.edges_directed(*idx, Direction::Outgoing) {
+                    if let Some(&to) = local.get(&edge.target()) {
+                        edges.push((i, to, *edge.weight()));
+                    }
+                }
+            }
+            if edges.is_empty() {
+                continue;
+            }
+            edges.sort();
+
+            let to_edge = |&(from, to, kind): &(usize, usize, DepKind)| CycleEdge {
+                from: self.graph[members[from]].clone(),
+                to: self.graph[members[to]].clone(),
+                kind,
+            };
+            let break_edges =
+                break_set(members.len(), &edges, cancel).ok_or(GraphError::Cancelled)?;
+            result.push(CycleExplanation {
+                packages: members.iter().map(|idx| self.graph[*idx].clone()).collect(),
+                break_edges: break_edges.iter().map(|&i| to_edge(&edges[i])).collect(),
+                edges: edges.iter().map(to_edge).collect(),
+            });
+        }
+
+        result.sort_by(|a, b| a.packages.cmp(&b.packages));
+        Ok(result)
+    }
+
+    /// Packages that must be rebuilt when any of `packages` change: those
+    /// depending on them through edges allowed by `follow`, up to `depth`
+    /// levels away, or transitively if `None`. The changed packages themselves
+    /// are not included. Sorted by name.
+    pub fn rebuild_set(&self, packages: &[&str], depth: Option<usize>, follow: Follow) -> Vec<&str> {
+        let starts: BTreeSet<_> = packages.iter().filter_map(|n| self.nodes.get(*n)).copied().collect();
+        let mut seen = BTreeSet::new();
+        let mut level: Vec<_> = starts.iter().copied().collect();
+        let mut remaining = depth;
+        while !level.is_empty() && remaining != Some(0) {
+            let mut next = Vec::new();
+            for idx in level {
+                for edge in self.graph.edges_directed(idx, Direction::Incoming) {
+                    let prev = edge.source();
+                    if follow.allows(*edge.weight()) && !starts.contains(&prev) && seen.insert(prev) {
+                        next.push(prev);
+                    }
+                }
+            }
+            level = next;
+            remaining = remaining.map(|d| d - 1);
+        }
+
+        let mut result: Vec<_> = seen.into_iter().map(|idx| self.graph[idx].as_str()).collect();
+        result.sort_unstable();
+        result
+    }
+
+    /// Stages to build `targets` in: every stage only depends on earlier
+    /// ones, so the packages of a stage can be built in parallel.
+    ///
+    /// Both PKGDEP and BUILDDEP count, since both are installed to build a
+    /// package, and so do dependencies through packages which are not
+    /// targets: if `a` depends on `b` which depends on the target `c`, `c`
+    /// is built before `a`. Stages are sorted by name.
+    /// See `rebuild_plan` for the stages as a `RebuildPlan`, i.e: to hand
+    /// over to a batch builder.
+    pub fn build_plan<'a>(&self, targets: &[&'a str]) -> Result<Vec<Vec<&'a str>>, GraphError> {
+        let targets: BTreeSet<_> = targets.iter().copied().collect();
+        // Targets each target waits for.
+        let mut constraints = DependencyGraph::new();
+        let mut waits: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        for target in targets.iter().copied() {
+            constraints.node(target);
+            let blockers = waits.entry(target).or_default();
+            let start = match self.nodes.get(target) {
+                Some(idx) => *idx,
+                None => continue,
+            };
+            let mut seen = BTreeSet::new();
+            let mut queue: VecDeque<_> = self
+                .graph
+                .neighbors_directed(start, Direction::Outgoing)
+                .collect();
+            while let Some(idx) = queue.pop_front() {
+                if !seen.insert(idx) {
+                    continue;
+                }
+                let name = self.graph[idx].as_str();
+                if targets.contains(name) {
+                    blockers.insert(name);
+                    constraints.add_dependency(target, name, DepKind::Build);
+                    continue;
+                }
+                queue.extend(self.graph.neighbors_directed(idx, Direction::Outgoing));
+            }
+        }
+        if let Some(cycle) = constraints.find_cycle() {
+            return Err(GraphError::Cycle(cycle));
+        }
+
+        let mut stages = Vec::new();
+        let mut built = BTreeSet::new();
+        while built.len() < targets.len() {
+            let stage: Vec<_> = targets
+                .iter()
+                .copied()
+                .filter(|t| !built.contains(t) && waits[t].iter().all(|b| built.contains(b)))
+                .collect();
+            built.extend(stage.iter().copied());
+            stages.push(stage);
+        }
+
+        Ok(stages)
+    }
+
+    /// A rebuild plan for `targets` on `archs`, staged by `build_plan`.
+    /// The reasons every target was selected for are kept in the plan.
+    pub fn rebuild_plan(
+        &self,
+        targets: &PackageSet,
+        archs: &[&str],
+        reason: &str,
+    ) -> Result<RebuildPlan, GraphError> {
+        let names: Vec<_> = targets.names().collect();
+        let stages = self
+            .build_plan(&names)?
+            .into_iter()
+            .map(|stage| stage.into_iter().map(|n| n.to_string()).collect())
+            .collect();
+        let mut plan = RebuildPlan::new(
+            names.iter().map(|n| n.to_string()).collect(),
+            archs.iter().map(|a| a.to_string()).collect(),
+            reason,
+        );
+        plan.stages = stages;
+        plan.reasons = targets
+            .iter()
+            .map(|(name, reasons)| (name.clone(), reasons.iter().map(|r| r.to_string()).collect()))
+            .collect();
+
+        Ok(plan)
+    }
+
+    /// How to build `seeds` from nothing, i.e: on a new architecture.
+    ///
+    /// Every package the seeds need is built, following both PKGDEP and
+    /// BUILDDEP as both are installed to build a package. Cycles are broken
+    /// as `explain_cycles` suggests: the packages depending on a dropped
+    /// dependency are first built without it, then rebuilt once everything
+    /// else is built.
+    pub fn bootstrap(&self, seeds: &[&str]) -> BootstrapPlan {
+        self.bootstrap_cancellable(seeds, &AtomicBool::new(false))
+            .expect("Cancelled without a way to cancel")
+    }
+
+    /// Same as `bootstrap`, giving up with `GraphError::Cancelled` once
+    /// `cancel` is set.
+    pub fn bootstrap_cancellable(
+        &self,
+        seeds: &[&str],
+        cancel: &AtomicBool,
+    ) -> Result<BootstrapPlan, GraphError> {
+        let mut needed = BTreeSet::new();
+        let mut queue: VecDeque<_> = seeds
+            .iter()
+            .filter_map(|n| self.nodes.get(*n))
+            .copied()
+            .collect();
+        while let Some(idx) = queue.pop_front() {
+            if needed.insert(idx) {
+                queue.extend(self.graph.neighbors_directed(idx, Direction::Outgoing));
+            }
+        }
+
+        let mut closure = DependencyGraph::new();
+        for idx in needed.iter() {
+            closure.node(&self.graph[*idx]);
+            for edge in self.graph.edges_directed(*idx, Direction::Outgoing) {
+                closure.add_dependency(
+                    &self.graph[*idx],
+                    &self.graph[edge.target()],
+                    *edge.weight(),
+                );
+            }
+        }
+        let broken: Vec<_> = closure
+            .explain_cycles_cancellable(cancel)?
+            .into_iter()
+            .flat_map(|c| c.break_edges)
+            .collect();
+
+        let mut pruned = DependencyGraph::new();
+        for name in closure.packages() {
+            pruned.node(name);
+            for (dep, kind) in closure.dependencies(name) {
+                let is_broken = |e: &CycleEdge| e.from == name && e.to == dep && e.kind == kind;
+                if !broken.iter().any(is_broken) {
+                    pruned.add_dependency(name, dep, kind);
+                }
+            }
+        }
+        let mut packages: Vec<_> = pruned.packages().collect();
+        packages.sort_unstable();
+        let order: Vec<_> = pruned
+            .build_plan(&packages)
+            .expect("Cycles left after breaking them")
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let stage1: BTreeSet<_> = broken.iter().map(|e| e.from.as_str()).collect();
+        let mut steps: Vec<_> = order
+            .iter()
+            .map(|name| BootstrapStep {
+                package: name.to_string(),
+                stage1: stage1.contains(name),
+            })
+            .collect();
+        let rebuilds = order.iter().filter(|n| stage1.contains(*n));
+        steps.extend(rebuilds.map(|name| BootstrapStep {
+            package: name.to_string(),
+            stage1: false,
+        }));
+
+        Ok(BootstrapPlan {
+            packages: packages.iter().map(|n| n.to_string()).collect(),
+            stage1: stage1.iter().map(|n| n.to_string()).collect(),
+            broken,
+            steps,
+        })
+    }
+
+    /// Packages directly depending on `name`, sorted by name.
+    pub fn reverse_dependencies(&self, name: &str) -> Vec<&str> {
+        self.rebuild_set(&[name], Some(1), Follow::Both)
+    }
+
+    /// Packages depending on `name` directly or indirectly, i.e: everything
+    /// that needs a rebuild when `name` changes. Sorted by name.
+    pub fn all_reverse_dependencies(&self, name: &str) -> Vec<&str> {
+        self.rebuild_set(&[name], None, Follow::Both)
+    }
+}
+
+/// Build status of a package, drawn as the color of its node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum BuildStatus {
+    Built,
+    /// Built, but older than its spec.
+    Outdated,
+    Failed,
+    /// Never built.
+    Missing,
+}
+
+impl BuildStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildStatus::Built => "built",
+            BuildStatus::Outdated => "outdated",
+            BuildStatus::Failed => "failed",
+            BuildStatus::Missing => "missing",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            BuildStatus::Built => "palegreen",
+            BuildStatus::Outdated => "khaki",
+            BuildStatus::Failed => "lightcoral",
+            BuildStatus::Missing => "lightgray",
+        }
+    }
+}
+
+/// What to export of a dependency graph, and what to annotate it with.
+#[derive(Debug, Clone, Default)]
+pub struct GraphFilter {
+    /// Packages to export with the dependencies between them, all of them if
+    /// `None`. i.e: the `rebuild_set` of `qt-5`.
+    pub packages: Option<BTreeSet<String>>,
+    pub follow: Follow,
+    /// Sections packages are clustered by.
+    pub sections: HashMap<String, Section>,
+    pub status: HashMap<String, BuildStatus>,
+}
+
+impl GraphFilter {
+    /// Record the sections of `packages` and of their sub-packages.
+    pub fn with_sections(mut self, packages: &[Package]) -> Self {
+        for package in packages {
+            let section = match Section::of(package) {
+                Some(s) => s,
+                None => continue,
+            };
+            for sub in package.subpackages() {
+                self.sections
+                    .insert(sub.name().to_string(), section.clone());
+            }
+            self.sections.insert(package.name().to_string(), section);
+        }
+        self
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        self.packages.as_ref().is_none_or(|p| p.contains(name))
+    }
+}
+
+/// Nodes and edges of `graph` selected by `filter`, sorted.
+fn selected<'a>(
+    graph: &'a DependencyGraph,
+    filter: &GraphFilter,
+) -> (Vec<&'a str>, Vec<(&'a str, &'a str, DepKind)>) {
+    let mut nodes: Vec<_> = graph.packages().filter(|n| filter.allows(n)).collect();
+    nodes.sort_unstable();
+    let mut edges: Vec<_> = graph
+        .graph
+        .edge_references()
+        .map(|e| {
+            let (from, to) = (&graph.graph[e.source()], &graph.graph[e.target()]);
+            (from.as_str(), to.as_str(), *e.weight())
+        })
+        .filter(|(from, to, kind)| {
+            filter.follow.allows(*kind) && filter.allows(from) && filter.allows(to)
+        })
+        .collect();
+    edges.sort_unstable();
+    (nodes, edges)
+}
+
+fn dot_id(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn write_dot_node<W: io::Write>(
+    writer: &mut W,
+    name: &str,
+    filter: &GraphFilter,
+    indent: &str,
+) -> io::Result<()> {
+    match filter.status.get(name) {
+        Some(status) => writeln!(
+            writer,
+            "{}{} [style=filled, fillcolor={}];",
+            indent,
+            dot_id(name),
+            status.color()
+        ),
+        None => writeln!(writer, "{}{};", indent, dot_id(name)),
+    }
+}
+
+/// Write `graph` in the DOT format of Graphviz. Packages with a section in
+/// `filter` are clustered by section, build dependencies are dashed.
+pub fn to_dot<W: io::Write>(
+    writer: &mut W,
+    graph: &DependencyGraph,
+    filter: &GraphFilter,
+) -> io::Result<()> {
+    let (nodes, edges) = selected(graph, filter);
+    writeln!(writer, "digraph dependencies {{")?;
+    let mut clusters: BTreeMap<&Section, Vec<&str>> = BTreeMap::new();
+    for name in nodes {
+        match filter.sections.get(name) {
+            Some(section) => clusters.entry(section).or_default().push(name),
+            None => write_dot_node(writer, name, filter, "    ")?,
+        }
+    }
+    for (section, names) in clusters {
+        writeln!(
+            writer,
+            "    subgraph {} {{",
+            dot_id(&format!("cluster_{}", section))
+        )?;
+        writeln!(writer, "        label={};", dot_id(&section.to_string()))?;
+        for name in names {
+            write_dot_node(writer, name, filter, "        ")?;
+        }
+        writeln!(writer, "    }}")?;
+    }
+    for (from, to, kind) in edges {
+        let style = match kind {
+            DepKind::Runtime => "",
+            DepKind::Build => " [style=dashed]",
+        };
+        writeln!(writer, "    {} -> {}{};", dot_id(from), dot_id(to), style)?;
+    }
+    writeln!(writer, "}}")
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write `graph` in the GraphML format, i.e: for Gephi. Nodes are identified
+/// by package names, and carry `section` and `status` if `filter` has them.
+/// Edges carry their `kind`, `runtime` or `build`.
+pub fn to_graphml<W: io::Write>(
+    writer: &mut W,
+    graph: &DependencyGraph,
+    filter: &GraphFilter,
+) -> io::Result<()> {
+    let (nodes, edges) = selected(graph, filter);
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    for (id, domain) in [("section", "node"), ("status", "node"), ("kind", "edge")].iter() {
+        writeln!(
+            writer,
+            r#"  <key id="{0}" for="{1}" attr.name="{0}" attr.type="string"/>"#,
+            id, domain
+        )?;
+    }
+    writeln!(
+        writer,
+        r#"  <graph id="dependencies" edgedefault="directed">"#
+    )?;
+    for name in nodes {
+        let section = filter.sections.get(name).map(|s| s.to_string());
+        let status = filter.status.get(name).map(|s| s.as_str());
+        if section.is_none() && status.is_none() {
+            writeln!(writer, r#"    <node id="{}"/>"#, xml_escape(name))?;
+            continue;
+        }
+        writeln!(writer, r#"    <node id="{}">"#, xml_escape(name))?;
+        if let Some(section) = section {
+            writeln!(
+                writer,
+                r#"      <data key="section">{}</data>"#,
+                xml_escape(&section)
+            )?;
+        }
+        if let Some(status) = status {
+            writeln!(writer, r#"      <data key="status">{}</data>"#, status)?;
+        }
+        writeln!(writer, "    </node>")?;
+    }
+    for (from, to, kind) in edges {
+        writeln!(
+            writer,
+            r#"    <edge source="{}" target="{}"><data key="kind">{}</data></edge>"#,
+            xml_escape(from),
+            xml_escape(to),
+            kind.as_str()
+        )?;
+    }
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct JsonNode<'a> {
+    id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    section: Option<&'a Section>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<BuildStatus>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    source: &'a str,
+    target: &'a str,
+    kind: DepKind,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+/// Write `graph` as JSON lists of nodes and edges, i.e: for d3:
+/// ```json
+/// {
+///   "nodes": [{ "id": "curl", "section": "app-web", "status": "built" }],
+///   "edges": [{ "source": "curl", "target": "openssl", "kind": "runtime" }]
+/// }
+/// ```
+/// Nodes are identified by package names, `section` and `status` are only
+/// present if `filter` has them.
+#[cfg(feature = "serde")]
+pub fn to_json<W: io::Write>(
+    writer: W,
+    graph: &DependencyGraph,
+    filter: &GraphFilter,
+) -> io::Result<()> {
+    let (nodes, edges) = selected(graph, filter);
+    let export = JsonGraph {
+        nodes: nodes
+            .into_iter()
+            .map(|id| JsonNode {
+                id,
+                section: filter.sections.get(id),
+                status: filter.status.get(id).copied(),
+            })
+            .collect(),
+        edges: edges
+            .into_iter()
+            .map(|(source, target, kind)| JsonEdge {
+                source,
+                target,
+                kind,
+            })
+            .collect(),
+    };
+    serde_json::to_writer_pretty(writer, &export)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apf::Context, package_set::Reason};
+
+    fn package(name: &str, pkgdep: &str, builddep: &str) -> Package {
+        let mut fields = Context::new();
+        fields.insert("PKGDEP".to_string(), pkgdep.to_string());
+        fields.insert("BUILDDEP".to_string(), builddep.to_string());
+        Package::new(name, fields)
+    }
+
+    #[test]
+    fn test_build_order() {
+        let packages = vec![
+            package("curl", "openssl>=1.1 zlib", ""),
+            package("openssl", "glibc", "perl"),
+            package("zlib", "glibc", ""),
+            package("git", "curl openssl", ""),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let order = graph.build_order().unwrap();
+        let pos = |n| order.iter().position(|p| *p == n).unwrap();
+        assert!(pos("glibc") < pos("openssl"));
+        assert!(pos("perl") < pos("openssl"));
+        assert!(pos("openssl") < pos("curl"));
+        assert!(pos("zlib") < pos("curl"));
+        assert!(pos("curl") < pos("git"));
+
+        assert_eq!(graph.reverse_dependencies("openssl"), vec!["curl", "git"]);
+        assert_eq!(graph.all_reverse_dependencies("glibc"), vec!["curl", "git", "openssl", "zlib"]);
+        assert_eq!(
+            graph.dependencies("openssl"),
+            vec![("glibc", DepKind::Runtime), ("perl", DepKind::Build)]
+        );
+    }
+
+    #[test]
+    fn test_cycle() {
+        let packages = vec![
+            package("a", "b", ""),
+            package("b", "c", ""),
+            package("c", "", "a"),
+            package("d", "a", ""),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let cycle = graph.find_cycle().unwrap();
+        assert_eq!(cycle.len(), 4);
+        assert_eq!(cycle.first(), cycle.last());
+        match graph.build_order() {
+            Err(GraphError::Cycle(path)) => assert_eq!(path, cycle),
+            _ => panic!("cycle not detected"),
+        }
+        // a -> d is not part of the cycle
+        assert!(!cycle.contains(&"d".to_string()));
+
+        let self_loop = DependencyGraph::from_packages(&[package("e", "e", "")], None).unwrap();
+        assert_eq!(self_loop.find_cycle().unwrap(), vec!["e", "e"]);
+    }
+
+    #[test]
+    fn test_explain_cycles() {
+        let packages = vec![
+            // A bootstrap loop, best broken at the build dependency.
+            package("gcc", "glibc", ""),
+            package("glibc", "", "gcc"),
+            // Two cycles sharing b -> c.
+            package("a", "b", ""),
+            package("b", "c", ""),
+            package("c", "a d", ""),
+            package("d", "b", ""),
+            package("e", "a", ""),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let cycles = graph.explain_cycles();
+        assert_eq!(cycles.len(), 2);
+
+        assert_eq!(cycles[0].packages, vec!["a", "b", "c", "d"]);
+        assert_eq!(cycles[0].edges.len(), 5);
+        assert_eq!(
+            cycles[0].break_edges,
+            vec![CycleEdge {
+                from: "b".to_string(),
+                to: "c".to_string(),
+                kind: DepKind::Runtime,
+            }]
+        );
+
+        assert_eq!(cycles[1].packages, vec!["gcc", "glibc"]);
+        assert_eq!(cycles[1].break_edges.len(), 1);
+        assert_eq!(cycles[1].break_edges[0].to_string(), "glibc -> gcc (BUILDDEP)");
+
+        let acyclic = DependencyGraph::from_packages(&packages[4..], None).unwrap();
+        assert!(acyclic.explain_cycles().is_empty());
+
+        // A ring too large for the exhaustive search.
+        let names: Vec<String> = (0..20).map(|i| format!("p{:02}", i)).collect();
+        let ring: Vec<_> = (0..20)
+            .map(|i| package(&names[i], &names[(i + 1) % 20], &names[(i + 2) % 20]))
+            .collect();
+        let graph = DependencyGraph::from_packages(&ring, None).unwrap();
+        let cycles = graph.explain_cycles();
+        assert_eq!(cycles.len(), 1);
+        let mut pruned = DependencyGraph::new();
+        for edge in cycles[0].edges.iter().filter(|e| !cycles[0].break_edges.contains(e)) {
+            pruned.add_dependency(&edge.from, &edge.to, edge.kind);
+        }
+        assert!(pruned.find_cycle().is_none());
+
+        let cancel = AtomicBool::new(true);
+        assert!(matches!(
+            graph.explain_cycles_cancellable(&cancel),
+            Err(GraphError::Cancelled)
+        ));
+        assert!(matches!(
+            graph.bootstrap_cancellable(&["p00"], &cancel),
+            Err(GraphError::Cancelled)
+        ));
+        assert!(acyclic
+            .explain_cycles_cancellable(&cancel)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rebuild_set() {
+        let packages = vec![
+            package("glibc", "", ""),
+            package("zlib", "glibc", ""),
+            package("openssl", "glibc", "perl"),
+            package("perl", "glibc", ""),
+            package("curl", "openssl zlib", ""),
+            package("git", "curl", "asciidoc"),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        assert_eq!(graph.rebuild_set(&["zlib"], None, Follow::Both), vec!["curl", "git"]);
+        assert_eq!(graph.rebuild_set(&["zlib"], Some(1), Follow::Both), vec!["curl"]);
+        assert_eq!(graph.rebuild_set(&["zlib"], Some(0), Follow::Both), Vec::<&str>::new());
+        assert_eq!(
+            graph.rebuild_set(&["perl"], None, Follow::Both),
+            vec!["curl", "git", "openssl"]
+        );
+        assert_eq!(graph.rebuild_set(&["perl"], None, Follow::Runtime), Vec::<&str>::new());
+        assert_eq!(graph.rebuild_set(&["perl"], None, Follow::Build), vec!["openssl"]);
+        assert_eq!(
+            graph.rebuild_set(&["openssl", "curl", "missing"], None, Follow::Both),
+            vec!["git"]
+        );
+    }
+
+    #[test]
+    fn test_arch() {
+        let mut p = package("foo", "bar", "");
+        let mut fields = p.fields().clone();
+        fields.insert("PKGDEP__ARM64".to_string(), "baz".to_string());
+        p = Package::new(p.name(), fields);
+        let graph = DependencyGraph::from_packages(&[p], Some("arm64")).unwrap();
+        assert_eq!(graph.dependencies("foo"), vec![("baz", DepKind::Runtime)]);
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let packages = vec![
+            package("curl", "openssl zlib", ""),
+            package("openssl", "", "perl"),
+            package("git", "curl", ""),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let sections = [("curl", "app-web"), ("openssl", "core-libs")];
+        let mut filter = GraphFilter {
+            sections: sections
+                .iter()
+                .map(|(p, s)| (p.to_string(), s.parse().unwrap()))
+                .collect(),
+            ..Default::default()
+        };
+        filter.status.insert("openssl".into(), BuildStatus::Failed);
+        let mut out = Vec::new();
+        to_dot(&mut out, &graph, &filter).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"digraph dependencies {
+    "git";
+    "perl";
+    "zlib";
+    subgraph "cluster_core-libs" {
+        label="core-libs";
+        "openssl" [style=filled, fillcolor=lightcoral];
+    }
+    subgraph "cluster_app-web" {
+        label="app-web";
+        "curl";
+    }
+    "curl" -> "openssl";
+    "curl" -> "zlib";
+    "git" -> "curl";
+    "openssl" -> "perl" [style=dashed];
+}
+"#
+        );
+
+        let subtree = graph.rebuild_set(&["perl"], None, Follow::Both);
+        filter.packages = Some(subtree.into_iter().map(String::from).collect());
+        filter.follow = Follow::Runtime;
+        let mut out = Vec::new();
+        to_dot(&mut out, &graph, &filter).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\"git\" -> \"curl\";"));
+        assert!(!out.contains("perl"));
+        assert!(!out.contains("zlib"));
+    }
+
+    #[test]
+    fn test_to_graphml() {
+        let packages = vec![package("a&b", "c", "d")];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let mut filter = GraphFilter::default();
+        filter.status.insert("c".into(), BuildStatus::Built);
+        let mut out = Vec::new();
+        to_graphml(&mut out, &graph, &filter).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(r#"<node id="a&amp;b"/>"#));
+        let node = "<node id=\"c\">\n      <data key=\"status\">built</data>\n    </node>";
+        assert!(out.contains(node));
+        let edge = r#"<edge source="a&amp;b" target="d"><data key="kind">build</data></edge>"#;
+        assert!(out.contains(edge));
+        assert!(out.ends_with("</graphml>\n"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_to_json() {
+        let packages = vec![
+            package("curl", "openssl", ""),
+            package("openssl", "", "perl"),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let mut filter = GraphFilter {
+            follow: Follow::Runtime,
+            ..Default::default()
+        };
+        let (sections, status) = (&mut filter.sections, &mut filter.status);
+        sections.insert("curl".into(), "app-web".parse().unwrap());
+        status.insert("openssl".into(), BuildStatus::Outdated);
+        let mut out = Vec::new();
+        to_json(&mut out, &graph, &filter).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "nodes": [
+                    { "id": "curl", "section": "app-web" },
+                    { "id": "openssl", "status": "outdated" },
+                    { "id": "perl" },
+                ],
+                "edges": [{ "source": "curl", "target": "openssl", "kind": "runtime" }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_plan() {
+        let packages = vec![
+            package("openssl", "glibc", "perl"),
+            package("perl", "glibc", ""),
+            package("curl", "openssl zlib", ""),
+            package("git", "curl", "asciidoc"),
+            package("asciidoc", "python-3", ""),
+            package("python-3", "openssl", ""),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let targets = ["git", "openssl", "zlib", "curl", "perl", "unknown"];
+        assert_eq!(
+            graph.build_plan(&targets).unwrap(),
+            vec![
+                vec!["perl", "unknown", "zlib"],
+                vec!["openssl"],
+                vec!["curl"],
+                vec!["git"],
+            ]
+        );
+        // git waits for openssl through asciidoc and python-3.
+        assert_eq!(
+            graph.build_plan(&["git", "openssl"]).unwrap(),
+            vec![vec!["openssl"], vec!["git"]]
+        );
+        assert_eq!(graph.build_plan(&[]).unwrap(), Vec::<Vec<&str>>::new());
+
+        let packages = vec![
+            package("a", "b", ""),
+            package("b", "c", ""),
+            package("c", "", "a"),
+        ];
+        let cyclic = DependencyGraph::from_packages(&packages, None).unwrap();
+        match cyclic.build_plan(&["a", "c"]) {
+            Err(GraphError::Cycle(path)) => assert_eq!(path.len(), 3),
+            r => panic!("Unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_rebuild_plan() {
+        let packages = vec![
+            package("openssl", "glibc", "perl"),
+            package("perl", "glibc", ""),
+            package("curl", "openssl", ""),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let mut targets = PackageSet::from_rebuild_set(&graph, &["perl"], None, Follow::Both);
+        targets.insert("perl", Reason::Explicit);
+        let plan = graph
+            .rebuild_plan(&targets, &["amd64", "arm64"], "perl 5.40")
+            .unwrap();
+        assert_eq!(plan.validate(), Ok(()));
+        assert_eq!(plan.targets, vec!["curl", "openssl", "perl"]);
+        assert_eq!(plan.archs, vec!["amd64", "arm64"]);
+        assert_eq!(plan.reason, "perl 5.40");
+        assert_eq!(plan.stages, vec![vec!["perl"], vec!["openssl"], vec!["curl"]]);
+        assert_eq!(plan.reasons["curl"], vec!["reverse dependency of openssl"]);
+        assert_eq!(plan.reasons["perl"], vec!["explicitly requested"]);
+    }
+
+    #[test]
+    fn test_bootstrap() {
+        let packages = vec![
+            package("gcc", "glibc", ""),
+            package("glibc", "", "gcc"),
+            package("make", "glibc", "gcc"),
+            package("bash", "glibc", "make"),
+            package("unrelated", "glibc", ""),
+        ];
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let plan = graph.bootstrap(&["bash"]);
+        assert_eq!(plan.packages, vec!["bash", "gcc", "glibc", "make"]);
+        assert_eq!(plan.stage1, vec!["glibc"]);
+        assert_eq!(plan.broken.len(), 1);
+        assert_eq!(plan.broken[0].to_string(), "glibc -> gcc (BUILDDEP)");
+        let steps: Vec<_> = plan
+            .steps
+            .iter()
+            .map(|s| (s.package.as_str(), s.stage1))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                ("glibc", true),
+                ("gcc", false),
+                ("make", false),
+                ("bash", false),
+                ("glibc", false),
+            ]
+        );
+
+        let plan = graph.bootstrap(&["unknown"]);
+        assert!(plan.packages.is_empty() && plan.steps.is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+//! Fields whose values differ between architectures, i.e: to spot a
+//! `PKGDEP__ARM64` copied from `PKGDEP` but not updated along with it, or a
+//! `noarch` package which would not be the same on every architecture.
+
+#[cfg(feature = "std")]
+use crate::package::PackageError;
+use crate::{arch::Arch, package::Package};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A field of a package, or sub-package, with different values between
+/// architectures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub package: String,
+    pub field: String,
+    /// Value for each architecture, `None` where the field is not set.
+    pub values: BTreeMap<Arch, Option<String>>,
+}
+
+impl Divergence {
+    /// Architectures sharing each value, i.e: to tell the odd one out.
+    /// Sorted by value.
+    pub fn groups(&self) -> BTreeMap<Option<&str>, Vec<Arch>> {
+        let mut groups: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (arch, value) in self.values.iter() {
+            groups.entry(value.as_deref()).or_default().push(*arch);
+        }
+        groups
+    }
+}
+
+/// Divergences of a package across the architectures it builds for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DivergenceReport {
+    /// Whether `ABHOST` is `noarch` for any architecture. Such a package is
+    /// only built once, so any divergence is a mistake.
+    pub noarch: bool,
+    /// Sorted by package, then field.
+    pub divergences: Vec<Divergence>,
+}
+
+impl DivergenceReport {
+    pub fn is_empty(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Compare a package evaluated for each architecture, i.e: by
+/// `Package::resolve_for`. Sub-packages are compared by name; one missing
+/// on an architecture has none of its fields set there.
+pub fn compare(resolved: &[(Arch, Package)]) -> DivergenceReport {
+    // Package name -> field -> architecture -> value
+    let mut fields: BTreeMap<&str, BTreeMap<&str, BTreeMap<Arch, &str>>> = BTreeMap::new();
+    let mut noarch = false;
+    for (arch, package) in resolved {
+        noarch |= package
+            .fields()
+            .get("ABHOST")
+            .is_some_and(|v| v == "noarch");
+        let mut units = vec![(package.name(), package.fields())];
+        units.extend(package.subpackages().iter().map(|s| (s.name(), s.fields())));
+        for (name, context) in units {
+            let unit = fields.entry(name).or_default();
+            for (key, value) in context.iter() {
+                unit.entry(key).or_default().insert(*arch, value.as_str());
+            }
+        }
+    }
+
+    let mut divergences = Vec::new();
+    for (name, unit) in fields {
+        for (field, by_arch) in unit {
+            let values: BTreeMap<_, _> = resolved
+                .iter()
+                .map(|(arch, _)| (*arch, by_arch.get(arch).map(|v| v.to_string())))
+                .collect();
+            if values.values().collect::<BTreeSet<_>>().len() > 1 {
+                divergences.push(Divergence {
+                    package: name.to_string(),
+                    field: field.to_string(),
+                    values,
+                });
+            }
+        }
+    }
+
+    DivergenceReport {
+        noarch,
+        divergences,
+    }
+}
+
+/// Evaluate `package` for every architecture it builds for, and compare the
+/// results.
+#[cfg(feature = "std")]
+pub fn find_divergences(package: &Package) -> Result<DivergenceReport, PackageError> {
+    let mut resolved = Vec::new();
+    for arch in Arch::ALL {
+        let package = package.resolve_for(*arch)?;
+        if package.builds_on(arch.as_str()) {
+            resolved.push((*arch, package));
+        }
+    }
+
+    Ok(compare(&resolved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apf::Context;
+
+    fn package(fields: &[(&str, &str)]) -> Package {
+        let fields: Context = fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Package::new("foo", fields)
+    }
+
+    #[test]
+    fn test_compare() {
+        let resolved = vec![
+            (Arch::Amd64, package(&[("VER", "1"), ("PKGDEP", "bar")])),
+            (Arch::Arm64, package(&[("VER", "1"), ("PKGDEP", "baz")])),
+            (
+                Arch::Riscv64,
+                package(&[("VER", "1"), ("PKGDEP", "bar"), ("NOLTO", "1")]),
+            ),
+        ];
+        let report = compare(&resolved);
+        assert!(!report.noarch);
+        let fields: Vec<_> = report
+            .divergences
+            .iter()
+            .map(|d| d.field.as_str())
+            .collect();
+        assert_eq!(fields, vec!["NOLTO", "PKGDEP"]);
+        let groups = report.divergences[1].groups();
+        assert_eq!(groups[&Some("bar")], vec![Arch::Amd64, Arch::Riscv64]);
+        assert_eq!(groups[&Some("baz")], vec![Arch::Arm64]);
+        assert_eq!(report.divergences[0].groups()[&None].len(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_find_divergences() {
+        let report = find_divergences(&package(&[
+            ("ABHOST", "noarch"),
+            ("PKGDEP", "bar"),
+            ("PKGDEP__ARM64", "baz"),
+            ("FAIL_ARCH", "!(amd64|arm64)"),
+        ]))
+        .unwrap();
+        assert!(report.noarch);
+        assert_eq!(report.divergences.len(), 1);
+        assert_eq!(
+            report.divergences[0].values.keys().collect::<Vec<_>>(),
+            vec![&Arch::Amd64, &Arch::Arm64]
+        );
+
+        assert!(find_divergences(&package(&[("PKGDEP", "bar")]))
+            .unwrap()
+            .is_empty());
+    }
+}
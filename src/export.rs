@@ -0,0 +1,233 @@
+//! Shared pieces of the metadata exporters.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where exported metadata comes from.
+/// Embedded into emitted documents so consumers can trace them back to an exact
+/// tree state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Provenance {
+    /// Commit hash of the tree, if it is a git checkout.
+    pub tree_commit: Option<String>,
+    /// Whether the checkout had uncommitted changes.
+    pub dirty: bool,
+    /// Version of this crate.
+    pub crate_version: String,
+    /// When the tree was scanned, in seconds since the Unix epoch.
+    pub scanned_at: u64,
+}
+
+#[cfg(feature = "std")]
+fn git(tree: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(tree)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl Provenance {
+    /// Collect provenance of the tree at `tree`, running `git` in it.
+    /// Commit and dirty state are left empty if `git` is unavailable or the tree
+    /// is not a checkout.
+    #[cfg(feature = "std")]
+    pub fn collect<P: AsRef<Path>>(tree: P) -> Self {
+        let tree = tree.as_ref();
+        let tree_commit = git(tree, &["rev-parse", "HEAD"]);
+        let dirty = tree_commit.is_some()
+            && git(tree, &["status", "--porcelain"]).is_some_and(|s| !s.is_empty());
+
+        Provenance {
+            tree_commit,
+            dirty,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            scanned_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    /// Flatten into key-value pairs, for formats without nesting.
+    pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("tree_commit", self.tree_commit.clone().unwrap_or_default()),
+            ("dirty", (self.dirty as u8).to_string()),
+            ("crate_version", self.crate_version.clone()),
+            ("scanned_at", self.scanned_at.to_string()),
+        ]
+    }
+}
+
+/// An exported document, optionally annotated with its provenance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Document<T> {
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub provenance: Option<Provenance>,
+    pub data: T,
+}
+
+impl<T> Document<T> {
+    pub fn new(data: T) -> Self {
+        Document {
+            provenance: None,
+            data,
+        }
+    }
+
+    pub fn with_provenance(data: T, provenance: Provenance) -> Self {
+        Document {
+            provenance: Some(provenance),
+            data,
+        }
+    }
+}
+
+/// Serialize a context with its keys sorted, for use with `serialize_with`.
+#[cfg(feature = "serde")]
+pub(crate) fn serialize_sorted<S: serde::Serializer>(
+    context: &crate::apf::Context,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(context.iter().collect::<std::collections::BTreeMap<_, _>>())
+}
+
+/// Serialize `value` as a TOML document.
+/// `value` must serialize to a map, as TOML has no top-level arrays.
+#[cfg(feature = "toml")]
+pub fn to_toml<T: Serialize>(value: &T) -> Result<String, toml::ser::Error> {
+    toml::to_string_pretty(value)
+}
+
+/// Serialize `value` as a YAML document.
+#[cfg(feature = "yaml")]
+pub fn to_yaml<T: Serialize>(value: &T) -> Result<String, serde_yaml::Error> {
+    serde_yaml::to_string(value)
+}
+
+/// Serialize `value` as canonical JSON.
+///
+/// The output follows RFC 8785: no insignificant whitespace, object keys sorted
+/// by their UTF-16 code units, and strings escaped only where required. Floating
+/// point numbers are rejected rather than risking different renderings, so
+/// equal values always produce byte-identical output, suitable for hashing and
+/// signing.
+#[cfg(feature = "serde")]
+pub fn to_canonical_json<T: Serialize>(value: &T) -> Result<String, serde_json::Error> {
+    let value = serde_json::to_value(value)?;
+    let mut out = String::new();
+    write_canonical_value(&value, &mut out)?;
+    Ok(out)
+}
+
+#[cfg(feature = "serde")]
+fn write_canonical_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(feature = "serde")]
+fn write_canonical_value(value: &serde_json::Value, out: &mut String) -> Result<(), serde_json::Error> {
+    use serde_json::Value;
+
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => {
+            if n.is_f64() {
+                return Err(serde::ser::Error::custom(
+                    "floating point numbers are not allowed in canonical JSON",
+                ));
+            }
+            out.push_str(&n.to_string());
+        }
+        Value::String(s) => write_canonical_string(s, out),
+        Value::Array(a) => {
+            out.push('[');
+            for (i, v) in a.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_value(v, out)?;
+            }
+            out.push(']');
+        }
+        Value::Object(o) => {
+            let mut entries: Vec<_> = o.iter().collect();
+            entries.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+            out.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_string(k, out);
+                out.push(':');
+                write_canonical_value(v, out)?;
+            }
+            out.push('}');
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::apf::Context;
+
+    #[test]
+    fn test_canonical_json() {
+        let mut context = Context::new();
+        context.insert("VER".to_string(), "1.0".to_string());
+        context.insert("PKGDES".to_string(), "Tab\there \"quoted\" \u{1} 中文".to_string());
+        context.insert("\u{e000}".to_string(), String::new());
+        context.insert("\u{1f600}".to_string(), String::new());
+        assert_eq!(
+            to_canonical_json(&context).unwrap(),
+            "{\"PKGDES\":\"Tab\\there \\\"quoted\\\" \\u0001 中文\",\"VER\":\"1.0\",\"\u{1f600}\":\"\",\"\u{e000}\":\"\"}"
+        );
+
+        let doc = Document::with_provenance(
+            vec![1, 2],
+            Provenance {
+                tree_commit: None,
+                dirty: true,
+                crate_version: "0.1.0".to_string(),
+                scanned_at: 42,
+            },
+        );
+        assert_eq!(
+            to_canonical_json(&doc).unwrap(),
+            r#"{"data":[1,2],"provenance":{"crate_version":"0.1.0","dirty":true,"scanned_at":42,"tree_commit":null}}"#
+        );
+        assert!(to_canonical_json(&1.5f64).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! C interface to the parser, for autobuild and other C/C++ consumers.
+//! The matching header is `include/abbs.h`, generated by cbindgen with the
+//! `cbindgen.toml` at the root of the repository.
+//!
+//! Build a shared or static library with i.e:
+//! `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! Strings returned by these functions are owned by the caller and must be
+//! released with `abbs_string_free`, contexts with `abbs_free`. Panics never
+//! cross into C: they are reported like any other failure.
+
+use crate::apf::{self, Context};
+use std::{
+    ffi::{CStr, CString},
+    fs,
+    os::raw::{c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+/// Variables defined by one or more parsed files.
+pub struct AbbsContext(Context);
+
+/// Store `message` in `*error` if the caller asked for it.
+unsafe fn set_error(error: *mut *mut c_char, message: String) {
+    if error.is_null() {
+        return;
+    }
+    // Messages never contain NUL, but an empty string beats a panic.
+    let message = CString::new(message).unwrap_or_default();
+    *error = message.into_raw();
+}
+
+/// Run `f`, with a panic turned into an error so it does not unwind into the
+/// caller.
+fn catch<T, F: FnOnce() -> Result<T, String>>(f: F) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => payload
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_default(),
+        };
+        Err(format!("Internal error: {}", message))
+    })
+}
+
+unsafe fn parse_into(context: &mut Context, path: *const c_char) -> Result<(), String> {
+    if path.is_null() {
+        return Err("path is NULL".to_string());
+    }
+    let path = CStr::from_ptr(path)
+        .to_str()
+        .map_err(|_| "path is not valid UTF-8".to_string())?;
+    let content = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    apf::parse(&content, context).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+/// Create an empty context.
+#[no_mangle]
+pub extern "C" fn abbs_context_new() -> *mut AbbsContext {
+    catch(|| Ok(Box::into_raw(Box::new(AbbsContext(Context::new()))))).unwrap_or(ptr::null_mut())
+}
+
+/// Parse the file at `path` into a new context.
+/// Returns NULL on failure, with a message in `*error` unless `error` is NULL.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string, and `error` NULL or valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn abbs_parse_file(
+    path: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut AbbsContext {
+    let result = catch(|| {
+        let mut context = Context::new();
+        parse_into(&mut context, path)?;
+        Ok(Box::into_raw(Box::new(AbbsContext(context))))
+    });
+    match result {
+        Ok(context) => context,
+        Err(e) => {
+            set_error(error, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Parse the file at `path` on top of `context`, i.e: a defines file on top of
+/// the context of its spec. Returns 0 on success, or -1 with a message in
+/// `*error` unless `error` is NULL. On failure, `context` keeps the variables
+/// defined before the error.
+///
+/// # Safety
+/// `context` must come from this library, `path` must be a valid
+/// NUL-terminated string, and `error` NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn abbs_parse_file_into(
+    context: *mut AbbsContext,
+    path: *const c_char,
+    error: *mut *mut c_char,
+) -> c_int {
+    let context = match context.as_mut() {
+        Some(c) => c,
+        None => {
+            set_error(error, "context is NULL".to_string());
+            return -1;
+        }
+    };
+    match catch(|| parse_into(&mut context.0, path)) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(error, e);
+            -1
+        }
+    }
+}
+
+/// Value of the variable `name`, or NULL if it is not set.
+///
+/// # Safety
+/// `context` must come from this library and `name` must be a valid
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn abbs_context_get(
+    context: *const AbbsContext,
+    name: *const c_char,
+) -> *mut c_char {
+    let context = match context.as_ref() {
+        Some(c) => c,
+        None => return ptr::null_mut(),
+    };
+    if name.is_null() {
+        return ptr::null_mut();
+    }
+    let value = catch(|| {
+        let value = CStr::from_ptr(name)
+            .to_str()
+            .ok()
+            .and_then(|name| context.0.get(name))
+            .and_then(|value| CString::new(value.as_str()).ok());
+        Ok(value)
+    });
+    match value {
+        Ok(Some(value)) => value.into_raw(),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Release a context. NULL is ignored.
+///
+/// # Safety
+/// `context` must come from this library and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn abbs_free(context: *mut AbbsContext) {
+    if !context.is_null() {
+        let _ = catch(|| {
+            drop(Box::from_raw(context));
+            Ok(())
+        });
+    }
+}
+
+/// Release a string returned by this library. NULL is ignored.
+///
+/// # Safety
+/// `s` must come from this library and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn abbs_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        let _ = catch(|| {
+            drop(CString::from_raw(s));
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    unsafe fn get(context: *const AbbsContext, name: &str) -> Option<String> {
+        let value = abbs_context_get(context, c(name).as_ptr());
+        if value.is_null() {
+            return None;
+        }
+        let result = CStr::from_ptr(value).to_str().unwrap().to_string();
+        abbs_string_free(value);
+        Some(result)
+    }
+
+    #[test]
+    fn test_ffi() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = dir.path().join("spec");
+        let defines = dir.path().join("defines");
+        fs::write(&spec, "VER=1.0\n").unwrap();
+        fs::write(&defines, "PKGDES=\"Foo $VER\"\nA=$(\n").unwrap();
+        let spec = c(spec.to_str().unwrap());
+        let defines = c(defines.to_str().unwrap());
+
+        unsafe {
+            let context = abbs_parse_file(spec.as_ptr(), ptr::null_mut());
+            assert!(!context.is_null());
+            assert_eq!(get(context, "VER").as_deref(), Some("1.0"));
+            assert_eq!(get(context, "PKGDES"), None);
+
+            let mut error = ptr::null_mut();
+            assert_eq!(abbs_parse_file_into(context, defines.as_ptr(), &mut error), -1);
+            assert!(!error.is_null());
+            assert!(CStr::from_ptr(error).to_str().unwrap().contains("defines"));
+            abbs_string_free(error);
+            assert_eq!(get(context, "PKGDES").as_deref(), Some("Foo 1.0"));
+            abbs_free(context);
+
+            let mut error = ptr::null_mut();
+            let missing = c("/nonexistent/spec");
+            assert!(abbs_parse_file(missing.as_ptr(), &mut error).is_null());
+            abbs_string_free(error);
+            assert!(abbs_context_get(ptr::null(), missing.as_ptr()).is_null());
+            abbs_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_catch() {
+        assert_eq!(catch(|| Ok(1)), Ok(1));
+        let result: Result<(), _> = catch(|| panic!("boom"));
+        assert_eq!(result, Err("Internal error: boom".to_string()));
+        let result: Result<(), _> = catch(|| panic!("{}", 42));
+        assert_eq!(result, Err("Internal error: 42".to_string()));
+    }
+}
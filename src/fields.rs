@@ -0,0 +1,494 @@
+//! Registry of the fields understood in spec and defines files.
+//! Each field has a type, used for typed access with `Package::get_typed`,
+//! and possibly a replacement if it is deprecated.
+
+use crate::{
+    apf::Context,
+    dependency::{parse_dependencies, Dependency},
+    package::split_arch_suffix,
+};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FieldType {
+    Text,
+    /// `0` or `1`, see `parse_bool`.
+    Bool,
+    Integer,
+    /// Whitespace separated words, i.e: `SRCS`.
+    List,
+    /// Whitespace separated dependencies, i.e: `PKGDEP`.
+    Dependencies,
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FieldType::Text => "text",
+            FieldType::Bool => "boolean",
+            FieldType::Integer => "integer",
+            FieldType::List => "list",
+            FieldType::Dependencies => "dependency list",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub kind: FieldType,
+    /// Field to use instead, if deprecated.
+    pub deprecated: Option<&'static str>,
+}
+
+const fn field(name: &'static str, kind: FieldType) -> FieldInfo {
+    FieldInfo {
+        name,
+        kind,
+        deprecated: None,
+    }
+}
+
+const fn deprecated(name: &'static str, kind: FieldType, replacement: &'static str) -> FieldInfo {
+    FieldInfo {
+        name,
+        kind,
+        deprecated: Some(replacement),
+    }
+}
+
+/// Known fields, sorted by name.
+pub const FIELDS: &[FieldInfo] = &[
+    field("ABCONFIGHACK", FieldType::Bool),
+    field("ABELFDEP", FieldType::Bool),
+    field("ABHOST", FieldType::Text),
+    field("ABSHADOW", FieldType::Bool),
+    field("ABSPLITDBG", FieldType::Bool),
+    field("ABSTRIP", FieldType::Bool),
+    field("ABTYPE", FieldType::Text),
+    field("AUTOTOOLS_AFTER", FieldType::Text),
+    field("BUILDDEP", FieldType::Dependencies),
+    deprecated("BZRSRC", FieldType::Text, "SRCS"),
+    field("CARGO_AFTER", FieldType::Text),
+    deprecated("CHKSUM", FieldType::Text, "CHKSUMS"),
+    field("CHKSUMS", FieldType::List),
+    field("CHKUPDATE", FieldType::Text),
+    field("CMAKE_AFTER", FieldType::Text),
+    field("DUMMYSRC", FieldType::Bool),
+    field("FAIL_ARCH", FieldType::Text),
+    deprecated("GITBRCH", FieldType::Text, "SRCS"),
+    deprecated("GITCO", FieldType::Text, "SRCS"),
+    deprecated("GITSRC", FieldType::Text, "SRCS"),
+    deprecated("HGSRC", FieldType::Text, "SRCS"),
+    field("LICENSE", FieldType::Text),
+    field("MAINTAINER", FieldType::Text),
+    field("MAKE_AFTER", FieldType::Text),
+    field("MESON_AFTER", FieldType::Text),
+    field("NOLTO", FieldType::Bool),
+    field("NOPARALLEL", FieldType::Bool),
+    field("NOSTATIC", FieldType::Bool),
+    field("PKGBREAK", FieldType::Dependencies),
+    field("PKGCONFL", FieldType::Dependencies),
+    field("PKGDEP", FieldType::Dependencies),
+    field("PKGDES", FieldType::Text),
+    field("PKGEPOCH", FieldType::Integer),
+    field("PKGNAME", FieldType::Text),
+    field("PKGPROV", FieldType::Dependencies),
+    field("PKGRECOM", FieldType::Dependencies),
+    field("PKGREP", FieldType::Dependencies),
+    field("PKGSEC", FieldType::Text),
+    field("PKGSUG", FieldType::Dependencies),
+    field("QTPROJ_AFTER", FieldType::Text),
+    field("RECONF", FieldType::Bool),
+    field("REL", FieldType::Integer),
+    field("SRCS", FieldType::List),
+    deprecated("SRCTBL", FieldType::Text, "SRCS"),
+    field("SUBDIR", FieldType::Text),
+    deprecated("SVNCO", FieldType::Text, "SRCS"),
+    deprecated("SVNSRC", FieldType::Text, "SRCS"),
+    field("USECLANG", FieldType::Bool),
+    field("VER", FieldType::Text),
+    field("WAF_AFTER", FieldType::Text),
+];
+
+/// Registry entry of `key`, architecture suffix ignored, i.e: `PKGDEP__AMD64`
+/// is a `PKGDEP`.
+pub fn lookup(key: &str) -> Option<&'static FieldInfo> {
+    let name = split_arch_suffix(key).map_or(key, |(field, _)| field);
+    FIELDS
+        .binary_search_by(|f| f.name.cmp(name))
+        .ok()
+        .map(|i| &FIELDS[i])
+}
+
+/// Number of single character insertions, deletions and substitutions
+/// turning `a` into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The known field `name` was most likely meant to be, if any, i.e: `PKGDEP`
+/// for `PKGDEPS`.
+pub fn suggest(name: &str) -> Option<&'static str> {
+    let name = split_arch_suffix(name).map_or(name, |(field, _)| field);
+    closest(
+        name,
+        FIELDS
+            .iter()
+            .filter(|f| f.deprecated.is_none())
+            .map(|f| f.name),
+    )
+}
+
+/// The one of `candidates` closest to `name`, if close enough to be a typo.
+/// Ties go to the first in alphabetical order.
+pub(crate) fn closest<'a, I: IntoIterator<Item = &'a str>>(
+    name: &str,
+    candidates: I,
+) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d > 0 && *d <= 2 && *d < name.len() / 2)
+        .min()
+        .map(|(_, c)| c)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldError {
+    UnknownField(String),
+    /// The field is not of a type convertible to the one asked for.
+    WrongType {
+        field: String,
+        kind: FieldType,
+    },
+    BadValue {
+        field: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldError::UnknownField(field) => write!(f, "Unknown field {}", field),
+            FieldError::WrongType { field, kind } => write!(f, "{} is a {} field", field, kind),
+            FieldError::BadValue {
+                field,
+                value,
+                reason,
+            } => write!(f, "Invalid {} `{}`: {}", field, value, reason),
+        }
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+/// Types fields can be read as.
+pub trait FieldValue: Sized {
+    /// Whether fields of type `kind` can be read as `Self`.
+    fn accepts(kind: FieldType) -> bool;
+    /// Convert a value, or return the reason of rejection.
+    fn parse(value: &str) -> Result<Self, String>;
+}
+
+impl FieldValue for String {
+    fn accepts(_: FieldType) -> bool {
+        true
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        Ok(value.to_string())
+    }
+}
+
+impl FieldValue for bool {
+    fn accepts(kind: FieldType) -> bool {
+        kind == FieldType::Bool
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        parse_bool(value).ok_or_else(|| "must be a boolean".to_string())
+    }
+}
+
+impl FieldValue for u64 {
+    fn accepts(kind: FieldType) -> bool {
+        kind == FieldType::Integer
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        value.parse().map_err(|_| "must be a number".to_string())
+    }
+}
+
+impl FieldValue for Vec<String> {
+    fn accepts(kind: FieldType) -> bool {
+        matches!(kind, FieldType::List | FieldType::Dependencies)
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        split_list(value)
+    }
+}
+
+impl FieldValue for Vec<Dependency> {
+    fn accepts(kind: FieldType) -> bool {
+        kind == FieldType::Dependencies
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        parse_dependencies(value).map_err(|e| e.to_string())
+    }
+}
+
+/// Booleans the way autobuild reads them: `1`, `y`, `yes`, `true` and `on`
+/// are true, `0`, `n`, `no`, `false` and `off` false, in any case.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "y" | "yes" | "true" | "on" => Some(true),
+        "0" | "n" | "no" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Split a list value on whitespace, except inside quotes or after a
+/// backslash, i.e: `a 'b c' d\ e` is `a`, `b c` and `d e`.
+/// Quotes nested in the value survive evaluation, so `PKGDEP="a 'b c'"`
+/// splits the same way as written.
+pub fn split_list(value: &str) -> Result<Vec<String>, String> {
+    let mut items = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => items.extend(current.take()),
+            '\\' => {
+                let escaped = chars.next().ok_or("trailing backslash")?;
+                current.get_or_insert_with(String::new).push(escaped);
+            }
+            '\'' => {
+                let word = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or("unterminated single quote")? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next().ok_or("unterminated double quote")? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or("unterminated double quote")? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    items.extend(current);
+    Ok(items)
+}
+
+/// Typed getters on `Context`. Empty values count as unset, like in autobuild.
+pub trait ContextExt {
+    fn get_bool(&self, key: &str) -> Result<Option<bool>, FieldError>;
+    fn get_u64(&self, key: &str) -> Result<Option<u64>, FieldError>;
+    /// List value split with `split_list`, i.e: `get_list("PKGDEP")`.
+    fn get_list(&self, key: &str) -> Result<Option<Vec<String>>, FieldError>;
+}
+
+fn get_parsed<T, F>(context: &Context, key: &str, parse: F) -> Result<Option<T>, FieldError>
+where
+    F: Fn(&str) -> Result<T, String>,
+{
+    match context.get(key).filter(|v| !v.is_empty()) {
+        Some(value) => parse(value)
+            .map(Some)
+            .map_err(|reason| FieldError::BadValue {
+                field: key.to_string(),
+                value: value.to_string(),
+                reason,
+            }),
+        None => Ok(None),
+    }
+}
+
+impl ContextExt for Context {
+    fn get_bool(&self, key: &str) -> Result<Option<bool>, FieldError> {
+        get_parsed(self, key, <bool as FieldValue>::parse)
+    }
+
+    fn get_u64(&self, key: &str) -> Result<Option<u64>, FieldError> {
+        get_parsed(self, key, <u64 as FieldValue>::parse)
+    }
+
+    fn get_list(&self, key: &str) -> Result<Option<Vec<String>>, FieldError> {
+        get_parsed(self, key, split_list)
+    }
+}
+
+/// Read `value` of the field `key` as `T`.
+pub fn get_typed<T: FieldValue>(key: &str, value: &str) -> Result<T, FieldError> {
+    let info = lookup(key).ok_or_else(|| FieldError::UnknownField(key.to_string()))?;
+    if !T::accepts(info.kind) {
+        return Err(FieldError::WrongType {
+            field: key.to_string(),
+            kind: info.kind,
+        });
+    }
+    T::parse(value).map_err(|reason| FieldError::BadValue {
+        field: key.to_string(),
+        value: value.to_string(),
+        reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apf;
+    use crate::{autobuild::BOOLEAN_FIELDS, fmt::LIST_FIELDS, lint::OBSOLETE_FIELDS};
+
+    #[test]
+    fn test_registry() {
+        assert!(FIELDS.windows(2).all(|w| w[0].name < w[1].name));
+        for (name, replacement) in OBSOLETE_FIELDS {
+            assert_eq!(lookup(name).unwrap().deprecated, Some(*replacement));
+        }
+        for name in BOOLEAN_FIELDS {
+            assert_eq!(lookup(name).unwrap().kind, FieldType::Bool);
+        }
+        for name in LIST_FIELDS {
+            assert_ne!(lookup(name).unwrap().kind, FieldType::Text);
+        }
+        assert_eq!(lookup("PKGDEP__AMD64").unwrap().name, "PKGDEP");
+        assert!(lookup("PKGDEPS").is_none());
+    }
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(suggest("PKGDEPS"), Some("PKGDEP"));
+        assert_eq!(suggest("PGKDES__AMD64"), Some("PKGDES"));
+        assert_eq!(suggest("BUILDEP"), Some("BUILDDEP"));
+        // Deprecated fields are never suggested.
+        assert_eq!(suggest("SRCTBLS"), None);
+        assert_eq!(suggest("FOO"), None);
+    }
+
+    #[test]
+    fn test_get_typed() {
+        assert_eq!(get_typed::<bool>("NOPARALLEL", "1"), Ok(true));
+        assert_eq!(get_typed::<u64>("REL__AMD64", "2"), Ok(2));
+        assert_eq!(
+            get_typed::<Vec<String>>("PKGDEP", "foo  bar>=1"),
+            Ok(vec!["foo".to_string(), "bar>=1".to_string()])
+        );
+        assert_eq!(
+            get_typed::<Vec<Dependency>>("PKGDEP", "foo bar>=1")
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            get_typed::<bool>("NOPARALLEL", "maybe")
+                .unwrap_err()
+                .to_string(),
+            "Invalid NOPARALLEL `maybe`: must be a boolean"
+        );
+        assert_eq!(
+            get_typed::<bool>("PKGDES", "1").unwrap_err().to_string(),
+            "PKGDES is a text field"
+        );
+        assert_eq!(
+            get_typed::<String>("PKGDEPS", "foo"),
+            Err(FieldError::UnknownField("PKGDEPS".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_context_ext() {
+        let mut context = Context::new();
+        for (k, v) in [
+            ("A", "1"),
+            ("B", "No"),
+            ("C", ""),
+            ("D", "maybe"),
+            ("N", "42"),
+            ("M", "-1"),
+        ] {
+            context.insert(k.to_string(), v.to_string());
+        }
+        assert_eq!(context.get_bool("A"), Ok(Some(true)));
+        assert_eq!(context.get_bool("B"), Ok(Some(false)));
+        assert_eq!(context.get_bool("C"), Ok(None));
+        assert_eq!(context.get_bool("Z"), Ok(None));
+        assert_eq!(
+            context.get_bool("D").unwrap_err().to_string(),
+            "Invalid D `maybe`: must be a boolean"
+        );
+        assert_eq!(context.get_u64("N"), Ok(Some(42)));
+        assert_eq!(context.get_u64("C"), Ok(None));
+        assert!(context.get_u64("M").is_err());
+        assert!(context.get_u64("A").is_ok());
+    }
+
+    #[test]
+    fn test_split_list() {
+        assert_eq!(split_list("  a\tb\n c ").unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(
+            split_list(r#"a 'b c' d\ e "f \"g\" \h"x"#).unwrap(),
+            vec!["a", "b c", "d e", r#"f "g" \hx"#]
+        );
+        assert_eq!(split_list("''").unwrap(), vec![""]);
+        assert!(split_list("").unwrap().is_empty());
+        assert_eq!(split_list("'a").unwrap_err(), "unterminated single quote");
+        assert_eq!(split_list("a\\").unwrap_err(), "trailing backslash");
+
+        let mut context = Context::new();
+        let mut raw = Context::new();
+        apf::parse(
+            r#"SRCS="file::rename=a\ b::https://example.com/x 'tbl::https://example.com/y z'""#,
+            &mut raw,
+        )
+        .unwrap();
+        context.insert("PKGDEP".to_string(), "foo  bar".to_string());
+        assert_eq!(
+            context.get_list("PKGDEP"),
+            Ok(Some(vec!["foo".to_string(), "bar".to_string()]))
+        );
+        assert_eq!(context.get_list("BUILDDEP"), Ok(None));
+        assert_eq!(
+            raw.get_list("SRCS").unwrap().unwrap(),
+            vec![
+                "file::rename=a b::https://example.com/x",
+                "tbl::https://example.com/y z"
+            ]
+        );
+        context.insert("PKGSUG".to_string(), "foo 'bar".to_string());
+        assert!(context.get_list("PKGSUG").is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! Fixtures shared by the tests of several modules.
+
+use crate::{apf::Context, package::Package};
+#[cfg(feature = "std")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A package named `name` with `fields`, not loaded from a directory.
+pub fn package(name: &str, fields: &[(&str, &str)]) -> Package {
+    let fields: Context = fields
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    Package::new(name, fields)
+}
+
+/// Write `content` to `path` under `root`, creating the directories leading
+/// to it.
+#[cfg(feature = "std")]
+pub fn write_file(root: &Path, path: &str, content: &str) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+/// Write a package with `spec` and `autobuild/defines` to
+/// `root/section/name`. Returns its directory.
+#[cfg(feature = "std")]
+pub fn write_package(root: &Path, section: &str, name: &str, spec: &str, defines: &str) -> PathBuf {
+    let dir = root.join(section).join(name);
+    write_file(&dir, "spec", spec);
+    write_file(&dir, "autobuild/defines", defines);
+    dir
+}
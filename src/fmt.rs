@@ -0,0 +1,470 @@
+//! Canonical formatting of spec and defines files, in the spirit of rustfmt.
+//!
+//! The canonical style:
+//! - Known fields come in `FIELD_ORDER`, other fields after them in their
+//!   original order. Comments directly above a field move with it.
+//! - Relationship and description fields are always double-quoted, single
+//!   quotes are replaced by double quotes where that does not change anything.
+//! - Relationship lists longer than `max_width` are wrapped with a line
+//!   continuation, aligned to the opening quote.
+//! - No trailing blanks, no indented top-level lines, at most one blank line in
+//!   a row and a single newline at the end of the file.
+//!
+//! Formatting never changes what a file evaluates to: if a reordered file
+//! evaluates differently, the original order is kept.
+
+use crate::apf::{self, quote, quote_with, Context, Node, ParseError, Style, SyntaxTree};
+
+/// Canonical order of well-known fields.
+pub const FIELD_ORDER: &[&str] = &[
+    "VER", "REL", "SRCS", "CHKSUMS", "CHKUPDATE", "SUBDIR", "DUMMYSRC", "PKGNAME", "PKGSEC",
+    "PKGDEP", "BUILDDEP", "PKGRECOM", "PKGSUG", "PKGPROV", "PKGREP", "PKGBREAK", "PKGCONFL",
+    "PKGDES",
+];
+
+/// Fields holding whitespace separated lists, which may be wrapped.
+pub const LIST_FIELDS: &[&str] = &[
+    "SRCS", "CHKSUMS", "PKGDEP", "BUILDDEP", "PKGRECOM", "PKGSUG", "PKGPROV", "PKGREP",
+    "PKGBREAK", "PKGCONFL",
+];
+
+/// Fields always written in double quotes.
+const QUOTED_FIELDS: &[&str] = &["PKGDES"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Lines of relationship lists are wrapped beyond this width.
+    pub max_width: usize,
+    /// Whether to sort fields into `FIELD_ORDER`.
+    pub reorder: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            max_width: 80,
+            reorder: true,
+        }
+    }
+}
+
+/// Content of `raw` if it is a single double-quoted string without command
+/// substitutions, i.e: `"foo $VER"`.
+fn double_quoted_content(raw: &str) -> Option<&str> {
+    let inner = raw.strip_prefix('"')?.strip_suffix('"')?;
+    let mut escaped = false;
+    for c in inner.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' | '`' => return None,
+            _ => (),
+        }
+    }
+    if escaped || inner.contains("$(") {
+        return None;
+    }
+    Some(inner)
+}
+
+/// Words of a list field, with line continuations removed.
+fn list_words(inner: &str) -> Option<Vec<&str>> {
+    let words: Vec<_> = inner.split_whitespace().filter(|w| *w != "\\").collect();
+    // An escape anywhere else is too clever to be moved around.
+    if words.iter().any(|w| w.contains('\\')) {
+        return None;
+    }
+    Some(words)
+}
+
+fn wrap_list(name: &str, words: &[&str], max_width: usize) -> String {
+    let indent = " ".repeat(name.len() + 2);
+    let mut result = String::from("\"");
+    let mut width = name.len() + 2;
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            // Room for the word, then ` \` or the closing quote.
+            if width + 1 + word.len() + 2 > max_width {
+                result.push_str(" \\\n");
+                result.push_str(&indent);
+                width = indent.len();
+            } else {
+                result.push(' ');
+                width += 1;
+            }
+        }
+        result.push_str(word);
+        width += word.len();
+    }
+    result.push('"');
+    result
+}
+
+/// Canonical form of the raw value of `name`.
+fn canonical_value(name: &str, raw: &str, options: &FormatOptions) -> String {
+    let is_list = LIST_FIELDS.contains(&name);
+    let always_quoted = is_list || QUOTED_FIELDS.contains(&name);
+
+    let quoted = if let Some(inner) = raw.strip_prefix('\'').and_then(|r| r.strip_suffix('\'')) {
+        if inner.contains(|c| "'\"$`\\".contains(c)) {
+            return raw.to_string();
+        }
+        quote_with(inner, Style::Double)
+    } else if !raw.is_empty() && quote(raw) == raw {
+        if !always_quoted {
+            return raw.to_string();
+        }
+        quote_with(raw, Style::Double)
+    } else {
+        raw.to_string()
+    };
+
+    if !is_list {
+        return quoted;
+    }
+    match double_quoted_content(&quoted).and_then(list_words) {
+        Some(words) => wrap_list(name, &words, options.max_width),
+        None => quoted,
+    }
+}
+
+/// Canonical whitespace between top-level statements.
+fn canonical_whitespace(ws: &str, first: bool) -> String {
+    if !ws.contains('\n') || ws.contains("\\\n") {
+        return ws.to_string();
+    }
+    let newlines = ws.matches('\n').count();
+    if first {
+        String::new()
+    } else if newlines >= 2 {
+        "\n\n".to_string()
+    } else {
+        "\n".to_string()
+    }
+}
+
+/// One line of a flat file.
+enum Line {
+    Blank,
+    Comment(String),
+    /// A single assignment, possibly followed by a comment.
+    Field(String, String),
+}
+
+/// Split a file made of one assignment or comment per line into lines.
+/// Returns `None` if anything else is found.
+fn flat_lines(tree: &SyntaxTree) -> Option<Vec<Line>> {
+    let mut lines = Vec::new();
+    let mut current: Option<Line> = None;
+    for node in tree.nodes() {
+        match node {
+            Node::Whitespace(ws) if ws.contains('\n') => {
+                if ws.contains("\\\n") {
+                    return None;
+                }
+                lines.push(current.take().unwrap_or(Line::Blank));
+                for _ in 1..ws.matches('\n').count() {
+                    lines.push(Line::Blank);
+                }
+            }
+            Node::Whitespace(ws) => match &mut current {
+                Some(Line::Field(_, text)) => text.push_str(ws),
+                _ => return None,
+            },
+            Node::Comment(c) => match &mut current {
+                None => current = Some(Line::Comment(c.clone())),
+                Some(Line::Field(_, text)) => text.push_str(c),
+                _ => return None,
+            },
+            Node::Assignment(a) => match current {
+                None => current = Some(Line::Field(a.name().to_string(), a.to_string())),
+                _ => return None,
+            },
+            Node::Separator(_) | Node::Command(_) => return None,
+        }
+    }
+    if let Some(line) = current {
+        lines.push(line);
+    }
+
+    Some(lines)
+}
+
+fn field_rank(name: &str) -> usize {
+    FIELD_ORDER
+        .iter()
+        .position(|f| *f == name)
+        .unwrap_or(FIELD_ORDER.len())
+}
+
+/// Sort the fields of a flat file, keeping comments above a field with it.
+/// Returns `None` if the file is not flat or already in order.
+fn reorder(tree: &SyntaxTree) -> Option<String> {
+    let lines = flat_lines(tree)?;
+    let first_field = lines.iter().position(|l| matches!(l, Line::Field(..)))?;
+    // Comments at the top, separated from the first field by a blank line.
+    let header_end = lines[..first_field]
+        .iter()
+        .rposition(|l| matches!(l, Line::Blank))
+        .unwrap_or(0);
+
+    let mut entries: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut pending = Vec::new();
+    for line in lines[header_end..].iter() {
+        match line {
+            Line::Blank => (),
+            Line::Comment(c) => pending.push(c.clone()),
+            Line::Field(name, text) => {
+                pending.push(text.clone());
+                entries.push((field_rank(name), std::mem::take(&mut pending)));
+            }
+        }
+    }
+    if entries.windows(2).all(|w| w[0].0 <= w[1].0) {
+        return None;
+    }
+    entries.sort_by_key(|e| e.0);
+
+    let mut result = String::new();
+    for line in lines[..header_end].iter() {
+        if let Line::Comment(c) = line {
+            result.push_str(c);
+            result.push('\n');
+        }
+    }
+    if header_end > 0 {
+        result.push('\n');
+    }
+    for line in entries.into_iter().flat_map(|e| e.1).chain(pending) {
+        result.push_str(&line);
+        result.push('\n');
+    }
+
+    Some(result)
+}
+
+/// Evaluate `c`, with list fields compared regardless of whitespace.
+fn evaluate(c: &str) -> Option<Context> {
+    let mut context = Context::new();
+    apf::parse(c, &mut context).ok()?;
+    for (k, v) in context.iter_mut() {
+        if LIST_FIELDS.contains(&k.as_str()) {
+            *v = v.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+    }
+    Some(context)
+}
+
+/// Format `c` with the default options.
+pub fn format(c: &str) -> Result<String, ParseError> {
+    format_with(c, &FormatOptions::default())
+}
+
+pub fn format_with(c: &str, options: &FormatOptions) -> Result<String, ParseError> {
+    let tree = apf::parse_lossless(c)?;
+    let mut nodes = tree.into_nodes();
+    for (i, node) in nodes.iter_mut().enumerate() {
+        match node {
+            Node::Assignment(a) => {
+                let value = canonical_value(a.name(), a.raw_value(), options);
+                a.set_raw_value(value);
+            }
+            Node::Whitespace(ws) => *ws = canonical_whitespace(ws, i == 0),
+            _ => (),
+        }
+    }
+    nodes.retain(|n| !matches!(n, Node::Whitespace(ws) if ws.is_empty()));
+    let tree = SyntaxTree::from_nodes(nodes);
+
+    let mut formatted = tree.to_string();
+    while formatted.ends_with('\n') {
+        formatted.pop();
+    }
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+
+    if options.reorder {
+        if let Some(reordered) = reorder(&tree) {
+            // Keep the original order if moving fields changes their values,
+            // i.e: a field referring to one defined after it.
+            if evaluate(&reordered).is_some() && evaluate(&reordered) == evaluate(c) {
+                formatted = reordered;
+            }
+        }
+    }
+
+    Ok(formatted)
+}
+
+/// A line of a `Diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+/// Line-based difference between a file and its formatted version.
+/// Displays as a unified diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    lines: Vec<DiffLine>,
+}
+
+/// Lines of context around each change.
+const DIFF_CONTEXT: usize = 3;
+
+impl Diff {
+    pub fn new(old: &str, new: &str) -> Self {
+        let a: Vec<_> = old.lines().collect();
+        let b: Vec<_> = new.lines().collect();
+        // Longest common subsequence, filled from the end.
+        let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i][j] = if a[i] == b[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        let mut lines = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                lines.push(DiffLine::Context(a[i].to_string()));
+                i += 1;
+                j += 1;
+            } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                lines.push(DiffLine::Removed(a[i].to_string()));
+                i += 1;
+            } else {
+                lines.push(DiffLine::Added(b[j].to_string()));
+                j += 1;
+            }
+        }
+
+        Diff { lines }
+    }
+
+    pub fn lines(&self) -> &[DiffLine] {
+        &self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.iter().all(|l| matches!(l, DiffLine::Context(_)))
+    }
+}
+
+impl std::fmt::Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let changed: Vec<_> = (0..self.lines.len())
+            .filter(|i| !matches!(self.lines[*i], DiffLine::Context(_)))
+            .collect();
+        let mut idx = 0;
+        while idx < changed.len() {
+            // Group changes whose context overlaps into one hunk.
+            let start = changed[idx].saturating_sub(DIFF_CONTEXT);
+            let mut last = changed[idx];
+            while idx + 1 < changed.len() && changed[idx + 1] <= last + 2 * DIFF_CONTEXT + 1 {
+                idx += 1;
+                last = changed[idx];
+            }
+            idx += 1;
+            let end = (last + DIFF_CONTEXT + 1).min(self.lines.len());
+
+            let before = &self.lines[..start];
+            let old_start = before.iter().filter(|l| !matches!(l, DiffLine::Added(_))).count();
+            let new_start = before.iter().filter(|l| !matches!(l, DiffLine::Removed(_))).count();
+            let hunk = &self.lines[start..end];
+            let old_len = hunk.iter().filter(|l| !matches!(l, DiffLine::Added(_))).count();
+            let new_len = hunk.iter().filter(|l| !matches!(l, DiffLine::Removed(_))).count();
+            writeln!(
+                f,
+                "@@ -{},{} +{},{} @@",
+                old_start + 1,
+                old_len,
+                new_start + 1,
+                new_len
+            )?;
+            for line in hunk {
+                match line {
+                    DiffLine::Context(l) => writeln!(f, " {}", l)?,
+                    DiffLine::Removed(l) => writeln!(f, "-{}", l)?,
+                    DiffLine::Added(l) => writeln!(f, "+{}", l)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check-only mode: the changes formatting would make, or `None` if `c` is
+/// already formatted.
+pub fn check(c: &str, options: &FormatOptions) -> Result<Option<Diff>, ParseError> {
+    let formatted = format_with(c, options)?;
+    if formatted == c {
+        return Ok(None);
+    }
+
+    Ok(Some(Diff::new(c, &formatted)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let input = "\n\n# Header\n\nPKGDES='A tool'  \n\n\n# Runtime deps\nPKGDEP=glibc\n   PKGSEC=utils\nPKGNAME=foo\nABTYPE=cmake";
+        let expected = "# Header\n\nPKGNAME=foo\nPKGSEC=utils\n# Runtime deps\nPKGDEP=\"glibc\"\nPKGDES=\"A tool\"\nABTYPE=cmake\n";
+        assert_eq!(format(input).unwrap(), expected);
+        assert_eq!(format(expected).unwrap(), expected);
+        assert_eq!(check(expected, &FormatOptions::default()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let deps: Vec<_> = (0..20).map(|i| format!("libfoo{}", i)).collect();
+        let input = format!("PKGDEP=\"{}\"\n", deps.join(" "));
+        let formatted = format(&input).unwrap();
+        assert!(formatted.lines().all(|l| l.len() <= 80));
+        assert!(formatted.starts_with("PKGDEP=\"libfoo0 libfoo1"));
+        assert!(formatted.contains(" \\\n        libfoo"));
+        assert_eq!(evaluate(&formatted), evaluate(&input));
+        // Short lists are joined back into one line.
+        assert_eq!(format("PKGDEP=\"a \\\n        b\"\n").unwrap(), "PKGDEP=\"a b\"\n");
+        // Anything fancy is left alone.
+        let fancy = "PKGDEP=\"a $(echo b)\"\n";
+        assert_eq!(format(fancy).unwrap(), fancy);
+    }
+
+    #[test]
+    fn test_no_unsafe_reorder() {
+        // SRCS would move before the field it refers to.
+        let input = "A=1\nSRCS=\"tbl::$A\"\n";
+        assert_eq!(format(input).unwrap(), input);
+        let input = "PKGDES=\"$VER\"\nVER=1\n";
+        assert_eq!(format(input).unwrap(), input);
+        // Not flat, only whitespace is touched.
+        let input = "PKGDES=x  \nif true; then\n    A=1\nfi\nVER=1\n";
+        assert_eq!(format(input).unwrap(), "PKGDES=\"x\"\nif true; then\n    A=1\nfi\nVER=1\n");
+    }
+
+    #[test]
+    fn test_diff() {
+        let diff = check("VER=1\nPKGDEP=a\nB=1\nC=1\nD=1\nE=1\nF=1\nG=1\nH=1\nPKGDES='x'\n", &FormatOptions {
+            reorder: false,
+            ..FormatOptions::default()
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            diff.to_string(),
+            "@@ -1,5 +1,5 @@\n VER=1\n-PKGDEP=a\n+PKGDEP=\"a\"\n B=1\n C=1\n D=1\n@@ -7,4 +7,4 @@\n F=1\n G=1\n H=1\n-PKGDES='x'\n+PKGDES=\"x\"\n"
+        );
+    }
+}
@@ -0,0 +1,408 @@
+//! Group files from the `groups/` directory of a tree, listing packages to
+//! build together. One entry per line, `#` starts a comment:
+//! - `SECTION/NAME` or `NAME` is a package,
+//! - `groups/NAME` includes every entry of another group.
+
+use crate::package::Package;
+use std::{collections::BTreeMap, fmt};
+#[cfg(feature = "std")]
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Prefix of entries referencing another group.
+pub const GROUP_PREFIX: &str = "groups/";
+
+/// A package listed in a group, i.e: `app-utils/foo`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PackageRef {
+    pub section: Option<String>,
+    pub name: String,
+}
+
+impl PackageRef {
+    /// Whether `package` is the one referenced: by name or directory name,
+    /// and by section if one is given.
+    pub fn matches(&self, package: &Package) -> bool {
+        let path = package.path();
+        let dir_name = path
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy());
+        if package.name() != self.name && dir_name.as_deref() != Some(&self.name) {
+            return false;
+        }
+        match &self.section {
+            Some(section) => path
+                .and_then(|p| p.parent())
+                .and_then(|p| p.file_name())
+                .is_some_and(|s| s.to_string_lossy() == *section),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for PackageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.section {
+            Some(section) => write!(f, "{}/{}", section, self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupEntry {
+    Package(PackageRef),
+    /// Another group, by name.
+    Group(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    /// In the order of the file.
+    pub entries: Vec<GroupEntry>,
+}
+
+#[derive(Debug)]
+pub enum GroupError {
+    #[cfg(feature = "std")]
+    IOError(PathBuf, io::Error),
+    /// An entry which is neither a package nor a group, with its group and line.
+    BadEntry {
+        group: String,
+        line: usize,
+        entry: String,
+    },
+    UnknownGroup(String),
+    /// A group listing a package not in the tree.
+    UnknownPackage {
+        group: String,
+        package: PackageRef,
+    },
+    /// Groups including each other, starting and ending with the same group.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for GroupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            GroupError::IOError(p, e) => write!(f, "Failed to read {}: {}", p.display(), e),
+            GroupError::BadEntry { group, line, entry } => {
+                write!(
+                    f,
+                    "Invalid entry `{}` in group {} at line {}",
+                    entry, group, line
+                )
+            }
+            GroupError::UnknownGroup(name) => write!(f, "No group called {}", name),
+            GroupError::UnknownPackage { group, package } => {
+                write!(
+                    f,
+                    "Group {} lists {}, which is not in the tree",
+                    group, package
+                )
+            }
+            GroupError::Cycle(groups) => {
+                write!(f, "Groups include each other: {}", groups.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for GroupError {}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty() && !s.starts_with('.') && s.chars().all(|c| !c.is_whitespace() && c != '/')
+}
+
+impl Group {
+    pub fn parse(name: &str, content: &str) -> Result<Self, GroupError> {
+        let mut entries = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let parsed = match entry.strip_prefix(GROUP_PREFIX) {
+                Some(group) if is_name(group) => Some(GroupEntry::Group(group.to_string())),
+                Some(_) => None,
+                None => match entry.split_once('/') {
+                    Some((section, name)) if is_name(section) && is_name(name) => {
+                        Some(GroupEntry::Package(PackageRef {
+                            section: Some(section.to_string()),
+                            name: name.to_string(),
+                        }))
+                    }
+                    Some(_) => None,
+                    None if is_name(entry) => Some(GroupEntry::Package(PackageRef {
+                        section: None,
+                        name: entry.to_string(),
+                    })),
+                    None => None,
+                },
+            };
+            match parsed {
+                Some(entry) => entries.push(entry),
+                None => {
+                    return Err(GroupError::BadEntry {
+                        group: name.to_string(),
+                        line: i + 1,
+                        entry: entry.to_string(),
+                    })
+                }
+            }
+        }
+
+        Ok(Group {
+            name: name.to_string(),
+            entries,
+        })
+    }
+}
+
+/// All groups of a tree, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Groups {
+    groups: BTreeMap<String, Group>,
+}
+
+impl Groups {
+    /// Load every file in `TREE/groups`. A tree without groups has none.
+    #[cfg(feature = "std")]
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Self, GroupError> {
+        let dir = root.as_ref().join("groups");
+        let mut groups = Groups::default();
+        if !dir.is_dir() {
+            return Ok(groups);
+        }
+        let entries = fs::read_dir(&dir).map_err(|e| GroupError::IOError(dir.clone(), e))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| GroupError::IOError(dir.clone(), e))?
+                .path();
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            if name.starts_with('.') || !path.is_file() {
+                continue;
+            }
+            let content =
+                fs::read_to_string(&path).map_err(|e| GroupError::IOError(path.clone(), e))?;
+            groups.insert(Group::parse(&name, &content)?);
+        }
+
+        Ok(groups)
+    }
+
+    pub fn insert(&mut self, group: Group) {
+        self.groups.insert(group.name.clone(), group);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Group> {
+        self.groups.get(name)
+    }
+
+    /// Groups, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = &Group> {
+        self.groups.values()
+    }
+
+    fn expand_into<'a>(
+        &'a self,
+        name: &str,
+        stack: &mut Vec<String>,
+        result: &mut Vec<&'a PackageRef>,
+    ) -> Result<(), GroupError> {
+        if let Some(i) = stack.iter().position(|g| g == name) {
+            let mut cycle = stack[i..].to_vec();
+            cycle.push(name.to_string());
+            return Err(GroupError::Cycle(cycle));
+        }
+        let group = self
+            .get(name)
+            .ok_or_else(|| GroupError::UnknownGroup(name.to_string()))?;
+        stack.push(name.to_string());
+        for entry in group.entries.iter() {
+            match entry {
+                GroupEntry::Package(package) => {
+                    if !result.contains(&package) {
+                        result.push(package);
+                    }
+                }
+                GroupEntry::Group(group) => self.expand_into(group, stack, result)?,
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Packages of the group `name`, nested groups included, in order of
+    /// appearance and without duplicates.
+    pub fn expand(&self, name: &str) -> Result<Vec<&PackageRef>, GroupError> {
+        let mut result = Vec::new();
+        self.expand_into(name, &mut Vec::new(), &mut result)?;
+        Ok(result)
+    }
+
+    /// Same as `expand`, with the entries looked up in `packages`.
+    pub fn resolve<'a>(
+        &self,
+        name: &str,
+        packages: &'a [Package],
+    ) -> Result<Vec<&'a Package>, GroupError> {
+        self.expand(name)?
+            .into_iter()
+            .map(|r| {
+                packages
+                    .iter()
+                    .find(|p| r.matches(p))
+                    .ok_or_else(|| GroupError::UnknownPackage {
+                        group: name.to_string(),
+                        package: r.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Check every group: included groups exist without cycles, and listed
+    /// packages are in `packages`.
+    pub fn validate(&self, packages: &[Package]) -> Vec<GroupError> {
+        let mut errors = Vec::new();
+        for group in self.iter() {
+            if let Err(e) = self.expand(&group.name) {
+                errors.push(e);
+            }
+            for entry in group.entries.iter() {
+                if let GroupEntry::Package(package) = entry {
+                    if !packages.iter().any(|p| package.matches(p)) {
+                        errors.push(GroupError::UnknownPackage {
+                            group: group.name.clone(),
+                            package: package.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let group = Group::parse(
+            "plasma",
+            "# Plasma\nkde/plasma-desktop # shell\n\n  kwin\ngroups/kde-frameworks\n",
+        )
+        .unwrap();
+        assert_eq!(
+            group.entries,
+            vec![
+                GroupEntry::Package(PackageRef {
+                    section: Some("kde".to_string()),
+                    name: "plasma-desktop".to_string()
+                }),
+                GroupEntry::Package(PackageRef {
+                    section: None,
+                    name: "kwin".to_string()
+                }),
+                GroupEntry::Group("kde-frameworks".to_string()),
+            ]
+        );
+        for bad in ["a/b/c", "groups/", "foo bar"] {
+            let err = Group::parse("g", &format!("ok\n{}\n", bad)).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid entry `{}` in group g at line 2", bad)
+            );
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut groups = Groups::default();
+        groups.insert(Group::parse("a", "foo\ngroups/b\nbar\n").unwrap());
+        groups.insert(Group::parse("b", "bar\nbaz\n").unwrap());
+        let names: Vec<_> = groups
+            .expand("a")
+            .unwrap()
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(names, vec!["foo", "bar", "baz"]);
+        assert!(matches!(
+            groups.expand("c"),
+            Err(GroupError::UnknownGroup(_))
+        ));
+
+        groups.insert(Group::parse("b", "groups/c\n").unwrap());
+        groups.insert(Group::parse("c", "groups/a\n").unwrap());
+        assert_eq!(
+            groups.expand("a").unwrap_err().to_string(),
+            "Groups include each other: a -> b -> c -> a"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_load_resolve() {
+        let root = tempfile::tempdir().unwrap();
+        let mut packages = Vec::new();
+        for (section, name) in [("app-utils", "foo"), ("core-libs", "bar")] {
+            let dir = root.path().join(section).join(name);
+            fs::create_dir_all(dir.join("autobuild")).unwrap();
+            fs::write(dir.join("spec"), "VER=1\n").unwrap();
+            fs::write(dir.join("autobuild/defines"), "").unwrap();
+            packages.push(Package::from_dir(&dir).unwrap());
+        }
+        fs::create_dir_all(root.path().join("groups")).unwrap();
+        fs::write(
+            root.path().join("groups/base"),
+            "core-libs/bar\ngroups/extra\n",
+        )
+        .unwrap();
+        fs::write(
+            root.path().join("groups/extra"),
+            "foo\napp-utils/bar\nmissing\n",
+        )
+        .unwrap();
+
+        let groups = Groups::load(root.path()).unwrap();
+        assert_eq!(groups.iter().count(), 2);
+        let errors: Vec<_> = groups
+            .validate(&packages)
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Group extra lists app-utils/bar, which is not in the tree",
+                "Group extra lists missing, which is not in the tree",
+            ]
+        );
+        assert!(groups.resolve("base", &packages).is_err());
+
+        fs::write(root.path().join("groups/extra"), "foo\n").unwrap();
+        let groups = Groups::load(root.path()).unwrap();
+        let resolved: Vec<_> = groups
+            .resolve("base", &packages)
+            .unwrap()
+            .iter()
+            .map(|p| p.name())
+            .collect();
+        assert_eq!(resolved, vec!["bar", "foo"]);
+        assert!(Groups::load(root.path().join("nowhere"))
+            .unwrap()
+            .get("base")
+            .is_none());
+    }
+}
@@ -0,0 +1,302 @@
+//! `debian/control` stanzas of packages, as dpkg sees them once built.
+//! i.e: `PKGDEP="glibc>=2.31"` becomes `Depends: glibc (>= 2.31)`.
+
+use crate::{
+    apf::Context,
+    dependency::{parse_dependencies, Comparator, Dependency, DependencyError},
+    maintainer::maintainers_of,
+    package::Package,
+    version::{Version, VersionError},
+};
+use std::fmt;
+
+/// Relationship fields and the control fields they become, in output order.
+const RELATIONSHIPS: &[(&str, &str)] = &[
+    ("PKGDEP", "Depends"),
+    ("PKGRECOM", "Recommends"),
+    ("PKGSUG", "Suggests"),
+    ("PKGBREAK", "Breaks"),
+    ("PKGCONFL", "Conflicts"),
+    ("PKGREP", "Replaces"),
+    ("PKGPROV", "Provides"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ControlError {
+    /// Package name, error
+    InvalidVersion(String, VersionError),
+    /// Package name, field, error
+    InvalidDependency(String, String, DependencyError),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlError::InvalidVersion(package, e) => {
+                write!(f, "Invalid version of {}: {}", package, e)
+            }
+            ControlError::InvalidDependency(package, field, e) => {
+                write!(f, "Invalid {} of {}: {}", field, package, e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+/// Fields of one binary package, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlStanza {
+    fields: Vec<(String, String)>,
+}
+
+impl ControlStanza {
+    /// Value of `key`, i.e: `get("Depends")`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Append a field, i.e: `Installed-Size` once the package is built.
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.fields.push((key.to_string(), value.to_string()));
+    }
+}
+
+impl fmt::Display for ControlStanza {
+    /// One field per line; lines after the first of a value are indented,
+    /// and empty ones written as ` .`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in self.fields.iter() {
+            let mut lines = value.lines();
+            writeln!(f, "{}: {}", key, lines.next().unwrap_or_default())?;
+            for line in lines {
+                if line.trim().is_empty() {
+                    writeln!(f, " .")?;
+                } else {
+                    writeln!(f, " {}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A dependency in dpkg syntax, i.e: `glibc>=2.31` becomes `glibc (>= 2.31)`
+/// and `python-3==3.8.2` becomes `python-3 (= 3.8.2)`.
+pub fn debian_relationship(dependency: &Dependency) -> String {
+    let mut result = dependency.name.clone();
+    if let Some(arch) = &dependency.arch_qualifier {
+        result.push(':');
+        result.push_str(arch);
+    }
+    if let Some(req) = &dependency.version_req {
+        let op = match req.op {
+            Comparator::GreaterOrEqual => ">=",
+            Comparator::LessOrEqual => "<=",
+            Comparator::Equal => "=",
+        };
+        result.push_str(&format!(" ({} {})", op, req.version));
+    }
+    result
+}
+
+/// Value of a relationship field in dpkg syntax, i.e: `glibc>=2.31 bash`
+/// becomes `glibc (>= 2.31), bash`.
+pub fn convert_relationships(value: &str) -> Result<String, DependencyError> {
+    Ok(parse_dependencies(value)?
+        .iter()
+        .map(debian_relationship)
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+/// Stanza of one binary package. Spec variables missing from a sub-package,
+/// i.e: with `SpecInheritance::None`, are taken from the parent.
+fn stanza(
+    name: &str,
+    fields: &Context,
+    parent: &Context,
+    arch: &str,
+    maintainer: Option<&str>,
+) -> Result<ControlStanza, ControlError> {
+    let get = |key: &str| fields.get(key).or_else(|| parent.get(key));
+    let version = match fields.get("VER") {
+        Some(_) => Version::from_context(fields),
+        None => Version::from_context(parent),
+    }
+    .map_err(|e| ControlError::InvalidVersion(name.to_string(), e))?;
+
+    let mut stanza = ControlStanza::default();
+    stanza.push("Package", name);
+    stanza.push("Version", &version.to_string());
+    if let Some(section) = fields.get("PKGSEC") {
+        stanza.push("Section", section);
+    }
+    let noarch = get("ABHOST").is_some_and(|h| h == "noarch");
+    stanza.push("Architecture", if noarch { "all" } else { arch });
+    if let Some(maintainer) = maintainer {
+        stanza.push("Maintainer", maintainer);
+    }
+    for (field, control) in RELATIONSHIPS {
+        let value = match fields.get(*field) {
+            Some(value) if !value.trim().is_empty() => value,
+            _ => continue,
+        };
+        let value = convert_relationships(value)
+            .map_err(|e| ControlError::InvalidDependency(name.to_string(), field.to_string(), e))?;
+        stanza.push(control, &value);
+    }
+    if let Some(description) = fields.get("PKGDES") {
+        stanza.push("Description", description);
+    }
+
+    Ok(stanza)
+}
+
+/// Stanzas of the binary packages `package` builds for `arch`: the package
+/// itself, or each sub-package of a group building for `arch`. Empty if it
+/// does not build for `arch` at all.
+/// `package` should be evaluated for `arch`, i.e: by `Package::resolve_for`;
+/// overrides left are folded in.
+pub fn to_control(package: &Package, arch: &str) -> Result<Vec<ControlStanza>, ControlError> {
+    if !package.builds_on(arch) {
+        return Ok(Vec::new());
+    }
+    let maintainer = maintainers_of(package).first().map(|m| m.to_string());
+    let parent = package.resolve(arch);
+    if package.subpackages().is_empty() {
+        return Ok(vec![stanza(
+            package.name(),
+            &parent,
+            &parent,
+            arch,
+            maintainer.as_deref(),
+        )?]);
+    }
+
+    package
+        .subpackages()
+        .iter()
+        .filter(|s| s.builds_on(arch))
+        .map(|s| {
+            stanza(
+                s.name(),
+                &s.resolve(arch),
+                &parent,
+                arch,
+                maintainer.as_deref(),
+            )
+        })
+        .collect()
+}
+
+/// Stanzas separated by blank lines, as in a `debian/control` file.
+pub fn format_control(stanzas: &[ControlStanza]) -> String {
+    stanzas
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::package;
+    use crate::providers::stanzas;
+    #[cfg(feature = "std")]
+    use std::fs;
+
+    #[test]
+    fn test_convert_relationships() {
+        assert_eq!(
+            convert_relationships(" glibc>=2.31 gcc-runtime:amd64\n python-3==3.8.2 foo<=1:2.0-1")
+                .unwrap(),
+            "glibc (>= 2.31), gcc-runtime:amd64, python-3 (= 3.8.2), foo (<= 1:2.0-1)"
+        );
+        assert_eq!(convert_relationships("").unwrap(), "");
+        assert!(convert_relationships("foo>2").is_err());
+    }
+
+    #[test]
+    fn test_to_control() {
+        let foo = package(
+            "foo",
+            &[
+                ("VER", "1.2"),
+                ("REL", "3"),
+                ("PKGEPOCH", "1"),
+                ("PKGSEC", "utils"),
+                ("PKGDEP", "glibc>=2.31 bash"),
+                ("PKGDEP__ARM64", "glibc>=2.31"),
+                ("PKGPROV", "bar==1.2"),
+                ("PKGDES", "Foo utilities"),
+                ("MAINTAINER", "Foo Bar <foo@example.com>"),
+            ],
+        );
+        let control = to_control(&foo, "arm64").unwrap();
+        assert_eq!(control.len(), 1);
+        assert_eq!(
+            control[0].to_string(),
+            "Package: foo\n\
+             Version: 1:1.2-3\n\
+             Section: utils\n\
+             Architecture: arm64\n\
+             Maintainer: Foo Bar <foo@example.com>\n\
+             Depends: glibc (>= 2.31)\n\
+             Provides: bar (= 1.2)\n\
+             Description: Foo utilities\n"
+        );
+
+        let noarch = package("tzdata", &[("VER", "2024a"), ("ABHOST", "noarch")]);
+        let control = format_control(&to_control(&noarch, "amd64").unwrap());
+        let parsed = stanzas(&control);
+        assert_eq!(parsed.len(), 1);
+        assert!(parsed[0].contains(&("Architecture", "all".to_string())));
+
+        let wine = package("wine", &[("VER", "9.0"), ("FAIL_ARCH", "amd64")]);
+        assert!(to_control(&wine, "amd64").unwrap().is_empty());
+
+        let bad = package("bad", &[("VER", "1"), ("PKGDEP", "foo>2")]);
+        assert_eq!(
+            to_control(&bad, "amd64").unwrap_err().to_string(),
+            "Invalid PKGDEP of bad: Invalid version requirement in dependency `foo>2`."
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_subpackages() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("foo");
+        fs::create_dir_all(dir.join("autobuild").join("01-libfoo")).unwrap();
+        fs::create_dir_all(dir.join("autobuild").join("02-foo-dev")).unwrap();
+        fs::write(dir.join("spec"), "VER=1.2\n").unwrap();
+        fs::write(
+            dir.join("autobuild").join("01-libfoo").join("defines"),
+            "PKGNAME=libfoo\nPKGDES=\"Foo library\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("autobuild").join("02-foo-dev").join("defines"),
+            "PKGNAME=foo-dev\nPKGDEP=\"libfoo==$VER\"\nABHOST=noarch\n",
+        )
+        .unwrap();
+
+        let package = Package::from_dir(&dir).unwrap();
+        let control = to_control(&package, "amd64").unwrap();
+        let formatted = format_control(&control);
+        assert_eq!(stanzas(&formatted).len(), 2);
+        assert_eq!(control[0].get("Package"), Some("libfoo"));
+        assert_eq!(control[0].get("Version"), Some("1.2"));
+        assert_eq!(control[1].get("Architecture"), Some("all"));
+        assert_eq!(control[1].get("Depends"), Some("libfoo (= 1.2)"));
+    }
+}
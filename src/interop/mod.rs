@@ -0,0 +1,200 @@
+//! Converting packages to and from the formats of other packaging tools.
+//!
+//! The importers are experimental: they only read the metadata of simple
+//! build recipes, as a start when porting a package into the tree.
+
+pub mod debian;
+pub mod pkgbuild;
+pub mod rpm;
+
+use crate::{
+    dependency::Dependency,
+    package::NewPackage,
+    srcs::{Source, SourceOptions, VcsKind},
+};
+use std::fmt;
+
+/// A package read from the build recipe of another distribution, i.e: to
+/// write out with `package::scaffold`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Imported {
+    pub package: NewPackage,
+    /// What could not be carried over exactly and needs a look by hand,
+    /// i.e: a `pkgconfig(zlib)` dependency.
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ImportError {
+    /// A field every package needs, i.e: `pkgver`.
+    MissingField(String),
+    /// Line where a quote or an array is left open.
+    Unterminated(usize),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::MissingField(e) => write!(f, "Missing field `{}`.", e),
+            ImportError::Unterminated(line) => write!(f, "Unterminated value at line {}.", line),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Whether `url` names an archive, which is unpacked, or a single file.
+fn is_archive(url: &str) -> bool {
+    let name = url.rsplit('/').next().unwrap_or(url);
+    [
+        ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz", ".tar.zst", ".tar.lz",
+        ".zip", ".7z",
+    ]
+    .iter()
+    .any(|ext| name.ends_with(ext))
+}
+
+/// `SRCS` entry of a source URL, i.e: `tbl::https://example.com/foo.tar.xz`.
+/// `rename` is the name of the file once downloaded.
+fn source(url: &str, rename: Option<String>) -> Source {
+    let options = SourceOptions::new();
+    if is_archive(url) {
+        Source::Tarball {
+            url: url.to_string(),
+            rename,
+            options,
+        }
+    } else {
+        Source::File {
+            url: url.to_string(),
+            rename,
+            options,
+        }
+    }
+}
+
+/// `SRCS` entry of a version control URL with the revision in its fragment,
+/// i.e: `git+https://example.com/foo.git#tag=v1.0`. `None` for URLs of any
+/// other kind.
+fn vcs_source(url: &str, rename: Option<String>) -> Option<Source> {
+    let (kind, url) = url.split_once('+')?;
+    let (url, fragment) = url.split_once('#').unwrap_or((url, ""));
+    let (key, value) = fragment.split_once('=').unwrap_or(("", ""));
+    let revision = (!value.is_empty()).then(|| value.to_string());
+    let options = SourceOptions::new();
+    let url = url.to_string();
+    let kind = match kind {
+        "git" => {
+            let (commit, branch) = match key {
+                "tag" => (revision.map(|t| format!("tags/{}", t)), None),
+                "commit" => (revision, None),
+                "branch" => (None, revision),
+                _ => (None, None),
+            };
+            return Some(Source::Git {
+                url,
+                commit,
+                branch,
+                rename,
+                options,
+            });
+        }
+        "svn" => VcsKind::Svn,
+        "hg" => VcsKind::Hg,
+        "bzr" => VcsKind::Bzr,
+        "fossil" => VcsKind::Fossil,
+        _ => return None,
+    };
+
+    Some(Source::Vcs {
+        kind,
+        url,
+        revision,
+        rename,
+        options,
+    })
+}
+
+/// `PKGDEP` entry of `name`, optionally with a version comparison written as
+/// in RPM and pacman, i.e: `("foo", Some((">=", "1.0")))`. `>` and `<` have
+/// no equivalent, so they are dropped with a note, as are names which are
+/// not valid in the tree.
+fn dependency(name: &str, req: Option<(&str, &str)>, notes: &mut Vec<String>) -> Option<String> {
+    let entry = match req {
+        None => name.to_string(),
+        Some((op, version)) => match op {
+            ">=" | "<=" => format!("{}{}{}", name, op, version),
+            "=" | "==" => format!("{}=={}", name, version),
+            _ => {
+                notes.push(format!(
+                    "Dropped the version of dependency `{} {} {}`",
+                    name, op, version
+                ));
+                name.to_string()
+            }
+        },
+    };
+    match entry.parse::<Dependency>() {
+        Ok(_) => Some(entry),
+        Err(_) => match name.parse::<Dependency>() {
+            // i.e: a version only valid in the original distribution.
+            Ok(_) => {
+                notes.push(format!("Dropped the version of dependency `{}`", entry));
+                Some(name.to_string())
+            }
+            Err(_) => {
+                notes.push(format!("Skipped dependency `{}`", entry));
+                None
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source() {
+        let s = |url: &str| match vcs_source(url, None) {
+            Some(s) => s.to_string(),
+            None => source(url, None).to_string(),
+        };
+        assert_eq!(
+            s("https://example.com/foo-1.0.tar.xz"),
+            "tbl::https://example.com/foo-1.0.tar.xz"
+        );
+        assert_eq!(
+            s("https://example.com/foo.patch"),
+            "file::https://example.com/foo.patch"
+        );
+        assert_eq!(
+            s("git+https://example.com/foo.git#tag=v1.0"),
+            "git::commit=tags/v1.0::https://example.com/foo.git"
+        );
+        assert_eq!(
+            s("svn+https://example.com/foo#revision=42"),
+            "svn::revision=42::https://example.com/foo"
+        );
+    }
+
+    #[test]
+    fn test_dependency() {
+        let mut notes = Vec::new();
+        assert_eq!(
+            dependency("foo", Some(("=", "1.0")), &mut notes).as_deref(),
+            Some("foo==1.0")
+        );
+        assert_eq!(
+            dependency("foo", Some((">=", "1:2.0")), &mut notes).as_deref(),
+            Some("foo>=1:2.0")
+        );
+        assert!(notes.is_empty());
+        assert_eq!(
+            dependency("foo", Some((">", "1.0")), &mut notes).as_deref(),
+            Some("foo")
+        );
+        assert_eq!(dependency("pkgconfig(zlib)", None, &mut notes), None);
+        assert_eq!(notes.len(), 2);
+    }
+}
@@ -0,0 +1,324 @@
+//! Importing the metadata of an Arch Linux `PKGBUILD`.
+//!
+//! Only top level assignments are read, with quotes and `$var` or `${var}`
+//! expanded; functions and anything computed are ignored.
+
+use super::{dependency, source, vcs_source, ImportError, Imported};
+use std::collections::HashMap;
+
+/// Checksum arrays and the algorithm of their `CHKSUMS` entries, by
+/// preference.
+const CHECKSUMS: &[(&str, &str)] = &[
+    ("sha256sums", "sha256"),
+    ("sha512sums", "sha512"),
+    ("b2sums", "blake2b"),
+];
+
+/// Whether `s` is open at the end: an unclosed quote or parenthesis.
+fn is_open(s: &str) -> bool {
+    let mut depth = 0i32;
+    let mut quote = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => (),
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            _ => (),
+        }
+    }
+    quote.is_some() || depth > 0
+}
+
+/// Expand the variable starting after the `$` at the start of `chars`.
+fn expand(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    vars: &HashMap<String, Vec<String>>,
+    notes: &mut Vec<String>,
+) -> String {
+    let (name, literal) = if chars.peek() == Some(&'{') {
+        chars.next();
+        let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+        let literal = format!("${{{}}}", name);
+        (name, literal)
+    } else {
+        let mut name = String::new();
+        while let Some(c) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || *c == '_') {
+                break;
+            }
+            name.push(*c);
+            chars.next();
+        }
+        let literal = format!("${}", name);
+        (name, literal)
+    };
+    match vars.get(&name).and_then(|v| v.first()) {
+        Some(value) => value.clone(),
+        None if name.is_empty() => literal,
+        None => {
+            notes.push(format!("Cannot expand `{}`", literal));
+            literal
+        }
+    }
+}
+
+/// Words of a shell value, quotes removed and variables expanded.
+/// Comments end at the end of their line.
+fn words(value: &str, vars: &HashMap<String, Vec<String>>, notes: &mut Vec<String>) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                result.extend(word.take());
+            }
+            '#' if word.is_none() => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                w.extend(chars.by_ref().take_while(|c| *c != '\''));
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => w.extend(chars.next()),
+                        '$' => w.push_str(&expand(&mut chars, vars, notes)),
+                        c => w.push(c),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            '$' => {
+                let expanded = expand(&mut chars, vars, notes);
+                word.get_or_insert_with(String::new).push_str(&expanded);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    result.extend(word);
+    result
+}
+
+/// Top level assignments of `content`, in order. Arrays are left in their
+/// parentheses.
+fn assignments(content: &str) -> Result<Vec<(String, String)>, ImportError> {
+    let mut result = Vec::new();
+    let mut in_function = false;
+    let mut lines = content.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        if in_function {
+            in_function = !line.starts_with('}');
+            continue;
+        }
+        if line.trim_end().ends_with("() {") || line.trim_end().ends_with("()") {
+            in_function = true;
+            continue;
+        }
+        let (name, value) = match line.split_once('=') {
+            Some((name, value))
+                if !name.is_empty()
+                    && !name.starts_with(|c: char| c.is_ascii_digit())
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+            {
+                (name, value)
+            }
+            _ => continue,
+        };
+        let mut value = value.to_string();
+        while is_open(&value) {
+            let (_, line) = lines.next().ok_or(ImportError::Unterminated(i + 1))?;
+            value.push('\n');
+            value.push_str(line);
+        }
+        result.push((name.to_string(), value));
+    }
+    Ok(result)
+}
+
+/// Split a `depends` entry into its name and version comparison, i.e:
+/// `foo>=1.0` is `("foo", Some((">=", "1.0")))`.
+fn split_requirement(entry: &str) -> (&str, Option<(&str, &str)>) {
+    let start = match entry.find(['<', '>', '=']) {
+        Some(start) => start,
+        None => return (entry, None),
+    };
+    let end = entry[start..]
+        .find(|c| !"<>=".contains(c))
+        .map_or(entry.len(), |i| start + i);
+    (&entry[..start], Some((&entry[start..end], &entry[end..])))
+}
+
+/// Read the name, version, description, sources, checksums and
+/// dependencies of a `PKGBUILD`. Variables in `source` referring to
+/// `pkgver` are kept as `${VER}`. The section is left for the maintainer to
+/// choose.
+pub fn import(content: &str) -> Result<Imported, ImportError> {
+    let mut vars: HashMap<String, Vec<String>> = HashMap::new();
+    let mut notes = Vec::new();
+    for (name, value) in assignments(content)? {
+        let value = value.trim();
+        let value = match value.strip_prefix('(') {
+            Some(inner) => inner.strip_suffix(')').unwrap_or(inner),
+            None => value,
+        };
+        let words = if name == "source" {
+            let mut templated = vars.clone();
+            templated.insert("pkgver".to_string(), vec!["${VER}".to_string()]);
+            words(value, &templated, &mut notes)
+        } else {
+            words(value, &vars, &mut notes)
+        };
+        vars.insert(name, words);
+    }
+
+    let mut imported = Imported::default();
+    let scalar = |name: &str| vars.get(name).and_then(|v| v.first()).cloned();
+    let package = &mut imported.package;
+    package.name = scalar("pkgname").ok_or(ImportError::MissingField("pkgname".to_string()))?;
+    package.ver = scalar("pkgver").ok_or(ImportError::MissingField("pkgver".to_string()))?;
+    package.description = scalar("pkgdesc").unwrap_or_default();
+    if vars.get("pkgname").is_some_and(|n| n.len() > 1) {
+        notes.push("Only the first package of a split package is imported".to_string());
+    }
+
+    for entry in vars.get("source").into_iter().flatten() {
+        let (rename, url) = match entry.split_once("::") {
+            Some((rename, url)) => (Some(rename.to_string()), url),
+            None => (None, entry.as_str()),
+        };
+        if let Some(source) = vcs_source(url, rename.clone()) {
+            package.srcs.push(source.to_string());
+        } else if url.contains("://") {
+            package.srcs.push(source(url, rename).to_string());
+        } else {
+            notes.push(format!("Skipped local source `{}`", url));
+        }
+    }
+    if let Some((name, algorithm)) = CHECKSUMS.iter().find(|(n, _)| vars.contains_key(*n)) {
+        package.chksums = vars[*name]
+            .iter()
+            .map(|c| match c.as_str() {
+                "SKIP" => c.clone(),
+                _ => format!("{}::{}", algorithm, c),
+            })
+            .collect();
+    }
+    if package.chksums.len() != package.srcs.len() {
+        notes.push("Checksums do not match the sources".to_string());
+        package.chksums.clear();
+    }
+
+    for (name, deps) in [
+        ("depends", &mut package.dependencies),
+        ("makedepends", &mut package.build_dependencies),
+    ] {
+        for entry in vars.get(name).into_iter().flatten() {
+            let (dep, req) = split_requirement(entry);
+            deps.extend(dependency(dep, req, &mut notes));
+        }
+    }
+    if vars.contains_key("optdepends") {
+        notes.push("Skipped optdepends".to_string());
+    }
+
+    imported.notes = notes;
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PKGBUILD: &str = r#"# Maintainer: Foo Bar <foo@example.com>
+_name=foo
+pkgname=${_name}-utils
+pkgver=1.2.3
+pkgrel=2
+pkgdesc="Utilities for \"foo\""
+arch=('x86_64')
+depends=('glibc>=2.31' 'zlib' # Compression
+         'bar=1.0' 'baz>2')
+makedepends=(cmake 'pkgconfig(qux)')
+source=("https://example.com/$_name-$pkgver.tar.gz"
+        "git+https://example.com/extra.git#tag=v${pkgver}"
+        fix-build.patch)
+sha256sums=('0123abcd'
+            'SKIP'
+            'abcd0123')
+
+build() {
+  cd "$srcdir/$_name-$pkgver"
+  pkgver=0
+  make
+}
+"#;
+
+    #[test]
+    fn test_import() {
+        let imported = import(PKGBUILD).unwrap();
+        let package = &imported.package;
+        assert_eq!(package.name, "foo-utils");
+        assert_eq!(package.ver, "1.2.3");
+        assert_eq!(package.rel, 0);
+        assert_eq!(package.description, "Utilities for \"foo\"");
+        assert_eq!(
+            package.srcs,
+            vec![
+                "tbl::https://example.com/foo-${VER}.tar.gz",
+                "git::commit=tags/v${VER}::https://example.com/extra.git",
+            ]
+        );
+        // Checksums are left out along with the local source.
+        assert!(package.chksums.is_empty());
+        assert_eq!(
+            package.dependencies,
+            vec!["glibc>=2.31", "zlib", "bar==1.0", "baz"]
+        );
+        assert_eq!(package.build_dependencies, vec!["cmake"]);
+        assert_eq!(imported.notes.len(), 4);
+        let package = package.to_package().unwrap();
+        assert_eq!(
+            package.fields()["SRCS"]
+                .split_whitespace()
+                .collect::<Vec<_>>(),
+            vec![
+                "tbl::https://example.com/foo-1.2.3.tar.gz",
+                "git::commit=tags/v1.2.3::https://example.com/extra.git",
+            ]
+        );
+
+        assert_eq!(
+            import("pkgname=foo\n").unwrap_err(),
+            ImportError::MissingField("pkgver".to_string())
+        );
+        assert_eq!(
+            import("pkgname=foo\ndepends=(bar\n").unwrap_err(),
+            ImportError::Unterminated(2)
+        );
+    }
+
+    #[test]
+    fn test_chksums() {
+        let imported = import(
+            "pkgname=foo\npkgver=1\nsource=(https://example.com/foo.tar.xz)\nb2sums=(abcd)\n",
+        )
+        .unwrap();
+        assert_eq!(imported.package.chksums, vec!["blake2b::abcd"]);
+        assert!(imported.notes.is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+//! Importing the metadata of an RPM `.spec` file.
+//!
+//! Only the preamble of the main package is read, with `%define`, `%global`
+//! and the macros of the tags expanded; conditionals are not evaluated.
+
+use super::{dependency, source, ImportError, Imported};
+use std::collections::HashMap;
+
+/// Section markers ending the preamble of the main package.
+const SECTIONS: &[&str] = &[
+    "%description",
+    "%package",
+    "%prep",
+    "%build",
+    "%install",
+    "%check",
+    "%files",
+    "%changelog",
+];
+
+/// Expand the macros of `value`, i.e: `%{name}`, `%version` and
+/// `%{?dist}`. Unknown macros are kept as they are.
+fn expand(value: &str, macros: &HashMap<String, String>, notes: &mut Vec<String>) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        let (body, literal) = match chars.peek() {
+            Some('%') => {
+                chars.next();
+                result.push('%');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut depth = 1;
+                let mut body = String::new();
+                for c in chars.by_ref() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    body.push(c);
+                }
+                let literal = format!("%{{{}}}", body);
+                (body, literal)
+            }
+            _ => {
+                let mut body = String::new();
+                while let Some(c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || *c == '_') {
+                        break;
+                    }
+                    body.push(*c);
+                    chars.next();
+                }
+                let literal = format!("%{}", body);
+                (body, literal)
+            }
+        };
+        match body.strip_prefix('?') {
+            // `%{?name}` and `%{?name:value}`, empty unless `name` is defined.
+            Some(conditional) => {
+                let (name, then) = match conditional.split_once(':') {
+                    Some((name, then)) => (name, Some(then)),
+                    None => (conditional, None),
+                };
+                if let Some(value) = macros.get(name) {
+                    match then {
+                        Some(then) => result.push_str(&expand(then, macros, notes)),
+                        None => result.push_str(value),
+                    }
+                }
+            }
+            None => match macros.get(&body) {
+                Some(value) => result.push_str(value),
+                None => {
+                    if !body.is_empty() {
+                        notes.push(format!("Cannot expand `{}`", literal));
+                    }
+                    result.push_str(&literal);
+                }
+            },
+        }
+    }
+    result
+}
+
+/// Entries of a `Requires` or `BuildRequires` tag, i.e:
+/// `foo >= 1.0, bar` is `[("foo", Some((">=", "1.0"))), ("bar", None)]`.
+fn requirements(value: &str) -> Vec<(&str, Option<(&str, &str)>)> {
+    let tokens: Vec<_> = value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let is_op = |t: &str| matches!(t, ">=" | "<=" | "=" | "==" | ">" | "<");
+        match (tokens.get(i + 1), tokens.get(i + 2)) {
+            (Some(op), Some(version)) if is_op(op) => {
+                result.push((tokens[i], Some((*op, *version))));
+                i += 3;
+            }
+            _ => {
+                result.push((tokens[i], None));
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Read the name, version, summary, sources and dependencies of an RPM
+/// spec. `%{version}` in sources is kept as `${VER}`. The section is left
+/// for the maintainer to choose.
+pub fn import(content: &str) -> Result<Imported, ImportError> {
+    let mut macros: HashMap<String, String> = HashMap::new();
+    let mut notes = Vec::new();
+    let mut sources = Vec::new();
+    let mut imported = Imported::default();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let first = line.split_whitespace().next().unwrap_or_default();
+        if SECTIONS.contains(&first) {
+            break;
+        }
+        if first == "%define" || first == "%global" {
+            let mut parts = line[first.len()..].trim().splitn(2, char::is_whitespace);
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                let value = expand(value.trim(), &macros, &mut notes);
+                macros.insert(name.to_string(), value);
+            }
+            continue;
+        }
+        if line.starts_with('%') {
+            continue;
+        }
+        let (tag, value) = match line.split_once(':') {
+            Some((tag, value)) if !tag.contains(char::is_whitespace) => {
+                (tag.to_ascii_lowercase(), value.trim())
+            }
+            _ => continue,
+        };
+        if tag == "source"
+            || tag
+                .strip_prefix("source")
+                .is_some_and(|n| n.parse::<u32>().is_ok())
+        {
+            // Expanded at the end, once the version is known.
+            sources.push(value.to_string());
+            continue;
+        }
+        let value = expand(value, &macros, &mut notes);
+        match tag.as_str() {
+            "name" | "version" | "release" | "url" | "summary" => {
+                macros.insert(tag.clone(), value.clone());
+            }
+            "requires" | "buildrequires" => {
+                let deps = match tag.as_str() {
+                    "requires" => &mut imported.package.dependencies,
+                    _ => &mut imported.package.build_dependencies,
+                };
+                for (name, req) in requirements(&value) {
+                    deps.extend(dependency(name, req, &mut notes));
+                }
+            }
+            _ if tag.starts_with("requires(") => {
+                notes.push(format!("Skipped `{}`", line));
+            }
+            _ => (),
+        }
+    }
+
+    let package = &mut imported.package;
+    package.name = macros
+        .get("name")
+        .cloned()
+        .ok_or(ImportError::MissingField("Name".to_string()))?;
+    package.ver = macros
+        .get("version")
+        .cloned()
+        .ok_or(ImportError::MissingField("Version".to_string()))?;
+    package.description = macros.get("summary").cloned().unwrap_or_default();
+
+    macros.insert("version".to_string(), "${VER}".to_string());
+    for value in sources {
+        let url = expand(&value, &macros, &mut notes);
+        // `URL#/NAME` names the downloaded file.
+        let (url, rename) = match url.split_once("#/") {
+            Some((url, rename)) => (url.to_string(), Some(rename.to_string())),
+            None => (url, None),
+        };
+        if url.contains("://") {
+            package.srcs.push(source(&url, rename).to_string());
+        } else {
+            notes.push(format!("Skipped local source `{}`", url));
+        }
+    }
+
+    imported.notes = notes;
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = "\
+%global srcname foo
+%global debug_package %{nil}
+
+Name:           %{srcname}-utils
+Version:        1.2.3
+Release:        2%{?dist}
+Summary:        Utilities for foo
+License:        MIT
+URL:            https://example.com/%{srcname}
+Source0:        %{url}/archive/v%{version}.tar.gz#/%{name}-%{version}.tar.gz
+Source1:        foo.service
+Patch0:         fix-build.patch
+
+BuildRequires:  cmake >= 3.10, gcc-c++
+BuildRequires:  pkgconfig(zlib)
+Requires:       glibc >= 2.31 bar = 1.0 baz > 2
+Requires(post): systemd
+
+%description
+Utilities for foo.
+
+%package devel
+Requires:       qux
+";
+
+    #[test]
+    fn test_import() {
+        let imported = import(SPEC).unwrap();
+        let package = &imported.package;
+        assert_eq!(package.name, "foo-utils");
+        assert_eq!(package.ver, "1.2.3");
+        assert_eq!(package.description, "Utilities for foo");
+        assert_eq!(
+            package.srcs,
+            vec![
+                "tbl::rename=foo-utils-${VER}.tar.gz::https://example.com/foo/archive/v${VER}.tar.gz"
+            ]
+        );
+        assert_eq!(package.dependencies, vec!["glibc>=2.31", "bar==1.0", "baz"]);
+        assert_eq!(package.build_dependencies, vec!["cmake>=3.10", "gcc-c++"]);
+        // `%{nil}`, `pkgconfig(zlib)`, `baz > 2`, `Requires(post)` and the
+        // local source.
+        assert_eq!(imported.notes.len(), 5);
+
+        assert_eq!(
+            import("Name: foo\n").unwrap_err(),
+            ImportError::MissingField("Version".to_string())
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! Parser and tooling for ABBS trees.
+//!
+//! The `std` feature, on by default, enables everything that touches the
+//! filesystem or runs programs: loading packages from directories, walking
+//! trees, the bash cross-check, and `export::Provenance::collect`. Without
+//! it, only evaluation of file contents handed over by the caller is
+//! available, i.e: for sandboxed or wasm environments.
+//! `apf` then does not use `std::io`: `apf::parse_reader` and
+//! `apf::parse_with_sources` need `std` as well. The crate is not `no_std`
+//! however, as the shell parser and `regex` still link `std`.
+
+pub mod apf;
+pub mod arch;
+pub mod autobuild;
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "std")]
+pub mod compat;
+pub mod conflicts;
+pub mod dependency;
+pub mod deps;
+pub mod divergence;
+pub mod export;
+pub mod fields;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(test)]
+mod fixtures;
+pub mod fmt;
+pub mod groups;
+pub mod interop;
+pub mod lint;
+pub mod maintainer;
+pub mod package;
+pub mod package_set;
+pub mod plan;
+pub mod providers;
+#[cfg(feature = "python")]
+mod python;
+pub mod query;
+#[cfg(feature = "std")]
+pub mod refactor;
+pub mod repository;
+#[cfg(feature = "std")]
+pub mod rewrite;
+pub mod section;
+pub mod spdx;
+pub mod spec;
+pub mod srcs;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod tree;
+pub mod updates;
+pub mod validate;
+pub mod version;
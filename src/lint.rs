@@ -0,0 +1,1087 @@
+//! Lints for spec and defines files.
+//! Each check is a `Rule`; a `Linter` runs a set of rules over a file and
+//! collects their findings into a `LintReport`.
+
+use crate::{
+    apf::{self, Context, Node, SyntaxTree},
+    autobuild::is_builtin_variable,
+    dependency::parse_dependencies,
+    fields,
+    fmt::LIST_FIELDS,
+    spdx::LicenseExpr,
+};
+#[cfg(feature = "std")]
+use crate::package::subpackage_dir_name;
+#[cfg(feature = "std")]
+use std::{fs, io, path::Path};
+use regex::Regex;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+};
+
+/// Descriptions longer than this are hard to read in package managers.
+pub const PKGDES_MAX_LEN: usize = 80;
+
+/// Whether `PKGDES` should end with a period.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum TrailingPeriod {
+    /// Like the rest of the tree, i.e: `PKGDES="Foo utilities"`.
+    #[default]
+    Forbid,
+    Require,
+    Ignore,
+}
+
+/// Settings of the built-in rules, i.e:
+/// `Linter::with_config(&LintConfig::default().pkgdes_max_len(72))`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct LintConfig {
+    /// Longest `PKGDES`, in characters.
+    pub pkgdes_max_len: usize,
+    pub pkgdes_trailing_period: TrailingPeriod,
+    /// Allow characters outside ASCII in `PKGDES`, i.e: for a tree declared
+    /// to be UTF-8.
+    pub pkgdes_utf8: bool,
+    /// Words `PKGDES` must not start with, compared ignoring case.
+    pub pkgdes_articles: Vec<String>,
+    /// Names of the rules not to run.
+    pub disabled: Vec<String>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            pkgdes_max_len: PKGDES_MAX_LEN,
+            pkgdes_trailing_period: TrailingPeriod::default(),
+            pkgdes_utf8: false,
+            pkgdes_articles: vec!["A".to_string(), "An".to_string()],
+            disabled: Vec::new(),
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn pkgdes_max_len(mut self, len: usize) -> Self {
+        self.pkgdes_max_len = len;
+        self
+    }
+
+    pub fn pkgdes_trailing_period(mut self, policy: TrailingPeriod) -> Self {
+        self.pkgdes_trailing_period = policy;
+        self
+    }
+
+    pub fn pkgdes_utf8(mut self, utf8: bool) -> Self {
+        self.pkgdes_utf8 = utf8;
+        self
+    }
+
+    pub fn pkgdes_articles(mut self, articles: Vec<String>) -> Self {
+        self.pkgdes_articles = articles;
+        self
+    }
+
+    /// Do not run the rule called `name`.
+    pub fn disable(mut self, name: &str) -> Self {
+        self.disabled.push(name.to_string());
+        self
+    }
+}
+
+/// Fields replaced by `SRCS` and `CHKSUMS`.
+pub const OBSOLETE_FIELDS: &[(&str, &str)] = &[
+    ("SRCTBL", "SRCS"),
+    ("CHKSUM", "CHKSUMS"),
+    ("GITSRC", "SRCS"),
+    ("GITCO", "SRCS"),
+    ("GITBRCH", "SRCS"),
+    ("SVNSRC", "SRCS"),
+    ("SVNCO", "SRCS"),
+    ("HGSRC", "SRCS"),
+    ("BZRSRC", "SRCS"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// Location of a finding, as 1-based line and column plus length in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Name of the rule reporting it.
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// `None` for findings about the file as a whole, i.e: a missing field.
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Spec,
+    Defines,
+}
+
+/// Everything a rule can look at.
+pub struct LintInput<'a> {
+    pub kind: FileKind,
+    pub source: &'a str,
+    pub tree: &'a SyntaxTree,
+    /// Variables in scope before the file, i.e: the spec variables for a
+    /// defines file.
+    pub scope: &'a Context,
+    /// Values after evaluation. Partial if evaluation failed half-way.
+    pub context: &'a Context,
+}
+
+impl LintInput<'_> {
+    /// Top-level nodes with their position in the source.
+    pub fn positioned_nodes(&self) -> Vec<(Span, &Node)> {
+        let (mut line, mut col) = (1, 1);
+        let mut result = Vec::new();
+        for node in self.tree.nodes() {
+            let text = node.to_string();
+            let first_line = text.split('\n').next().unwrap_or("");
+            result.push((
+                Span {
+                    line,
+                    col,
+                    len: first_line.len(),
+                },
+                node,
+            ));
+            match text.rfind('\n') {
+                Some(i) => {
+                    line += text.matches('\n').count();
+                    col = text.len() - i;
+                }
+                None => col += text.len(),
+            }
+        }
+        result
+    }
+
+    /// Span of the last top-level assignment of `name`.
+    pub fn assignment_span(&self, name: &str) -> Option<Span> {
+        self.positioned_nodes()
+            .into_iter()
+            .rev()
+            .find(|(_, n)| matches!(n, Node::Assignment(a) if a.name() == name))
+            .map(|(span, _)| span)
+    }
+}
+
+pub trait Rule: Send + Sync {
+    /// Short kebab-case name, i.e: `missing-pkgdes`.
+    fn name(&self) -> &'static str;
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>);
+}
+
+struct MissingPkgdes;
+
+impl Rule for MissingPkgdes {
+    fn name(&self) -> &'static str {
+        "missing-pkgdes"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        if input.kind == FileKind::Defines && input.tree.find("PKGDES").is_none() {
+            diagnostics.push(Diagnostic {
+                rule: self.name(),
+                severity: Severity::Error,
+                message: "PKGDES is not defined".to_string(),
+                span: None,
+            });
+        }
+    }
+}
+
+struct PkgdesTooLong {
+    max_len: usize,
+}
+
+impl Rule for PkgdesTooLong {
+    fn name(&self) -> &'static str {
+        "pkgdes-too-long"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        let len = match input.context.get("PKGDES") {
+            Some(des) => des.chars().count(),
+            None => return,
+        };
+        if len > self.max_len {
+            diagnostics.push(Diagnostic {
+                rule: self.name(),
+                severity: Severity::Warning,
+                message: format!(
+                    "PKGDES is {} characters long, more than {}",
+                    len, self.max_len
+                ),
+                span: input.assignment_span("PKGDES"),
+            });
+        }
+    }
+}
+
+struct PkgdesTrailingPeriod {
+    policy: TrailingPeriod,
+}
+
+impl Rule for PkgdesTrailingPeriod {
+    fn name(&self) -> &'static str {
+        "pkgdes-trailing-period"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        let des = match input.context.get("PKGDES") {
+            Some(des) => des.trim_end(),
+            None => return,
+        };
+        // An ellipsis is not a period.
+        let period = des.ends_with('.') && !des.ends_with("..");
+        let message = match self.policy {
+            TrailingPeriod::Forbid if period => "PKGDES ends with a period",
+            TrailingPeriod::Require if !period => "PKGDES does not end with a period",
+            _ => return,
+        };
+        diagnostics.push(Diagnostic {
+            rule: self.name(),
+            severity: Severity::Warning,
+            message: message.to_string(),
+            span: input.assignment_span("PKGDES"),
+        });
+    }
+}
+
+/// `PKGDES` repeating the name of the package, i.e: `foo: Foo utilities`.
+struct PkgdesStartsWithName;
+
+impl Rule for PkgdesStartsWithName {
+    fn name(&self) -> &'static str {
+        "pkgdes-starts-with-name"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        let (des, name) = match (input.context.get("PKGDES"), input.context.get("PKGNAME")) {
+            (Some(des), Some(name)) if !name.is_empty() => (des, name),
+            _ => return,
+        };
+        let starts = des
+            .get(..name.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(name))
+            && des[name.len()..]
+                .chars()
+                .next()
+                .is_none_or(|c| !c.is_alphanumeric() && !"-_+.".contains(c));
+        if starts {
+            diagnostics.push(Diagnostic {
+                rule: self.name(),
+                severity: Severity::Warning,
+                message: format!("PKGDES starts with the package name {}", name),
+                span: input.assignment_span("PKGDES"),
+            });
+        }
+    }
+}
+
+struct PkgdesNonAscii {
+    utf8: bool,
+}
+
+impl Rule for PkgdesNonAscii {
+    fn name(&self) -> &'static str {
+        "pkgdes-non-ascii"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        if self.utf8 {
+            return;
+        }
+        let c = match input
+            .context
+            .get("PKGDES")
+            .and_then(|des| des.chars().find(|c| !c.is_ascii()))
+        {
+            Some(c) => c,
+            None => return,
+        };
+        diagnostics.push(Diagnostic {
+            rule: self.name(),
+            severity: Severity::Warning,
+            message: format!("PKGDES has `{}`, which is not ASCII", c),
+            span: input.assignment_span("PKGDES"),
+        });
+    }
+}
+
+struct PkgdesLeadingArticle {
+    articles: Vec<String>,
+}
+
+impl Rule for PkgdesLeadingArticle {
+    fn name(&self) -> &'static str {
+        "pkgdes-leading-article"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        let first = match input
+            .context
+            .get("PKGDES")
+            .and_then(|des| des.split_whitespace().next())
+        {
+            Some(first) => first,
+            None => return,
+        };
+        if self.articles.iter().any(|a| a.eq_ignore_ascii_case(first)) {
+            diagnostics.push(Diagnostic {
+                rule: self.name(),
+                severity: Severity::Info,
+                message: format!("PKGDES starts with the article \"{}\"", first),
+                span: input.assignment_span("PKGDES"),
+            });
+        }
+    }
+}
+
+struct ObsoleteField;
+
+impl Rule for ObsoleteField {
+    fn name(&self) -> &'static str {
+        "obsolete-field"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        for (span, node) in input.positioned_nodes() {
+            let name = match node {
+                Node::Assignment(a) => a.name(),
+                _ => continue,
+            };
+            if let Some((_, replacement)) = OBSOLETE_FIELDS.iter().find(|(f, _)| *f == name) {
+                diagnostics.push(Diagnostic {
+                    rule: self.name(),
+                    severity: Severity::Warning,
+                    message: format!("{} is obsolete, use {} instead", name, replacement),
+                    span: Some(span),
+                });
+            }
+        }
+    }
+}
+
+/// Assignments to fields which are not known, but close to one that is, i.e:
+/// `PKGDEPS`. Other unknown names are left alone, as helper variables are
+/// common.
+struct UnknownField;
+
+impl Rule for UnknownField {
+    fn name(&self) -> &'static str {
+        "unknown-field"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        for (span, node) in input.positioned_nodes() {
+            let name = match node {
+                Node::Assignment(a) => a.name(),
+                _ => continue,
+            };
+            if fields::lookup(name).is_some() || is_builtin_variable(name) {
+                continue;
+            }
+            if let Some(suggestion) = fields::suggest(name) {
+                diagnostics.push(Diagnostic {
+                    rule: self.name(),
+                    severity: Severity::Warning,
+                    message: format!(
+                        "{} is not a known field, did you mean {}?",
+                        name, suggestion
+                    ),
+                    span: Some(span),
+                });
+            }
+        }
+    }
+}
+
+/// `LICENSE` values which are not SPDX expressions, or name licenses which
+/// are unknown or deprecated.
+struct InvalidLicense;
+
+impl Rule for InvalidLicense {
+    fn name(&self) -> &'static str {
+        "invalid-license"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        // Only where it is set, not in every file seeing it.
+        let span = match input.assignment_span("LICENSE") {
+            Some(span) => Some(span),
+            None => return,
+        };
+        let value = match input.context.get("LICENSE") {
+            Some(value) => value,
+            None => return,
+        };
+        let mut report = |severity, message| {
+            diagnostics.push(Diagnostic {
+                rule: self.name(),
+                severity,
+                message,
+                span,
+            })
+        };
+        let expr = match value.parse::<LicenseExpr>() {
+            Ok(expr) => expr,
+            Err(e) => {
+                report(
+                    Severity::Error,
+                    format!("LICENSE is not an SPDX expression: {}", e),
+                );
+                return;
+            }
+        };
+        let issues = expr.issues();
+        for issue in issues.iter() {
+            report(Severity::Warning, issue.to_string());
+        }
+        let normalized = expr.normalize().to_string();
+        if issues.is_empty() && &normalized != value {
+            report(
+                Severity::Info,
+                format!("LICENSE is normally written `{}`", normalized),
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReassignmentKind {
+    /// `X="$X foo"` with `X` already set: intentional.
+    Append,
+    /// `X=foo` after an earlier `X=...` in the same file, dropping its value.
+    Overwrite,
+    /// `X="$X foo"` with `X` never set before, which fails evaluation.
+    UndefinedSelfReference,
+}
+
+/// An assignment to a variable referencing or replacing its previous value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reassignment {
+    pub name: String,
+    pub kind: ReassignmentKind,
+    pub line: usize,
+    /// Line of the previous assignment in the file. `None` if the previous
+    /// value comes from the scope.
+    pub previous_line: Option<usize>,
+}
+
+fn references(name: &str, raw_value: &str) -> bool {
+    // Nothing is expanded in single quotes.
+    if raw_value.starts_with('\'') {
+        return false;
+    }
+    let pattern = format!(r"\$\{{?{}\b", regex::escape(name));
+    Regex::new(&pattern).is_ok_and(|re| re.is_match(raw_value))
+}
+
+/// Top-level assignments of `tree` to variables already set, in the file or
+/// in `scope`, or referencing themselves without a previous value.
+/// Assignments nested in commands, i.e: conditionals, are not considered.
+pub fn find_reassignments(tree: &SyntaxTree, scope: &Context) -> Vec<Reassignment> {
+    let mut seen = HashMap::new();
+    let mut result = Vec::new();
+    for assignment in tree.assignments() {
+        let name = assignment.name();
+        let previous_line = seen.insert(name, assignment.line());
+        let is_set = previous_line.is_some() || scope.contains_key(name);
+        let kind = match (references(name, assignment.raw_value()), is_set) {
+            (true, true) => ReassignmentKind::Append,
+            (true, false) => ReassignmentKind::UndefinedSelfReference,
+            (false, _) if previous_line.is_some() => ReassignmentKind::Overwrite,
+            (false, _) => continue,
+        };
+        result.push(Reassignment {
+            name: name.to_string(),
+            kind,
+            line: assignment.line(),
+            previous_line,
+        });
+    }
+    result
+}
+
+/// Overwritten assignments and self-references without a previous value.
+/// Appends are fine.
+struct Reassigned;
+
+impl Rule for Reassigned {
+    fn name(&self) -> &'static str {
+        "reassignment"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        let nodes = input.positioned_nodes();
+        for reassignment in find_reassignments(input.tree, input.scope) {
+            let (severity, message) = match (reassignment.kind, reassignment.previous_line) {
+                (ReassignmentKind::Append, _) => continue,
+                (ReassignmentKind::Overwrite, Some(line)) => (
+                    Severity::Warning,
+                    format!(
+                        "{} is assigned again, overwriting its value from line {}",
+                        reassignment.name, line
+                    ),
+                ),
+                (ReassignmentKind::Overwrite, None) => continue,
+                (ReassignmentKind::UndefinedSelfReference, _) => (
+                    Severity::Error,
+                    format!(
+                        "{} references itself, but is not set before",
+                        reassignment.name
+                    ),
+                ),
+            };
+            let span = nodes.iter().find_map(|(span, node)| match node {
+                Node::Assignment(a)
+                    if a.line() == reassignment.line && a.name() == reassignment.name =>
+                {
+                    Some(*span)
+                }
+                _ => None,
+            });
+            diagnostics.push(Diagnostic {
+                rule: self.name(),
+                severity,
+                message,
+                span,
+            });
+        }
+    }
+}
+
+struct DuplicateDependency;
+
+impl Rule for DuplicateDependency {
+    fn name(&self) -> &'static str {
+        "duplicate-dependency"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        for field in LIST_FIELDS
+            .iter()
+            .filter(|f| f.starts_with("PKG") || **f == "BUILDDEP")
+        {
+            let deps = match input.context.get(*field).map(|v| parse_dependencies(v)) {
+                Some(Ok(deps)) => deps,
+                _ => continue,
+            };
+            let mut seen = HashSet::new();
+            for dep in deps {
+                if !seen.insert(dep.name.clone()) {
+                    diagnostics.push(Diagnostic {
+                        rule: self.name(),
+                        severity: Severity::Warning,
+                        message: format!("{} is listed more than once in {}", dep.name, field),
+                        span: input.assignment_span(field),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// `[ cond ] && FOO=bar` fails the whole file when the condition is false
+/// and it is the last command, as autobuild sources files with `set -e`.
+struct NonzeroExit;
+
+impl Rule for NonzeroExit {
+    fn name(&self) -> &'static str {
+        "nonzero-exit"
+    }
+
+    fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+        for (span, node) in input.positioned_nodes() {
+            let command = match node {
+                Node::Command(c) => c,
+                _ => continue,
+            };
+            let is_test = ["[ ", "[[ ", "test "]
+                .iter()
+                .any(|p| command.starts_with(p));
+            if is_test && command.contains("&&") && !command.contains("||") {
+                diagnostics.push(Diagnostic {
+                    rule: self.name(),
+                    severity: Severity::Error,
+                    message:
+                        "This returns non-zero when the condition is false, use if ... fi instead"
+                            .to_string(),
+                    span: Some(span),
+                });
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LintReport {
+    pub path: Option<PathBuf>,
+    /// Sorted by position, file-wide findings first, then by rule and message.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl LintReport {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error)
+    }
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self
+            .path
+            .as_ref()
+            .map_or("<input>".to_string(), |p| p.display().to_string());
+        for d in self.diagnostics.iter() {
+            match d.span {
+                Some(span) => write!(f, "{}:{}:{}", path, span.line, span.col)?,
+                None => write!(f, "{}", path)?,
+            }
+            writeln!(f, ": {}: {} [{}]", d.severity, d.message, d.rule)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Linter {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Default for Linter {
+    /// Linter with all built-in rules.
+    fn default() -> Self {
+        Linter::with_config(&LintConfig::default())
+    }
+}
+
+impl Linter {
+    /// Linter with the built-in rules set up by `config`, except the
+    /// disabled ones.
+    pub fn with_config(config: &LintConfig) -> Self {
+        let mut linter = Linter::empty();
+        linter.register(MissingPkgdes);
+        linter.register(PkgdesTooLong {
+            max_len: config.pkgdes_max_len,
+        });
+        linter.register(PkgdesTrailingPeriod {
+            policy: config.pkgdes_trailing_period,
+        });
+        linter.register(PkgdesStartsWithName);
+        linter.register(PkgdesNonAscii {
+            utf8: config.pkgdes_utf8,
+        });
+        linter.register(PkgdesLeadingArticle {
+            articles: config.pkgdes_articles.clone(),
+        });
+        linter.register(ObsoleteField);
+        linter.register(UnknownField);
+        linter.register(InvalidLicense);
+        linter.register(DuplicateDependency);
+        linter.register(Reassigned);
+        linter.register(NonzeroExit);
+        for name in config.disabled.iter() {
+            linter.disable(name);
+        }
+        linter
+    }
+
+    pub fn empty() -> Self {
+        Linter { rules: Vec::new() }
+    }
+
+    pub fn register<R: Rule + 'static>(&mut self, rule: R) {
+        self.rules.push(Box::new(rule));
+    }
+
+    /// Remove the rule called `name`.
+    pub fn disable(&mut self, name: &str) {
+        self.rules.retain(|r| r.name() != name);
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().map(|r| r.name())
+    }
+
+    /// Lint `source`. `context` holds the variables in scope before it, i.e:
+    /// the spec variables when linting a defines file.
+    pub fn lint(&self, kind: FileKind, source: &str, context: &Context) -> LintReport {
+        let mut report = LintReport::default();
+        let tree = match apf::parse_lossless(source) {
+            Ok(tree) => tree,
+            Err(e) => {
+                report.diagnostics.push(Diagnostic {
+                    rule: "syntax",
+                    severity: Severity::Error,
+                    message: e.to_string(),
+                    span: None,
+                });
+                return report;
+            }
+        };
+        let scope = context;
+        let mut context = context.clone();
+        // Evaluation errors are not lints; the variables seen so far are enough.
+        let _ = apf::parse(source, &mut context);
+
+        let input = LintInput {
+            kind,
+            source,
+            tree: &tree,
+            scope,
+            context: &context,
+        };
+        for rule in self.rules.iter() {
+            rule.check(&input, &mut report.diagnostics);
+        }
+        report
+            .diagnostics
+            .sort_by(|a, b| (a.span, a.rule, &a.message).cmp(&(b.span, b.rule, &b.message)));
+
+        report
+    }
+
+    /// Lint the file at `path`.
+    #[cfg(feature = "std")]
+    pub fn lint_file<P: AsRef<Path>>(
+        &self,
+        kind: FileKind,
+        path: P,
+        context: &Context,
+    ) -> io::Result<LintReport> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let mut report = self.lint(kind, &source, context);
+        report.path = Some(path.to_path_buf());
+        Ok(report)
+    }
+
+    /// Lint the package in `dir`: its spec, then its defines, or those of its
+    /// sub-packages, with the spec variables in scope.
+    #[cfg(feature = "std")]
+    pub fn lint_package<P: AsRef<Path>>(&self, dir: P) -> io::Result<Vec<LintReport>> {
+        let dir = dir.as_ref();
+        let spec_path = dir.join("spec");
+        let spec = fs::read_to_string(&spec_path)?;
+        let mut report = self.lint(FileKind::Spec, &spec, &Context::new());
+        report.path = Some(spec_path);
+        let mut reports = vec![report];
+
+        let mut context = Context::new();
+        let _ = apf::parse(&spec, &mut context);
+        let autobuild = dir.join("autobuild");
+        let mut defines = Vec::new();
+        if autobuild.join("defines").exists() || !autobuild.is_dir() {
+            defines.push(autobuild.join("defines"));
+        } else {
+            for entry in fs::read_dir(&autobuild)? {
+                let path = entry?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if subpackage_dir_name(&name).is_some() && path.join("defines").is_file() {
+                    defines.push(path.join("defines"));
+                }
+            }
+            defines.sort();
+        }
+        for path in defines {
+            reports.push(self.lint_file(FileKind::Defines, path, &context)?);
+        }
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_of(report: &LintReport) -> Vec<&str> {
+        report.diagnostics.iter().map(|d| d.rule).collect()
+    }
+
+    #[test]
+    fn test_builtin_rules() {
+        let linter = Linter::default();
+        let mut spec = Context::new();
+        spec.insert("VER".to_string(), "1.0".to_string());
+
+        let defines = format!(
+            "PKGNAME=foo\nPKGDEP=\"glibc bar glibc>=2.31\"\nPKGDES=\"{}\"\n[[ \"$ARCH\" = amd64 ]] && PKGDEP+=\" baz\"\n",
+            "x".repeat(81)
+        );
+        let report = linter.lint(FileKind::Defines, &defines, &spec);
+        assert_eq!(
+            rules_of(&report),
+            vec!["duplicate-dependency", "pkgdes-too-long", "nonzero-exit"]
+        );
+        assert_eq!(report.diagnostics[0].span.unwrap().line, 2);
+        assert_eq!(report.diagnostics[2].span.unwrap().line, 4);
+        assert!(report.has_errors());
+
+        let report = linter.lint(FileKind::Defines, "PKGNAME=foo\n", &spec);
+        assert_eq!(rules_of(&report), vec!["missing-pkgdes"]);
+        assert_eq!(
+            report.to_string(),
+            "<input>: error: PKGDES is not defined [missing-pkgdes]\n"
+        );
+
+        let report = linter.lint(
+            FileKind::Defines,
+            "PKGDES=foo\nPKGDEPS=bar\n_helper=1\nSRCDIR=x\n",
+            &spec,
+        );
+        assert_eq!(rules_of(&report), vec!["unknown-field"]);
+        assert_eq!(
+            report.diagnostics[0].message,
+            "PKGDEPS is not a known field, did you mean PKGDEP?"
+        );
+
+        let report = linter.lint(
+            FileKind::Spec,
+            "VER=1\nSRCTBL=\"https://example.com/a.tar.xz\"\n",
+            &Context::new(),
+        );
+        assert_eq!(rules_of(&report), vec!["obsolete-field"]);
+        assert_eq!(
+            report.diagnostics[0].span,
+            Some(Span {
+                line: 2,
+                col: 1,
+                len: 37
+            })
+        );
+        assert!(!report.has_errors());
+    }
+
+    #[test]
+    fn test_pkgdes_rules() {
+        let lint = |linter: &Linter, pkgdes: &str| -> Vec<&'static str> {
+            let source = format!("PKGNAME=foo\nPKGDES=\"{}\"\n", pkgdes);
+            let report = linter.lint(FileKind::Defines, &source, &Context::new());
+            report.diagnostics.iter().map(|d| d.rule).collect()
+        };
+        let linter = Linter::default();
+        assert!(lint(&linter, "Foobar utilities...").is_empty());
+        assert_eq!(
+            lint(&linter, "A tool for bar."),
+            vec!["pkgdes-leading-article", "pkgdes-trailing-period"]
+        );
+        assert_eq!(
+            lint(&linter, "Foo: utilities \u{2014} for bar"),
+            vec!["pkgdes-non-ascii", "pkgdes-starts-with-name"]
+        );
+
+        let config = LintConfig::default()
+            .pkgdes_max_len(10)
+            .pkgdes_trailing_period(TrailingPeriod::Require)
+            .pkgdes_utf8(true)
+            .pkgdes_articles(Vec::new())
+            .disable("pkgdes-starts-with-name");
+        let linter = Linter::with_config(&config);
+        assert_eq!(
+            lint(&linter, "Foo: utilities \u{2014} for bar"),
+            vec!["pkgdes-too-long", "pkgdes-trailing-period"]
+        );
+        assert!(lint(&linter, "An editor.").is_empty());
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_config_toml() {
+        let config: LintConfig = toml::from_str(
+            "pkgdes_max_len = 72\npkgdes_trailing_period = \"ignore\"\ndisabled = [\"nonzero-exit\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            LintConfig::default()
+                .pkgdes_max_len(72)
+                .pkgdes_trailing_period(TrailingPeriod::Ignore)
+                .disable("nonzero-exit")
+        );
+        assert!(!Linter::with_config(&config)
+            .rules()
+            .any(|r| r == "nonzero-exit"));
+    }
+
+    #[test]
+    fn test_invalid_license() {
+        let linter = Linter::default();
+        let messages = |source: &str| -> Vec<_> {
+            linter
+                .lint(FileKind::Defines, source, &Context::new())
+                .diagnostics
+                .into_iter()
+                .filter(|d| d.rule == "invalid-license")
+                .map(|d| (d.severity, d.message))
+                .collect()
+        };
+        assert!(messages("PKGDES=foo\nLICENSE=\"MIT OR Apache-2.0\"\n").is_empty());
+        assert_eq!(
+            messages("PKGDES=foo\nLICENSE=\"GPL-2.0+ AND Foo\"\n"),
+            vec![
+                (
+                    Severity::Warning,
+                    "Foo is not on the SPDX license list".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    "GPL-2.0+ is deprecated, use GPL-2.0-or-later instead".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            messages("PKGDES=foo\nLICENSE=\"mit and (zlib)\"\n"),
+            vec![(
+                Severity::Info,
+                "LICENSE is normally written `MIT AND Zlib`".to_string()
+            )]
+        );
+        assert_eq!(
+            messages("PKGDES=foo\nLICENSE=\"MIT/X11\"\n")[0].0,
+            Severity::Error
+        );
+        // Seen from the spec, but not set in this file.
+        let mut spec = Context::new();
+        spec.insert("LICENSE".to_string(), "MIT/X11".to_string());
+        assert!(linter
+            .lint(FileKind::Defines, "PKGDES=foo\n", &spec)
+            .diagnostics
+            .is_empty());
+    }
+
+    #[test]
+    fn test_reassignments() {
+        let source = "PKGDEP=\"foo\"\nPKGDEP=\"${PKGDEP} bar\"\nPKGDES=a\nPKGDES=b\n\
+                      BUILDDEP=\"$BUILDDEP baz\"\nPKGSUG='$PKGSUG'\nPKGRECOM=\"$PKGRECOM_X\"\n";
+        let tree = apf::parse_lossless(source).unwrap();
+        let mut scope = Context::new();
+        let kinds: Vec<_> = find_reassignments(&tree, &scope)
+            .into_iter()
+            .map(|r| (r.name, r.kind, r.previous_line))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("PKGDEP".to_string(), ReassignmentKind::Append, Some(1)),
+                ("PKGDES".to_string(), ReassignmentKind::Overwrite, Some(3)),
+                (
+                    "BUILDDEP".to_string(),
+                    ReassignmentKind::UndefinedSelfReference,
+                    None
+                ),
+            ]
+        );
+        scope.insert("BUILDDEP".to_string(), "gcc".to_string());
+        assert_eq!(
+            find_reassignments(&tree, &scope)[2].kind,
+            ReassignmentKind::Append
+        );
+
+        let report = Linter::default().lint(FileKind::Defines, source, &Context::new());
+        let messages: Vec<_> = report
+            .diagnostics
+            .iter()
+            .map(|d| (d.span.unwrap().line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    4,
+                    "PKGDES is assigned again, overwriting its value from line 3"
+                ),
+                (5, "BUILDDEP references itself, but is not set before"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_rule() {
+        struct NoFoo;
+        impl Rule for NoFoo {
+            fn name(&self) -> &'static str {
+                "no-foo"
+            }
+            fn check(&self, input: &LintInput, diagnostics: &mut Vec<Diagnostic>) {
+                if input.context.contains_key("FOO") {
+                    diagnostics.push(Diagnostic {
+                        rule: self.name(),
+                        severity: Severity::Info,
+                        message: "FOO".to_string(),
+                        span: input.assignment_span("FOO"),
+                    });
+                }
+            }
+        }
+
+        let mut linter = Linter::default();
+        linter.register(NoFoo);
+        linter.disable("missing-pkgdes");
+        let report = linter.lint(FileKind::Defines, "A=1; FOO=2\n", &Context::new());
+        assert_eq!(rules_of(&report), vec!["no-foo"]);
+        assert_eq!(
+            report.diagnostics[0].span,
+            Some(Span {
+                line: 1,
+                col: 6,
+                len: 5
+            })
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_lint_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let autobuild = dir.path().join("autobuild");
+        fs::create_dir_all(autobuild.join("01-libfoo")).unwrap();
+        fs::create_dir_all(autobuild.join("02-foo-dev")).unwrap();
+        fs::write(dir.path().join("spec"), "VER=1.0\nSRCTBL=foo\n").unwrap();
+        fs::write(autobuild.join("01-libfoo/defines"), "PKGDES=\"Foo $VER\"\n").unwrap();
+        fs::write(autobuild.join("02-foo-dev/defines"), "PKGNAME=foo-dev\n").unwrap();
+
+        let reports = Linter::default().lint_package(dir.path()).unwrap();
+        let rules: Vec<_> = reports.iter().map(rules_of).collect();
+        assert_eq!(rules, vec![vec!["obsolete-field"], vec![], vec!["missing-pkgdes"]]);
+        let last = reports[2].path.as_deref();
+        assert_eq!(last, Some(autobuild.join("02-foo-dev/defines").as_path()));
+    }
+}
@@ -1,11 +1,11 @@
-mod apf;
-
+use abbs::apf;
 use anyhow::Result;
 use std::fs::File;
 use std::io::Read;
 use std::collections::HashMap;
 //use rayon::prelude::*;
 
+#[allow(dead_code)] // Only used by the speed test below.
 const SPEC_DIR: &str = "";
 const TEST_PATH: &str = "";
 
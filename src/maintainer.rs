@@ -0,0 +1,190 @@
+//! Package maintainers, from `MAINTAINER` fields and from comments like
+//! `# Maintainer: Foo Bar <foo@example.com>` in spec and defines files.
+
+use crate::package::Package;
+use regex::Regex;
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Maintainer {
+    pub name: String,
+    pub email: Option<String>,
+}
+
+impl Maintainer {
+    /// Whether `who` is the name or the email of this maintainer, ignoring
+    /// case.
+    pub fn is(&self, who: &str) -> bool {
+        self.name.eq_ignore_ascii_case(who)
+            || self
+                .email
+                .as_deref()
+                .is_some_and(|e| e.eq_ignore_ascii_case(who))
+    }
+}
+
+impl FromStr for Maintainer {
+    type Err = String;
+
+    /// `Name <user@example.com>`, or only a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, email) = match s.split_once('<') {
+            Some((name, rest)) => match rest.strip_suffix('>') {
+                Some(email) if email.contains('@') && !email.contains(['<', '>']) => {
+                    (name.trim(), Some(email.trim().to_string()))
+                }
+                _ => return Err(format!("Invalid email in maintainer `{}`", s)),
+            },
+            None => (s, None),
+        };
+        if name.is_empty() {
+            return Err(format!("Missing name in maintainer `{}`", s));
+        }
+        Ok(Maintainer {
+            name: name.to_string(),
+            email,
+        })
+    }
+}
+
+impl fmt::Display for Maintainer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.email {
+            Some(email) => write!(f, "{} <{}>", self.name, email),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// Maintainers named in `# Maintainer: ...` comments of `source`, in order.
+/// Malformed entries are skipped.
+pub fn parse_comments(source: &str) -> Vec<Maintainer> {
+    let re = Regex::new(r"(?im)^[ \t]*#+[ \t]*maintainer[ \t]*:(.*)$")
+        .expect("Bad maintainer comment pattern");
+    re.captures_iter(source)
+        .filter_map(|c| c[1].parse().ok())
+        .collect()
+}
+
+fn push_unique(maintainers: &mut Vec<Maintainer>, maintainer: Maintainer) {
+    if !maintainers.contains(&maintainer) {
+        maintainers.push(maintainer);
+    }
+}
+
+/// Maintainers of `package` and its sub-packages: the `MAINTAINER` fields,
+/// then comments of the files on disk if the package was loaded from a
+/// directory. Without duplicates.
+pub fn maintainers_of(package: &Package) -> Vec<Maintainer> {
+    let mut maintainers = Vec::new();
+    let fields =
+        std::iter::once(package.fields()).chain(package.subpackages().iter().map(|s| s.fields()));
+    for fields in fields {
+        if let Some(Ok(maintainer)) = fields.get("MAINTAINER").map(|m| m.parse()) {
+            push_unique(&mut maintainers, maintainer);
+        }
+    }
+    #[cfg(feature = "std")]
+    if let Some(dir) = package.path() {
+        let mut files = vec![dir.join("spec"), dir.join("autobuild").join("defines")];
+        files.extend(
+            package
+                .subpackages()
+                .iter()
+                .map(|s| s.path().join("defines")),
+        );
+        for file in files {
+            let source = std::fs::read_to_string(file).unwrap_or_default();
+            for maintainer in parse_comments(&source) {
+                push_unique(&mut maintainers, maintainer);
+            }
+        }
+    }
+    maintainers
+}
+
+/// Packages maintained by `who`, a name or an email.
+pub fn maintained_by<'a>(packages: &'a [Package], who: &str) -> Vec<&'a Package> {
+    packages
+        .iter()
+        .filter(|p| maintainers_of(p).iter().any(|m| m.is(who)))
+        .collect()
+}
+
+/// Packages nobody maintains.
+pub fn unmaintained(packages: &[Package]) -> Vec<&Package> {
+    packages
+        .iter()
+        .filter(|p| maintainers_of(p).is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let maintainer: Maintainer = " Foo Bar <foo@example.com> ".parse().unwrap();
+        assert_eq!(maintainer.name, "Foo Bar");
+        assert_eq!(maintainer.email.as_deref(), Some("foo@example.com"));
+        assert_eq!(maintainer.to_string(), "Foo Bar <foo@example.com>");
+        assert!(maintainer.is("FOO@example.com"));
+        assert!(maintainer.is("foo bar"));
+        assert_eq!("Foo".parse::<Maintainer>().unwrap().email, None);
+        assert!("<foo@example.com>".parse::<Maintainer>().is_err());
+        assert!("Foo <foo>".parse::<Maintainer>().is_err());
+
+        let source = "# Maintainer: Foo <foo@example.com>\n#maintainer:Bar\nPKGDES=\"Baz\" # Maintainer: no\n# Maintainer: <>\n";
+        let names: Vec<_> = parse_comments(source).into_iter().map(|m| m.name).collect();
+        assert_eq!(names, vec!["Foo", "Bar"]);
+    }
+
+    #[test]
+    fn test_queries() {
+        let package = |name: &str, maintainer: Option<&str>| {
+            let mut fields = crate::apf::Context::new();
+            if let Some(m) = maintainer {
+                fields.insert("MAINTAINER".to_string(), m.to_string());
+            }
+            Package::new(name, fields)
+        };
+        let packages = vec![
+            package("foo", Some("Foo <foo@example.com>")),
+            package("bar", None),
+            package("baz", Some("Foo")),
+        ];
+        let names = |packages: Vec<&Package>| {
+            packages
+                .iter()
+                .map(|p| p.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(maintained_by(&packages, "foo")), vec!["foo", "baz"]);
+        assert_eq!(
+            names(maintained_by(&packages, "foo@example.com")),
+            vec!["foo"]
+        );
+        assert_eq!(names(unmaintained(&packages)), vec!["bar"]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_comments_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("autobuild")).unwrap();
+        std::fs::write(
+            dir.path().join("spec"),
+            "# Maintainer: Foo <foo@example.com>\nVER=1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("autobuild/defines"),
+            "PKGNAME=foo\nMAINTAINER=\"Foo <foo@example.com>\"\n",
+        )
+        .unwrap();
+        let package = Package::from_dir(dir.path()).unwrap();
+        assert_eq!(maintainers_of(&package).len(), 1);
+    }
+}
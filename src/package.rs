@@ -0,0 +1,1051 @@
+//! Package model built on top of the parsed spec/defines context.
+
+#[cfg(feature = "std")]
+use crate::{
+    apf::{self, ParseOptions},
+    arch::Arch,
+};
+use crate::{
+    apf::{quote_template, quote_with, Context, ParseError, Style},
+    autobuild,
+    fields::{self, FieldError, FieldValue},
+    validate::{ValidationError, ValidatorRegistry},
+};
+#[cfg(feature = "cache")]
+use crate::cache::ParseCache;
+#[cfg(feature = "serde")]
+use crate::export;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "std")]
+use std::{fs, path::Component};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Separator between a field name and its architecture suffix.
+/// i.e: `PKGDEP__AMD64`
+const ARCH_SEPARATOR: &str = "__";
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Package {
+    name: String,
+    path: Option<PathBuf>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "export::serialize_sorted"))]
+    fields: Context,
+    #[cfg_attr(feature = "serde", serde(default))]
+    subpackages: Vec<SubPackage>,
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    loader: Loader,
+}
+
+/// Which spec variables a sub-package's defines can see.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum SpecInheritance {
+    /// Every spec variable, like autobuild does.
+    #[default]
+    All,
+    /// Only the listed variables, i.e: `VER` and `REL`.
+    Only(Vec<String>),
+    /// Nothing, the defines are evaluated on their own.
+    None,
+}
+
+impl SpecInheritance {
+    #[cfg(feature = "std")]
+    fn apply(&self, spec: &Context) -> Context {
+        match self {
+            SpecInheritance::All => spec.clone(),
+            SpecInheritance::Only(names) => names
+                .iter()
+                .filter_map(|n| spec.get_key_value(n))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            SpecInheritance::None => Context::new(),
+        }
+    }
+}
+
+/// One of several packages built from a single spec.
+/// i.e: `autobuild/01-libfoo/defines` and `autobuild/02-foo-dev/defines`.
+/// Each sub-package sees the variables of the parent spec allowed by its
+/// `SpecInheritance`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SubPackage {
+    name: String,
+    path: PathBuf,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "export::serialize_sorted"))]
+    fields: Context,
+    #[cfg_attr(feature = "serde", serde(default))]
+    inheritance: SpecInheritance,
+}
+
+impl SubPackage {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Directory containing the `defines` of this sub-package.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Raw fields, including the inherited spec variables.
+    pub fn fields(&self) -> &Context {
+        &self.fields
+    }
+
+    /// Whether `FAIL_ARCH` of this sub-package allows building for `arch`.
+    pub fn builds_on(&self, arch: &str) -> bool {
+        !autobuild::fail_arch_excludes(&self.fields, arch)
+    }
+
+    /// The policy the fields were evaluated with.
+    pub fn inheritance(&self) -> &SpecInheritance {
+        &self.inheritance
+    }
+
+    /// Fields as seen by autobuild when building for `arch`.
+    pub fn resolve(&self, arch: &str) -> Context {
+        resolve_arch_fields(&self.fields, arch)
+    }
+}
+
+#[derive(Debug)]
+pub enum PackageError {
+    IOError(PathBuf, io::Error),
+    ParseError(PathBuf, ParseError),
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageError::IOError(p, e) => write!(f, "Failed to read {}: {}", p.display(), e),
+            PackageError::ParseError(p, e) => write!(f, "Failed to parse {}: {}", p.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for PackageError {}
+
+#[cfg(feature = "std")]
+/// How the contents of a file are evaluated, i.e: `apf::parse` or through a
+/// cache. The path of the file comes first.
+pub(crate) type ParseFn<'a> = dyn Fn(&Path, &str, &mut Context) -> Result<(), ParseError> + 'a;
+
+/// `apf::parse` as a `ParseFn`.
+#[cfg(feature = "std")]
+pub(crate) fn parse_content(
+    _: &Path,
+    content: &str,
+    context: &mut Context,
+) -> Result<(), ParseError> {
+    apf::parse(content, context)
+}
+
+/// `path`, relative to the directory `base` in `root`, if it does not lead
+/// out of `root`.
+#[cfg(feature = "std")]
+fn confined(root: &Path, base: &Path, path: &str) -> Option<PathBuf> {
+    let mut components: Vec<_> = base.strip_prefix(root).ok()?.components().collect();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(_) => components.push(component),
+            Component::CurDir => (),
+            Component::ParentDir => {
+                components.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(root.join(components.iter().collect::<PathBuf>()))
+}
+
+/// Where package files are read from, i.e: the filesystem or a git revision.
+#[cfg(feature = "std")]
+pub(crate) trait PackageFiles {
+    fn read(&self, path: &Path) -> io::Result<String>;
+    fn is_file(&self, path: &Path) -> bool;
+    fn is_dir(&self, path: &Path) -> bool;
+    /// Paths of the entries in the directory `path`.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Hand the contents of `path` to `f`, without necessarily copying them.
+    fn read_with(
+        &self,
+        path: &Path,
+        f: &mut dyn FnMut(&str) -> Result<(), ParseError>,
+    ) -> io::Result<Result<(), ParseError>> {
+        Ok(f(&self.read(path)?))
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) struct Filesystem;
+
+#[cfg(feature = "std")]
+impl PackageFiles for Filesystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?.map(|e| e.map(|e| e.path())).collect()
+    }
+}
+
+/// `Filesystem`, with files mapped into memory rather than read. Falls back
+/// to reading where mapping fails, i.e: on platforms without mmap.
+#[cfg(feature = "mmap")]
+pub(crate) struct MappedFilesystem;
+
+#[cfg(feature = "mmap")]
+impl PackageFiles for MappedFilesystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        Filesystem.read(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        Filesystem.is_file(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        Filesystem.is_dir(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        Filesystem.read_dir(path)
+    }
+
+    fn read_with(
+        &self,
+        path: &Path,
+        f: &mut dyn FnMut(&str) -> Result<(), ParseError>,
+    ) -> io::Result<Result<(), ParseError>> {
+        let file = fs::File::open(path)?;
+        // SAFETY: the mapping is only read while the file is open, and trees
+        // are not expected to be modified during a scan.
+        match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(map) => {
+                let content = std::str::from_utf8(&map)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(f(content))
+            }
+            Err(_) => Ok(f(&fs::read_to_string(path)?)),
+        }
+    }
+}
+
+/// Where `Loader` reads the files of a package from.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub(crate) enum FileSource {
+    #[default]
+    Filesystem,
+    #[cfg(feature = "mmap")]
+    Mapped,
+    /// The tree object `tree` of the git repository at `repo`.
+    #[cfg(feature = "git")]
+    Git { repo: PathBuf, tree: git2::Oid },
+}
+
+/// How `Loader` evaluates the files of a package.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub(crate) enum ParseMode {
+    #[default]
+    Plain,
+    Options(ParseOptions),
+    /// Following `source` at most this deep, see
+    /// `Package::from_dir_with_sources`.
+    Sources(usize),
+}
+
+/// How a package was loaded, so that `Package::resolve_for` evaluates it
+/// again the same way.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Loader {
+    pub inheritance: SpecInheritance,
+    pub files: FileSource,
+    pub parse: ParseMode,
+}
+
+#[cfg(feature = "std")]
+impl Loader {
+    /// Load the package in `dir`, with the variables of `scope` set first.
+    pub fn load(&self, dir: &Path, scope: &Context) -> Result<Package, PackageError> {
+        let with_options;
+        let with_sources;
+        let parse: &ParseFn = match &self.parse {
+            ParseMode::Plain => &parse_content,
+            ParseMode::Options(options) => {
+                with_options = move |_: &Path, c: &str, context: &mut Context| {
+                    apf::parse_with_options(c, context, options)
+                };
+                &with_options
+            }
+            ParseMode::Sources(max_depth) => {
+                with_sources = parse_confined(dir, *max_depth);
+                &with_sources
+            }
+        };
+        let inheritance = &self.inheritance;
+        let package = match &self.files {
+            FileSource::Filesystem => Package::load_in(dir, scope, inheritance, parse, &Filesystem),
+            #[cfg(feature = "mmap")]
+            FileSource::Mapped => Package::load_in(dir, scope, inheritance, parse, &MappedFilesystem),
+            #[cfg(feature = "git")]
+            FileSource::Git { repo, tree } => {
+                crate::tree::load_git_package(repo, *tree, dir, scope, inheritance, parse)
+            }
+        }?;
+
+        Ok(package.loaded_by(self.clone()))
+    }
+}
+
+/// Evaluate files following `. FILE` and `source FILE` at most `max_depth`
+/// deep, with `FILE` relative to the file being evaluated and confined to
+/// `dir`.
+#[cfg(feature = "std")]
+fn parse_confined(
+    dir: &Path,
+    max_depth: usize,
+) -> impl Fn(&Path, &str, &mut Context) -> Result<(), ParseError> + '_ {
+    move |path: &Path, content: &str, context: &mut Context| {
+        let base = path.parent().unwrap_or(dir);
+        let read = |file: &str| match confined(dir, base, file) {
+            Some(path) => fs::read_to_string(path),
+            None => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "outside of the package",
+            )),
+        };
+        apf::parse_with_sources(content, context, &read, max_depth)
+    }
+}
+
+#[cfg(feature = "std")]
+fn parse_file(
+    path: &Path,
+    context: &mut Context,
+    parse: &ParseFn,
+    files: &dyn PackageFiles,
+) -> Result<(), PackageError> {
+    files
+        .read_with(path, &mut |content| parse(path, content, context))
+        .map_err(|e| PackageError::IOError(path.to_path_buf(), e))?
+        .map_err(|e| PackageError::ParseError(path.to_path_buf(), e))
+}
+
+#[cfg(feature = "std")]
+fn dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Strip the ordering prefix of a sub-package directory, i.e: `01-libfoo`.
+#[cfg(feature = "std")]
+pub(crate) fn subpackage_dir_name(name: &str) -> Option<&str> {
+    let (prefix, rest) = name.split_once('-')?;
+    if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_digit()) || rest.is_empty() {
+        return None;
+    }
+    Some(rest)
+}
+
+#[cfg(feature = "std")]
+fn load_subpackages(
+    autobuild: &Path,
+    scope: &Context,
+    spec: &Context,
+    inheritance: &SpecInheritance,
+    parse: &ParseFn,
+    files: &dyn PackageFiles,
+) -> Result<Vec<SubPackage>, PackageError> {
+    let entries = files
+        .read_dir(autobuild)
+        .map_err(|e| PackageError::IOError(autobuild.to_path_buf(), e))?;
+    let mut dirs = Vec::new();
+    for path in entries {
+        let name = dir_name(&path);
+        if let Some(name) = subpackage_dir_name(&name) {
+            if files.is_file(&path.join("defines")) {
+                dirs.push((path.clone(), name.to_string()));
+            }
+        }
+    }
+    dirs.sort();
+
+    let mut subpackages = Vec::new();
+    for (path, dir_name) in dirs {
+        let mut fields = scope.clone();
+        fields.extend(inheritance.apply(spec));
+        parse_file(&path.join("defines"), &mut fields, parse, files)?;
+        subpackages.push(SubPackage {
+            name: fields.get("PKGNAME").cloned().unwrap_or(dir_name),
+            path,
+            fields,
+            inheritance: inheritance.clone(),
+        });
+    }
+
+    Ok(subpackages)
+}
+
+impl Package {
+    pub fn new(name: &str, fields: Context) -> Self {
+        Package {
+            name: name.to_string(),
+            path: None,
+            fields,
+            subpackages: Vec::new(),
+            #[cfg(feature = "std")]
+            loader: Loader::default(),
+        }
+    }
+
+    /// Load the package in `dir`, i.e: `TREE/app-utils/foo`.
+    /// `spec` is parsed first, then `autobuild/defines` with the spec variables
+    /// in scope. The name is taken from `PKGNAME`, or the directory name.
+    ///
+    /// If there is no `autobuild/defines`, the package is loaded as a group of
+    /// sub-packages from `autobuild/NN-NAME/defines` instead, and its own fields
+    /// only contain the spec variables.
+    #[cfg(feature = "std")]
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, PackageError> {
+        Package::from_dir_with(dir, &SpecInheritance::All)
+    }
+
+    /// Same as `from_dir`, but sub-packages only see the spec variables
+    /// allowed by `inheritance`.
+    #[cfg(feature = "std")]
+    pub fn from_dir_with<P: AsRef<Path>>(
+        dir: P,
+        inheritance: &SpecInheritance,
+    ) -> Result<Self, PackageError> {
+        Package::load(dir.as_ref(), inheritance, &parse_content, &Filesystem)
+    }
+
+    /// Same as `from_dir_with`, with every file parsed with `options`, see
+    /// `apf::parse_with_options`.
+    #[cfg(feature = "std")]
+    pub fn from_dir_with_options<P: AsRef<Path>>(
+        dir: P,
+        inheritance: &SpecInheritance,
+        options: &ParseOptions,
+    ) -> Result<Self, PackageError> {
+        let loader = Loader {
+            inheritance: inheritance.clone(),
+            parse: ParseMode::Options(options.clone()),
+            ..Default::default()
+        };
+        loader.load(dir.as_ref(), &Context::new())
+    }
+
+    /// Same as `from_dir_with`, but `. FILE` and `source FILE` are followed
+    /// at most `max_depth` deep, see `apf::parse_with_sources`. `FILE` is
+    /// relative to the directory of the spec or defines file being loaded,
+    /// and may not lead out of `dir`.
+    #[cfg(feature = "std")]
+    pub fn from_dir_with_sources<P: AsRef<Path>>(
+        dir: P,
+        inheritance: &SpecInheritance,
+        max_depth: usize,
+    ) -> Result<Self, PackageError> {
+        let loader = Loader {
+            inheritance: inheritance.clone(),
+            parse: ParseMode::Sources(max_depth),
+            ..Default::default()
+        };
+        loader.load(dir.as_ref(), &Context::new())
+    }
+
+    /// Same as `from_dir_with`, but files unchanged since they were put in
+    /// `cache` are not parsed again.
+    #[cfg(feature = "cache")]
+    pub fn from_dir_cached<P: AsRef<Path>>(
+        dir: P,
+        inheritance: &SpecInheritance,
+        cache: &ParseCache,
+    ) -> Result<Self, PackageError> {
+        Package::load(
+            dir.as_ref(),
+            inheritance,
+            &|_, c, context| cache.parse(c, context),
+            &Filesystem,
+        )
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn load(
+        dir: &Path,
+        inheritance: &SpecInheritance,
+        parse: &ParseFn,
+        files: &dyn PackageFiles,
+    ) -> Result<Self, PackageError> {
+        Package::load_in(dir, &Context::new(), inheritance, parse, files)
+    }
+
+    /// Same as `load`, with the variables of `scope` set before evaluating
+    /// any file. They are left out of the fields unless a file changes them.
+    #[cfg(feature = "std")]
+    pub(crate) fn load_in(
+        dir: &Path,
+        scope: &Context,
+        inheritance: &SpecInheritance,
+        parse: &ParseFn,
+        files: &dyn PackageFiles,
+    ) -> Result<Self, PackageError> {
+        let mut fields = scope.clone();
+        parse_file(&dir.join("spec"), &mut fields, parse, files)?;
+
+        let autobuild = dir.join("autobuild");
+        let defines = autobuild.join("defines");
+        let mut subpackages = Vec::new();
+        if files.is_dir(&autobuild) && !files.is_file(&defines) && !files.is_dir(&defines) {
+            subpackages = load_subpackages(&autobuild, scope, &fields, inheritance, parse, files)?;
+        }
+        if subpackages.is_empty() {
+            parse_file(&defines, &mut fields, parse, files)?;
+        }
+        let unchanged = |fields: &mut Context| {
+            fields.retain(|k, v| scope.get(k) != Some(v));
+        };
+        unchanged(&mut fields);
+        for sub in subpackages.iter_mut() {
+            unchanged(&mut sub.fields);
+        }
+
+        let name = match fields.get("PKGNAME") {
+            Some(name) => name.clone(),
+            None => dir_name(dir),
+        };
+
+        Ok(Package {
+            name,
+            path: Some(dir.to_path_buf()),
+            fields,
+            subpackages,
+            loader: Loader {
+                inheritance: inheritance.clone(),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Set how the package was loaded, when not through `Loader::load`.
+    #[cfg(feature = "std")]
+    pub(crate) fn loaded_by(mut self, loader: Loader) -> Self {
+        self.loader = loader;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Directory the package was loaded from.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Raw fields, including architecture-specific overrides.
+    pub fn fields(&self) -> &Context {
+        &self.fields
+    }
+
+    /// Fields as seen by autobuild when building for `arch`.
+    pub fn resolve(&self, arch: &str) -> Context {
+        resolve_arch_fields(&self.fields, arch)
+    }
+
+    /// The package evaluated again as autobuild would when building natively
+    /// for `arch`, i.e: with `ARCH` set for conditionals like
+    /// `if [[ "$ARCH" = riscv64 ]]`, and overrides for `arch` folded into
+    /// their base fields, in sub-packages too.
+    #[cfg(feature = "std")]
+    pub fn resolve_for(&self, arch: Arch) -> Result<Package, PackageError> {
+        self.resolve_cross(arch, arch)
+    }
+
+    /// Same as `resolve_for`, cross-compiling on `build`.
+    /// The package is evaluated again the way it was loaded, i.e: from the
+    /// same git revision, or with the same `ParseOptions`. A package not
+    /// loaded from a directory cannot be evaluated again, only its overrides
+    /// are folded.
+    #[cfg(feature = "std")]
+    pub fn resolve_cross(&self, host: Arch, build: Arch) -> Result<Package, PackageError> {
+        let mut package = match &self.path {
+            Some(dir) => self.loader.load(dir, &host.cross_preset(build))?,
+            None => self.clone(),
+        };
+        package.fields = resolve_arch_fields(&package.fields, host.as_str());
+        for sub in package.subpackages.iter_mut() {
+            sub.fields = resolve_arch_fields(&sub.fields, host.as_str());
+        }
+
+        Ok(package)
+    }
+
+    /// Raw field `key` read as `T`, i.e: `get_typed::<bool>("NOPARALLEL")`.
+    /// `None` if the field is not set.
+    pub fn get_typed<T: FieldValue>(&self, key: &str) -> Result<Option<T>, FieldError> {
+        self.fields
+            .get(key)
+            .map(|value| fields::get_typed(key, value))
+            .transpose()
+    }
+
+    /// Whether the package builds for `arch`: `FAIL_ARCH` does not exclude
+    /// it, and for a group, not every sub-package is excluded either.
+    pub fn builds_on(&self, arch: &str) -> bool {
+        !autobuild::fail_arch_excludes(&self.fields, arch)
+            && (self.subpackages.is_empty() || self.subpackages.iter().any(|s| s.builds_on(arch)))
+    }
+
+    /// Sub-packages, in build order. Empty unless the package is a group.
+    pub fn subpackages(&self) -> &[SubPackage] {
+        &self.subpackages
+    }
+
+    /// The package as a TOML document.
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        export::to_toml(self)
+    }
+
+    /// The package as a YAML document.
+    #[cfg(feature = "yaml")]
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        export::to_yaml(self)
+    }
+
+    /// Check the raw fields against `registry`.
+    pub fn validate(&self, registry: &ValidatorRegistry) -> Vec<ValidationError> {
+        registry.validate_context(&self.fields)
+    }
+
+    /// SHA-256 of the name and evaluated fields of the package and its
+    /// sub-packages, as hex. Fields are taken in sorted order and runs of
+    /// whitespace in values count as a single space, so only a change of
+    /// metadata changes it, not editing comments or formatting of the files.
+    /// Overrides for every architecture are included, i.e: to tell whether
+    /// any build needs to run again.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        let mut update = |data: &str| {
+            // Length-prefixed, so values cannot run into each other.
+            hasher.update((data.len() as u64).to_le_bytes());
+            hasher.update(data.as_bytes());
+        };
+        let mut units = vec![(self.name(), self.fields())];
+        units.extend(self.subpackages.iter().map(|s| (s.name(), s.fields())));
+        for (name, fields) in units {
+            update(name);
+            let mut fields: Vec<_> = fields.iter().collect();
+            fields.sort();
+            for (key, value) in fields {
+                update(key);
+                update(&value.split_whitespace().collect::<Vec<_>>().join(" "));
+            }
+        }
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Split `FIELD__ARCH` into `(FIELD, ARCH)`.
+/// Returns `None` for plain fields.
+pub fn split_arch_suffix(key: &str) -> Option<(&str, &str)> {
+    let idx = key.rfind(ARCH_SEPARATOR)?;
+    let (field, suffix) = (&key[..idx], &key[idx + ARCH_SEPARATOR.len()..]);
+    if field.is_empty()
+        || suffix.is_empty()
+        || !suffix
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    Some((field, suffix))
+}
+
+/// Fold `FIELD__ARCH` overrides into their base fields.
+///
+/// Following autobuild, an override for the target architecture always wins over
+/// the base field regardless of assignment order, and overrides for other
+/// architectures are dropped from the result.
+pub fn resolve_arch_fields(fields: &Context, arch: &str) -> Context {
+    let target = arch.to_ascii_uppercase();
+    let mut resolved = Context::new();
+    let mut overrides = Vec::new();
+
+    for (key, value) in fields.iter() {
+        match split_arch_suffix(key) {
+            Some((field, suffix)) => {
+                if suffix == target {
+                    overrides.push((field, value));
+                }
+            }
+            None => {
+                resolved.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    for (field, value) in overrides {
+        resolved.insert(field.to_string(), value.clone());
+    }
+
+    resolved
+}
+
+/// Description of a package to create with `scaffold`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NewPackage {
+    pub name: String,
+    pub ver: String,
+    /// Written only if not 0.
+    pub rel: u64,
+    /// Sources, i.e: `tbl::https://example.com/foo-$VER.tar.xz`. `$VER` and
+    /// other variables are expanded, unlike in the other fields.
+    pub srcs: Vec<String>,
+    /// Checksums of `srcs`, in the same order. Missing ones are `SKIP`.
+    pub chksums: Vec<String>,
+    pub chkupdate: Option<String>,
+    pub abtype: Option<String>,
+    /// `PKGSEC`, i.e: `utils`.
+    pub section: String,
+    pub description: String,
+    pub dependencies: Vec<String>,
+    pub build_dependencies: Vec<String>,
+}
+
+impl NewPackage {
+    /// The package as evaluated from the files written by `scaffold`.
+    pub fn to_package(&self) -> Result<Package, ParseError> {
+        let (spec, defines) = scaffold(self)?;
+        let mut fields = Context::new();
+        crate::apf::parse(&spec, &mut fields)?;
+        crate::apf::parse(&defines, &mut fields)?;
+        Ok(Package::new(&self.name, fields))
+    }
+}
+
+/// Contents of the `spec` and `autobuild/defines` files of a new package, in
+/// the canonical format. Fails if the variables in `srcs` do not parse,
+/// i.e: an unterminated `${VER`.
+pub fn scaffold(package: &NewPackage) -> Result<(String, String), ParseError> {
+    let double_quote = |value: &str| quote_with(value, Style::Double);
+    let mut spec = format!("VER={}\n", double_quote(&package.ver));
+    if package.rel != 0 {
+        spec += &format!("REL={}\n", package.rel);
+    }
+    if !package.srcs.is_empty() {
+        let chksums: Vec<_> = (0..package.srcs.len())
+            .map(|i| package.chksums.get(i).map_or("SKIP", |c| c.as_str()))
+            .collect();
+        spec += &format!("SRCS={}\n", quote_template(&package.srcs.join(" ")));
+        spec += &format!("CHKSUMS={}\n", double_quote(&chksums.join(" ")));
+    }
+    if let Some(chkupdate) = &package.chkupdate {
+        spec += &format!("CHKUPDATE={}\n", double_quote(chkupdate));
+    }
+
+    let mut defines = format!(
+        "PKGNAME={}\nPKGSEC={}\n",
+        double_quote(&package.name),
+        double_quote(&package.section)
+    );
+    if !package.dependencies.is_empty() {
+        defines += &format!("PKGDEP={}\n", double_quote(&package.dependencies.join(" ")));
+    }
+    if !package.build_dependencies.is_empty() {
+        defines += &format!(
+            "BUILDDEP={}\n",
+            double_quote(&package.build_dependencies.join(" "))
+        );
+    }
+    defines += &format!("PKGDES={}\n", double_quote(&package.description));
+    if let Some(abtype) = &package.abtype {
+        defines += &format!("ABTYPE={}\n", double_quote(abtype));
+    }
+
+    Ok((crate::fmt::format(&spec)?, crate::fmt::format(&defines)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_subpackages() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("foo");
+        fs::create_dir_all(dir.join("autobuild").join("01-libfoo")).unwrap();
+        fs::create_dir_all(dir.join("autobuild").join("02-foo-dev")).unwrap();
+        fs::create_dir_all(dir.join("autobuild").join("patches")).unwrap();
+        fs::write(dir.join("spec"), "VER=1.2\n").unwrap();
+        fs::write(
+            dir.join("autobuild").join("01-libfoo").join("defines"),
+            "PKGNAME=libfoo\nPKGDES=\"Foo library $VER\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("autobuild").join("02-foo-dev").join("defines"),
+            "PKGDEP=\"libfoo==$VER\"\n",
+        )
+        .unwrap();
+
+        let pkg = Package::from_dir(&dir).unwrap();
+        assert_eq!(pkg.name(), "foo");
+        assert_eq!(pkg.fields().len(), 1);
+        let subs = pkg.subpackages();
+        assert_eq!(subs.len(), 2);
+        assert_eq!(subs[0].name(), "libfoo");
+        assert_eq!(subs[0].fields()["PKGDES"], "Foo library 1.2");
+        assert_eq!(subs[1].name(), "foo-dev");
+        assert_eq!(subs[1].fields()["PKGDEP"], "libfoo==1.2");
+        assert!(subs[1].fields().get("PKGDES").is_none());
+        assert_eq!(subs[1].inheritance(), &SpecInheritance::All);
+
+        let only = SpecInheritance::Only(vec!["VER".to_string()]);
+        fs::write(dir.join("spec"), "VER=1.2\nREL=3\n").unwrap();
+        let pkg = Package::from_dir_with(&dir, &only).unwrap();
+        assert_eq!(pkg.subpackages()[1].fields()["PKGDEP"], "libfoo==1.2");
+        assert!(pkg.subpackages()[1].fields().get("REL").is_none());
+        assert_eq!(pkg.subpackages()[1].inheritance(), &only);
+
+        let pkg = Package::from_dir_with(&dir, &SpecInheritance::None);
+        assert!(matches!(pkg, Err(PackageError::ParseError(..))));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_fingerprint() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("foo");
+        fs::create_dir_all(dir.join("autobuild")).unwrap();
+        fs::write(dir.join("spec"), "VER=1.2\n").unwrap();
+        fs::write(
+            dir.join("autobuild").join("defines"),
+            "PKGNAME=foo\nPKGDEP=\"bar baz\"\n",
+        )
+        .unwrap();
+        let fingerprint = Package::from_dir(&dir).unwrap().fingerprint();
+        assert_eq!(fingerprint.len(), 64);
+
+        fs::write(
+            dir.join("autobuild").join("defines"),
+            "# Dependencies\nPKGDEP=\"\n    bar\n    baz\n\"\n\nPKGNAME=foo\n",
+        )
+        .unwrap();
+        assert_eq!(Package::from_dir(&dir).unwrap().fingerprint(), fingerprint);
+
+        fs::write(dir.join("spec"), "VER=1.3\n").unwrap();
+        assert_ne!(Package::from_dir(&dir).unwrap().fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_split_arch_suffix() {
+        assert_eq!(split_arch_suffix("PKGDEP__AMD64"), Some(("PKGDEP", "AMD64")));
+        assert_eq!(split_arch_suffix("VER__PPC64EL"), Some(("VER", "PPC64EL")));
+        assert_eq!(split_arch_suffix("PKGDEP"), None);
+        assert_eq!(split_arch_suffix("__AMD64"), None);
+        assert_eq!(split_arch_suffix("PKGDEP__"), None);
+        assert_eq!(split_arch_suffix("my__var"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut fields = Context::new();
+        fields.insert("VER".to_string(), "1.0".to_string());
+        fields.insert("PKGDEP".to_string(), "glibc".to_string());
+        fields.insert("PKGDEP__AMD64".to_string(), "glibc nasm".to_string());
+        fields.insert("VER__ARM64".to_string(), "1.1".to_string());
+        fields.insert("BUILDDEP__ARM64".to_string(), "llvm".to_string());
+        let pkg = Package::new("foo", fields);
+
+        let amd64 = pkg.resolve("amd64");
+        assert_eq!(amd64.len(), 2);
+        assert_eq!(amd64["VER"], "1.0");
+        assert_eq!(amd64["PKGDEP"], "glibc nasm");
+
+        let arm64 = pkg.resolve("arm64");
+        assert_eq!(arm64.len(), 3);
+        assert_eq!(arm64["VER"], "1.1");
+        assert_eq!(arm64["PKGDEP"], "glibc");
+        assert_eq!(arm64["BUILDDEP"], "llvm");
+
+        // raw view keeps everything
+        assert_eq!(pkg.fields().len(), 5);
+    }
+
+    #[test]
+    fn test_get_typed() {
+        let mut fields = Context::new();
+        fields.insert("NOPARALLEL".to_string(), "1".to_string());
+        fields.insert("PKGDEP__AMD64".to_string(), "glibc nasm".to_string());
+        let pkg = Package::new("foo", fields);
+        assert_eq!(pkg.get_typed::<bool>("NOPARALLEL"), Ok(Some(true)));
+        assert_eq!(pkg.get_typed::<bool>("NOSTATIC"), Ok(None));
+        let deps = pkg.get_typed::<Vec<String>>("PKGDEP__AMD64").unwrap().unwrap();
+        assert_eq!(deps, vec!["glibc", "nasm"]);
+        assert!(pkg.get_typed::<u64>("NOPARALLEL").is_err());
+    }
+
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    #[test]
+    fn test_toml_yaml() {
+        let mut fields = Context::new();
+        fields.insert("VER".to_string(), "1.0".to_string());
+        fields.insert("PKGDES".to_string(), "Foo".to_string());
+        let pkg = Package::new("foo", fields);
+        assert_eq!(
+            pkg.to_toml().unwrap(),
+            "name = \"foo\"\nsubpackages = []\n\n[fields]\nPKGDES = \"Foo\"\nVER = \"1.0\"\n"
+        );
+        assert_eq!(
+            pkg.to_yaml().unwrap(),
+            "name: foo\npath: null\nfields:\n  PKGDES: Foo\n  VER: '1.0'\nsubpackages: []\n"
+        );
+    }
+
+    #[test]
+    fn test_scaffold() {
+        let (spec, defines) = scaffold(&NewPackage {
+            name: "foo".to_string(),
+            ver: "1.0".to_string(),
+            srcs: vec![
+                "tbl::https://example.com/foo-$VER.tar.xz".to_string(),
+                "git::commit=tags/v$VER::https://example.com/bar".to_string(),
+            ],
+            chksums: vec!["sha256::abcd".to_string()],
+            section: "utils".to_string(),
+            description: "The \"foo\" tool".to_string(),
+            dependencies: vec!["glibc".to_string(), "zlib".to_string()],
+            abtype: Some("cmakeninja".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            spec,
+            "VER=\"1.0\"\nSRCS=\"tbl::https://example.com/foo-$VER.tar.xz \\\n      git::commit=tags/v$VER::https://example.com/bar\"\nCHKSUMS=\"sha256::abcd SKIP\"\n"
+        );
+        assert_eq!(
+            defines,
+            "PKGNAME=\"foo\"\nPKGSEC=\"utils\"\nPKGDEP=\"glibc zlib\"\nPKGDES=\"The \\\"foo\\\" tool\"\nABTYPE=\"cmakeninja\"\n"
+        );
+
+        let mut context = Context::new();
+        crate::apf::parse(&spec, &mut context).unwrap();
+        crate::apf::parse(&defines, &mut context).unwrap();
+        let srcs: Vec<_> = context.get("SRCS").unwrap().split_whitespace().collect();
+        assert_eq!(
+            srcs,
+            vec![
+                "tbl::https://example.com/foo-1.0.tar.xz",
+                "git::commit=tags/v1.0::https://example.com/bar"
+            ]
+        );
+        assert_eq!(context.get("PKGDES").unwrap(), "The \"foo\" tool");
+
+        let unterminated = NewPackage {
+            name: "foo".to_string(),
+            ver: "1.0".to_string(),
+            srcs: vec!["tbl::https://example.com/foo-${VER".to_string()],
+            ..Default::default()
+        };
+        assert!(scaffold(&unterminated).is_err());
+        assert!(unterminated.to_package().is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_dir_with_sources() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("foo");
+        fs::create_dir_all(dir.join("autobuild/01-foo")).unwrap();
+        fs::write(dir.join("spec"), "VER=1\n").unwrap();
+        fs::write(dir.join("autobuild/common"), "PKGSEC=utils\n").unwrap();
+        fs::write(root.path().join("secret"), "A=1\n").unwrap();
+        let defines = dir.join("autobuild/01-foo/defines");
+        fs::write(&defines, "PKGNAME=foo\n. ../common\n").unwrap();
+
+        let package = Package::from_dir_with_sources(&dir, &SpecInheritance::All, 1).unwrap();
+        assert_eq!(package.subpackages()[0].fields()["PKGSEC"], "utils");
+        assert!(Package::from_dir(&dir).is_err());
+
+        fs::write(&defines, "PKGNAME=foo\n. ../../../secret\n").unwrap();
+        assert!(Package::from_dir_with_sources(&dir, &SpecInheritance::All, 1).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_resolve_for() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("foo");
+        fs::create_dir_all(dir.join("autobuild")).unwrap();
+        fs::write(
+            dir.join("spec"),
+            "VER=1\nif [[ \"$ARCH\" = riscv64 ]]; then\n    VER=2\nfi\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("autobuild").join("defines"),
+            "PKGNAME=foo\nPKGDEP=\"bar\"\nPKGDEP__RISCV64=\"baz\"\nTARGET=\"${HOST}\"\nCROSSING=\"$CROSS\"\n",
+        )
+        .unwrap();
+
+        let mut package = Package {
+            path: Some(dir.clone()),
+            ..Package::new("foo", Context::new())
+        };
+
+        let resolved = package.resolve_for(Arch::Riscv64).unwrap();
+        let fields = resolved.fields();
+        assert_eq!(resolved.name(), "foo");
+        assert_eq!(fields.get("VER").unwrap(), "2");
+        assert_eq!(fields.get("PKGDEP").unwrap(), "baz");
+        assert_eq!(fields.get("TARGET").unwrap(), "riscv64-aosc-linux-gnu");
+        assert_eq!(fields.get("CROSSING").unwrap(), "");
+        assert!(fields.get("ARCH").is_none());
+        assert!(fields.get("PKGDEP__RISCV64").is_none());
+
+        let resolved = package.resolve_cross(Arch::Riscv64, Arch::Amd64).unwrap();
+        assert_eq!(resolved.fields().get("CROSSING").unwrap(), "riscv64");
+        let resolved = package.resolve_for(Arch::Amd64).unwrap();
+        assert_eq!(resolved.fields().get("VER").unwrap(), "1");
+        assert_eq!(resolved.fields().get("PKGDEP").unwrap(), "bar");
+
+        package.path = None;
+        package
+            .fields
+            .insert("PKGDEP__AMD64".to_string(), "qux".to_string());
+        let resolved = package.resolve_for(Arch::Amd64).unwrap();
+        assert_eq!(resolved.fields().get("PKGDEP").unwrap(), "qux");
+    }
+}
@@ -0,0 +1,292 @@
+//! Package selections with provenance.
+//! Every package in a set remembers why it was selected, so build orders and
+//! reports derived from the set can explain themselves, i.e:
+//! `DependencyGraph::rebuild_plan` keeps the reasons in the plan.
+//!
+//! Sets hold binary package names, the nodes of `DependencyGraph`: a package
+//! with sub-packages is selected through its sub-packages.
+
+use crate::{
+    deps::{DependencyGraph, Follow},
+    groups::{GroupError, Groups},
+    package::Package,
+    query::Query,
+};
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Why a package is part of a selection.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Reason {
+    /// Requested by name.
+    Explicit,
+    /// Listed in the named group.
+    Group(String),
+    /// Matched the given query.
+    Query(String),
+    /// Needed by the given package.
+    DependencyOf(String),
+    /// Depends on the given package.
+    ReverseDependencyOf(String),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Explicit => write!(f, "explicitly requested"),
+            Reason::Group(g) => write!(f, "member of group {}", g),
+            Reason::Query(q) => write!(f, "matched query `{}`", q),
+            Reason::DependencyOf(p) => write!(f, "dependency of {}", p),
+            Reason::ReverseDependencyOf(p) => write!(f, "reverse dependency of {}", p),
+        }
+    }
+}
+
+/// Binary packages of `package`, as in `DependencyGraph::from_packages`.
+fn unit_names(package: &Package) -> Vec<&str> {
+    if package.subpackages().is_empty() {
+        vec![package.name()]
+    } else {
+        package.subpackages().iter().map(|s| s.name()).collect()
+    }
+}
+
+/// A set of package names, each annotated with the reasons of its selection.
+/// Iteration is always in package name order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageSet {
+    entries: BTreeMap<String, BTreeSet<Reason>>,
+}
+
+impl PackageSet {
+    pub fn new() -> Self {
+        PackageSet::default()
+    }
+
+    /// Create a set where every package shares the same reason.
+    pub fn from_names<I, S>(names: I, reason: Reason) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut set = PackageSet::new();
+        for name in names {
+            set.insert(name, reason.clone());
+        }
+        set
+    }
+
+    /// Packages of the group `name`, nested groups included, looked up in
+    /// `packages`.
+    pub fn from_group(groups: &Groups, name: &str, packages: &[Package]) -> Result<Self, GroupError> {
+        let reason = Reason::Group(name.to_string());
+        let mut set = PackageSet::new();
+        for package in groups.resolve(name, packages)? {
+            for unit in unit_names(package) {
+                set.insert(unit, reason.clone());
+            }
+        }
+        Ok(set)
+    }
+
+    /// Packages of `packages` matching `query`.
+    pub fn from_query(query: &Query, packages: &[Package]) -> Self {
+        let reason = Reason::Query(query.to_string());
+        let mut set = PackageSet::new();
+        for package in query.filter(packages) {
+            for unit in unit_names(package) {
+                set.insert(unit, reason.clone());
+            }
+        }
+        set
+    }
+
+    /// Same as `DependencyGraph::rebuild_set`, with every package annotated
+    /// with the packages of the set, or `changed`, it directly depends on.
+    pub fn from_rebuild_set(
+        graph: &DependencyGraph,
+        changed: &[&str],
+        depth: Option<usize>,
+        follow: Follow,
+    ) -> Self {
+        let names = graph.rebuild_set(changed, depth, follow);
+        let mut set = PackageSet::new();
+        for name in names.iter().copied() {
+            for (dep, kind) in graph.dependencies(name) {
+                if follow.allows(kind) && (changed.contains(&dep) || names.contains(&dep)) {
+                    set.insert(name, Reason::ReverseDependencyOf(dep.to_string()));
+                }
+            }
+        }
+        set
+    }
+
+    /// Add a package, or record an additional reason if it is already present.
+    pub fn insert<S: Into<String>>(&mut self, name: S, reason: Reason) {
+        self.entries.entry(name.into()).or_default().insert(reason);
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn reasons(&self, name: &str) -> Option<&BTreeSet<Reason>> {
+        self.entries.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|k| k.as_str())
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, String, BTreeSet<Reason>> {
+        self.entries.iter()
+    }
+
+    /// Packages in either set. Reasons from both sides are kept.
+    pub fn union(&self, other: &PackageSet) -> PackageSet {
+        let mut result = self.clone();
+        result.extend(other.clone());
+        result
+    }
+
+    /// Packages in both sets. Reasons from both sides are kept.
+    pub fn intersection(&self, other: &PackageSet) -> PackageSet {
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(name, reasons)| {
+                other.entries.get(name).map(|other_reasons| {
+                    (name.clone(), reasons.union(other_reasons).cloned().collect())
+                })
+            })
+            .collect();
+        PackageSet { entries }
+    }
+
+    /// Packages in this set but not in `other`.
+    pub fn difference(&self, other: &PackageSet) -> PackageSet {
+        let entries = self
+            .entries
+            .iter()
+            .filter(|(name, _)| !other.entries.contains_key(*name))
+            .map(|(name, reasons)| (name.clone(), reasons.clone()))
+            .collect();
+        PackageSet { entries }
+    }
+}
+
+impl Extend<(String, BTreeSet<Reason>)> for PackageSet {
+    fn extend<T: IntoIterator<Item = (String, BTreeSet<Reason>)>>(&mut self, iter: T) {
+        for (name, reasons) in iter {
+            self.entries.entry(name).or_default().extend(reasons);
+        }
+    }
+}
+
+impl IntoIterator for PackageSet {
+    type Item = (String, BTreeSet<Reason>);
+    type IntoIter = btree_map::IntoIter<String, BTreeSet<Reason>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a PackageSet {
+    type Item = (&'a String, &'a BTreeSet<Reason>);
+    type IntoIter = btree_map::Iter<'a, String, BTreeSet<Reason>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl fmt::Display for PackageSet {
+    /// One package per line, followed by its reasons.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, reasons) in self.entries.iter() {
+            let reasons: Vec<String> = reasons.iter().map(|r| r.to_string()).collect();
+            writeln!(f, "{}: {}", name, reasons.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::package, groups::Group};
+
+    #[test]
+    fn test_set_operations() {
+        let group = PackageSet::from_names(vec!["a", "b", "c"], Reason::Group("base".to_string()));
+        let mut deps = PackageSet::from_names(vec!["b", "d"], Reason::DependencyOf("e".to_string()));
+        deps.insert("b", Reason::Explicit);
+
+        let union = group.union(&deps);
+        assert_eq!(union.names().collect::<Vec<_>>(), vec!["a", "b", "c", "d"]);
+        assert_eq!(union.reasons("b").unwrap().len(), 3);
+
+        let intersection = group.intersection(&deps);
+        assert_eq!(intersection.names().collect::<Vec<_>>(), vec!["b"]);
+        assert_eq!(intersection.reasons("b").unwrap().len(), 3);
+
+        let difference = group.difference(&deps);
+        assert_eq!(difference.names().collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(
+            difference.reasons("a").unwrap().iter().collect::<Vec<_>>(),
+            vec![&Reason::Group("base".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_constructors() {
+        let packages = vec![
+            package("glibc", &[]),
+            package("zlib", &[("PKGDEP", "glibc")]),
+            package("curl", &[("PKGDEP", "zlib"), ("PKGSEC", "net")]),
+        ];
+
+        let mut groups = Groups::default();
+        groups.insert(Group::parse("base", "glibc\ncurl\n").unwrap());
+        let set = PackageSet::from_group(&groups, "base", &packages).unwrap();
+        assert_eq!(set.names().collect::<Vec<_>>(), vec!["curl", "glibc"]);
+        assert!(PackageSet::from_group(&groups, "missing", &packages).is_err());
+
+        let query = Query::parse("PKGSEC == \"net\"").unwrap();
+        let set = PackageSet::from_query(&query, &packages);
+        assert_eq!(set.to_string(), "curl: matched query `PKGSEC == \"net\"`\n");
+
+        let graph = DependencyGraph::from_packages(&packages, None).unwrap();
+        let set = PackageSet::from_rebuild_set(&graph, &["glibc"], None, Follow::Both);
+        assert_eq!(
+            set.to_string(),
+            "curl: reverse dependency of zlib\nzlib: reverse dependency of glibc\n"
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let mut set = PackageSet::new();
+        set.insert("zlib", Reason::ReverseDependencyOf("glibc".to_string()));
+        set.insert("bash", Reason::Explicit);
+        set.insert("bash", Reason::Query("PKGSEC == shells".to_string()));
+        assert_eq!(
+            set.to_string(),
+            "bash: explicitly requested, matched query `PKGSEC == shells`\n\
+             zlib: reverse dependency of glibc\n"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Declarative rebuild plans.
+//! A plan lists the packages to rebuild, the architectures to build them for,
+//! why the rebuild is needed and the stages in which they should be built.
+//! Packages within one stage have no dependencies on each other.
+//! `DependencyGraph::rebuild_plan` produces one from the dependency graph.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RebuildPlan {
+    pub targets: Vec<String>,
+    pub archs: Vec<String>,
+    pub reason: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stages: Vec<Vec<String>>,
+    /// Why each target was selected, i.e: from the `PackageSet` the plan was
+    /// made for. Targets without reasons are left out.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub reasons: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PlanError {
+    NoTargets,
+    NoArchs,
+    EmptyStage(usize),
+    DuplicatePackage(String),
+    UnknownPackage(String),
+    UnscheduledPackage(String),
+    FormatError(String),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::NoTargets => write!(f, "Plan has no target packages."),
+            PlanError::NoArchs => write!(f, "Plan has no target architectures."),
+            PlanError::EmptyStage(i) => write!(f, "Stage {} is empty.", i + 1),
+            PlanError::DuplicatePackage(p) => write!(f, "Package {} is listed more than once.", p),
+            PlanError::UnknownPackage(p) => write!(f, "Package {} is planned but not a target.", p),
+            PlanError::UnscheduledPackage(p) => write!(f, "Target {} is not in any stage.", p),
+            PlanError::FormatError(e) => write!(f, "Bad plan file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+impl RebuildPlan {
+    pub fn new(targets: Vec<String>, archs: Vec<String>, reason: &str) -> Self {
+        RebuildPlan {
+            targets,
+            archs,
+            reason: reason.to_string(),
+            stages: Vec::new(),
+            reasons: BTreeMap::new(),
+        }
+    }
+
+    /// Check that the plan is self-consistent.
+    /// A plan without stages is valid; once staged, every target must appear in
+    /// exactly one stage and nothing else may be staged. Reasons may only be
+    /// given for targets.
+    pub fn validate(&self) -> Result<(), PlanError> {
+        if self.targets.is_empty() {
+            return Err(PlanError::NoTargets);
+        }
+        if self.archs.is_empty() {
+            return Err(PlanError::NoArchs);
+        }
+        let mut targets = HashSet::new();
+        for t in self.targets.iter() {
+            if !targets.insert(t.as_str()) {
+                return Err(PlanError::DuplicatePackage(t.clone()));
+            }
+        }
+        if let Some(p) = self.reasons.keys().find(|p| !targets.contains(p.as_str())) {
+            return Err(PlanError::UnknownPackage(p.clone()));
+        }
+        if self.stages.is_empty() {
+            return Ok(());
+        }
+
+        let mut staged = HashSet::new();
+        for (i, stage) in self.stages.iter().enumerate() {
+            if stage.is_empty() {
+                return Err(PlanError::EmptyStage(i));
+            }
+            for p in stage.iter() {
+                if !targets.contains(p.as_str()) {
+                    return Err(PlanError::UnknownPackage(p.clone()));
+                }
+                if !staged.insert(p.as_str()) {
+                    return Err(PlanError::DuplicatePackage(p.clone()));
+                }
+            }
+        }
+        if let Some(t) = self.targets.iter().find(|t| !staged.contains(t.as_str())) {
+            return Err(PlanError::UnscheduledPackage(t.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Read and validate a plan in JSON format.
+    #[cfg(feature = "serde")]
+    pub fn from_json(s: &str) -> Result<Self, PlanError> {
+        let plan: RebuildPlan =
+            serde_json::from_str(s).map_err(|e| PlanError::FormatError(e.to_string()))?;
+        plan.validate()?;
+        Ok(plan)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, PlanError> {
+        serde_json::to_string_pretty(self).map_err(|e| PlanError::FormatError(e.to_string()))
+    }
+}
+
+impl fmt::Display for RebuildPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Rebuild plan: {}", self.reason)?;
+        writeln!(f, "Architectures: {}", self.archs.join(", "))?;
+        writeln!(f, "Targets ({}): {}", self.targets.len(), self.targets.join(", "))?;
+        for (i, stage) in self.stages.iter().enumerate() {
+            writeln!(f, "Stage {}: {}", i + 1, stage.join(", "))?;
+        }
+        for (name, reasons) in self.reasons.iter() {
+            writeln!(f, "{}: {}", name, reasons.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(n: &[&str]) -> Vec<String> {
+        n.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut plan = RebuildPlan::new(names(&["a", "b", "c"]), names(&["amd64"]), "soname bump");
+        assert_eq!(plan.validate(), Ok(()));
+        plan.stages = vec![names(&["a"]), names(&["b", "c"])];
+        assert_eq!(plan.validate(), Ok(()));
+
+        plan.stages = vec![names(&["a"]), names(&["b"])];
+        assert_eq!(plan.validate(), Err(PlanError::UnscheduledPackage("c".to_string())));
+        plan.stages = vec![names(&["a", "b"]), names(&["b", "c"])];
+        assert_eq!(plan.validate(), Err(PlanError::DuplicatePackage("b".to_string())));
+        plan.stages = vec![names(&["a", "b", "c", "d"])];
+        assert_eq!(plan.validate(), Err(PlanError::UnknownPackage("d".to_string())));
+        plan.stages = vec![names(&["a", "b", "c"]), vec![]];
+        assert_eq!(plan.validate(), Err(PlanError::EmptyStage(1)));
+        plan.stages.clear();
+        plan.reasons.insert("d".to_string(), names(&["explicitly requested"]));
+        assert_eq!(plan.validate(), Err(PlanError::UnknownPackage("d".to_string())));
+
+        plan.archs.clear();
+        assert_eq!(plan.validate(), Err(PlanError::NoArchs));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut plan = RebuildPlan::new(names(&["a", "b"]), names(&["amd64", "arm64"]), "icu 67");
+        plan.stages = vec![names(&["a"]), names(&["b"])];
+        assert_eq!(
+            plan.to_string(),
+            "Rebuild plan: icu 67\nArchitectures: amd64, arm64\nTargets (2): a, b\nStage 1: a\nStage 2: b\n"
+        );
+        plan.reasons.insert("b".to_string(), names(&["reverse dependency of icu"]));
+        assert!(plan.to_string().ends_with("Stage 2: b\nb: reverse dependency of icu\n"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let plan = RebuildPlan::from_json(
+            r#"{"targets": ["a", "b"], "archs": ["amd64"], "reason": "test", "stages": [["b"], ["a"]]}"#,
+        )
+        .unwrap();
+        assert_eq!(plan.stages, vec![names(&["b"]), names(&["a"])]);
+        assert_eq!(RebuildPlan::from_json(&plan.to_json().unwrap()).unwrap(), plan);
+        assert!(RebuildPlan::from_json(r#"{"targets": [], "archs": ["amd64"], "reason": ""}"#).is_err());
+    }
+}
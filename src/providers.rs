@@ -0,0 +1,359 @@
+//! Packages available outside of the tree, i.e: installed ones or those of a
+//! repository, so dependencies they satisfy are told apart from those truly
+//! missing.
+//!
+//! A `PackageIndex` reads the stanzas of a dpkg `status` file or of a
+//! repository `Packages` index; with the `http` feature it can also fetch the
+//! latter. Other sources implement `Provider`.
+
+#[cfg(feature = "http")]
+use crate::updates::{HttpGet, UpdateError};
+use crate::{
+    dependency::{parse_dependencies, Dependency},
+    deps::{DepKind, GraphError},
+    package::{resolve_arch_fields, Package},
+    version::Version,
+};
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use std::{fs, io, path::Path};
+
+/// A source of packages outside of the tree.
+pub trait Provider {
+    /// Whether this source has a package satisfying `dependency`, version and
+    /// architecture qualifier included.
+    fn satisfies(&self, dependency: &Dependency) -> bool;
+}
+
+/// A package, or a virtual package it provides, in an index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub name: String,
+    /// `None` for virtual packages provided without a version, and versions
+    /// which are not valid.
+    pub version: Option<Version>,
+    pub architecture: Option<String>,
+    /// The package providing this one, if it is virtual.
+    pub provided_by: Option<String>,
+}
+
+/// Fields of each stanza of a Debian control file, i.e: `Package: bash`.
+/// Continuation lines are joined to their field with a newline.
+pub(crate) fn stanzas(content: &str) -> Vec<Vec<(&str, String)>> {
+    let mut result = Vec::new();
+    let mut current: Vec<(&str, String)> = Vec::new();
+    for line in content.lines() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                result.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = current.last_mut() {
+                value.push('\n');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            current.push((key.trim(), value.trim().to_string()));
+        }
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+/// Packages of a dpkg `status` file or a repository `Packages` index.
+#[derive(Debug, Clone, Default)]
+pub struct PackageIndex {
+    entries: HashMap<String, Vec<IndexEntry>>,
+}
+
+impl PackageIndex {
+    /// Read a repository `Packages` index.
+    pub fn parse(content: &str) -> Self {
+        PackageIndex::parse_filtered(content, |_| true)
+    }
+
+    /// Read a dpkg `status` file, keeping only installed packages.
+    pub fn parse_dpkg_status(content: &str) -> Self {
+        PackageIndex::parse_filtered(content, |stanza| {
+            stanza
+                .iter()
+                .any(|(k, v)| *k == "Status" && v.ends_with(" installed"))
+        })
+    }
+
+    fn parse_filtered<F: Fn(&[(&str, String)]) -> bool>(content: &str, keep: F) -> Self {
+        let mut index = PackageIndex::default();
+        for stanza in stanzas(content) {
+            if !keep(&stanza) {
+                continue;
+            }
+            let field = |name: &str| {
+                stanza
+                    .iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.as_str())
+            };
+            let name = match field("Package") {
+                Some(name) => name,
+                None => continue,
+            };
+            let architecture = field("Architecture").map(|a| a.to_string());
+            index.insert(IndexEntry {
+                name: name.to_string(),
+                version: field("Version").and_then(|v| v.parse().ok()),
+                architecture: architecture.clone(),
+                provided_by: None,
+            });
+            // i.e: `Provides: foo (= 1.0), bar`
+            for provided in field("Provides").unwrap_or_default().split(',') {
+                let (virtual_name, version) = match provided.split_once('(') {
+                    Some((virtual_name, version)) => {
+                        let version = version.trim_end().trim_end_matches(')');
+                        let version = version.trim_start_matches('=').trim();
+                        (virtual_name.trim(), version.parse().ok())
+                    }
+                    None => (provided.trim(), None),
+                };
+                if !virtual_name.is_empty() {
+                    index.insert(IndexEntry {
+                        name: virtual_name.to_string(),
+                        version,
+                        architecture: architecture.clone(),
+                        provided_by: Some(name.to_string()),
+                    });
+                }
+            }
+        }
+        index
+    }
+
+    /// Read a repository `Contents` index, i.e:
+    /// `usr/bin/bash    shells/bash`. It tells which packages exist, but
+    /// neither their versions nor their architectures.
+    pub fn parse_contents(content: &str) -> Self {
+        let mut index = PackageIndex::default();
+        let mut seen = HashSet::new();
+        for line in content.lines() {
+            // The header, if any, is `FILE    LOCATION`.
+            let locations = match line.trim_end().rsplit_once(char::is_whitespace) {
+                Some((_, "LOCATION")) | None => continue,
+                Some((_, locations)) => locations,
+            };
+            for location in locations.split(',') {
+                let name = location.rsplit('/').next().unwrap_or(location);
+                if !name.is_empty() && seen.insert(name.to_string()) {
+                    index.insert(IndexEntry {
+                        name: name.to_string(),
+                        version: None,
+                        architecture: None,
+                        provided_by: None,
+                    });
+                }
+            }
+        }
+        index
+    }
+
+    /// Read the dpkg database of the running system.
+    #[cfg(feature = "std")]
+    pub fn open_dpkg_status() -> io::Result<Self> {
+        PackageIndex::open_dpkg_status_at("/var/lib/dpkg/status")
+    }
+
+    /// Same as `open_dpkg_status`, from another `status` file.
+    #[cfg(feature = "std")]
+    pub fn open_dpkg_status_at<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(PackageIndex::parse_dpkg_status(&fs::read_to_string(path)?))
+    }
+
+    /// Download and read a repository `Packages` index, i.e:
+    /// `https://repo.aosc.io/debs/dists/stable/main/binary-amd64/Packages`.
+    #[cfg(feature = "http")]
+    pub fn fetch<C: HttpGet>(client: &C, url: &str) -> Result<Self, UpdateError> {
+        match client.get(url)? {
+            Some(content) => Ok(PackageIndex::parse(&content)),
+            None => Err(UpdateError::BadResponse(url.to_string())),
+        }
+    }
+
+    pub fn insert(&mut self, entry: IndexEntry) {
+        self.entries
+            .entry(entry.name.clone())
+            .or_default()
+            .push(entry);
+    }
+
+    /// Entries named `name`, real or virtual.
+    pub fn get(&self, name: &str) -> &[IndexEntry] {
+        self.entries.get(name).map_or(&[], |e| e.as_slice())
+    }
+
+    /// Every entry, real or virtual, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &IndexEntry> {
+        self.entries.values().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.values().map(|e| e.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Provider for PackageIndex {
+    fn satisfies(&self, dependency: &Dependency) -> bool {
+        self.get(&dependency.name).iter().any(|entry| {
+            let arch_matches = match (&dependency.arch_qualifier, &entry.architecture) {
+                (Some(wanted), Some(arch)) => wanted == arch || arch == "all",
+                _ => true,
+            };
+            let version_matches = match (&dependency.version_req, &entry.version) {
+                (None, _) => true,
+                (Some(req), Some(version)) => req.matches(version),
+                (Some(_), None) => false,
+            };
+            arch_matches && version_matches
+        })
+    }
+}
+
+/// A dependency neither the tree nor any provider satisfies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingDependency {
+    /// Package or sub-package depending on it.
+    pub package: String,
+    pub kind: DepKind,
+    pub dependency: Dependency,
+}
+
+/// Dependencies of `packages` satisfied neither by a package of `packages`,
+/// including what they provide in `PKGPROV`, nor by any of `providers`. The
+/// tree is taken to satisfy any version of its packages. Overrides for
+/// `arch` are applied first if given. Sorted by package.
+pub fn find_missing(
+    packages: &[Package],
+    arch: Option<&str>,
+    providers: &[&dyn Provider],
+) -> Result<Vec<MissingDependency>, GraphError> {
+    let mut units = Vec::new();
+    for package in packages {
+        if package.subpackages().is_empty() {
+            units.push((package.name(), package.fields()));
+        }
+        for sub in package.subpackages() {
+            units.push((sub.name(), sub.fields()));
+        }
+    }
+    let units: Vec<_> = units
+        .into_iter()
+        .map(|(name, fields)| match arch {
+            Some(arch) => (name, resolve_arch_fields(fields, arch)),
+            None => (name, fields.clone()),
+        })
+        .collect();
+
+    let mut in_tree: HashSet<String> = units.iter().map(|(name, _)| name.to_string()).collect();
+    for (name, fields) in units.iter() {
+        let provided = parse_dependencies(fields.get("PKGPROV").map_or("", |v| v.as_str()))
+            .map_err(|e| GraphError::BadDependency(name.to_string(), e))?;
+        in_tree.extend(provided.into_iter().map(|d| d.name));
+    }
+
+    let mut missing = Vec::new();
+    for (name, fields) in units.iter() {
+        for kind in [DepKind::Runtime, DepKind::Build].iter() {
+            let value = match fields.get(kind.field()) {
+                Some(v) => v,
+                None => continue,
+            };
+            let deps = parse_dependencies(value)
+                .map_err(|e| GraphError::BadDependency(name.to_string(), e))?;
+            for dep in deps {
+                if in_tree.contains(dep.name.as_str())
+                    || providers.iter().any(|p| p.satisfies(&dep))
+                {
+                    continue;
+                }
+                missing.push(MissingDependency {
+                    package: name.to_string(),
+                    kind: *kind,
+                    dependency: dep,
+                });
+            }
+        }
+    }
+
+    missing.sort_by(|a, b| a.package.cmp(&b.package));
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::package;
+
+    const PACKAGES: &str = "\
+Package: glibc
+Version: 2.36-1
+Architecture: amd64
+Provides: libc6 (= 2.36), libc
+Description: GNU C Library
+ The C library.
+
+Package: tzdata
+Version: 2024a
+Architecture: all
+";
+
+    #[test]
+    fn test_index() {
+        let index = PackageIndex::parse(PACKAGES);
+        assert_eq!(index.len(), 4);
+        let satisfies = |entry: &str| index.satisfies(&entry.parse().unwrap());
+        assert!(satisfies("glibc>=2.30"));
+        assert!(!satisfies("glibc>=2.40"));
+        assert!(satisfies("glibc:amd64"));
+        assert!(!satisfies("glibc:arm64"));
+        assert!(satisfies("tzdata:arm64"));
+        assert!(satisfies("libc6==2.36"));
+        assert!(satisfies("libc"));
+        assert!(!satisfies("libc>=1.0"));
+        assert!(!satisfies("musl"));
+
+        let status = "Package: bash\nStatus: install ok installed\nVersion: 5.2\n\n\
+                      Package: zsh\nStatus: deinstall ok config-files\nVersion: 5.9\n";
+        let installed = PackageIndex::parse_dpkg_status(status);
+        assert_eq!(installed.get("bash").len(), 1);
+        assert!(installed.get("zsh").is_empty());
+    }
+
+    #[test]
+    fn test_find_missing() {
+        let packages = vec![
+            package("curl", &[("PKGDEP", "openssl glibc>=2.30 nghttp2")]),
+            package("openssl", &[("PKGDEP", "glibc"), ("BUILDDEP", "perl")]),
+            package("perl-base", &[("PKGPROV", "perl")]),
+        ];
+        let index = PackageIndex::parse(PACKAGES);
+        let missing = find_missing(&packages, None, &[&index]).unwrap();
+        let names: Vec<_> = missing
+            .iter()
+            .map(|m| (m.package.as_str(), m.kind, m.dependency.to_string()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("curl", DepKind::Runtime, "nghttp2".to_string())]
+        );
+
+        let missing = find_missing(&packages, None, &[]).unwrap();
+        assert_eq!(missing.len(), 3);
+    }
+}
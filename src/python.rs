@@ -0,0 +1,221 @@
+//! Python bindings, so acbs and friends can use the parser directly instead of
+//! matching spec files with regular expressions.
+//!
+//! Build the extension module with `maturin build`, see `pyproject.toml`. Then
+//! ```python
+//! import abbs
+//! abbs.parse('VER=1.0\nSRCS="tbl::https://example.com/foo-$VER.tar.xz"')
+//! for package in abbs.Tree("/path/to/aosc-os-abbs").walk():
+//!     print(package.name, package.fields.get("VER"))
+//! ```
+
+use crate::{
+    apf::{self, Context},
+    package::{Package, SubPackage},
+    tree::{ScanIter, Tree},
+};
+use pyo3::{create_exception, exceptions::PyException, prelude::*};
+use std::path::PathBuf;
+
+create_exception!(abbs, AbbsError, PyException, "Failure to read or parse a file.");
+
+/// Evaluate `content` on top of `context`, i.e: the spec variables when
+/// parsing a defines file. Returns all variables as a dict.
+#[pyfunction]
+#[pyo3(signature = (content, context = None))]
+fn parse(content: &str, context: Option<Context>) -> PyResult<Context> {
+    let mut context = context.unwrap_or_default();
+    apf::parse(content, &mut context).map_err(|e| AbbsError::new_err(e.to_string()))?;
+    Ok(context)
+}
+
+#[pyclass(name = "SubPackage", module = "abbs", frozen)]
+struct PySubPackage(SubPackage);
+
+#[pymethods]
+impl PySubPackage {
+    #[getter]
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.0.path().to_path_buf()
+    }
+
+    #[getter]
+    fn fields(&self) -> Context {
+        self.0.fields().clone()
+    }
+
+    /// Fields with the overrides for `arch` applied, i.e: `PKGDEP__AMD64`.
+    fn resolve(&self, arch: &str) -> Context {
+        self.0.resolve(arch)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<SubPackage {}>", self.0.name())
+    }
+}
+
+#[pyclass(name = "Package", module = "abbs", frozen)]
+struct PyPackage(Package);
+
+#[pymethods]
+impl PyPackage {
+    /// Load the package in `dir`, i.e: `TREE/app-utils/foo`.
+    #[staticmethod]
+    fn from_dir(dir: PathBuf) -> PyResult<Self> {
+        Package::from_dir(dir)
+            .map(PyPackage)
+            .map_err(|e| AbbsError::new_err(e.to_string()))
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    #[getter]
+    fn path(&self) -> Option<PathBuf> {
+        self.0.path().map(|p| p.to_path_buf())
+    }
+
+    #[getter]
+    fn fields(&self) -> Context {
+        self.0.fields().clone()
+    }
+
+    #[getter]
+    fn subpackages(&self) -> Vec<PySubPackage> {
+        self.0.subpackages().iter().cloned().map(PySubPackage).collect()
+    }
+
+    /// Fields with the overrides for `arch` applied, i.e: `PKGDEP__AMD64`.
+    fn resolve(&self, arch: &str) -> Context {
+        self.0.resolve(arch)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Package {}>", self.0.name())
+    }
+}
+
+/// Iterator over the packages of a tree, returned by `Tree.walk`.
+/// Packages that fail to load are skipped; their errors are collected in
+/// `errors` as `(path, message)` pairs.
+#[pyclass(name = "TreeWalker", module = "abbs")]
+struct PyTreeWalker {
+    iter: ScanIter,
+    #[pyo3(get)]
+    errors: Vec<(PathBuf, String)>,
+}
+
+#[pymethods]
+impl PyTreeWalker {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> Option<PyPackage> {
+        loop {
+            match slf.iter.next()? {
+                (_, Ok(package)) => return Some(PyPackage(package)),
+                (path, Err(e)) => slf.errors.push((path, e.to_string())),
+            }
+        }
+    }
+}
+
+#[pyclass(name = "Tree", module = "abbs", frozen)]
+struct PyTree(Tree);
+
+#[pymethods]
+impl PyTree {
+    #[new]
+    fn new(root: PathBuf) -> Self {
+        PyTree(Tree::open(root))
+    }
+
+    #[getter]
+    fn root(&self) -> PathBuf {
+        self.0.root().to_path_buf()
+    }
+
+    /// Directories of all packages in the tree, sorted by path.
+    fn package_dirs(&self) -> PyResult<Vec<PathBuf>> {
+        self.0
+            .package_dirs()
+            .map_err(|e| AbbsError::new_err(e.to_string()))
+    }
+
+    /// Load the packages one by one, `jobs` at a time.
+    #[pyo3(signature = (jobs = 1))]
+    fn walk(&self, jobs: usize) -> PyResult<PyTreeWalker> {
+        let iter = self
+            .0
+            .scan_iter(jobs)
+            .map_err(|e| AbbsError::new_err(e.to_string()))?;
+        Ok(PyTreeWalker {
+            iter,
+            errors: Vec::new(),
+        })
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "abbs")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("AbbsError", m.py().get_type::<AbbsError>())?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_class::<PyPackage>()?;
+    m.add_class::<PySubPackage>()?;
+    m.add_class::<PyTree>()?;
+    m.add_class::<PyTreeWalker>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use std::{ffi::CString, fs};
+
+    #[test]
+    fn test_python() {
+        let root = tempfile::tempdir().unwrap();
+        for (name, spec) in [("foo", "VER=1.0\n"), ("broken", "VER=1 | cat\n")] {
+            let dir = root.path().join("app-utils").join(name).join("autobuild");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.parent().unwrap().join("spec"), spec).unwrap();
+            fs::write(dir.join("defines"), "PKGDES=\"Foo $VER\"\n").unwrap();
+        }
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "abbs").unwrap();
+            python_module(&module).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("abbs", module).unwrap();
+            locals.set_item("root", root.path()).unwrap();
+            let code = CString::new(
+                "
+assert abbs.parse('B=\"$A-2\"', {'A': '1'}) == {'A': '1', 'B': '1-2'}
+try:
+    abbs.parse('A=$(')
+    assert False
+except abbs.AbbsError:
+    pass
+walker = abbs.Tree(root).walk(2)
+packages = list(walker)
+assert [p.name for p in packages] == ['foo'], packages
+assert packages[0].fields['PKGDES'] == 'Foo 1.0'
+assert len(walker.errors) == 1 and walker.errors[0][0].endswith('broken')
+",
+            )
+            .unwrap();
+            py.run(&code, None, Some(&locals)).unwrap();
+        });
+    }
+}
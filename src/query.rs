@@ -0,0 +1,374 @@
+//! A small expression language to search parsed packages, i.e:
+//! ```text
+//! PKGDEP contains "python-3" && SECTION == "app-devel"
+//! ```
+//!
+//! A query is made of comparisons between a field and a double-quoted
+//! string, combined with `&&`, `||`, `!` and parentheses:
+//! - `FIELD == "value"` and `FIELD != "value"` compare the whole value.
+//! - `FIELD contains "value"` is true if one of the whitespace-separated
+//!   words of the field is `value`, or a dependency on `value`, i.e:
+//!   `python-3>=3.8` contains `python-3`.
+//! - `FIELD =~ "regex"` is true if the regular expression matches anywhere
+//!   in the value.
+//! - `FIELD` alone is true if the field is set and not empty.
+//!
+//! Unset fields compare as empty. Besides the package fields, `NAME` is the
+//! name of the package and `SECTION` the directory it lives in, i.e:
+//! `app-devel`. A group package matches if its own fields or the fields of
+//! any of its sub-packages satisfy the whole query.
+
+use crate::{apf::Context, dependency::Dependency, package::Package};
+use regex::Regex;
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    /// Byte offset of the error in the query.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid query at offset {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    IsSet(String),
+    Equal(String, String),
+    NotEqual(String, String),
+    Contains(String, String),
+    Matches(String, Regex),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+    Equal,
+    NotEqual,
+    Matches,
+}
+
+fn error(offset: usize, message: &str) -> QueryError {
+    QueryError {
+        offset,
+        message: message.to_string(),
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let mut next_is = |expected: char| chars.next_if(|&(_, c)| c == expected).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Equal,
+            '=' if next_is('~') => Token::Matches,
+            '!' if next_is('=') => Token::NotEqual,
+            '!' => Token::Not,
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => value.push(c),
+                            None => return Err(error(offset, "unterminated string")),
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => return Err(error(offset, "unterminated string")),
+                    }
+                }
+                Token::Str(value)
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_') {
+                    name.push(c);
+                }
+                Token::Ident(name)
+            }
+            _ => return Err(error(offset, &format!("unexpected `{}`", c))),
+        };
+        tokens.push((offset, token));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Length of the query, reported for errors at the end.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(o, _)| *o)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.unary()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, QueryError> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat(&Token::LParen) {
+            let expr = self.or()?;
+            if !self.eat(&Token::RParen) {
+                return Err(error(self.offset(), "expected `)`"));
+            }
+            return Ok(expr);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, QueryError> {
+        let field = match self.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            _ => return Err(error(self.offset(), "expected a field name")),
+        };
+        self.pos += 1;
+
+        let op = match self.peek() {
+            Some(Token::Equal) => Token::Equal,
+            Some(Token::NotEqual) => Token::NotEqual,
+            Some(Token::Matches) => Token::Matches,
+            Some(Token::Ident(op)) if op == "contains" => Token::Ident(op.clone()),
+            _ => return Ok(Expr::IsSet(field)),
+        };
+        self.pos += 1;
+
+        let offset = self.offset();
+        let value = match self.peek() {
+            Some(Token::Str(value)) => value.clone(),
+            _ => return Err(error(offset, "expected a quoted string")),
+        };
+        self.pos += 1;
+
+        Ok(match op {
+            Token::Equal => Expr::Equal(field, value),
+            Token::NotEqual => Expr::NotEqual(field, value),
+            Token::Matches => {
+                let re = Regex::new(&value).map_err(|e| error(offset, &e.to_string()))?;
+                Expr::Matches(field, re)
+            }
+            _ => Expr::Contains(field, value),
+        })
+    }
+}
+
+/// Fields a query is evaluated against: a package, or one of its
+/// sub-packages.
+struct Fields<'a> {
+    name: &'a str,
+    section: &'a str,
+    fields: &'a Context,
+}
+
+impl Fields<'_> {
+    fn get(&self, field: &str) -> &str {
+        match field {
+            "NAME" => self.name,
+            "SECTION" => self.section,
+            _ => self.fields.get(field).map_or("", String::as_str),
+        }
+    }
+}
+
+fn contains(value: &str, needle: &str) -> bool {
+    value.split_whitespace().any(|word| {
+        word == needle || word.parse::<Dependency>().is_ok_and(|dep| dep.name == needle)
+    })
+}
+
+impl Expr {
+    fn eval(&self, fields: &Fields) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(fields) && b.eval(fields),
+            Expr::Or(a, b) => a.eval(fields) || b.eval(fields),
+            Expr::Not(e) => !e.eval(fields),
+            Expr::IsSet(field) => !fields.get(field).is_empty(),
+            Expr::Equal(field, value) => fields.get(field) == value,
+            Expr::NotEqual(field, value) => fields.get(field) != value,
+            Expr::Contains(field, value) => contains(fields.get(field), value),
+            Expr::Matches(field, re) => re.is_match(fields.get(field)),
+        }
+    }
+}
+
+/// A compiled query.
+#[derive(Debug, Clone)]
+pub struct Query {
+    expr: Expr,
+    source: String,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            pos: 0,
+            end: query.len(),
+        };
+        let expr = parser.or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(error(parser.offset(), "expected `&&` or `||`"));
+        }
+        Ok(Query {
+            expr,
+            source: query.to_string(),
+        })
+    }
+
+    pub fn matches(&self, package: &Package) -> bool {
+        let section = package
+            .path()
+            .and_then(|p| p.parent())
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let fields = Fields {
+            name: package.name(),
+            section,
+            fields: package.fields(),
+        };
+        self.expr.eval(&fields)
+            || package.subpackages().iter().any(|sub| {
+                self.expr.eval(&Fields {
+                    name: sub.name(),
+                    section,
+                    fields: sub.fields(),
+                })
+            })
+    }
+
+    /// The packages matching this query, in order.
+    pub fn filter<'a>(&'a self, packages: &'a [Package]) -> impl Iterator<Item = &'a Package> {
+        packages.iter().filter(move |p| self.matches(p))
+    }
+}
+
+impl fmt::Display for Query {
+    /// The query as it was parsed.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Query::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::package;
+
+    #[test]
+    fn test_query() {
+        let packages = [
+            package("foo", &[("PKGDEP", "glibc python-3>=3.8"), ("VER", "1.0")]),
+            package("bar", &[("PKGDEP", "python-3-six"), ("VER", "2.0")]),
+            package("baz", &[("VER", "2.0-rc1")]),
+        ];
+        let names = |query: &str| -> Vec<&str> {
+            let query: Query = query.parse().unwrap();
+            packages.iter().filter(|p| query.matches(p)).map(|p| p.name()).collect()
+        };
+
+        assert_eq!(names(r#"PKGDEP contains "python-3""#), vec!["foo"]);
+        assert_eq!(names(r#"VER == "2.0" || NAME == "foo""#), vec!["foo", "bar"]);
+        assert_eq!(names(r#"!PKGDEP"#), vec!["baz"]);
+        assert_eq!(names(r#"VER =~ "^2\\." && !(NAME != "baz")"#), vec!["baz"]);
+        assert_eq!(names(r#"PKGDEP != "" && VER != "1.0""#), vec!["bar"]);
+        assert_eq!(names(r#"SECTION == "app-devel""#), Vec::<&str>::new());
+
+        let query = Query::parse(r#"VER =~ "^2""#).unwrap();
+        assert_eq!(query.filter(&packages).count(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_query_group() {
+        use std::fs;
+
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("app-devel").join("foo");
+        let autobuild = dir.join("autobuild");
+        fs::create_dir_all(autobuild.join("01-libfoo")).unwrap();
+        fs::create_dir_all(autobuild.join("02-foo-doc")).unwrap();
+        fs::write(dir.join("spec"), "VER=1.0\n").unwrap();
+        fs::write(autobuild.join("01-libfoo").join("defines"), "PKGDEP=\"glibc\"\n").unwrap();
+        fs::write(autobuild.join("02-foo-doc").join("defines"), "PKGDEP=\"python-3\"\n").unwrap();
+        let package = Package::from_dir(&dir).unwrap();
+
+        let matches = |query: &str| Query::parse(query).unwrap().matches(&package);
+        assert!(matches(r#"PKGDEP contains "python-3" && SECTION == "app-devel""#));
+        assert!(matches(r#"NAME == "libfoo" && VER == "1.0""#));
+        assert!(!matches(r#"NAME == "libfoo" && PKGDEP contains "python-3""#));
+    }
+
+    #[test]
+    fn test_bad_query() {
+        let err = |query: &str| Query::parse(query).unwrap_err();
+        assert_eq!(err("VER ==").offset, 6);
+        assert_eq!(err(r#"VER == "1" VER"#).offset, 11);
+        assert_eq!(err(r#"(VER == "1""#).message, "expected `)`");
+        assert_eq!(err(r#"VER == "1"#).message, "unterminated string");
+        assert_eq!(err(r#"VER =~ "(""#).offset, 7);
+        assert_eq!(err("VER = 1").message, "unexpected `=`");
+        assert_eq!(err("").message, "expected a field name");
+    }
+}
@@ -0,0 +1,620 @@
+//! Tree-wide refactorings, built as a `Rewrite` to preview or apply.
+
+use crate::{
+    apf::Node,
+    autobuild::is_builtin_variable,
+    deps::{DependencyGraph, Follow},
+    fields,
+    package::{split_arch_suffix, Package},
+    rewrite::{Edit, Rewrite, RewriteError},
+    spec::SpecFile,
+    tree::Tree,
+};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Relationship fields naming other packages. `PKGPROV` is left out: it names
+/// the package itself, not one it relates to.
+pub const RELATIONSHIP_FIELDS: &[&str] = &[
+    "PKGDEP", "BUILDDEP", "PKGRECOM", "PKGSUG", "PKGBREAK", "PKGCONFL", "PKGREP",
+];
+
+/// The spec and defines files of the package in `dir`, sub-packages
+/// included.
+pub fn package_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![dir.join("spec")];
+    let autobuild = dir.join("autobuild");
+    if autobuild.join("defines").is_file() {
+        files.push(autobuild.join("defines"));
+    } else if autobuild.is_dir() {
+        let mut subpackages = Vec::new();
+        for entry in fs::read_dir(&autobuild)? {
+            let defines = entry?.path().join("defines");
+            if defines.is_file() {
+                subpackages.push(defines);
+            }
+        }
+        subpackages.sort();
+        files.extend(subpackages);
+    }
+
+    Ok(files)
+}
+
+fn is_separator(raw: &[u8], i: usize) -> bool {
+    match raw[i] {
+        b'"' | b'\'' => true,
+        b'\\' => raw.get(i + 1) == Some(&b'\n'),
+        c => c.is_ascii_whitespace(),
+    }
+}
+
+/// Byte ranges of the entries of a raw relationship value, quotes and line
+/// continuations excluded.
+fn entry_spans(raw: &str) -> Vec<(usize, usize)> {
+    let bytes = raw.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if is_separator(bytes, i) {
+            i += if bytes[i] == b'\\' { 2 } else { 1 };
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && !is_separator(bytes, i) {
+            i += 1;
+        }
+        spans.push((start, i));
+    }
+    spans
+}
+
+/// Length of the package name at the start of `entry`, i.e: 7 for
+/// `libjpeg>=9`.
+fn name_len(entry: &str) -> usize {
+    entry.find([':', '<', '>', '=']).unwrap_or(entry.len())
+}
+
+/// `raw` with the entries for `from` renamed to `to`, versions and
+/// architecture qualifiers kept. `None` if there is none.
+fn rename_in(raw: &str, from: &str, to: &str) -> Option<String> {
+    let mut result = raw.to_string();
+    let mut found = false;
+    for (start, end) in entry_spans(raw).into_iter().rev() {
+        let len = name_len(&raw[start..end]);
+        if &raw[start..start + len] == from {
+            result.replace_range(start..start + len, to);
+            found = true;
+        }
+    }
+    found.then_some(result)
+}
+
+/// `raw` without the entries for `name`, together with the blanks separating
+/// them from their neighbours on the same line if possible. `None` if there
+/// is none.
+fn remove_in(raw: &str, name: &str) -> Option<String> {
+    let spans = entry_spans(raw);
+    let mut result = raw.to_string();
+    let mut found = false;
+    for (k, &(start, end)) in spans.iter().enumerate().rev() {
+        if raw[start..start + name_len(&raw[start..end])] != *name {
+            continue;
+        }
+        found = true;
+        // Blanks within the same quotes only.
+        let blank = |range: (usize, usize)| !raw[range.0..range.1].contains(['"', '\'']);
+        let before = k
+            .checked_sub(1)
+            .map(|p| (spans[p].1, start))
+            .filter(|r| blank(*r));
+        let after = spans.get(k + 1).map(|n| (end, n.0)).filter(|r| blank(*r));
+        let same_line = |range: &(usize, usize)| !raw[range.0..range.1].contains('\n');
+        let range = match (before, after) {
+            (Some(b), _) if same_line(&b) => (b.0, end),
+            (_, Some(a)) if same_line(&a) => (start, a.1),
+            (Some(b), _) => (b.0, end),
+            (_, Some(a)) => (start, a.1),
+            (None, None) => (start, end),
+        };
+        result.replace_range(range.0..range.1, "");
+    }
+    found.then_some(result)
+}
+
+/// Set every relationship field of the tree for which `edit` returns a new
+/// raw value, architecture-specific overrides included. Every assignment of
+/// each field is edited, i.e: `PKGDEP="$PKGDEP foo"` in an `if` block too.
+fn edit_relationships<F>(tree: &Tree, edit: F) -> Result<Rewrite, RewriteError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut rewrite = Rewrite::new();
+    let dirs = tree
+        .package_dirs()
+        .map_err(|e| RewriteError::IOError(tree.root().to_path_buf(), e))?;
+    for dir in dirs {
+        let files = package_files(&dir).map_err(|e| RewriteError::IOError(dir.clone(), e))?;
+        for path in files {
+            let content =
+                fs::read_to_string(&path).map_err(|e| RewriteError::IOError(path.clone(), e))?;
+            let file =
+                SpecFile::parse(&content).map_err(|e| RewriteError::ParseError(path.clone(), e))?;
+            // Assignments are counted by name, see `SpecFile::set_nth_raw`.
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for (name, raw) in file.tree().all_assignments() {
+                let count = counts.entry(name).or_default();
+                let index = *count;
+                *count += 1;
+                let field = split_arch_suffix(name).map_or(name, |(field, _)| field);
+                if !RELATIONSHIP_FIELDS.contains(&field) {
+                    continue;
+                }
+                if let Some(raw) = edit(raw) {
+                    rewrite.edit(
+                        &path,
+                        Edit::SetNthRaw {
+                            name: name.to_string(),
+                            index,
+                            raw,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(rewrite)
+}
+
+/// Replace the dependency `from` by `to` in every relationship field of the
+/// tree, i.e: `libjpeg>=9` becomes `libjpeg-turbo>=9`.
+pub fn rename_dependency(tree: &Tree, from: &str, to: &str) -> Result<Rewrite, RewriteError> {
+    edit_relationships(tree, |raw| rename_in(raw, from, to))
+}
+
+/// Remove the dependency `name` from every relationship field of the tree.
+pub fn remove_dependency(tree: &Tree, name: &str) -> Result<Rewrite, RewriteError> {
+    edit_relationships(tree, |raw| remove_in(raw, name))
+}
+
+/// Increment `REL` of every package, or set it to 1 where it is not set.
+/// Every spec or defines file assigning `REL` is edited, so that sub-packages
+/// overriding it are bumped too. `REL` is added to the spec if it does not
+/// set it while a defines file inherits it. Packages not loaded from a
+/// directory are skipped.
+pub fn bump_rel(packages: &[&Package]) -> Result<Rewrite, RewriteError> {
+    let mut rewrite = Rewrite::new();
+    for package in packages {
+        let dir = match package.path() {
+            Some(dir) => dir,
+            None => continue,
+        };
+        let spec = dir.join("spec");
+        let files = package_files(dir).map_err(|e| RewriteError::IOError(dir.to_path_buf(), e))?;
+        // Whether the spec sets `REL`, and whether a defines file takes it
+        // from the spec.
+        let mut in_spec = false;
+        let mut inherited = files.len() == 1;
+        for path in files {
+            let content =
+                fs::read_to_string(&path).map_err(|e| RewriteError::IOError(path.clone(), e))?;
+            let file =
+                SpecFile::parse(&content).map_err(|e| RewriteError::ParseError(path.clone(), e))?;
+            let raw = match file.get_raw("REL") {
+                Some(raw) => raw,
+                None => {
+                    inherited |= path != spec;
+                    continue;
+                }
+            };
+            in_spec |= path == spec;
+            let value = raw.trim_matches(|c| c == '"' || c == '\'');
+            let rel: u64 = match value {
+                "" => 0,
+                _ => value.parse().map_err(|_| RewriteError::BadValue {
+                    path: path.clone(),
+                    field: "REL".to_string(),
+                    value: raw.to_string(),
+                })?,
+            };
+            rewrite.edit(
+                &path,
+                Edit::Set {
+                    name: "REL".to_string(),
+                    value: (rel + 1).to_string(),
+                },
+            );
+        }
+        if !in_spec && inherited {
+            rewrite.edit(
+                spec,
+                Edit::Set {
+                    name: "REL".to_string(),
+                    value: "1".to_string(),
+                },
+            );
+        }
+    }
+
+    Ok(rewrite)
+}
+
+/// `bump_rel` for the packages to rebuild when `changed` change, i.e: the
+/// dependents of a library after a soname bump. See
+/// `DependencyGraph::rebuild_set` for `depth` and `follow`. Returns the
+/// packages bumped, sorted by name, and the rewrite.
+pub fn bump_rel_dependents<'a>(
+    packages: &'a [Package],
+    graph: &DependencyGraph,
+    changed: &[&str],
+    depth: Option<usize>,
+    follow: Follow,
+) -> Result<(Vec<&'a Package>, Rewrite), RewriteError> {
+    let names = graph.rebuild_set(changed, depth, follow);
+    // Nodes are sub-packages for groups, bump the group once.
+    let mut rebuild: Vec<_> = packages
+        .iter()
+        .filter(|p| {
+            names.contains(&p.name()) || p.subpackages().iter().any(|s| names.contains(&s.name()))
+        })
+        .collect();
+    rebuild.sort_by(|a, b| a.name().cmp(b.name()));
+    let rewrite = bump_rel(&rebuild)?;
+    Ok((rebuild, rewrite))
+}
+
+/// A top-level assignment of a helper variable nothing uses: its name starts
+/// with `_` or has lowercase letters, and nothing else in the package refers
+/// to it, other than unused variables themselves. Other variables may be read
+/// by autobuild without being known fields, i.e: `NOCARGOAUDIT` or `ABMK`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadVariable {
+    pub path: PathBuf,
+    pub name: String,
+    /// Line of the assignment, starting from 1.
+    pub line: usize,
+}
+
+/// Names of the variables referred to in `text`, i.e: `VER` for `$VER`,
+/// `${VER/./_}` or `${#VER}`.
+fn references(text: &str) -> Vec<String> {
+    let re = Regex::new(r"\$\{?[#!]?([A-Za-z_][A-Za-z0-9_]*)").expect("Bad reference pattern");
+    re.captures_iter(text).map(|c| c[1].to_string()).collect()
+}
+
+/// Contents of the files of `dir` other than `skip`, i.e: build scripts
+/// using variables from `defines`. Patches and files which are not text are
+/// left out.
+fn read_scripts(dir: &Path, skip: &[PathBuf], result: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path.file_name().is_some_and(|n| n != "patches") {
+                read_scripts(&path, skip, result)?;
+            }
+        } else if !skip.contains(&path) {
+            if let Ok(content) = fs::read_to_string(&path) {
+                result.push(content);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Variables assigned at the top level of the spec and defines files of the
+/// package in `dir` but never used, see `DeadVariable`. References are
+/// looked for in every file of the package, so a variable only used by a
+/// build script is kept. Sorted by file, then line.
+pub fn find_dead_variables(dir: &Path) -> Result<Vec<DeadVariable>, RewriteError> {
+    let files = package_files(dir).map_err(|e| RewriteError::IOError(dir.to_path_buf(), e))?;
+    let mut scripts = Vec::new();
+    read_scripts(dir, &files, &mut scripts)
+        .map_err(|e| RewriteError::IOError(dir.to_path_buf(), e))?;
+
+    let mut used: HashSet<String> = HashSet::new();
+    for script in scripts.iter() {
+        used.extend(references(script));
+    }
+    // Every top-level assignment, with the variables its value refers to.
+    let mut assignments = Vec::new();
+    for path in files {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(RewriteError::IOError(path, e)),
+        };
+        let file =
+            SpecFile::parse(&content).map_err(|e| RewriteError::ParseError(path.clone(), e))?;
+        for node in file.tree().nodes() {
+            match node {
+                Node::Assignment(a) => {
+                    let refs = references(a.raw_value());
+                    assignments.push((path.clone(), a.name().to_string(), a.line(), refs));
+                }
+                Node::Command(command) => used.extend(references(command)),
+                _ => (),
+            }
+        }
+    }
+
+    let base = |name: &str| {
+        split_arch_suffix(name)
+            .map_or(name, |(field, _)| field)
+            .to_string()
+    };
+    let is_helper = |name: &str| {
+        (name.starts_with('_') || name.chars().any(|c| c.is_ascii_lowercase()))
+            && fields::lookup(name).is_none()
+            && !is_builtin_variable(name)
+    };
+    // Dropping unused variables may leave the ones only they refer to unused
+    // in turn.
+    let mut dead: HashSet<String> = HashSet::new();
+    loop {
+        let mut referenced = used.clone();
+        for (_, name, _, refs) in assignments.iter() {
+            if !dead.contains(&base(name)) {
+                referenced.extend(refs.iter().filter(|r| **r != base(name)).cloned());
+            }
+        }
+        let before = dead.len();
+        dead.extend(
+            assignments
+                .iter()
+                .map(|(_, name, _, _)| base(name))
+                .filter(|name| is_helper(name) && !referenced.contains(name)),
+        );
+        if dead.len() == before {
+            break;
+        }
+    }
+
+    let mut result: Vec<_> = assignments
+        .into_iter()
+        .filter(|(_, name, _, _)| dead.contains(&base(name)))
+        .map(|(path, name, line, _)| DeadVariable { path, name, line })
+        .collect();
+    result.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    Ok(result)
+}
+
+/// Remove the variables `find_dead_variables` finds in each package.
+/// Packages not loaded from a directory are skipped.
+pub fn remove_dead_variables(packages: &[&Package]) -> Result<Rewrite, RewriteError> {
+    let mut rewrite = Rewrite::new();
+    for dir in packages.iter().filter_map(|p| p.path()) {
+        let mut dead = find_dead_variables(dir)?;
+        dead.dedup_by(|a, b| a.path == b.path && a.name == b.name);
+        for variable in dead {
+            rewrite.edit(
+                variable.path,
+                Edit::Remove {
+                    name: variable.name,
+                },
+            );
+        }
+    }
+
+    Ok(rewrite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::write_file;
+
+    #[test]
+    fn test_rename_in() {
+        assert_eq!(
+            rename_in(
+                "\"libjpeg>=9 libjpeg-dev \\\n       libjpeg:amd64\"",
+                "libjpeg",
+                "libjpeg-turbo"
+            )
+            .unwrap(),
+            "\"libjpeg-turbo>=9 libjpeg-dev \\\n       libjpeg-turbo:amd64\""
+        );
+        assert_eq!(rename_in("libjpeg", "libjpeg", "jpeg").unwrap(), "jpeg");
+        assert_eq!(rename_in("\"libjpeg-dev $EXTRA\"", "libjpeg", "jpeg"), None);
+    }
+
+    #[test]
+    fn test_remove_in() {
+        let remove = |raw: &str| remove_in(raw, "b").unwrap();
+        assert_eq!(remove("\"a b c\""), "\"a c\"");
+        assert_eq!(remove("\"b c\""), "\"c\"");
+        assert_eq!(remove("\"a b>=1\""), "\"a\"");
+        assert_eq!(remove("\"b\""), "\"\"");
+        assert_eq!(remove("b"), "");
+        assert_eq!(remove("\"a \\\n   b \\\n   c\""), "\"a \\\n   c\"");
+        assert_eq!(remove("\"a \\\n   b c\""), "\"a \\\n   c\"");
+        assert_eq!(remove("\"a \\\n   b\""), "\"a\"");
+        assert_eq!(remove_in("\"a bb\"", "b"), None);
+    }
+
+    #[test]
+    fn test_rename_dependency() {
+        let root = tempfile::tempdir().unwrap();
+        write_file(root.path(), "app-utils/foo/spec", "VER=1\n");
+        write_file(
+            root.path(),
+            "app-utils/foo/autobuild/defines",
+            "# Deps\nPKGDEP=\"glibc libjpeg\"\nPKGDEP__AMD64=\"${PKGDEP} libjpeg:amd64\"\nPKGPROV=\"libjpeg\"\n\
+             if [[ \"$CROSS\" ]]; then\n    PKGDEP=\"$PKGDEP libjpeg\"\nfi\nPKGDEP=\"$PKGDEP libjpeg\"\n",
+        );
+        write_file(root.path(), "core-libs/bar/spec", "VER=1\n");
+        write_file(
+            root.path(),
+            "core-libs/bar/autobuild/01-libbar/defines",
+            "PKGNAME=libbar\nBUILDDEP=\"libjpeg>=9\"\n",
+        );
+        write_file(
+            root.path(),
+            "core-libs/bar/autobuild/02-bar-dev/defines",
+            "PKGNAME=bar-dev\n",
+        );
+
+        let tree = Tree::open(root.path());
+        let rewrite = rename_dependency(&tree, "libjpeg", "libjpeg-turbo").unwrap();
+        let changes = rewrite.apply().unwrap();
+        assert_eq!(changes.len(), 2);
+        let read = |path: &str| fs::read_to_string(root.path().join(path)).unwrap();
+        assert_eq!(
+            read("app-utils/foo/autobuild/defines"),
+            "# Deps\nPKGDEP=\"glibc libjpeg-turbo\"\nPKGDEP__AMD64=\"${PKGDEP} libjpeg-turbo:amd64\"\nPKGPROV=\"libjpeg\"\n\
+             if [[ \"$CROSS\" ]]; then\n    PKGDEP=\"$PKGDEP libjpeg-turbo\"\nfi\nPKGDEP=\"$PKGDEP libjpeg-turbo\"\n"
+        );
+        assert_eq!(
+            read("core-libs/bar/autobuild/01-libbar/defines"),
+            "PKGNAME=libbar\nBUILDDEP=\"libjpeg-turbo>=9\"\n"
+        );
+
+        remove_dependency(&tree, "libjpeg-turbo")
+            .unwrap()
+            .apply()
+            .unwrap();
+        assert_eq!(
+            read("app-utils/foo/autobuild/defines"),
+            "# Deps\nPKGDEP=\"glibc\"\nPKGDEP__AMD64=\"${PKGDEP}\"\nPKGPROV=\"libjpeg\"\n\
+             if [[ \"$CROSS\" ]]; then\n    PKGDEP=\"$PKGDEP\"\nfi\nPKGDEP=\"$PKGDEP\"\n"
+        );
+        assert!(remove_dependency(&tree, "libjpeg-turbo")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_bump_rel_dependents() {
+        let root = tempfile::tempdir().unwrap();
+        write_file(root.path(), "core-libs/libfoo/spec", "VER=1\n");
+        write_file(
+            root.path(),
+            "core-libs/libfoo/autobuild/defines",
+            "PKGNAME=libfoo\n",
+        );
+        write_file(root.path(), "app-utils/foo/spec", "VER=1\nREL=\"2\"\n");
+        write_file(
+            root.path(),
+            "app-utils/foo/autobuild/defines",
+            "PKGNAME=foo\nPKGDEP=\"libfoo\"\n",
+        );
+        write_file(root.path(), "app-utils/bar/spec", "VER=1\nREL=1\n");
+        write_file(
+            root.path(),
+            "app-utils/bar/autobuild/01-bar/defines",
+            "PKGNAME=bar\nBUILDDEP=\"libfoo\"\n",
+        );
+        write_file(
+            root.path(),
+            "app-utils/bar/autobuild/02-bar-doc/defines",
+            "PKGNAME=bar-doc\nPKGDEP=\"libfoo\"\nREL=3\n",
+        );
+        write_file(root.path(), "app-utils/qux/spec", "VER=1\n");
+        write_file(
+            root.path(),
+            "app-utils/qux/autobuild/01-qux/defines",
+            "PKGNAME=qux\nPKGDEP=\"libfoo\"\n",
+        );
+        write_file(
+            root.path(),
+            "app-utils/qux/autobuild/02-qux-doc/defines",
+            "PKGNAME=qux-doc\nREL=3\n",
+        );
+        write_file(root.path(), "app-utils/baz/spec", "VER=1\n");
+        write_file(
+            root.path(),
+            "app-utils/baz/autobuild/defines",
+            "PKGNAME=baz\nPKGDEP=\"foo\"\n",
+        );
+
+        let scan = Tree::open(root.path()).scan().unwrap();
+        let graph = DependencyGraph::from_packages(&scan.packages, None).unwrap();
+        let (bumped, rewrite) =
+            bump_rel_dependents(&scan.packages, &graph, &["libfoo"], Some(1), Follow::Both)
+                .unwrap();
+        let names: Vec<_> = bumped.iter().map(|p| p.name()).collect();
+        assert_eq!(names, vec!["bar", "foo", "qux"]);
+        rewrite.apply().unwrap();
+        let read = |path: &str| fs::read_to_string(root.path().join(path)).unwrap();
+        assert_eq!(read("app-utils/foo/spec"), "VER=1\nREL=\"3\"\n");
+        assert_eq!(read("app-utils/bar/spec"), "VER=1\nREL=2\n");
+        assert_eq!(
+            read("app-utils/bar/autobuild/02-bar-doc/defines"),
+            "PKGNAME=bar-doc\nPKGDEP=\"libfoo\"\nREL=4\n"
+        );
+        // `qux` takes `REL` from the spec, which did not set it.
+        assert_eq!(read("app-utils/qux/spec"), "VER=1\nREL=1\n");
+        assert_eq!(
+            read("app-utils/qux/autobuild/02-qux-doc/defines"),
+            "PKGNAME=qux-doc\nREL=4\n"
+        );
+        assert_eq!(read("app-utils/baz/spec"), "VER=1\n");
+
+        let baz: Vec<_> = scan.packages.iter().filter(|p| p.name() == "baz").collect();
+        bump_rel(&baz).unwrap().apply().unwrap();
+        assert_eq!(read("app-utils/baz/spec"), "VER=1\nREL=1\n");
+
+        write_file(root.path(), "app-utils/baz/spec", "VER=1\nREL=$((1 + 1))\n");
+        assert!(matches!(bump_rel(&baz), Err(RewriteError::BadValue { .. })));
+    }
+
+    #[test]
+    fn test_dead_variables() {
+        let root = tempfile::tempdir().unwrap();
+        write_file(
+            root.path(),
+            "app-utils/foo/spec",
+            "VER=1.2\n_MAJOR=1\n_UNUSED=\"$_MAJOR\" # old\nSRCS=\"tbl::https://example.com/foo-$VER.tar.xz\"\n",
+        );
+        write_file(
+            root.path(),
+            "app-utils/foo/autobuild/defines",
+            "PKGNAME=foo\nPKGDEP=\"bar\"\nNOCARGOAUDIT=1\nABMK=\"-j1\"\n_EXTRA=\"baz\"\n_EXTRA__AMD64=\"qux\"\n_FLAGS=\"-O2\"\n\
+             _SELF=1\n_SELF=\"$_SELF 2\"\nif true; then\n    PKGDEP=\"$PKGDEP $_EXTRA\"\nfi\n",
+        );
+        write_file(
+            root.path(),
+            "app-utils/foo/autobuild/build",
+            "make CFLAGS=\"${_FLAGS}\"\n",
+        );
+        write_file(
+            root.path(),
+            "app-utils/foo/autobuild/patches/0001.patch",
+            "+$_SELF\n",
+        );
+
+        let dir = root.path().join("app-utils/foo");
+        let dead: Vec<_> = find_dead_variables(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.name, d.line))
+            .collect();
+        assert_eq!(
+            dead,
+            vec![
+                ("_SELF".to_string(), 8),
+                ("_SELF".to_string(), 9),
+                ("_MAJOR".to_string(), 2),
+                ("_UNUSED".to_string(), 3),
+            ]
+        );
+
+        let scan = Tree::open(root.path()).scan().unwrap();
+        let packages: Vec<_> = scan.packages.iter().collect();
+        remove_dead_variables(&packages).unwrap().apply().unwrap();
+        let read = |path: &str| fs::read_to_string(dir.join(path)).unwrap();
+        assert_eq!(
+            read("spec"),
+            "VER=1.2\nSRCS=\"tbl::https://example.com/foo-$VER.tar.xz\"\n"
+        );
+        assert!(!read("autobuild/defines").contains("_SELF"));
+        assert!(read("autobuild/defines").contains("NOCARGOAUDIT=1\nABMK="));
+        assert!(find_dead_variables(&dir).unwrap().is_empty());
+    }
+}
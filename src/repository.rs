@@ -0,0 +1,250 @@
+//! Checking the tree against the packages built into a repository, i.e: to
+//! find packages waiting for a rebuild, or left over in the repository once
+//! dropped from the tree.
+
+use crate::{
+    package::{resolve_arch_fields, Package},
+    providers::{IndexEntry, PackageIndex},
+    version::Version,
+};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashSet},
+    fmt,
+};
+
+/// A difference between the tree and a repository for one architecture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The tree has a newer version than the repository, i.e: not rebuilt
+    /// since the last update.
+    TreeAhead {
+        package: String,
+        tree: Version,
+        repository: Version,
+    },
+    /// The repository has a newer version than the tree, i.e: a change
+    /// reverted without bumping `PKGEPOCH`.
+    TreeBehind {
+        package: String,
+        tree: Version,
+        repository: Version,
+    },
+    /// In the repository, but built from no package of the tree.
+    NoSource { package: String },
+    /// Builds for the architecture, but is not in the repository.
+    NotBuilt { package: String },
+}
+
+impl Inconsistency {
+    pub fn package(&self) -> &str {
+        match self {
+            Inconsistency::TreeAhead { package, .. }
+            | Inconsistency::TreeBehind { package, .. }
+            | Inconsistency::NoSource { package }
+            | Inconsistency::NotBuilt { package } => package,
+        }
+    }
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inconsistency::TreeAhead {
+                package,
+                tree,
+                repository,
+            } => write!(
+                f,
+                "{} is {} in the tree, but still {} in the repository",
+                package, tree, repository
+            ),
+            Inconsistency::TreeBehind {
+                package,
+                tree,
+                repository,
+            } => write!(
+                f,
+                "{} is {} in the tree, older than {} in the repository",
+                package, tree, repository
+            ),
+            Inconsistency::NoSource { package } => {
+                write!(f, "{} is in the repository, but not in the tree", package)
+            }
+            Inconsistency::NotBuilt { package } => {
+                write!(f, "{} was never built into the repository", package)
+            }
+        }
+    }
+}
+
+/// Whether `entry` is a package built for `arch`. Entries without an
+/// architecture, i.e: from a `Contents` index, are taken to be.
+fn built_for(entry: &IndexEntry, arch: &str) -> bool {
+    entry.provided_by.is_none()
+        && entry
+            .architecture
+            .as_deref()
+            .is_none_or(|a| a == arch || a == "all")
+}
+
+/// Compare `packages`, with the overrides for `arch` applied, to `index`,
+/// the `Packages` or `Contents` index of a repository for `arch`:
+/// - Packages and sub-packages building for `arch` are looked up by name, and
+///   their versions compared to the newest one in the repository. Versions
+///   that are missing or not valid on either side are not compared.
+/// - Packages of the repository neither in the tree nor the `-dbg` package
+///   of one are reported without source.
+///
+/// Sorted by package.
+pub fn check_repository(
+    packages: &[Package],
+    index: &PackageIndex,
+    arch: &str,
+) -> Vec<Inconsistency> {
+    let mut result = Vec::new();
+    let mut in_tree = HashSet::new();
+    for package in packages {
+        let mut units = Vec::new();
+        if package.subpackages().is_empty() {
+            units.push((package.name(), package.fields(), package.builds_on(arch)));
+        }
+        for sub in package.subpackages() {
+            let builds = package.builds_on(arch) && sub.builds_on(arch);
+            units.push((sub.name(), sub.fields(), builds));
+        }
+
+        for (name, fields, builds) in units {
+            in_tree.insert(name);
+            if !builds {
+                continue;
+            }
+            let built: Vec<_> = index
+                .get(name)
+                .iter()
+                .filter(|e| built_for(e, arch))
+                .collect();
+            if built.is_empty() {
+                result.push(Inconsistency::NotBuilt {
+                    package: name.to_string(),
+                });
+                continue;
+            }
+            let tree = match Version::from_context(&resolve_arch_fields(fields, arch)) {
+                Ok(version) => version,
+                Err(_) => continue,
+            };
+            let repository = match built.iter().filter_map(|e| e.version.as_ref()).max() {
+                Some(version) => version.clone(),
+                None => continue,
+            };
+            let package = name.to_string();
+            match tree.cmp(&repository) {
+                Ordering::Greater => result.push(Inconsistency::TreeAhead {
+                    package,
+                    tree,
+                    repository,
+                }),
+                Ordering::Less => result.push(Inconsistency::TreeBehind {
+                    package,
+                    tree,
+                    repository,
+                }),
+                Ordering::Equal => (),
+            }
+        }
+    }
+
+    let orphans: BTreeSet<_> = index
+        .iter()
+        .filter(|e| built_for(e, arch))
+        .map(|e| e.name.as_str())
+        .filter(|name| {
+            !in_tree.contains(name)
+                && !name
+                    .strip_suffix("-dbg")
+                    .is_some_and(|base| in_tree.contains(base))
+        })
+        .collect();
+    result.extend(orphans.into_iter().map(|name| Inconsistency::NoSource {
+        package: name.to_string(),
+    }));
+
+    result.sort_by(|a, b| a.package().cmp(b.package()));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::package;
+
+    const PACKAGES: &str = "\
+Package: bash
+Version: 5.2.21
+Architecture: amd64
+Provides: sh
+
+Package: bash-dbg
+Version: 5.2.21
+Architecture: amd64
+
+Package: curl
+Version: 8.4.0-1
+Architecture: amd64
+
+Package: zlib
+Version: 1:1.3
+Architecture: amd64
+
+Package: tzdata
+Version: 2024a
+Architecture: all
+
+Package: yasm
+Version: 1.3.0
+Architecture: amd64
+";
+
+    #[test]
+    fn test_check_repository() {
+        let packages = vec![
+            package("bash", &[("VER", "5.2.21")]),
+            package("curl", &[("VER", "8.5.0")]),
+            package("zlib", &[("VER", "1.3.1")]),
+            package("tzdata", &[("VER", "2024a"), ("ABHOST", "noarch")]),
+            package("nasm", &[("VER", "2.16")]),
+            package("wine", &[("VER", "9.0"), ("FAIL_ARCH", "amd64")]),
+        ];
+        let index = PackageIndex::parse(PACKAGES);
+        let result: Vec<_> = check_repository(&packages, &index, "amd64")
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            result,
+            vec![
+                "curl is 8.5.0 in the tree, but still 8.4.0-1 in the repository",
+                "nasm was never built into the repository",
+                "yasm is in the repository, but not in the tree",
+                "zlib is 1.3.1 in the tree, older than 1:1.3 in the repository",
+            ]
+        );
+
+        let contents =
+            "FILE    LOCATION\nusr/bin/bash    shells/bash\nusr/bin/yasm    devel/yasm\n";
+        let index = PackageIndex::parse_contents(contents);
+        let result = check_repository(&packages[..2], &index, "amd64");
+        assert_eq!(
+            result,
+            vec![
+                Inconsistency::NotBuilt {
+                    package: "curl".to_string()
+                },
+                Inconsistency::NoSource {
+                    package: "yasm".to_string()
+                },
+            ]
+        );
+    }
+}
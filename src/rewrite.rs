@@ -0,0 +1,290 @@
+//! Edits across many spec and defines files, applied all or nothing.
+//!
+//! Every file is edited with the lossless editor and must parse again before
+//! anything is written. If writing any file fails, the files already written
+//! are restored.
+
+use crate::{
+    apf::{self, ParseError},
+    spec::SpecFile,
+};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// Set a field to a literal value, see `SpecFile::set`.
+    Set {
+        name: String,
+        value: String,
+    },
+    /// Set a field to a value as written, see `SpecFile::set_raw`.
+    SetRaw {
+        name: String,
+        raw: String,
+    },
+    /// Set the `index`th assignment of a field, nested ones included, to a
+    /// value as written, see `SpecFile::set_nth_raw`.
+    SetNthRaw {
+        name: String,
+        index: usize,
+        raw: String,
+    },
+    Remove {
+        name: String,
+    },
+}
+
+impl Edit {
+    fn apply(&self, file: &mut SpecFile) {
+        match self {
+            Edit::Set { name, value } => file.set(name, value),
+            Edit::SetRaw { name, raw } => file.set_raw(name, raw),
+            Edit::SetNthRaw { name, index, raw } => {
+                file.set_nth_raw(name, *index, raw);
+            }
+            Edit::Remove { name } => {
+                file.remove(name);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RewriteError {
+    IOError(PathBuf, io::Error),
+    /// A file which does not parse before editing.
+    ParseError(PathBuf, ParseError),
+    /// A file which would not parse anymore after editing.
+    BrokenFile(PathBuf, ParseError),
+    /// A field whose value cannot be edited as asked, i.e: a `REL` to
+    /// increment which is not a number.
+    BadValue {
+        path: PathBuf,
+        field: String,
+        value: String,
+    },
+    /// Writing failed, and some of the files already written could not be
+    /// restored either.
+    RollbackFailed {
+        error: Box<RewriteError>,
+        unrestored: Vec<PathBuf>,
+    },
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewriteError::IOError(p, e) => write!(f, "Failed to access {}: {}", p.display(), e),
+            RewriteError::ParseError(p, e) => {
+                write!(f, "Failed to parse {}: {:?}", p.display(), e)
+            }
+            RewriteError::BrokenFile(p, e) => {
+                write!(f, "Editing {} breaks it: {:?}", p.display(), e)
+            }
+            RewriteError::BadValue { path, field, value } => write!(
+                f,
+                "Cannot edit {} in {}: unexpected value `{}`",
+                field,
+                path.display(),
+                value
+            ),
+            RewriteError::RollbackFailed { error, unrestored } => {
+                let paths: Vec<_> = unrestored.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "{}, and failed to restore {}", error, paths.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for RewriteError {}
+
+/// A file changed by `Rewrite::apply`, with its contents before and after.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: PathBuf,
+    pub old: String,
+    pub new: String,
+}
+
+/// Edits to apply, by file.
+#[derive(Debug, Clone, Default)]
+pub struct Rewrite {
+    files: BTreeMap<PathBuf, Vec<Edit>>,
+}
+
+/// Where `path` is written before being moved over the original.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".rewrite");
+    path.with_file_name(name)
+}
+
+fn write(path: &Path, content: &str) -> io::Result<()> {
+    let temp = temp_path(path);
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+impl Rewrite {
+    pub fn new() -> Self {
+        Rewrite::default()
+    }
+
+    /// Add `edit` to the file at `path`. Edits of a file apply in the order
+    /// they were added.
+    pub fn edit<P: AsRef<Path>>(&mut self, path: P, edit: Edit) -> &mut Self {
+        self.files
+            .entry(path.as_ref().to_path_buf())
+            .or_default()
+            .push(edit);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Files to edit, sorted.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(|p| p.as_path())
+    }
+
+    /// Edit every file in memory and check that it still parses, without
+    /// writing anything. Files left unchanged by their edits are omitted.
+    pub fn preview(&self) -> Result<Vec<Change>, RewriteError> {
+        let mut changes = Vec::new();
+        for (path, edits) in self.files.iter() {
+            let old =
+                fs::read_to_string(path).map_err(|e| RewriteError::IOError(path.clone(), e))?;
+            let mut file =
+                SpecFile::parse(&old).map_err(|e| RewriteError::ParseError(path.clone(), e))?;
+            for edit in edits {
+                edit.apply(&mut file);
+            }
+            let new = file.to_string();
+            apf::parse_lossless(&new).map_err(|e| RewriteError::BrokenFile(path.clone(), e))?;
+            if new != old {
+                changes.push(Change {
+                    path: path.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Apply every edit, or none of them if a file fails to read, to parse or
+    /// to be written.
+    pub fn apply(&self) -> Result<Vec<Change>, RewriteError> {
+        let changes = self.preview()?;
+        for (i, change) in changes.iter().enumerate() {
+            if let Err(e) = write(&change.path, &change.new) {
+                let error = RewriteError::IOError(change.path.clone(), e);
+                let unrestored: Vec<_> = changes[..i]
+                    .iter()
+                    .filter(|c| write(&c.path, &c.old).is_err())
+                    .map(|c| c.path.clone())
+                    .collect();
+                if unrestored.is_empty() {
+                    return Err(error);
+                }
+                return Err(RewriteError::RollbackFailed {
+                    error: Box::new(error),
+                    unrestored,
+                });
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "# Foo\nPKGDEP=\"foo bar\"\nPKGDES=Foo\n").unwrap();
+        fs::write(&b, "VER=1.0\n").unwrap();
+
+        let mut rewrite = Rewrite::new();
+        rewrite
+            .edit(
+                &a,
+                Edit::SetRaw {
+                    name: "PKGDEP".to_string(),
+                    raw: "\"foo baz\"".to_string(),
+                },
+            )
+            .edit(
+                &a,
+                Edit::Remove {
+                    name: "PKGDES".to_string(),
+                },
+            )
+            .edit(
+                &b,
+                Edit::Set {
+                    name: "VER".to_string(),
+                    value: "1.0".to_string(),
+                },
+            );
+        let changes = rewrite.apply().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, a);
+        assert_eq!(
+            fs::read_to_string(&a).unwrap(),
+            "# Foo\nPKGDEP=\"foo baz\"\n"
+        );
+        assert!(!temp_path(&a).exists());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&a, "VER=1.0\n").unwrap();
+        fs::write(&b, "VER=1.0\n").unwrap();
+        let set = |value: &str| Edit::Set {
+            name: "VER".to_string(),
+            value: value.to_string(),
+        };
+
+        // Nothing is written if any file would break.
+        let mut rewrite = Rewrite::new();
+        rewrite.edit(&a, set("2.0")).edit(
+            &b,
+            Edit::SetRaw {
+                name: "VER".to_string(),
+                raw: "\"2.0".to_string(),
+            },
+        );
+        assert!(matches!(
+            rewrite.apply(),
+            Err(RewriteError::BrokenFile(p, _)) if p == b
+        ));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "VER=1.0\n");
+
+        // Files already written are restored if writing another one fails.
+        fs::create_dir(temp_path(&b)).unwrap();
+        let mut rewrite = Rewrite::new();
+        rewrite.edit(&a, set("2.0")).edit(&b, set("2.0"));
+        assert!(matches!(
+            rewrite.apply(),
+            Err(RewriteError::IOError(p, _)) if p == b
+        ));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "VER=1.0\n");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "VER=1.0\n");
+    }
+}
@@ -0,0 +1,136 @@
+//! Section directories of a tree, i.e: `app-utils` is the `utils` section of
+//! the `app` category.
+
+use crate::package::Package;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    /// Base system, i.e: `core-libs`.
+    Core,
+    Extra,
+    /// End-user applications, i.e: `app-utils`.
+    App,
+    Desktop,
+    /// Language runtimes and their libraries, i.e: `lang-python`.
+    Lang,
+    Runtime,
+    Meta,
+    /// Any other prefix.
+    Other(String),
+}
+
+impl Category {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Category::Core => "core",
+            Category::Extra => "extra",
+            Category::App => "app",
+            Category::Desktop => "desktop",
+            Category::Lang => "lang",
+            Category::Runtime => "runtime",
+            Category::Meta => "meta",
+            Category::Other(s) => s,
+        }
+    }
+}
+
+impl From<&str> for Category {
+    fn from(s: &str) -> Self {
+        match s {
+            "core" => Category::Core,
+            "extra" => Category::Extra,
+            "app" => Category::App,
+            "desktop" => Category::Desktop,
+            "lang" => Category::Lang,
+            "runtime" => Category::Runtime,
+            "meta" => Category::Meta,
+            _ => Category::Other(s.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Category {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Section {
+    pub category: Category,
+    /// The part after the category, i.e: `utils`.
+    pub name: String,
+}
+
+impl Section {
+    /// Section of the directory `package` was loaded from.
+    pub fn of(package: &Package) -> Option<Self> {
+        package
+            .path()?
+            .parent()?
+            .file_name()?
+            .to_str()?
+            .parse()
+            .ok()
+    }
+}
+
+impl FromStr for Section {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((category, name)) if !category.is_empty() && !name.is_empty() => Ok(Section {
+                category: category.into(),
+                name: name.to_string(),
+            }),
+            _ => Err(format!(
+                "Section `{}` is not in the form of CATEGORY-NAME",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.category, self.name)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Section {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let section: Section = "app-utils".parse().unwrap();
+        assert_eq!(section.category, Category::App);
+        assert_eq!(section.name, "utils");
+        let section: Section = "lang-python-extra".parse().unwrap();
+        assert_eq!(section.category, Category::Lang);
+        assert_eq!(section.name, "python-extra");
+        assert_eq!(section.to_string(), "lang-python-extra");
+        let section: Section = "vendor-foo".parse().unwrap();
+        assert_eq!(section.category, Category::Other("vendor".to_string()));
+        assert!("groups".parse::<Section>().is_err());
+        assert!("-foo".parse::<Section>().is_err());
+    }
+}
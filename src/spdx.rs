@@ -0,0 +1,643 @@
+//! SPDX license expressions of the `LICENSE` field.
+//! i.e: `LICENSE="GPL-2.0-or-later AND (MIT OR Apache-2.0)"`
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// Identifiers of the SPDX license list found in packaging, in their
+/// canonical case.
+pub const LICENSES: &[&str] = &[
+    "0BSD",
+    "AFL-2.1",
+    "AFL-3.0",
+    "AGPL-1.0-only",
+    "AGPL-1.0-or-later",
+    "AGPL-3.0-only",
+    "AGPL-3.0-or-later",
+    "Apache-1.1",
+    "Apache-2.0",
+    "APSL-2.0",
+    "Artistic-1.0",
+    "Artistic-1.0-Perl",
+    "Artistic-2.0",
+    "Beerware",
+    "BitTorrent-1.1",
+    "BlueOak-1.0.0",
+    "BSD-1-Clause",
+    "BSD-2-Clause",
+    "BSD-2-Clause-Patent",
+    "BSD-3-Clause",
+    "BSD-3-Clause-Clear",
+    "BSD-3-Clause-LBNL",
+    "BSD-4-Clause",
+    "BSD-4-Clause-UC",
+    "BSD-Source-Code",
+    "BSL-1.0",
+    "bzip2-1.0.6",
+    "CC-BY-3.0",
+    "CC-BY-4.0",
+    "CC-BY-SA-3.0",
+    "CC-BY-SA-4.0",
+    "CC0-1.0",
+    "CDDL-1.0",
+    "CDDL-1.1",
+    "CECILL-2.1",
+    "CECILL-B",
+    "CECILL-C",
+    "ClArtistic",
+    "CPL-1.0",
+    "curl",
+    "ECL-2.0",
+    "EFL-2.0",
+    "EPL-1.0",
+    "EPL-2.0",
+    "EUPL-1.1",
+    "EUPL-1.2",
+    "FSFAP",
+    "FSFUL",
+    "FSFULLR",
+    "FTL",
+    "GFDL-1.1-only",
+    "GFDL-1.1-or-later",
+    "GFDL-1.2-only",
+    "GFDL-1.2-or-later",
+    "GFDL-1.3-only",
+    "GFDL-1.3-or-later",
+    "GPL-1.0-only",
+    "GPL-1.0-or-later",
+    "GPL-2.0-only",
+    "GPL-2.0-or-later",
+    "GPL-3.0-only",
+    "GPL-3.0-or-later",
+    "HPND",
+    "ICU",
+    "IJG",
+    "Imlib2",
+    "Info-ZIP",
+    "IPA",
+    "IPL-1.0",
+    "ISC",
+    "LGPL-2.0-only",
+    "LGPL-2.0-or-later",
+    "LGPL-2.1-only",
+    "LGPL-2.1-or-later",
+    "LGPL-3.0-only",
+    "LGPL-3.0-or-later",
+    "LGPLLR",
+    "Libpng",
+    "libpng-2.0",
+    "libtiff",
+    "LPL-1.02",
+    "LPPL-1.3c",
+    "MirOS",
+    "MIT",
+    "MIT-0",
+    "MIT-CMU",
+    "MPL-1.0",
+    "MPL-1.1",
+    "MPL-2.0",
+    "MPL-2.0-no-copyleft-exception",
+    "MS-PL",
+    "MS-RL",
+    "NCSA",
+    "NTP",
+    "OFL-1.0",
+    "OFL-1.1",
+    "OLDAP-2.8",
+    "OpenSSL",
+    "OSL-3.0",
+    "PHP-3.0",
+    "PHP-3.01",
+    "PostgreSQL",
+    "PSF-2.0",
+    "Python-2.0",
+    "QPL-1.0",
+    "Ruby",
+    "SGI-B-2.0",
+    "SISSL",
+    "Sleepycat",
+    "SMLNJ",
+    "Spencer-94",
+    "TCL",
+    "Unicode-3.0",
+    "Unicode-DFS-2016",
+    "Unlicense",
+    "UPL-1.0",
+    "Vim",
+    "W3C",
+    "WTFPL",
+    "X11",
+    "XFree86-1.1",
+    "Xnet",
+    "Zlib",
+    "zlib-acknowledgement",
+    "ZPL-2.0",
+    "ZPL-2.1",
+];
+
+/// Identifiers of the SPDX exception list, in their canonical case.
+pub const EXCEPTIONS: &[&str] = &[
+    "Autoconf-exception-2.0",
+    "Autoconf-exception-3.0",
+    "Bison-exception-2.2",
+    "Classpath-exception-2.0",
+    "eCos-exception-2.0",
+    "Font-exception-2.0",
+    "GCC-exception-2.0",
+    "GCC-exception-3.1",
+    "GPL-3.0-linking-exception",
+    "LGPL-3.0-linking-exception",
+    "Linux-syscall-note",
+    "LLVM-exception",
+    "OCaml-LGPL-linking-exception",
+    "OpenJDK-assembly-exception-1.0",
+    "openvpn-openssl-exception",
+    "Qt-GPL-exception-1.0",
+    "Qt-LGPL-exception-1.1",
+    "Swift-exception",
+    "u-boot-exception-2.0",
+    "Universal-FOSS-exception-1.0",
+    "WxWindows-exception-3.1",
+];
+
+/// GNU licenses whose bare identifier is deprecated, in favour of the
+/// `-only` and `-or-later` variants, i.e: `GPL-2.0+` is `GPL-2.0-or-later`.
+const GNU_DEPRECATED: &[&str] = &[
+    "AGPL-1.0", "AGPL-3.0", "GFDL-1.1", "GFDL-1.2", "GFDL-1.3", "GPL-1.0", "GPL-2.0", "GPL-3.0",
+    "LGPL-2.0", "LGPL-2.1", "LGPL-3.0",
+];
+
+/// Other deprecated identifiers and the expressions replacing them.
+const DEPRECATED: &[(&str, &str)] = &[
+    ("BSD-2-Clause-FreeBSD", "BSD-2-Clause"),
+    ("BSD-2-Clause-NetBSD", "BSD-2-Clause"),
+    ("eCos-2.0", "GPL-2.0-or-later WITH eCos-exception-2.0"),
+    (
+        "GPL-2.0-with-autoconf-exception",
+        "GPL-2.0-only WITH Autoconf-exception-2.0",
+    ),
+    (
+        "GPL-2.0-with-bison-exception",
+        "GPL-2.0-or-later WITH Bison-exception-2.2",
+    ),
+    (
+        "GPL-2.0-with-classpath-exception",
+        "GPL-2.0-only WITH Classpath-exception-2.0",
+    ),
+    (
+        "GPL-2.0-with-font-exception",
+        "GPL-2.0-only WITH Font-exception-2.0",
+    ),
+    (
+        "GPL-2.0-with-GCC-exception",
+        "GPL-2.0-or-later WITH GCC-exception-2.0",
+    ),
+    (
+        "GPL-3.0-with-autoconf-exception",
+        "GPL-3.0-only WITH Autoconf-exception-3.0",
+    ),
+    (
+        "GPL-3.0-with-GCC-exception",
+        "GPL-3.0-only WITH GCC-exception-3.1",
+    ),
+    ("Nunit", "zlib-acknowledgement"),
+    ("StandardML-NJ", "SMLNJ"),
+    (
+        "wxWindows",
+        "LGPL-2.0-or-later WITH WxWindows-exception-3.1",
+    ),
+];
+
+/// `id` as written in `list`, compared ignoring case.
+fn canonical(list: &[&'static str], id: &str) -> Option<&'static str> {
+    list.iter().find(|l| l.eq_ignore_ascii_case(id)).copied()
+}
+
+/// Whether `id` is defined by the document rather than the license list,
+/// i.e: `LicenseRef-Proprietary`.
+fn is_custom(id: &str) -> bool {
+    let id = id.rsplit(':').next().unwrap_or(id);
+    id.starts_with("LicenseRef-") || id.starts_with("AdditionRef-")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpdxError {
+    Empty,
+    /// A token out of place, i.e: `MIT AND`.
+    Unexpected(String),
+    UnexpectedEnd,
+}
+
+impl fmt::Display for SpdxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpdxError::Empty => write!(f, "License expression is empty."),
+            SpdxError::Unexpected(e) => write!(f, "Unexpected `{}` in license expression.", e),
+            SpdxError::UnexpectedEnd => write!(f, "License expression ends unexpectedly."),
+        }
+    }
+}
+
+impl std::error::Error for SpdxError {}
+
+/// Problems with the identifiers of a well-formed expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseIssue {
+    /// Neither on the license list nor a `LicenseRef-`.
+    UnknownLicense(String),
+    UnknownException(String),
+    /// A deprecated identifier, as written, and the expression replacing it.
+    Deprecated {
+        id: String,
+        replacement: String,
+    },
+}
+
+impl fmt::Display for LicenseIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LicenseIssue::UnknownLicense(id) => {
+                write!(f, "{} is not on the SPDX license list", id)
+            }
+            LicenseIssue::UnknownException(id) => {
+                write!(f, "{} is not on the SPDX exception list", id)
+            }
+            LicenseIssue::Deprecated { id, replacement } => {
+                write!(f, "{} is deprecated, use {} instead", id, replacement)
+            }
+        }
+    }
+}
+
+/// A parsed SPDX license expression. `WITH` binds tighter than `AND`,
+/// which binds tighter than `OR`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseExpr {
+    /// `MIT`, `MPL-1.1+` or `GPL-2.0-only WITH Classpath-exception-2.0`.
+    License {
+        id: String,
+        /// The `+` suffix.
+        or_later: bool,
+        exception: Option<String>,
+    },
+    And(Vec<LicenseExpr>),
+    Or(Vec<LicenseExpr>),
+}
+
+impl LicenseExpr {
+    /// License identifiers, in order, i.e: to look up their texts.
+    pub fn licenses(&self) -> Vec<&str> {
+        match self {
+            LicenseExpr::License { id, .. } => vec![id],
+            LicenseExpr::And(exprs) | LicenseExpr::Or(exprs) => {
+                exprs.iter().flat_map(|e| e.licenses()).collect()
+            }
+        }
+    }
+
+    /// Unknown and deprecated identifiers, in order. Identifiers are compared
+    /// ignoring case.
+    pub fn issues(&self) -> Vec<LicenseIssue> {
+        let mut issues = Vec::new();
+        self.collect_issues(&mut issues);
+        issues
+    }
+
+    fn collect_issues(&self, issues: &mut Vec<LicenseIssue>) {
+        let (id, or_later, exception) = match self {
+            LicenseExpr::License {
+                id,
+                or_later,
+                exception,
+            } => (id, *or_later, exception),
+            LicenseExpr::And(exprs) | LicenseExpr::Or(exprs) => {
+                for expr in exprs {
+                    expr.collect_issues(issues);
+                }
+                return;
+            }
+        };
+        let written = format!("{}{}", id, if or_later { "+" } else { "" });
+        if let Some(base) = canonical(GNU_DEPRECATED, id) {
+            let suffix = if or_later { "-or-later" } else { "-only" };
+            issues.push(LicenseIssue::Deprecated {
+                id: written,
+                replacement: format!("{}{}", base, suffix),
+            });
+        } else if let Some((_, replacement)) =
+            DEPRECATED.iter().find(|(d, _)| d.eq_ignore_ascii_case(id))
+        {
+            issues.push(LicenseIssue::Deprecated {
+                id: written,
+                replacement: replacement.to_string(),
+            });
+        } else if canonical(LICENSES, id).is_none() && !is_custom(id) {
+            issues.push(LicenseIssue::UnknownLicense(id.clone()));
+        }
+        if let Some(exception) = exception {
+            if canonical(EXCEPTIONS, exception).is_none() && !is_custom(exception) {
+                issues.push(LicenseIssue::UnknownException(exception.clone()));
+            }
+        }
+    }
+
+    /// The same expression with identifiers in their canonical case and
+    /// deprecated ones replaced, i.e: `gpl-2.0+ and (mit)` becomes
+    /// `GPL-2.0-or-later AND MIT`.
+    pub fn normalize(&self) -> LicenseExpr {
+        match self {
+            LicenseExpr::License {
+                id,
+                or_later,
+                exception,
+            } => {
+                let exception = exception
+                    .as_ref()
+                    .map(|e| canonical(EXCEPTIONS, e).unwrap_or(e).to_string());
+                if let Some(base) = canonical(GNU_DEPRECATED, id) {
+                    let suffix = if *or_later { "-or-later" } else { "-only" };
+                    return LicenseExpr::License {
+                        id: format!("{}{}", base, suffix),
+                        or_later: false,
+                        exception,
+                    };
+                }
+                let replacement = DEPRECATED.iter().find(|(d, _)| d.eq_ignore_ascii_case(id));
+                if let (Some((_, replacement)), false, None) = (replacement, or_later, &exception) {
+                    return replacement.parse().expect("Bad replacement of license");
+                }
+                LicenseExpr::License {
+                    id: canonical(LICENSES, id).unwrap_or(id).to_string(),
+                    or_later: *or_later,
+                    exception,
+                }
+            }
+            LicenseExpr::And(exprs) => LicenseExpr::And(flatten(exprs, true)),
+            LicenseExpr::Or(exprs) => LicenseExpr::Or(flatten(exprs, false)),
+        }
+    }
+}
+
+/// Normalized `exprs`, with those of the same operator merged in.
+fn flatten(exprs: &[LicenseExpr], and: bool) -> Vec<LicenseExpr> {
+    let mut result = Vec::new();
+    for expr in exprs.iter().map(|e| e.normalize()) {
+        match expr {
+            LicenseExpr::And(inner) if and => result.extend(inner),
+            LicenseExpr::Or(inner) if !and => result.extend(inner),
+            expr => result.push(expr),
+        }
+    }
+    result
+}
+
+impl fmt::Display for LicenseExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (exprs, op) = match self {
+            LicenseExpr::License {
+                id,
+                or_later,
+                exception,
+            } => {
+                f.write_str(id)?;
+                if *or_later {
+                    f.write_str("+")?;
+                }
+                if let Some(exception) = exception {
+                    write!(f, " WITH {}", exception)?;
+                }
+                return Ok(());
+            }
+            LicenseExpr::And(exprs) => (exprs, " AND "),
+            LicenseExpr::Or(exprs) => (exprs, " OR "),
+        };
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(op)?;
+            }
+            // `AND` binds tighter, so it needs no parentheses inside `OR`.
+            match (self, expr) {
+                (_, LicenseExpr::License { .. }) | (LicenseExpr::Or(_), LicenseExpr::And(_)) => {
+                    write!(f, "{}", expr)?
+                }
+                _ => write!(f, "({})", expr)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for LicenseExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Tokens of an expression: parentheses and words.
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in s.char_indices() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if let Some(start) = start.take() {
+                tokens.push(&s[start..i]);
+            }
+            if !c.is_whitespace() {
+                tokens.push(&s[i..i + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&s[start..]);
+    }
+    tokens
+}
+
+/// Whether `token` is the operator `op`, written in upper or lower case.
+fn is_op(token: Option<&&str>, op: &str) -> bool {
+    token.is_some_and(|t| *t == op || *t == op.to_ascii_lowercase())
+}
+
+fn is_id(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-.:".contains(c))
+}
+
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<&'a str, SpdxError> {
+        let token = self.tokens.get(self.pos).ok_or(SpdxError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn binary(
+        &mut self,
+        op: &str,
+        operand: fn(&mut Self) -> Result<LicenseExpr, SpdxError>,
+        build: fn(Vec<LicenseExpr>) -> LicenseExpr,
+    ) -> Result<LicenseExpr, SpdxError> {
+        let mut exprs = vec![operand(self)?];
+        while is_op(self.tokens.get(self.pos), op) {
+            self.pos += 1;
+            exprs.push(operand(self)?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            build(exprs)
+        })
+    }
+
+    fn or(&mut self) -> Result<LicenseExpr, SpdxError> {
+        self.binary("OR", Parser::and, LicenseExpr::Or)
+    }
+
+    fn and(&mut self) -> Result<LicenseExpr, SpdxError> {
+        self.binary("AND", Parser::primary, LicenseExpr::And)
+    }
+
+    fn primary(&mut self) -> Result<LicenseExpr, SpdxError> {
+        let token = self.next()?;
+        if token == "(" {
+            let expr = self.or()?;
+            return match self.next()? {
+                ")" => Ok(expr),
+                t => Err(SpdxError::Unexpected(t.to_string())),
+            };
+        }
+        let (id, or_later) = match token.strip_suffix('+') {
+            Some(id) => (id, true),
+            None => (token, false),
+        };
+        if !is_id(id) || ["AND", "OR", "WITH"].iter().any(|op| is_op(Some(&id), op)) {
+            return Err(SpdxError::Unexpected(token.to_string()));
+        }
+        let exception = if is_op(self.tokens.get(self.pos), "WITH") {
+            self.pos += 1;
+            let exception = self.next()?;
+            if !is_id(exception) {
+                return Err(SpdxError::Unexpected(exception.to_string()));
+            }
+            Some(exception.to_string())
+        } else {
+            None
+        };
+
+        Ok(LicenseExpr::License {
+            id: id.to_string(),
+            or_later,
+            exception,
+        })
+    }
+}
+
+impl FromStr for LicenseExpr {
+    type Err = SpdxError;
+
+    /// Parse an expression. Operators are written in either upper or lower
+    /// case; identifiers are kept as written.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s),
+            pos: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Err(SpdxError::Empty);
+        }
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            Some(t) => Err(SpdxError::Unexpected(t.to_string())),
+            None => Ok(expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> LicenseExpr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("GPL-2.0-only WITH Classpath-exception-2.0"),
+            LicenseExpr::License {
+                id: "GPL-2.0-only".to_string(),
+                or_later: false,
+                exception: Some("Classpath-exception-2.0".to_string()),
+            }
+        );
+        let expr = parse("MIT OR Apache-2.0 AND (BSD-3-Clause or Zlib) AND MPL-1.1+");
+        assert_eq!(
+            expr.to_string(),
+            "MIT OR Apache-2.0 AND (BSD-3-Clause OR Zlib) AND MPL-1.1+"
+        );
+        assert!(matches!(&expr, LicenseExpr::Or(e) if e.len() == 2));
+        assert_eq!(
+            expr.licenses(),
+            vec!["MIT", "Apache-2.0", "BSD-3-Clause", "Zlib", "MPL-1.1"]
+        );
+
+        assert_eq!("".parse::<LicenseExpr>(), Err(SpdxError::Empty));
+        assert_eq!(
+            "MIT AND".parse::<LicenseExpr>(),
+            Err(SpdxError::UnexpectedEnd)
+        );
+        assert_eq!("(MIT".parse::<LicenseExpr>(), Err(SpdxError::UnexpectedEnd));
+        assert_eq!(
+            "MIT Zlib".parse::<LicenseExpr>(),
+            Err(SpdxError::Unexpected("Zlib".to_string()))
+        );
+        assert_eq!(
+            "MIT/X11".parse::<LicenseExpr>(),
+            Err(SpdxError::Unexpected("MIT/X11".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_issues_normalize() {
+        let expr = parse(
+            "gpl-2.0+ and (mit) AND (Foo or LicenseRef-Bar) AND GPL-3.0 WITH Bogus-exception",
+        );
+        assert_eq!(
+            expr.issues(),
+            vec![
+                LicenseIssue::Deprecated {
+                    id: "gpl-2.0+".to_string(),
+                    replacement: "GPL-2.0-or-later".to_string(),
+                },
+                LicenseIssue::UnknownLicense("Foo".to_string()),
+                LicenseIssue::Deprecated {
+                    id: "GPL-3.0".to_string(),
+                    replacement: "GPL-3.0-only".to_string(),
+                },
+                LicenseIssue::UnknownException("Bogus-exception".to_string()),
+            ]
+        );
+        assert_eq!(
+            expr.normalize().to_string(),
+            "GPL-2.0-or-later AND MIT AND (Foo OR LicenseRef-Bar) AND GPL-3.0-only WITH Bogus-exception"
+        );
+        assert_eq!(
+            parse("GPL-2.0-with-classpath-exception OR mpl-2.0")
+                .normalize()
+                .to_string(),
+            "GPL-2.0-only WITH Classpath-exception-2.0 OR MPL-2.0"
+        );
+        assert!(parse("Apache-2.0 WITH LLVM-exception").issues().is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+//! In-place editing of spec and defines files.
+//! Built on the lossless syntax tree, so only the edited assignment changes and
+//! comments, ordering and formatting elsewhere are kept as they are.
+
+use crate::apf::{self, ParseError, Style, SyntaxTree};
+#[cfg(feature = "std")]
+use crate::package::PackageError;
+#[cfg(feature = "std")]
+use std::{fs, io};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone)]
+pub struct SpecFile {
+    path: Option<PathBuf>,
+    tree: SyntaxTree,
+}
+
+/// Quote `value` so it evaluates to itself, in the same style as `previous`
+/// where possible. Values needing quotes are otherwise double-quoted.
+fn quote_like(previous: Option<&str>, value: &str) -> String {
+    let style = match previous {
+        Some(p) if p.starts_with('"') => Style::Double,
+        Some(p) if p.starts_with('\'') => Style::Single,
+        _ if apf::quote(value) == value => Style::Minimal,
+        _ => Style::Double,
+    };
+    apf::quote_with(value, style)
+}
+
+impl SpecFile {
+    pub fn parse(c: &str) -> Result<Self, ParseError> {
+        Ok(SpecFile {
+            path: None,
+            tree: apf::parse_lossless(c)?,
+        })
+    }
+
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, PackageError> {
+        let path = path.as_ref();
+        let content =
+            fs::read_to_string(path).map_err(|e| PackageError::IOError(path.to_path_buf(), e))?;
+        let tree = apf::parse_lossless(&content)
+            .map_err(|e| PackageError::ParseError(path.to_path_buf(), e))?;
+
+        Ok(SpecFile {
+            path: Some(path.to_path_buf()),
+            tree,
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn tree(&self) -> &SyntaxTree {
+        &self.tree
+    }
+
+    /// Raw value of `name` as written, i.e: `"foo-$VER"` including quotes.
+    pub fn get_raw(&self, name: &str) -> Option<&str> {
+        self.tree.find(name).map(|a| a.raw_value())
+    }
+
+    /// Set `name` to the literal `value`, quoted as needed.
+    /// The existing quoting style is kept, i.e: `VER="1.0"` becomes
+    /// `VER="1.2.3"`. A new assignment is appended if `name` is not set.
+    pub fn set(&mut self, name: &str, value: &str) {
+        let raw = quote_like(self.get_raw(name), value);
+        self.tree.set_raw(name, &raw);
+    }
+
+    /// Set `name` to `raw` verbatim, i.e: `"foo-$VER"` to keep a reference.
+    pub fn set_raw(&mut self, name: &str, raw: &str) {
+        self.tree.set_raw(name, raw);
+    }
+
+    /// Set the `index`th assignment of `name` to `raw` verbatim, counting
+    /// those in blocks, see `SyntaxTree::all_assignments`. Returns whether
+    /// there is one.
+    pub fn set_nth_raw(&mut self, name: &str, index: usize, raw: &str) -> bool {
+        self.tree.set_nth_raw(name, index, raw)
+    }
+
+    /// Remove all assignments of `name`. Returns whether there was any.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.tree.remove(name)
+    }
+
+    /// Write the file back to where it was opened from.
+    #[cfg(feature = "std")]
+    pub fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => fs::write(path, self.to_string()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Spec file was not opened from a path",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SpecFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tree.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::apf::Context;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote_like(None, "1.2.3"), "1.2.3");
+        assert_eq!(quote_like(None, ""), "\"\"");
+        assert_eq!(quote_like(None, "a b"), "\"a b\"");
+        assert_eq!(quote_like(None, "~/foo"), "\"~/foo\"");
+        assert_eq!(quote_like(Some("\"1.0\""), "1.2"), "\"1.2\"");
+        assert_eq!(quote_like(Some("'1.0'"), "1.2"), "'1.2'");
+        assert_eq!(quote_like(Some("'1.0'"), "it's"), "\"it's\"");
+        assert_eq!(quote_like(Some("1.0"), "$x \"y\" `z` \\"), "\"\\$x \\\"y\\\" \\`z\\` \\\\\"");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_edit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spec");
+        fs::write(&path, "VER=\"1.0\"  # keep me\nSRCTBL=\"https://example.com/foo-$VER.tar.xz\"\nCHKSUM=\"sha256::abc\"\n").unwrap();
+
+        let mut spec = SpecFile::open(&path).unwrap();
+        spec.set("VER", "1.2.3");
+        assert!(spec.remove("SRCTBL"));
+        spec.set_raw("SRCS", "\"tbl::https://example.com/foo-$VER.tar.xz\"");
+        spec.save().unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "VER=\"1.2.3\"  # keep me\nCHKSUM=\"sha256::abc\"\nSRCS=\"tbl::https://example.com/foo-$VER.tar.xz\"\n"
+        );
+        let mut context = Context::new();
+        apf::parse(&content, &mut context).unwrap();
+        assert_eq!(context["SRCS"], "tbl::https://example.com/foo-1.2.3.tar.xz");
+        assert!(SpecFile::parse("A=1").unwrap().save().is_err());
+    }
+}
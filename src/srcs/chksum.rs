@@ -0,0 +1,345 @@
+//! `CHKSUMS` entries and verification.
+//! i.e: `CHKSUMS="sha256::9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08 SKIP"`
+
+use super::Source;
+use blake2::{Blake2b512, Blake2s256};
+use sha2::{Digest, Sha256, Sha512};
+#[cfg(feature = "std")]
+use std::{fs::File, path::Path};
+use std::{fmt, io, io::Read, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    #[default]
+    Sha256,
+    Sha512,
+    Blake2b,
+    Blake2s,
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Blake2b => "blake2b",
+            Algorithm::Blake2s => "blake2s",
+        }
+    }
+
+    /// Length of the digest in hex characters.
+    pub fn digest_len(&self) -> usize {
+        match self {
+            Algorithm::Sha256 | Algorithm::Blake2s => 64,
+            Algorithm::Sha512 | Algorithm::Blake2b => 128,
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = ChecksumError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Algorithm::Sha256),
+            "sha512" => Ok(Algorithm::Sha512),
+            "blake2b" => Ok(Algorithm::Blake2b),
+            "blake2s" => Ok(Algorithm::Blake2s),
+            _ => Err(ChecksumError::UnknownAlgorithm(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    /// `SKIP`, used for VCS sources.
+    Skip,
+    /// `ALGORITHM::DIGEST`, the digest being lowercase hex.
+    Digest { algorithm: Algorithm, digest: String },
+}
+
+#[derive(Debug)]
+pub enum ChecksumError {
+    UnknownAlgorithm(String),
+    BadEntry(String),
+    CountMismatch { sources: usize, chksums: usize },
+    Mismatch { expected: String, actual: String },
+    IOError(std::io::Error),
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumError::UnknownAlgorithm(a) => write!(f, "Unknown checksum algorithm {}.", a),
+            ChecksumError::BadEntry(e) => write!(f, "Bad checksum entry `{}`.", e),
+            ChecksumError::CountMismatch { sources, chksums } => write!(
+                f,
+                "{} sources but {} checksums are defined.",
+                sources, chksums
+            ),
+            ChecksumError::Mismatch { expected, actual } => {
+                write!(f, "Checksum mismatch: expected {}, got {}.", expected, actual)
+            }
+            ChecksumError::IOError(e) => write!(f, "Failed to read file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChecksumError {}
+
+impl From<std::io::Error> for ChecksumError {
+    fn from(err: std::io::Error) -> Self {
+        ChecksumError::IOError(err)
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = ChecksumError;
+
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        if entry == "SKIP" {
+            return Ok(Checksum::Skip);
+        }
+        let (algorithm, digest) = entry
+            .split_once("::")
+            .ok_or_else(|| ChecksumError::BadEntry(entry.to_string()))?;
+        let algorithm: Algorithm = algorithm.parse()?;
+        if digest.len() != algorithm.digest_len() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ChecksumError::BadEntry(entry.to_string()));
+        }
+
+        Ok(Checksum::Digest {
+            algorithm,
+            digest: digest.to_ascii_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Checksum::Skip => write!(f, "SKIP"),
+            Checksum::Digest { algorithm, digest } => write!(f, "{}::{}", algorithm.as_str(), digest),
+        }
+    }
+}
+
+fn hash_reader<D: Digest, R: Read>(mut reader: R) -> Result<String, std::io::Error> {
+    let mut hasher = D::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Compute the hex digest of everything `reader` yields.
+pub fn digest_reader<R: Read>(algorithm: Algorithm, reader: R) -> Result<String, std::io::Error> {
+    match algorithm {
+        Algorithm::Sha256 => hash_reader::<Sha256, _>(reader),
+        Algorithm::Sha512 => hash_reader::<Sha512, _>(reader),
+        Algorithm::Blake2b => hash_reader::<Blake2b512, _>(reader),
+        Algorithm::Blake2s => hash_reader::<Blake2s256, _>(reader),
+    }
+}
+
+impl Checksum {
+    /// Stream the file at `path` and check it against this checksum.
+    /// `SKIP` always passes without touching the file.
+    #[cfg(feature = "std")]
+    pub fn verify<P: AsRef<Path>>(&self, path: P) -> Result<(), ChecksumError> {
+        match self {
+            Checksum::Skip => Ok(()),
+            Checksum::Digest { algorithm, digest } => {
+                let actual = digest_reader(*algorithm, File::open(path)?)?;
+                if &actual != digest {
+                    return Err(ChecksumError::Mismatch {
+                        expected: digest.clone(),
+                        actual,
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parse the value of `CHKSUMS`.
+pub fn parse_chksums(value: &str) -> Result<Vec<Checksum>, ChecksumError> {
+    value.split_whitespace().map(str::parse).collect()
+}
+
+/// Pair every source with its checksum, in order.
+pub fn align<'a>(
+    sources: &'a [Source],
+    chksums: &'a [Checksum],
+) -> Result<Vec<(&'a Source, &'a Checksum)>, ChecksumError> {
+    if sources.len() != chksums.len() {
+        return Err(ChecksumError::CountMismatch {
+            sources: sources.len(),
+            chksums: chksums.len(),
+        });
+    }
+
+    Ok(sources.iter().zip(chksums.iter()).collect())
+}
+
+/// Format checksums as the value of `CHKSUMS`.
+pub fn format_chksums(chksums: &[Checksum]) -> String {
+    chksums
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Compute the value of `CHKSUMS` for `sources`, in order. Git and other VCS
+/// sources get `SKIP`; the content of the others is read from `open`, i.e: a
+/// downloaded file or a response body.
+pub fn compute_chksums<R, F>(
+    sources: &[Source],
+    algorithm: Algorithm,
+    mut open: F,
+) -> Result<String, ChecksumError>
+where
+    R: Read,
+    F: FnMut(&Source) -> io::Result<R>,
+{
+    let mut chksums = Vec::with_capacity(sources.len());
+    for source in sources {
+        let chksum = match source {
+            Source::Git { .. } | Source::Vcs { .. } => Checksum::Skip,
+            Source::Tarball { .. } | Source::File { .. } => Checksum::Digest {
+                algorithm,
+                digest: digest_reader(algorithm, open(source)?)?,
+            },
+        };
+        chksums.push(chksum);
+    }
+
+    Ok(format_chksums(&chksums))
+}
+
+/// Name of the file a source is saved as: its `rename` option, or the last
+/// component of its URL.
+pub fn file_name(source: &Source) -> &str {
+    if let Some(rename) = source.rename() {
+        return rename;
+    }
+    let url = source.url();
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+/// Same as `compute_chksums`, for sources already downloaded to `dir` under
+/// their `file_name`.
+#[cfg(feature = "std")]
+pub fn compute_chksums_in_dir<P: AsRef<Path>>(
+    sources: &[Source],
+    algorithm: Algorithm,
+    dir: P,
+) -> Result<String, ChecksumError> {
+    let dir = dir.as_ref();
+    compute_chksums(sources, algorithm, |source| File::open(dir.join(file_name(source))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srcs::parse_srcs;
+    #[cfg(feature = "std")]
+    use std::io::Write;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_parse() {
+        let chksums = parse_chksums(&format!("sha256::{} SKIP", HELLO_SHA256.to_uppercase())).unwrap();
+        assert_eq!(
+            chksums,
+            vec![
+                Checksum::Digest {
+                    algorithm: Algorithm::Sha256,
+                    digest: HELLO_SHA256.to_string()
+                },
+                Checksum::Skip
+            ]
+        );
+        let bad = vec!["sha256::1234", "md5::d41d8cd98f00b204e9800998ecf8427e", "skip", "sha256"];
+        for b in bad {
+            assert!(b.parse::<Checksum>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_align() {
+        let srcs = parse_srcs("tbl::https://example.com/a.tar.gz git::https://example.com/b.git").unwrap();
+        let chksums = parse_chksums(&format!("sha256::{} SKIP", HELLO_SHA256)).unwrap();
+        assert_eq!(align(&srcs, &chksums).unwrap().len(), 2);
+        assert!(matches!(
+            align(&srcs, &chksums[..1]),
+            Err(ChecksumError::CountMismatch { sources: 2, chksums: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_compute_chksums() {
+        let srcs = parse_srcs(
+            "tbl::https://example.com/a.tar.gz?download git::https://example.com/b.git \
+             file::rename=c.patch::https://example.com/fix",
+        )
+        .unwrap();
+        assert_eq!(file_name(&srcs[0]), "a.tar.gz");
+        assert_eq!(file_name(&srcs[2]), "c.patch");
+
+        let mut opened = Vec::new();
+        let chksums = compute_chksums(&srcs, Algorithm::default(), |source| {
+            opened.push(file_name(source).to_string());
+            Ok(&b"hello"[..])
+        })
+        .unwrap();
+        assert_eq!(chksums, format!("sha256::{} SKIP sha256::{}", HELLO_SHA256, HELLO_SHA256));
+        assert_eq!(opened, vec!["a.tar.gz", "c.patch"]);
+        assert_eq!(parse_chksums(&chksums).unwrap().len(), srcs.len());
+
+        let failed = compute_chksums(&srcs, Algorithm::Sha256, |_| -> io::Result<&[u8]> {
+            Err(io::Error::new(io::ErrorKind::NotFound, "gone"))
+        });
+        assert!(matches!(failed, Err(ChecksumError::IOError(_))));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_verify() {
+        let path = std::env::temp_dir().join(format!("abbs-chksum-{}", std::process::id()));
+        File::create(&path).unwrap().write_all(b"hello").unwrap();
+
+        let good: Checksum = format!("sha256::{}", HELLO_SHA256).parse().unwrap();
+        assert!(good.verify(&path).is_ok());
+        let bad: Checksum = format!("sha256::{}", "0".repeat(64)).parse().unwrap();
+        assert!(matches!(bad.verify(&path), Err(ChecksumError::Mismatch { .. })));
+        assert_eq!(
+            digest_reader(Algorithm::Blake2s, &b"hello"[..]).unwrap(),
+            "19213bacc58dee6dbde3ceb9a47cbb330b3d86f8cca8997eb00be456f140ca25"
+        );
+        assert!(Checksum::Skip.verify("/nonexistent").is_ok());
+
+        let name = path.file_name().unwrap().to_string_lossy();
+        let srcs = parse_srcs(&format!("file::rename={}::https://example.com/x", name)).unwrap();
+        assert_eq!(
+            compute_chksums_in_dir(&srcs, Algorithm::Sha256, std::env::temp_dir()).unwrap(),
+            format!("sha256::{}", HELLO_SHA256)
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
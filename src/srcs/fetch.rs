@@ -0,0 +1,407 @@
+//! Downloading sources, for tools delegating fetching to this crate.
+//! Tarballs and files are fetched over HTTP into `NAME.part`, resumed from
+//! where a previous attempt stopped, checked against their `CHKSUMS` entry and
+//! only then renamed to `NAME`. Git sources are cloned. URLs may be rewritten
+//! to mirrors first.
+
+use super::{
+    chksum::{align, file_name, parse_chksums, Checksum, ChecksumError},
+    get_sources, Source, SourceError,
+};
+use crate::apf::Context;
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Debug)]
+pub enum FetchError {
+    IOError(io::Error),
+    /// The request to the URL failed, and why.
+    HttpError(String, String),
+    GitError(git2::Error),
+    ChecksumError(ChecksumError),
+    SourceError(SourceError),
+    /// Sources of this type cannot be fetched, i.e: `svn`.
+    Unsupported(String),
+    /// The name to fetch a source to would lead out of the directory, i.e:
+    /// `rename=../foo`.
+    BadFileName(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::IOError(e) => write!(f, "Failed to write source: {}", e),
+            FetchError::HttpError(url, e) => write!(f, "Failed to download {}: {}", url, e),
+            FetchError::GitError(e) => write!(f, "Failed to clone source: {}", e),
+            FetchError::ChecksumError(e) => e.fmt(f),
+            FetchError::SourceError(e) => e.fmt(f),
+            FetchError::Unsupported(t) => write!(f, "Fetching {} sources is not supported.", t),
+            FetchError::BadFileName(name) => write!(f, "Invalid file name for source: `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<io::Error> for FetchError {
+    fn from(e: io::Error) -> Self {
+        FetchError::IOError(e)
+    }
+}
+
+impl From<git2::Error> for FetchError {
+    fn from(e: git2::Error) -> Self {
+        FetchError::GitError(e)
+    }
+}
+
+impl From<ChecksumError> for FetchError {
+    fn from(e: ChecksumError) -> Self {
+        FetchError::ChecksumError(e)
+    }
+}
+
+impl From<SourceError> for FetchError {
+    fn from(e: SourceError) -> Self {
+        FetchError::SourceError(e)
+    }
+}
+
+/// Move the checkout of `repo` to what was just fetched of `branch`, or of
+/// the branch it is on. A detached checkout follows `origin/HEAD`.
+fn update(repo: &git2::Repository, branch: Option<&str>) -> Result<(), git2::Error> {
+    let head = repo.head()?;
+    let name = match branch {
+        Some(branch) => branch,
+        None if head.is_branch() => head.shorthand().unwrap_or("HEAD"),
+        None => "HEAD",
+    };
+    let target = repo
+        .revparse_single(&format!("refs/remotes/origin/{}", name))?
+        .peel_to_commit()?;
+    repo.checkout_tree(
+        target.as_object(),
+        Some(git2::build::CheckoutBuilder::new().force()),
+    )?;
+    if name == "HEAD" {
+        repo.set_head_detached(target.id())
+    } else {
+        let reference = format!("refs/heads/{}", name);
+        repo.reference(&reference, target.id(), true, "fetch: update")?;
+        repo.set_head(&reference)
+    }
+}
+
+pub struct Fetcher {
+    agent: ureq::Agent,
+    mirrors: Vec<(String, String)>,
+}
+
+impl Default for Fetcher {
+    fn default() -> Self {
+        Fetcher {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .user_agent(concat!("abbs-rs/", env!("CARGO_PKG_VERSION")))
+                .build(),
+            mirrors: Vec::new(),
+        }
+    }
+}
+
+impl Fetcher {
+    pub fn new() -> Self {
+        Fetcher::default()
+    }
+
+    /// Fetch URLs starting with `prefix` from `replacement` instead, i.e:
+    /// `https://ftp.gnu.org/gnu/` from `https://mirrors.example.com/gnu/`.
+    /// The first matching mirror wins.
+    pub fn add_mirror(&mut self, prefix: &str, replacement: &str) {
+        self.mirrors.push((prefix.to_string(), replacement.to_string()));
+    }
+
+    /// `url` with mirrors applied.
+    pub fn mirror_url(&self, url: &str) -> String {
+        for (prefix, replacement) in self.mirrors.iter() {
+            if let Some(rest) = url.strip_prefix(prefix.as_str()) {
+                return format!("{}{}", replacement, rest);
+            }
+        }
+        url.to_string()
+    }
+
+    fn download(&self, url: &str, dest: &Path, chksum: &Checksum) -> Result<(), FetchError> {
+        let mut part = dest.as_os_str().to_owned();
+        part.push(".part");
+        let part = PathBuf::from(part);
+        let offset = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+
+        let mut request = self.agent.get(url);
+        if offset > 0 {
+            request = request.set("Range", &format!("bytes={}-", offset));
+        }
+        match request.call() {
+            Ok(response) => {
+                // Servers ignoring the range send everything again.
+                let resumed = response.status() == 206;
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(resumed)
+                    .truncate(!resumed)
+                    .open(&part)?;
+                io::copy(&mut response.into_reader(), &mut file)?;
+            }
+            // What we have is already complete.
+            Err(ureq::Error::Status(416, _)) if offset > 0 => (),
+            Err(e) => return Err(FetchError::HttpError(url.to_string(), e.to_string())),
+        }
+
+        if let Err(e) = chksum.verify(&part) {
+            // Start over next time rather than resuming a corrupt file.
+            fs::remove_file(&part)?;
+            return Err(e.into());
+        }
+        fs::rename(&part, dest)?;
+        Ok(())
+    }
+
+    fn clone(&self, url: &str, dest: &Path, commit: Option<&str>, branch: Option<&str>) -> Result<(), FetchError> {
+        let repo = if dest.join(".git").is_dir() {
+            let repo = git2::Repository::open(dest)?;
+            repo.find_remote("origin")?.fetch::<&str>(&[], None, None)?;
+            if commit.is_none() {
+                update(&repo, branch)?;
+            }
+            repo
+        } else {
+            let mut builder = git2::build::RepoBuilder::new();
+            if let Some(branch) = branch {
+                builder.branch(branch);
+            }
+            builder.clone(url, dest)?
+        };
+        if let Some(commit) = commit {
+            let object = repo.revparse_single(commit)?;
+            repo.checkout_tree(&object, Some(git2::build::CheckoutBuilder::new().force()))?;
+            repo.set_head_detached(object.peel_to_commit()?.id())?;
+        }
+        Ok(())
+    }
+
+    /// Fetch `source` into `dir`, under its `file_name`. A file already there
+    /// and matching `chksum` is not downloaded again, a clone already there is
+    /// updated. Names which are not a plain file name are an error.
+    /// Returns the path of the file, or of the clone for git sources, i.e:
+    /// `dir/foo` for `https://example.com/foo.git`.
+    pub fn fetch<P: AsRef<Path>>(
+        &self,
+        source: &Source,
+        chksum: &Checksum,
+        dir: P,
+    ) -> Result<PathBuf, FetchError> {
+        let name = file_name(source);
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(FetchError::BadFileName(name.to_string()));
+        }
+        let url = self.mirror_url(source.url());
+        match source {
+            Source::Tarball { .. } | Source::File { .. } => {
+                let dest = dir.as_ref().join(name);
+                if !(dest.is_file() && chksum.verify(&dest).is_ok()) {
+                    self.download(&url, &dest, chksum)?;
+                }
+                Ok(dest)
+            }
+            Source::Git { commit, branch, .. } => {
+                let name = name.strip_suffix(".git").unwrap_or(name);
+                if name.is_empty() || name == "." || name == ".." {
+                    return Err(FetchError::BadFileName(name.to_string()));
+                }
+                let dest = dir.as_ref().join(name);
+                self.clone(&url, &dest, commit.as_deref(), branch.as_deref())?;
+                Ok(dest)
+            }
+            Source::Vcs { .. } => Err(FetchError::Unsupported(source.type_name().to_string())),
+        }
+    }
+
+    /// Fetch every source of a package into `dir`, with URLs rendered from
+    /// `context` and checked against `CHKSUMS`.
+    pub fn fetch_all<P: AsRef<Path>>(&self, context: &Context, dir: P) -> Result<Vec<PathBuf>, FetchError> {
+        let mut sources = get_sources(context)?;
+        for source in sources.iter_mut() {
+            let url = source.rendered_url(context)?;
+            match source {
+                Source::Tarball { url: u, .. }
+                | Source::Git { url: u, .. }
+                | Source::Vcs { url: u, .. }
+                | Source::File { url: u, .. } => *u = url,
+            }
+        }
+        let chksums = match context.get("CHKSUMS") {
+            Some(value) => parse_chksums(value)?,
+            None => vec![Checksum::Skip; sources.len()],
+        };
+
+        fs::create_dir_all(dir.as_ref())?;
+        align(&sources, &chksums)?
+            .into_iter()
+            .map(|(source, chksum)| self.fetch(source, chksum, dir.as_ref()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srcs::chksum::{digest_reader, Algorithm};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Serve `body` `requests` times, honouring `Range: bytes=N-`.
+    fn serve(body: &'static [u8], requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut offset = 0;
+                for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(range) = line.strip_prefix("Range: bytes=") {
+                        offset = range.trim_end_matches('-').parse().unwrap();
+                    }
+                }
+                let status = if offset > 0 { "206 Partial Content" } else { "200 OK" };
+                let rest = &body[offset..];
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n", status, rest.len()).unwrap();
+                stream.write_all(rest).unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn test_mirror_url() {
+        let mut fetcher = Fetcher::new();
+        fetcher.add_mirror("https://ftp.gnu.org/gnu/", "https://mirror.example.com/gnu/");
+        assert_eq!(
+            fetcher.mirror_url("https://ftp.gnu.org/gnu/bash/bash-5.2.tar.gz"),
+            "https://mirror.example.com/gnu/bash/bash-5.2.tar.gz"
+        );
+        assert_eq!(fetcher.mirror_url("https://example.com/a"), "https://example.com/a");
+    }
+
+    #[test]
+    fn test_fetch() {
+        const BODY: &[u8] = b"0123456789abcdef";
+        let dir = tempfile::tempdir().unwrap();
+        let mut fetcher = Fetcher::new();
+        fetcher.add_mirror("https://example.com/", &format!("{}/", serve(BODY, 2)));
+        let chksum = Checksum::Digest {
+            algorithm: Algorithm::Sha256,
+            digest: digest_reader(Algorithm::Sha256, BODY).unwrap(),
+        };
+
+        // Resume a previous, interrupted download.
+        fs::write(dir.path().join("foo-1.0.tar.gz.part"), &BODY[..6]).unwrap();
+        let mut context = Context::new();
+        context.insert("VER".to_string(), "1.0".to_string());
+        context.insert("SRCS".to_string(), "tbl::https://example.com/foo-$VER.tar.gz".to_string());
+        context.insert("CHKSUMS".to_string(), chksum.to_string());
+        let paths = fetcher.fetch_all(&context, dir.path()).unwrap();
+        assert_eq!(paths, vec![dir.path().join("foo-1.0.tar.gz")]);
+        assert_eq!(fs::read(&paths[0]).unwrap(), BODY);
+        assert!(!dir.path().join("foo-1.0.tar.gz.part").exists());
+        // Already there, nothing is requested.
+        fetcher.fetch_all(&context, dir.path()).unwrap();
+
+        let bad = Checksum::Digest {
+            algorithm: Algorithm::Sha256,
+            digest: "0".repeat(64),
+        };
+        let source: Source = "tbl::rename=bar.tar.gz::https://example.com/bar".parse().unwrap();
+        assert!(matches!(
+            fetcher.fetch(&source, &bad, dir.path()),
+            Err(FetchError::ChecksumError(ChecksumError::Mismatch { .. }))
+        ));
+        assert!(!dir.path().join("bar.tar.gz.part").exists());
+
+        let svn: Source = "svn::svn://example.com/foo".parse().unwrap();
+        assert!(matches!(
+            fetcher.fetch(&svn, &Checksum::Skip, dir.path()),
+            Err(FetchError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_fetch_git() {
+        let upstream = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(upstream.path()).unwrap();
+        fs::write(upstream.path().join("README"), "hello\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("README")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Someone", "someone@example.com").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "Initial", &tree, &[])
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = Source::Git {
+            url: format!("file://{}", upstream.path().display()),
+            commit: Some(commit.to_string()),
+            branch: None,
+            rename: Some("foo.git".to_string()),
+            options: Default::default(),
+        };
+        let path = Fetcher::new().fetch(&source, &Checksum::Skip, dir.path()).unwrap();
+        assert_eq!(path, dir.path().join("foo"));
+        assert_eq!(fs::read_to_string(path.join("README")).unwrap(), "hello\n");
+        // Fetching again updates the existing clone.
+        Fetcher::new().fetch(&source, &Checksum::Skip, dir.path()).unwrap();
+
+        // Without a commit, the checkout follows the branch.
+        let git = |rename: &str| Source::Git {
+            url: format!("file://{}", upstream.path().display()),
+            commit: None,
+            branch: None,
+            rename: Some(rename.to_string()),
+            options: Default::default(),
+        };
+        let source = git("bar.git");
+        let path = Fetcher::new().fetch(&source, &Checksum::Skip, dir.path()).unwrap();
+        fs::write(upstream.path().join("README"), "bye\n").unwrap();
+        index.add_path(Path::new("README")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parent = repo.find_commit(commit).unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "Update", &tree, &[&parent])
+            .unwrap();
+        Fetcher::new().fetch(&source, &Checksum::Skip, dir.path()).unwrap();
+        assert_eq!(fs::read_to_string(path.join("README")).unwrap(), "bye\n");
+
+        for rename in ["", "..", "../foo", "/tmp/foo", ".git"] {
+            assert!(
+                matches!(
+                    Fetcher::new().fetch(&git(rename), &Checksum::Skip, dir.path()),
+                    Err(FetchError::BadFileName(_))
+                ),
+                "{}",
+                rename
+            );
+        }
+    }
+}
@@ -0,0 +1,404 @@
+//! Typed source entries from `SRCS`.
+//! i.e: `SRCS="git::commit=tags/v1.0;branch=stable::https://example.com/foo.git tbl::https://example.com/bar.tar.xz"`
+
+pub mod chksum;
+#[cfg(feature = "fetch")]
+pub mod fetch;
+
+pub use chksum::compute_chksums;
+
+use crate::apf::{self, Context};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+/// Variable a URL is evaluated into by `render_url`.
+const URL_VAR: &str = "__ABBS_URL";
+
+/// Extra `key=value` options of an entry not covered by the typed fields.
+pub type SourceOptions = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum VcsKind {
+    Svn,
+    Hg,
+    Bzr,
+    Fossil,
+}
+
+impl VcsKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VcsKind::Svn => "svn",
+            VcsKind::Hg => "hg",
+            VcsKind::Bzr => "bzr",
+            VcsKind::Fossil => "fossil",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "lowercase"))]
+pub enum Source {
+    /// `tbl::URL`
+    Tarball {
+        url: String,
+        rename: Option<String>,
+        options: SourceOptions,
+    },
+    /// `git::commit=COMMIT;branch=BRANCH::URL`
+    Git {
+        url: String,
+        commit: Option<String>,
+        branch: Option<String>,
+        rename: Option<String>,
+        options: SourceOptions,
+    },
+    /// `svn::revision=REV::URL`, and likewise for hg, bzr and fossil.
+    Vcs {
+        kind: VcsKind,
+        url: String,
+        revision: Option<String>,
+        rename: Option<String>,
+        options: SourceOptions,
+    },
+    /// `file::URL`, a single file copied as-is.
+    File {
+        url: String,
+        rename: Option<String>,
+        options: SourceOptions,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SourceError {
+    MissingUrl(String),
+    UnknownType(String),
+    BadOption(String),
+    NoSources,
+    /// A URL whose variables cannot be substituted, and why.
+    BadUrl(String, String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::MissingUrl(e) => write!(f, "Missing URL in source `{}`.", e),
+            SourceError::UnknownType(e) => write!(f, "Unknown type of source `{}`.", e),
+            SourceError::BadOption(e) => write!(f, "Bad option in source `{}`.", e),
+            SourceError::NoSources => write!(f, "Neither SRCS nor SRCTBL is defined."),
+            SourceError::BadUrl(url, e) => write!(f, "Cannot render URL `{}`: {}", url, e),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl Source {
+    pub fn url(&self) -> &str {
+        match self {
+            Source::Tarball { url, .. }
+            | Source::Git { url, .. }
+            | Source::Vcs { url, .. }
+            | Source::File { url, .. } => url,
+        }
+    }
+
+    pub fn rename(&self) -> Option<&str> {
+        match self {
+            Source::Tarball { rename, .. }
+            | Source::Git { rename, .. }
+            | Source::Vcs { rename, .. }
+            | Source::File { rename, .. } => rename.as_deref(),
+        }
+    }
+
+    pub fn options(&self) -> &SourceOptions {
+        match self {
+            Source::Tarball { options, .. }
+            | Source::Git { options, .. }
+            | Source::Vcs { options, .. }
+            | Source::File { options, .. } => options,
+        }
+    }
+
+    /// The URL with variables substituted from `context`, see `render_url`.
+    pub fn rendered_url(&self, context: &Context) -> Result<String, SourceError> {
+        render_url(self.url(), context)
+    }
+
+    /// Type prefix of the entry, i.e: `tbl`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Source::Tarball { .. } => "tbl",
+            Source::Git { .. } => "git",
+            Source::Vcs { kind, .. } => kind.as_str(),
+            Source::File { .. } => "file",
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    /// Format the entry back into `SRCS` syntax.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut opts = Vec::new();
+        match self {
+            Source::Git { commit, branch, .. } => {
+                if let Some(c) = commit {
+                    opts.push(format!("commit={}", c));
+                }
+                if let Some(b) = branch {
+                    opts.push(format!("branch={}", b));
+                }
+            }
+            Source::Vcs {
+                revision: Some(r), ..
+            } => opts.push(format!("revision={}", r)),
+            _ => (),
+        }
+        if let Some(r) = self.rename() {
+            opts.push(format!("rename={}", r));
+        }
+        for (k, v) in self.options() {
+            opts.push(format!("{}={}", k, v));
+        }
+
+        if opts.is_empty() {
+            write!(f, "{}::{}", self.type_name(), self.url())
+        } else {
+            write!(f, "{}::{}::{}", self.type_name(), opts.join(";"), self.url())
+        }
+    }
+}
+
+fn parse_options(entry: &str, s: &str) -> Result<SourceOptions, SourceError> {
+    let mut options = SourceOptions::new();
+    for opt in s.split(';').filter(|o| !o.is_empty()) {
+        match opt.split_once('=') {
+            Some((k, v)) if !k.is_empty() => {
+                options.insert(k.to_string(), v.to_string());
+            }
+            _ => return Err(SourceError::BadOption(entry.to_string())),
+        }
+    }
+    Ok(options)
+}
+
+impl FromStr for Source {
+    type Err = SourceError;
+
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = entry.splitn(3, "::").collect();
+        let (ty, mut options, url) = match parts.as_slice() {
+            [ty, url] => (*ty, SourceOptions::new(), *url),
+            [ty, opts, url] => (*ty, parse_options(entry, opts)?, *url),
+            _ => return Err(SourceError::MissingUrl(entry.to_string())),
+        };
+        if url.is_empty() {
+            return Err(SourceError::MissingUrl(entry.to_string()));
+        }
+        let url = url.to_string();
+        let rename = options.remove("rename");
+
+        let vcs = |kind| {
+            let mut options = options.clone();
+            Source::Vcs {
+                kind,
+                url: url.clone(),
+                revision: options.remove("revision"),
+                rename: rename.clone(),
+                options,
+            }
+        };
+        let source = match ty {
+            "tbl" | "tarball" => Source::Tarball {
+                url,
+                rename,
+                options,
+            },
+            "git" => Source::Git {
+                url,
+                commit: options.remove("commit"),
+                branch: options.remove("branch"),
+                rename,
+                options,
+            },
+            "svn" => vcs(VcsKind::Svn),
+            "hg" => vcs(VcsKind::Hg),
+            "bzr" => vcs(VcsKind::Bzr),
+            "fossil" => vcs(VcsKind::Fossil),
+            "file" => Source::File {
+                url,
+                rename,
+                options,
+            },
+            _ => return Err(SourceError::UnknownType(entry.to_string())),
+        };
+
+        Ok(source)
+    }
+}
+
+/// Parse the value of `SRCS`.
+pub fn parse_srcs(value: &str) -> Result<Vec<Source>, SourceError> {
+    value.split_whitespace().map(str::parse).collect()
+}
+
+/// Get sources of a package from its (resolved) context.
+/// Falls back to the legacy single-URL `SRCTBL` if `SRCS` is absent.
+pub fn get_sources(context: &Context) -> Result<Vec<Source>, SourceError> {
+    if let Some(srcs) = context.get("SRCS") {
+        return parse_srcs(srcs);
+    }
+    match context.get("SRCTBL") {
+        Some(url) if !url.trim().is_empty() => Ok(vec![Source::Tarball {
+            url: url.trim().to_string(),
+            rename: None,
+            options: SourceOptions::new(),
+        }]),
+        _ => Err(SourceError::NoSources),
+    }
+}
+
+/// Substitute variables in `url` from `context` as bash would inside double
+/// quotes, i.e: `https://example.com/foo-${VER//./_}.tar.xz`. Entries from a
+/// single-quoted `SRCS`, or read from the source text, still contain them.
+/// Unset variables are an error, like everywhere else in the parser.
+pub fn render_url(url: &str, context: &Context) -> Result<String, SourceError> {
+    if !url.contains('$') {
+        return Ok(url.to_string());
+    }
+    let mut quoted = String::with_capacity(url.len() + 2);
+    quoted.push('"');
+    for c in url.chars() {
+        if matches!(c, '"' | '\\' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+
+    let mut scratch = context.clone();
+    apf::parse_assignment(&format!("{}={}", URL_VAR, quoted), &mut scratch)
+        .map_err(|e| SourceError::BadUrl(url.to_string(), e.to_string()))?;
+    Ok(scratch.remove(URL_VAR).unwrap_or_default())
+}
+
+/// Rendered URLs of every source of a package, see `get_sources`.
+pub fn get_rendered_urls(context: &Context) -> Result<Vec<String>, SourceError> {
+    get_sources(context)?
+        .iter()
+        .map(|s| s.rendered_url(context))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_srcs() {
+        let srcs = parse_srcs(
+            "git::commit=tags/v1.0;branch=stable;copy-repo=true::https://example.com/foo.git \
+             tbl::rename=bar.tar.gz::https://example.com/bar-1.0.tar.gz\n\
+             file::https://example.com/baz.patch svn::revision=42::svn://example.com/qux",
+        )
+        .unwrap();
+        let mut copy_repo = SourceOptions::new();
+        copy_repo.insert("copy-repo".to_string(), "true".to_string());
+        assert_eq!(
+            srcs,
+            vec![
+                Source::Git {
+                    url: "https://example.com/foo.git".to_string(),
+                    commit: Some("tags/v1.0".to_string()),
+                    branch: Some("stable".to_string()),
+                    rename: None,
+                    options: copy_repo,
+                },
+                Source::Tarball {
+                    url: "https://example.com/bar-1.0.tar.gz".to_string(),
+                    rename: Some("bar.tar.gz".to_string()),
+                    options: SourceOptions::new(),
+                },
+                Source::File {
+                    url: "https://example.com/baz.patch".to_string(),
+                    rename: None,
+                    options: SourceOptions::new(),
+                },
+                Source::Vcs {
+                    kind: VcsKind::Svn,
+                    url: "svn://example.com/qux".to_string(),
+                    revision: Some("42".to_string()),
+                    rename: None,
+                    options: SourceOptions::new(),
+                },
+            ]
+        );
+        for (s, entry) in srcs.iter().zip(vec![
+            "git::commit=tags/v1.0;branch=stable;copy-repo=true::https://example.com/foo.git",
+            "tbl::rename=bar.tar.gz::https://example.com/bar-1.0.tar.gz",
+            "file::https://example.com/baz.patch",
+            "svn::revision=42::svn://example.com/qux",
+        ]) {
+            assert_eq!(s.to_string(), entry);
+        }
+    }
+
+    #[test]
+    fn test_parse_bad() {
+        let cases = vec![
+            "https://example.com/foo.tar.gz",
+            "tbl::",
+            "ftp::https://example.com/foo.tar.gz",
+            "git::commit::https://example.com/foo.git",
+        ];
+        for c in cases {
+            assert_eq!(c.parse::<Source>().is_ok(), false);
+        }
+    }
+
+    #[test]
+    fn test_srctbl_fallback() {
+        let mut context = Context::new();
+        assert_eq!(get_sources(&context), Err(SourceError::NoSources));
+        context.insert("SRCTBL".to_string(), "https://example.com/a.tar.xz".to_string());
+        assert_eq!(get_sources(&context).unwrap()[0].url(), "https://example.com/a.tar.xz");
+        context.insert("SRCS".to_string(), "tbl::https://example.com/b.tar.xz".to_string());
+        assert_eq!(get_sources(&context).unwrap()[0].url(), "https://example.com/b.tar.xz");
+    }
+
+    #[test]
+    fn test_render_url() {
+        let mut context = Context::new();
+        context.insert("VER".to_string(), "1.2.3".to_string());
+        context.insert(
+            "SRCS".to_string(),
+            "tbl::https://example.com/foo-$VER.tar.xz git::commit=v${VER}::https://example.com/foo.git".to_string(),
+        );
+        assert_eq!(
+            get_rendered_urls(&context).unwrap(),
+            vec!["https://example.com/foo-1.2.3.tar.xz", "https://example.com/foo.git"]
+        );
+
+        let src: Source = "tbl::https://example.com/${VER//./_}/a\"b`c`.tar".parse().unwrap();
+        assert_eq!(src.rendered_url(&context).unwrap(), "https://example.com/1_2_3/a\"b`c`.tar");
+        assert!(render_url("https://example.com/${VER", &context).is_err());
+        assert!(render_url("https://example.com/$NOPE", &context).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let src: Source = "svn::revision=42::svn://example.com/qux".parse().unwrap();
+        let json = serde_json::to_string(&src).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"vcs","kind":"svn","url":"svn://example.com/qux","revision":"42","rename":null,"options":{}}"#
+        );
+        assert_eq!(serde_json::from_str::<Source>(&json).unwrap(), src);
+    }
+}
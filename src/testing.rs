@@ -0,0 +1,149 @@
+//! Helpers to test the parser against random input: proptest strategies
+//! generating valid spec files along with the variables they define, and
+//! `fuzz_parse`, an entry point for cargo-fuzz, i.e:
+//! ```ignore
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| abbs::testing::fuzz_parse(data));
+//! ```
+
+use crate::{
+    apf::{self, Context},
+    fmt,
+};
+use proptest::prelude::*;
+
+/// Run `data` through every parser entry point. Errors are fine, but any
+/// panic, including `todo!()` and `unreachable!()` paths, is a bug. Also
+/// asserts that the lossless parser reproduces its input.
+pub fn fuzz_parse(data: &[u8]) {
+    let source = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+
+    let _ = apf::parse(source, &mut Context::new());
+    let _ = apf::parse_with_functions(source, &mut Context::new(), &mut Vec::new());
+    if let Ok(offset) = apf::parse_prelude(source, &mut Context::new()) {
+        assert!(source.is_char_boundary(offset));
+    }
+    if let Ok(tree) = apf::parse_lossless(source) {
+        assert_eq!(tree.to_string(), source);
+    }
+    let _ = fmt::format(source);
+    let _ = apf::eval_test(source, &Context::new());
+}
+
+/// A spec file from `arb_spec`, and the variables it should evaluate to.
+#[derive(Debug, Clone)]
+pub struct GeneratedSpec {
+    pub source: String,
+    pub expected: Context,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    /// Reference to the variable assigned this many statements ago.
+    Variable(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+/// Variable names, i.e: `VER` or `PKGDEP__AMD64`.
+pub fn arb_name() -> impl Strategy<Value = String> {
+    "[A-Z][A-Z0-9_]{0,12}"
+}
+
+fn arb_literal() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9 ._:/+=@,-]{0,16}"
+}
+
+fn arb_part() -> impl Strategy<Value = Part> {
+    prop_oneof![
+        3 => arb_literal().prop_map(Part::Literal),
+        1 => (1..8usize).prop_map(Part::Variable),
+    ]
+}
+
+fn arb_quoting() -> impl Strategy<Value = Quoting> {
+    prop_oneof![Just(Quoting::None), Just(Quoting::Single), Just(Quoting::Double)]
+}
+
+fn is_bare(s: &str) -> bool {
+    s.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "._:/+=@,-".contains(c))
+}
+
+/// Valid spec files made of assignments, with unquoted, single-quoted and
+/// double-quoted values referencing earlier variables.
+pub fn arb_spec() -> impl Strategy<Value = GeneratedSpec> {
+    let statement = (arb_name(), prop::collection::vec(arb_part(), 0..4), arb_quoting());
+    prop::collection::vec(statement, 0..16).prop_map(|statements| {
+        let mut source = String::new();
+        let mut expected = Context::new();
+        let mut assigned: Vec<String> = Vec::new();
+        for (name, parts, quoting) in statements {
+            let mut raw = String::new();
+            let mut value = String::new();
+            for part in parts {
+                match part {
+                    Part::Literal(s) => {
+                        raw += &s;
+                        value += &s;
+                    }
+                    // Only double quotes expand variables, and a reference
+                    // needs a previous assignment.
+                    Part::Variable(back) if matches!(quoting, Quoting::Double) && !assigned.is_empty() => {
+                        let referenced = &assigned[assigned.len() - back.min(assigned.len())];
+                        raw += &format!("${{{}}}", referenced);
+                        value += &expected[referenced];
+                    }
+                    Part::Variable(_) => (),
+                }
+            }
+            let quoting = match quoting {
+                Quoting::None if value.is_empty() || !is_bare(&value) => Quoting::Single,
+                q => q,
+            };
+            match quoting {
+                Quoting::None => source += &format!("{}={}\n", name, raw),
+                Quoting::Single => source += &format!("{}='{}'\n", name, raw),
+                Quoting::Double => source += &format!("{}=\"{}\"\n", name, raw),
+            }
+            expected.insert(name.clone(), value);
+            assigned.push(name);
+        }
+
+        GeneratedSpec { source, expected }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_generated_specs(spec in arb_spec()) {
+            let mut context = Context::new();
+            apf::parse(&spec.source, &mut context).unwrap();
+            prop_assert_eq!(&context, &spec.expected);
+
+            let tree = apf::parse_lossless(&spec.source).unwrap();
+            prop_assert_eq!(tree.to_string(), spec.source.clone());
+            let formatted = fmt::format(&spec.source).unwrap();
+            let mut context = Context::new();
+            apf::parse(&formatted, &mut context).unwrap();
+            prop_assert_eq!(&context, &spec.expected);
+        }
+
+        #[test]
+        fn test_fuzz_parse(source in "[A-Z=$'\"{}()\\[\\]|&;<># \\\\\n a-z0-9:/%-]{0,64}") {
+            fuzz_parse(source.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,874 @@
+//! Walking an ABBS tree.
+//! Packages live in `TREE/SECTION/NAME`, each with a `spec` file and an
+//! `autobuild/defines` file.
+
+#[cfg(feature = "serde")]
+use crate::{
+    apf::Context,
+    export::Provenance,
+    package::split_arch_suffix,
+    spdx::LicenseExpr,
+};
+#[cfg(feature = "cache")]
+use crate::cache::ParseCache;
+#[cfg(any(feature = "serde", feature = "cache"))]
+use crate::package::SpecInheritance;
+#[cfg(feature = "mmap")]
+use crate::package::{FileSource, Loader};
+use crate::package::{Package, PackageError};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+#[cfg(feature = "serde")]
+use std::cell::RefCell;
+use std::{
+    collections::BTreeMap,
+    fs, io, panic,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    vec,
+};
+
+/// Packages per thread `ScanIter` loads ahead of the one handed out last.
+/// Bounds its memory use regardless of the tree size.
+const AHEAD_PER_JOB: usize = 16;
+
+mod diff;
+#[cfg(feature = "git")]
+mod git;
+mod metrics;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
+#[cfg(feature = "tokio")]
+mod stream;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use diff::{diff, diff_sources, DependencyChange, TreeDiff, VersionChange};
+#[cfg(feature = "git")]
+pub(crate) use git::load_package as load_git_package;
+#[cfg(feature = "git")]
+pub use git::{ChangelogEntry, GitTree};
+pub use metrics::{FileMetrics, ParseMetrics};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{sync_sqlite, SqliteError, SqliteOptions, SyncStats, SQLITE_SCHEMA};
+pub use stats::{stats, TreeStats};
+#[cfg(feature = "tokio")]
+pub use stream::ScanStream;
+#[cfg(feature = "watch")]
+pub use watch::{FieldChange, IndexEvent, TreeIndex, TreeWatcher, WatchError};
+
+#[derive(Debug, Clone)]
+pub struct Tree {
+    root: PathBuf,
+    options: TreeOptions,
+}
+
+/// Order packages of a tree are listed, scanned and exported in. Either way
+/// it only depends on the names of the directories, not on the filesystem or
+/// the platform.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PackageOrder {
+    /// By section, then by package, i.e: `app-utils/foo` before
+    /// `core-libs/bar`.
+    #[default]
+    Path,
+    /// By package, then by section, i.e: `core-libs/bar` before
+    /// `app-utils/foo`.
+    Name,
+}
+
+/// How the files of a tree are read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeOptions {
+    /// Map files into memory instead of reading them into buffers. Needs the
+    /// `mmap` feature, ignored otherwise; files which cannot be mapped are
+    /// read as usual.
+    pub mmap: bool,
+    pub order: PackageOrder,
+}
+
+impl TreeOptions {
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    pub fn order(mut self, order: PackageOrder) -> Self {
+        self.order = order;
+        self
+    }
+}
+
+/// Result of loading every package in a tree.
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub packages: Vec<Package>,
+    pub errors: Vec<PackageError>,
+}
+
+fn sorted_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+
+    Ok(dirs)
+}
+
+impl Tree {
+    pub fn open<P: AsRef<Path>>(root: P) -> Self {
+        Tree::open_with(root, TreeOptions::default())
+    }
+
+    /// Same as `open`, with the files read according to `options`, i.e:
+    /// `Tree::open_with(root, TreeOptions::default().mmap(true))`.
+    pub fn open_with<P: AsRef<Path>>(root: P, options: TreeOptions) -> Self {
+        Tree {
+            root: root.as_ref().to_path_buf(),
+            options,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn options(&self) -> &TreeOptions {
+        &self.options
+    }
+
+    /// Directories of all packages in the tree, sorted by `TreeOptions::order`.
+    pub fn package_dirs(&self) -> io::Result<Vec<PathBuf>> {
+        let mut result = Vec::new();
+        for section in sorted_dirs(&self.root)? {
+            for dir in sorted_dirs(&section)? {
+                if dir.join("spec").is_file() {
+                    result.push(dir);
+                }
+            }
+        }
+        self.sort_dirs(&mut result);
+
+        Ok(result)
+    }
+
+    /// Sort package directories already sorted by path as set by
+    /// `TreeOptions::order`.
+    fn sort_dirs(&self, dirs: &mut [PathBuf]) {
+        if self.options.order == PackageOrder::Name {
+            // Stable, so packages of the same name stay sorted by section.
+            dirs.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        }
+    }
+
+    /// Load every package in the tree.
+    /// A broken package does not stop the scan; its error is collected instead.
+    pub fn scan(&self) -> io::Result<Scan> {
+        self.scan_cancellable(&AtomicBool::new(false))
+    }
+
+    /// Same as `scan`, giving up with an `Interrupted` error once `cancel` is
+    /// set, i.e: by the request handler of a web service when the client goes
+    /// away. Packages already being loaded are finished first.
+    pub fn scan_cancellable(&self, cancel: &AtomicBool) -> io::Result<Scan> {
+        let mut scan = Scan::default();
+        for (_, result) in self.scan_iter(default_jobs())? {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(cancelled());
+            }
+            match result {
+                Ok(p) => scan.packages.push(p),
+                Err(e) => scan.errors.push(e),
+            }
+        }
+
+        Ok(scan)
+    }
+
+    /// Same as `scan`, but only files changed since they were put in `cache`
+    /// are parsed again. Call `ParseCache::save` afterwards to keep the results.
+    #[cfg(feature = "cache")]
+    pub fn scan_cached(&self, cache: &ParseCache) -> io::Result<Scan> {
+        let dirs = self.package_dirs()?;
+        let results = load_parallel(&dirs, default_jobs(), |dir| {
+            Package::from_dir_cached(dir, &SpecInheritance::All, cache)
+        });
+        let mut scan = Scan::default();
+        for result in results {
+            match result {
+                Ok(p) => scan.packages.push(p),
+                Err(e) => scan.errors.push(e),
+            }
+        }
+
+        Ok(scan)
+    }
+
+    /// Load packages on `jobs` threads, yielding them in the order of
+    /// `package_dirs` as they become available.
+    /// Threads keep loading while earlier packages are handled, up to a
+    /// bounded number of packages ahead of the last one yielded.
+    pub fn scan_iter(&self, jobs: usize) -> io::Result<ScanIter> {
+        let dirs = self.package_dirs()?;
+        let jobs = jobs.max(1);
+        let (work, queue) = mpsc::channel::<(usize, PathBuf)>();
+        let (sender, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..jobs.min(dirs.len()) {
+            let queue = queue.clone();
+            let sender = sender.clone();
+            let options = self.options.clone();
+            thread::spawn(move || loop {
+                let job = queue.lock().expect("Package queue poisoned").recv();
+                let (i, dir) = match job {
+                    Ok(job) => job,
+                    // Every package was handed out, or the iterator dropped.
+                    Err(_) => return,
+                };
+                let result = panic::catch_unwind(|| load_package(&dir, &options));
+                if sender.send((i, dir, result)).is_err() {
+                    return;
+                }
+            });
+        }
+
+        let mut iter = ScanIter {
+            dirs: dirs.into_iter(),
+            ahead: jobs * AHEAD_PER_JOB,
+            next: 0,
+            queued: 0,
+            work: Some(work),
+            results: Mutex::new(results),
+            ready: BTreeMap::new(),
+        };
+        iter.queue();
+        Ok(iter)
+    }
+}
+
+/// Where the packages of a tree come from, i.e: a checkout or a revision in
+/// version control.
+pub trait TreeSource {
+    /// Load every package. Broken packages are collected, not fatal.
+    fn scan(&self) -> io::Result<Scan>;
+}
+
+impl TreeSource for Tree {
+    fn scan(&self) -> io::Result<Scan> {
+        Tree::scan(self)
+    }
+}
+
+/// Result of `parse_all_parallel`, keyed by package directory.
+#[cfg(feature = "parallel")]
+#[derive(Debug, Default)]
+pub struct ParallelScan {
+    pub packages: BTreeMap<PathBuf, Package>,
+    pub errors: BTreeMap<PathBuf, PackageError>,
+}
+
+#[cfg(feature = "parallel")]
+impl ParallelScan {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Load every package under `root` on a rayon thread pool of `threads`
+/// threads, or as many as there are CPUs if `threads` is 0.
+/// Unlike `Tree::scan_iter` everything is kept in memory until the end.
+#[cfg(feature = "parallel")]
+pub fn parse_all_parallel<P: AsRef<Path>>(root: P, threads: usize) -> io::Result<ParallelScan> {
+    parse_all_parallel_cancellable(root, threads, &AtomicBool::new(false))
+}
+
+/// Same as `parse_all_parallel`, giving up with an `Interrupted` error once
+/// `cancel` is set. Packages already being loaded are finished first.
+#[cfg(feature = "parallel")]
+pub fn parse_all_parallel_cancellable<P: AsRef<Path>>(
+    root: P,
+    threads: usize,
+    cancel: &AtomicBool,
+) -> io::Result<ParallelScan> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(io::Error::other)?;
+    let dirs = Tree::open(root).package_dirs()?;
+    let results: Option<Vec<_>> = pool.install(|| {
+        dirs.into_par_iter()
+            .map(|dir| {
+                if cancel.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let result = Package::from_dir(&dir);
+                Some((dir, result))
+            })
+            .collect()
+    });
+    let results = results.ok_or_else(cancelled)?;
+
+    let mut scan = ParallelScan::default();
+    for (dir, result) in results {
+        match result {
+            Ok(p) => {
+                scan.packages.insert(dir, p);
+            }
+            Err(e) => {
+                scan.errors.insert(dir, e);
+            }
+        }
+    }
+
+    Ok(scan)
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "Scan cancelled")
+}
+
+/// Load the packages in `dirs` on `jobs` threads, keeping their order.
+fn load_parallel<T, F>(dirs: &[PathBuf], jobs: usize, load: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&Path) -> T + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<T>> = dirs.iter().map(|_| None).collect();
+
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..jobs.min(dirs.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut loaded = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        match dirs.get(i) {
+                            Some(dir) => loaded.push((i, load(dir))),
+                            None => return loaded,
+                        }
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (i, result) in worker.join().expect("Package loader panicked") {
+                results[i] = Some(result);
+            }
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|r| r.expect("Package not loaded"))
+        .collect()
+}
+
+fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn load_package(dir: &Path, options: &TreeOptions) -> Result<Package, PackageError> {
+    #[cfg(feature = "mmap")]
+    if options.mmap {
+        let loader = Loader {
+            files: FileSource::Mapped,
+            ..Default::default()
+        };
+        return loader.load(dir, &crate::apf::Context::new());
+    }
+    #[cfg(not(feature = "mmap"))]
+    let _ = options;
+    Package::from_dir(dir)
+}
+
+type Loaded = (PathBuf, Result<Package, PackageError>);
+/// A package loaded by a thread of `ScanIter`, with its index.
+type LoadedAt = (usize, PathBuf, thread::Result<Result<Package, PackageError>>);
+
+/// Iterator returned by `Tree::scan_iter`.
+pub struct ScanIter {
+    dirs: vec::IntoIter<PathBuf>,
+    ahead: usize,
+    /// Index of the next package to yield.
+    next: usize,
+    /// Number of packages handed to the threads.
+    queued: usize,
+    work: Option<mpsc::Sender<(usize, PathBuf)>>,
+    /// Only locked to make the iterator `Sync`, i.e: for Python.
+    results: Mutex<mpsc::Receiver<LoadedAt>>,
+    /// Packages loaded before the ones they come after, by index.
+    ready: BTreeMap<usize, Loaded>,
+}
+
+impl ScanIter {
+    /// Hand packages to the threads until `ahead` of them are queued, loaded
+    /// or waiting to be yielded.
+    fn queue(&mut self) {
+        while self.queued < self.next + self.ahead {
+            let (work, dir) = match (&self.work, self.dirs.next()) {
+                (Some(work), Some(dir)) => (work, dir),
+                _ => {
+                    // Lets the threads stop once the queue is empty.
+                    self.work = None;
+                    return;
+                }
+            };
+            work.send((self.queued, dir)).expect("Package loaders stopped");
+            self.queued += 1;
+        }
+    }
+}
+
+impl Iterator for ScanIter {
+    type Item = Loaded;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(loaded) = self.ready.remove(&self.next) {
+                self.next += 1;
+                self.queue();
+                return Some(loaded);
+            }
+            if self.next == self.queued {
+                return None;
+            }
+            let results = self.results.get_mut().expect("Package results poisoned");
+            let (i, dir, result) = results.recv().expect("Package loaders stopped");
+            let result = result.unwrap_or_else(|e| panic::resume_unwind(e));
+            self.ready.insert(i, (dir, result));
+        }
+    }
+}
+
+/// Version of the document written by `export_json`.
+/// Bumped on any incompatible change to its layout.
+#[cfg(feature = "serde")]
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// Fields of a package, with `FIELD__ARCH` overrides split out.
+/// i.e: `PKGDEP__AMD64` ends up in `arch_overrides["amd64"]["PKGDEP"]`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Default, Serialize)]
+pub struct ExportedFields {
+    pub fields: BTreeMap<String, String>,
+    pub arch_overrides: BTreeMap<String, BTreeMap<String, String>>,
+    /// `LICENSE` normalized, if it is an SPDX expression.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<LicenseExpr>,
+}
+
+#[cfg(feature = "serde")]
+impl ExportedFields {
+    fn new(context: &Context) -> Self {
+        let mut exported = ExportedFields {
+            license: context
+                .get("LICENSE")
+                .and_then(|l| l.parse::<LicenseExpr>().ok())
+                .map(|l| l.normalize()),
+            ..Default::default()
+        };
+        for (key, value) in context {
+            match split_arch_suffix(key) {
+                Some((field, arch)) => {
+                    exported
+                        .arch_overrides
+                        .entry(arch.to_ascii_lowercase())
+                        .or_default()
+                        .insert(field.to_string(), value.clone());
+                }
+                None => {
+                    exported.fields.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        exported
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, Serialize)]
+pub struct ExportedSubPackage {
+    pub name: String,
+    /// Which spec variables the sub-package was evaluated with.
+    pub inheritance: SpecInheritance,
+    #[serde(flatten)]
+    pub fields: ExportedFields,
+}
+
+/// One entry of the `packages` array written by `export_json`.
+/// A package that failed to load has no name and an `error` instead of fields.
+#[cfg(feature = "serde")]
+#[derive(Debug, Serialize)]
+pub struct ExportedPackage {
+    /// Path relative to the tree root, i.e: `app-utils/foo`.
+    pub path: String,
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub fields: ExportedFields,
+    pub subpackages: Vec<ExportedSubPackage>,
+    pub error: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+impl ExportedPackage {
+    fn new(tree: &Tree, dir: &Path, result: Result<Package, PackageError>) -> Self {
+        // Joined with `/` on every platform, for the same document everywhere.
+        let path = dir
+            .strip_prefix(tree.root())
+            .unwrap_or(dir)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        match result {
+            Ok(p) => ExportedPackage {
+                path,
+                name: Some(p.name().to_string()),
+                fields: ExportedFields::new(p.fields()),
+                subpackages: p
+                    .subpackages()
+                    .iter()
+                    .map(|sub| ExportedSubPackage {
+                        name: sub.name().to_string(),
+                        inheritance: sub.inheritance().clone(),
+                        fields: ExportedFields::new(sub.fields()),
+                    })
+                    .collect(),
+                error: None,
+            },
+            Err(e) => ExportedPackage {
+                path,
+                name: None,
+                fields: ExportedFields::default(),
+                subpackages: Vec::new(),
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// The document written by `export_json`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Serialize)]
+pub struct TreeExport {
+    pub format_version: u32,
+    /// Which state of the tree the packages were loaded from.
+    pub provenance: Provenance,
+    pub packages: Vec<ExportedPackage>,
+}
+
+#[cfg(feature = "serde")]
+impl TreeExport {
+    /// Load every package of `tree`. Packages are sorted as set by
+    /// `TreeOptions::order` and keys of every map are sorted, so the same tree
+    /// always gives the same document, apart from `provenance.scanned_at`.
+    pub fn collect(tree: &Tree) -> io::Result<Self> {
+        let provenance = Provenance::collect(tree.root());
+        let packages = tree
+            .scan_iter(default_jobs())?
+            .map(|(dir, result)| ExportedPackage::new(tree, &dir, result))
+            .collect();
+
+        Ok(TreeExport {
+            format_version: EXPORT_FORMAT_VERSION,
+            provenance,
+            packages,
+        })
+    }
+
+    /// The export as a TOML document, with packages as `[[packages]]` tables.
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        crate::export::to_toml(self)
+    }
+
+    /// The export as a YAML document.
+    #[cfg(feature = "yaml")]
+    pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
+        crate::export::to_yaml(self)
+    }
+}
+
+/// Serializes the packages of a `ScanIter` one at a time, as they are loaded.
+#[cfg(feature = "serde")]
+struct PackageStream<'a> {
+    tree: &'a Tree,
+    scan: RefCell<Option<ScanIter>>,
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for PackageStream<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let scan = self.scan.borrow_mut().take().expect("Package stream serialized twice");
+        serializer.collect_seq(scan.map(|(dir, result)| ExportedPackage::new(self.tree, &dir, result)))
+    }
+}
+
+/// Same layout as `TreeExport`, without holding every package in memory.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct StreamingExport<'a> {
+    format_version: u32,
+    provenance: Provenance,
+    packages: PackageStream<'a>,
+}
+
+/// Write every package of the tree at `root` as a JSON document:
+///
+/// ```json
+/// {
+///   "format_version": 1,
+///   "provenance": {
+///     "tree_commit": "0123abcd...",
+///     "dirty": false,
+///     "crate_version": "0.1.0",
+///     "scanned_at": 1700000000
+///   },
+///   "packages": [
+///     {
+///       "path": "app-utils/foo",
+///       "name": "foo",
+///       "fields": { "PKGDEP": "bar", "VER": "1.0" },
+///       "arch_overrides": { "amd64": { "PKGDEP": "bar baz" } },
+///       "subpackages": [],
+///       "error": null
+///     }
+///   ]
+/// }
+/// ```
+///
+/// A package that fails to load is kept with its `error` set; it does not
+/// abort the export. Packages are loaded in parallel and written as soon as
+/// all packages before them are, so memory use does not grow with the tree.
+#[cfg(feature = "serde")]
+pub fn export_json<P: AsRef<Path>, W: io::Write>(root: P, writer: W) -> io::Result<()> {
+    export_json_with(&Tree::open(root), writer)
+}
+
+/// Same as `export_json`, with the packages of `tree` in the order set by its
+/// options.
+#[cfg(feature = "serde")]
+pub fn export_json_with<W: io::Write>(tree: &Tree, writer: W) -> io::Result<()> {
+    let export = StreamingExport {
+        format_version: EXPORT_FORMAT_VERSION,
+        provenance: Provenance::collect(tree.root()),
+        packages: PackageStream {
+            tree,
+            scan: RefCell::new(Some(tree.scan_iter(default_jobs())?)),
+        },
+    };
+    serde_json::to_writer_pretty(writer, &export)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::write_package;
+
+    #[test]
+    fn test_scan_iter_order() {
+        let root = tempfile::tempdir().unwrap();
+        let mut expected = Vec::new();
+        for i in 0..100 {
+            let name = format!("pkg{:03}", i);
+            write_package(root.path(), "section", &name, "VER=1\n", "");
+            expected.push(name);
+        }
+        let tree = Tree::open(root.path());
+        for jobs in [1, 3, 8] {
+            let names: Vec<_> = tree
+                .scan_iter(jobs)
+                .unwrap()
+                .map(|(_, p)| p.unwrap().name().to_string())
+                .collect();
+            assert_eq!(names, expected);
+        }
+
+        // Loading goes on ahead of what was handed out, but not further.
+        let mut iter = tree.scan_iter(2).unwrap();
+        assert_eq!(iter.queued, 2 * AHEAD_PER_JOB);
+        iter.next().unwrap().1.unwrap();
+        assert_eq!(iter.queued, 1 + 2 * AHEAD_PER_JOB);
+    }
+
+    #[test]
+    fn test_package_order() {
+        let root = tempfile::tempdir().unwrap();
+        write_package(root.path(), "app-utils", "foo", "VER=1\n", "");
+        write_package(root.path(), "core-libs", "bar", "VER=1\n", "");
+        write_package(root.path(), "app-utils", "bar", "VER=1\n", "");
+        let names = |order| {
+            let tree = Tree::open_with(root.path(), TreeOptions::default().order(order));
+            tree.package_dirs()
+                .unwrap()
+                .iter()
+                .map(|d| d.strip_prefix(root.path()).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(PackageOrder::Path),
+            vec![
+                Path::new("app-utils/bar"),
+                Path::new("app-utils/foo"),
+                Path::new("core-libs/bar")
+            ]
+        );
+        assert_eq!(
+            names(PackageOrder::Name),
+            vec![
+                Path::new("app-utils/bar"),
+                Path::new("core-libs/bar"),
+                Path::new("app-utils/foo")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_mmap() {
+        let root = tempfile::tempdir().unwrap();
+        write_package(root.path(), "app-utils", "foo", "VER=1.0\n", "PKGDES=\"Foo $VER\"\n");
+        write_package(root.path(), "app-utils", "empty", "", "");
+        let tree = Tree::open_with(root.path(), TreeOptions::default().mmap(true));
+        assert!(tree.options().mmap);
+        let scan = tree.scan().unwrap();
+        assert!(scan.errors.is_empty());
+        assert_eq!(scan.packages[1].fields()["PKGDES"], "Foo 1.0");
+    }
+
+    #[test]
+    fn test_scan() {
+        let root = tempfile::tempdir().unwrap();
+        write_package(root.path(), "app-utils", "foo", "VER=1.0\n", "PKGNAME=foo\nPKGDES=\"Foo $VER\"\n");
+        write_package(root.path(), "core-libs", "bar", "VER=2.0\n", "PKGDEP=\"foo\"\n");
+        write_package(root.path(), "core-libs", "broken", "VER=1.0 | cat\n", "PKGNAME=broken\n");
+        fs::create_dir_all(root.path().join("groups")).unwrap();
+        fs::create_dir_all(root.path().join(".git").join("objects")).unwrap();
+
+        let tree = Tree::open(root.path());
+        assert_eq!(tree.package_dirs().unwrap().len(), 3);
+        let scan = tree.scan().unwrap();
+        assert_eq!(scan.errors.len(), 1);
+        let names: Vec<_> = scan.packages.iter().map(|p| p.name()).collect();
+        assert_eq!(names, vec!["foo", "bar"]);
+        assert_eq!(scan.packages[0].fields()["PKGDES"], "Foo 1.0");
+
+        let err = tree.scan_cancellable(&AtomicBool::new(true)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_scan_cached() {
+        let root = tempfile::tempdir().unwrap();
+        write_package(root.path(), "app-utils", "foo", "VER=1.0\n", "PKGNAME=foo\nPKGDES=\"Foo $VER\"\n");
+        write_package(root.path(), "core-libs", "bar", "VER=2.0\n", "PKGDEP=\"foo\"\n");
+        let path = root.path().join("cache.cbor");
+
+        let cache = ParseCache::open(&path).unwrap();
+        let tree = Tree::open(root.path());
+        assert_eq!(tree.scan_cached(&cache).unwrap().packages.len(), 2);
+        assert_eq!((cache.hits(), cache.misses()), (0, 4));
+        cache.save().unwrap();
+
+        write_package(root.path(), "core-libs", "bar", "VER=2.1\n", "PKGDEP=\"foo\"\n");
+        let cache = ParseCache::open(&path).unwrap();
+        let scan = tree.scan_cached(&cache).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+        assert_eq!(scan.packages[0].fields()["PKGDES"], "Foo 1.0");
+        assert_eq!(scan.packages[1].fields()["VER"], "2.1");
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parse_all_parallel() {
+        let root = tempfile::tempdir().unwrap();
+        write_package(root.path(), "app-utils", "foo", "VER=1.0\n", "PKGNAME=foo\nPKGDES=\"Foo $VER\"\n");
+        write_package(root.path(), "core-libs", "bar", "VER=2.0\n", "PKGDEP=\"foo\"\n");
+        write_package(root.path(), "core-libs", "broken", "VER=1.0 | cat\n", "PKGNAME=broken\n");
+
+        let scan = parse_all_parallel(root.path(), 2).unwrap();
+        assert!(!scan.is_ok());
+        assert!(scan.errors.contains_key(&root.path().join("core-libs/broken")));
+        let foo = &scan.packages[&root.path().join("app-utils/foo")];
+        assert_eq!(foo.fields()["PKGDES"], "Foo 1.0");
+        assert_eq!(scan.packages.len(), 2);
+
+        let err =
+            parse_all_parallel_cancellable(root.path(), 2, &AtomicBool::new(true)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_export_json() {
+        let root = tempfile::tempdir().unwrap();
+        write_package(root.path(), "app-utils", "foo", "VER=1.0\n", "PKGDEP=bar\nPKGDEP__AMD64=\"bar baz\"\nLICENSE=\"gpl-2.0+ OR mit\"\n");
+        write_package(root.path(), "core-libs", "broken", "VER=1.0 | cat\n", "PKGNAME=broken\n");
+
+        let mut out = Vec::new();
+        export_json(root.path(), &mut out).unwrap();
+        let doc: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(doc["format_version"], 1);
+        let foo = &doc["packages"][0];
+        assert_eq!(foo["path"], "app-utils/foo");
+        assert_eq!(foo["name"], "foo");
+        assert_eq!(foo["fields"]["PKGDEP"], "bar");
+        assert!(foo["fields"].get("PKGDEP__AMD64").is_none());
+        assert_eq!(foo["arch_overrides"]["amd64"]["PKGDEP"], "bar baz");
+        assert_eq!(foo["license"], "GPL-2.0-or-later OR MIT");
+        assert!(foo["error"].is_null());
+        let broken = &doc["packages"][1];
+        assert!(broken["name"].is_null());
+        assert!(broken["error"].as_str().unwrap().contains("spec"));
+
+        let provenance = &doc["provenance"];
+        assert!(provenance.get("tree_commit").is_some());
+        assert_eq!(provenance["dirty"], false);
+        assert_eq!(provenance["crate_version"], env!("CARGO_PKG_VERSION"));
+        assert!(provenance["scanned_at"].as_u64().unwrap() > 0);
+
+        // Only the time of the scan differs between exports of the same tree.
+        let mut again = Vec::new();
+        export_json(root.path(), &mut again).unwrap();
+        let mut again: serde_json::Value = serde_json::from_slice(&again).unwrap();
+        again["provenance"]["scanned_at"] = doc["provenance"]["scanned_at"].clone();
+        assert_eq!(doc, again);
+    }
+
+    #[cfg(all(feature = "toml", feature = "yaml"))]
+    #[test]
+    fn test_export_toml_yaml() {
+        let root = tempfile::tempdir().unwrap();
+        write_package(root.path(), "app-utils", "foo", "VER=1.0\n", "PKGDEP__AMD64=bar\n");
+        write_package(root.path(), "core-libs", "broken", "VER=1.0 | cat\n", "");
+        let export = TreeExport::collect(&Tree::open(root.path())).unwrap();
+
+        let toml: toml::Value = toml::from_str(&export.to_toml().unwrap()).unwrap();
+        assert_eq!(toml["format_version"].as_integer(), Some(1));
+        assert!(toml["provenance"]["scanned_at"].as_integer().is_some());
+        assert_eq!(toml["packages"][0]["arch_overrides"]["amd64"]["PKGDEP"].as_str(), Some("bar"));
+        assert!(toml["packages"][1].get("name").is_none());
+        assert!(toml["packages"][1].get("error").is_some());
+
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&export.to_yaml().unwrap()).unwrap();
+        assert_eq!(yaml["packages"][0]["fields"]["VER"].as_str(), Some("1.0"));
+        assert!(yaml["packages"][1]["name"].is_null());
+    }
+}
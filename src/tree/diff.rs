@@ -0,0 +1,216 @@
+//! Semantic differences between two versions of a tree, i.e: two checkouts,
+//! for release notes and review tooling.
+
+use super::{Scan, Tree, TreeSource};
+use crate::{
+    apf::Context,
+    dependency::parse_dependencies,
+    deps::DepKind,
+    package::{Package, PackageError},
+    version::Version,
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::Path,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct VersionChange {
+    pub name: String,
+    /// `None` if the version could not be determined, i.e: VER is missing.
+    pub old: Option<Version>,
+    pub new: Option<Version>,
+}
+
+impl VersionChange {
+    /// Whether the new version sorts lower than the old one. Bumping
+    /// `PKGEPOCH` along with the change makes it an upgrade again.
+    pub fn is_downgrade(&self) -> bool {
+        matches!((&self.old, &self.new), (Some(old), Some(new)) if new < old)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DependencyChange {
+    pub name: String,
+    pub kind: DepKind,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Result of `diff`. Names are package names, or sub-package names for
+/// packages that have them, sorted.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TreeDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub version_changes: Vec<VersionChange>,
+    pub dependency_changes: Vec<DependencyChange>,
+    /// Packages that failed to load on either side; they are left out of the
+    /// comparison.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub errors: Vec<PackageError>,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.version_changes.is_empty()
+            && self.dependency_changes.is_empty()
+    }
+
+    /// Version changes that lower the version without an epoch bump, which
+    /// package managers would not pick up.
+    pub fn downgrades(&self) -> Vec<&VersionChange> {
+        self.version_changes.iter().filter(|c| c.is_downgrade()).collect()
+    }
+}
+
+fn units(packages: Vec<Package>) -> BTreeMap<String, Context> {
+    let mut result = BTreeMap::new();
+    for package in packages {
+        if package.subpackages().is_empty() {
+            result.insert(package.name().to_string(), package.fields().clone());
+        }
+        for sub in package.subpackages() {
+            result.insert(sub.name().to_string(), sub.fields().clone());
+        }
+    }
+
+    result
+}
+
+/// Names in a dependency field. Unparsable fields count as empty, the linter
+/// reports them.
+fn dependency_names(fields: &Context, kind: DepKind) -> BTreeSet<String> {
+    fields
+        .get(kind.field())
+        .and_then(|v| parse_dependencies(v).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|d| d.name)
+        .collect()
+}
+
+/// Compare the packages of the checkouts at `old_root` and `new_root`.
+pub fn diff<P: AsRef<Path>, Q: AsRef<Path>>(old_root: P, new_root: Q) -> io::Result<TreeDiff> {
+    diff_sources(&Tree::open(old_root), &Tree::open(new_root))
+}
+
+/// Same as `diff`, for any pair of tree sources, i.e: git revisions.
+pub fn diff_sources(old: &dyn TreeSource, new: &dyn TreeSource) -> io::Result<TreeDiff> {
+    let mut result = TreeDiff::default();
+    let Scan { packages, errors } = old.scan()?;
+    let old = units(packages);
+    result.errors.extend(errors);
+    let Scan { packages, errors } = new.scan()?;
+    let new = units(packages);
+    result.errors.extend(errors);
+
+    result.removed = old.keys().filter(|n| !new.contains_key(*n)).cloned().collect();
+    for (name, fields) in new.iter() {
+        let old_fields = match old.get(name) {
+            Some(f) => f,
+            None => {
+                result.added.push(name.clone());
+                continue;
+            }
+        };
+
+        let (old_version, new_version) = (
+            Version::from_context(old_fields).ok(),
+            Version::from_context(fields).ok(),
+        );
+        if old_version != new_version {
+            result.version_changes.push(VersionChange {
+                name: name.clone(),
+                old: old_version,
+                new: new_version,
+            });
+        }
+
+        for kind in [DepKind::Runtime, DepKind::Build] {
+            let (before, after) = (dependency_names(old_fields, kind), dependency_names(fields, kind));
+            if before != after {
+                result.dependency_changes.push(DependencyChange {
+                    name: name.clone(),
+                    kind,
+                    added: after.difference(&before).cloned().collect(),
+                    removed: before.difference(&after).cloned().collect(),
+                });
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::write_package;
+
+    #[test]
+    fn test_diff() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        write_package(old.path(), "app-utils", "foo", "VER=1.0\n", "PKGDEP=\"bar baz\"\n");
+        write_package(new.path(), "app-utils", "foo", "VER=1.1\nREL=1\n", "PKGDEP=\"bar qux>=2\"\nBUILDDEP=\"cmake\"\n");
+        write_package(old.path(), "app-utils", "same", "VER=1.0\n", "PKGDEP=\"bar\"\n");
+        write_package(new.path(), "app-utils", "same", "VER=1.0\n", "PKGDEP=\"bar\"\n");
+        write_package(old.path(), "app-utils", "gone", "VER=1.0\n", "");
+        write_package(new.path(), "app-utils", "down", "VER=0.9\n", "");
+        write_package(old.path(), "app-utils", "down", "VER=1.0\n", "");
+        write_package(new.path(), "app-utils", "epoch", "VER=0.9\nPKGEPOCH=1\n", "");
+        write_package(old.path(), "app-utils", "epoch", "VER=1.0\n", "");
+        write_package(new.path(), "app-utils", "fresh", "VER=1.0\n", "");
+        write_package(new.path(), "app-utils", "broken", "VER=1.0 | cat\n", "");
+
+        let result = diff(old.path(), new.path()).unwrap();
+        assert_eq!(result.added, vec!["fresh"]);
+        assert_eq!(result.removed, vec!["gone"]);
+        assert_eq!(result.errors.len(), 1);
+
+        let versions: Vec<_> = result
+            .version_changes
+            .iter()
+            .map(|c| (c.name.as_str(), c.new.as_ref().unwrap().to_string(), c.is_downgrade()))
+            .collect();
+        assert_eq!(
+            versions,
+            vec![
+                ("down", "0.9".to_string(), true),
+                ("epoch", "1:0.9".to_string(), false),
+                ("foo", "1.1-1".to_string(), false)
+            ]
+        );
+        let downgrades: Vec<_> = result.downgrades().iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(downgrades, vec!["down"]);
+
+        assert_eq!(
+            result.dependency_changes,
+            vec![
+                DependencyChange {
+                    name: "foo".to_string(),
+                    kind: DepKind::Runtime,
+                    added: vec!["qux".to_string()],
+                    removed: vec!["baz".to_string()],
+                },
+                DependencyChange {
+                    name: "foo".to_string(),
+                    kind: DepKind::Build,
+                    added: vec!["cmake".to_string()],
+                    removed: vec![],
+                },
+            ]
+        );
+        assert!(diff(old.path(), old.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,364 @@
+//! Reading a tree at any revision of its git repository, without a checkout.
+
+use super::{Scan, Tree, TreeSource};
+use crate::{
+    apf::Context,
+    package::{
+        parse_content, FileSource, Loader, Package, PackageError, PackageFiles, ParseFn,
+        SpecInheritance,
+    },
+    version::Version,
+};
+use git2::{ObjectType, Oid, Repository, Sort};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A tree at a given revision, i.e: from `Tree::open_git(repo, "HEAD~5")`.
+/// Package paths are relative to the root of the repository.
+pub struct GitTree {
+    repo: Repository,
+    commit: Oid,
+    tree: Oid,
+}
+
+impl Tree {
+    /// Open the tree in the git repository at `repo` as of `revision`, which
+    /// is anything `git rev-parse` understands, i.e: `HEAD~5` or a tag.
+    pub fn open_git<P: AsRef<Path>>(repo: P, revision: &str) -> Result<GitTree, git2::Error> {
+        let repo = Repository::open(repo)?;
+        let (commit, tree) = {
+            let commit = repo.revparse_single(revision)?.peel_to_commit()?;
+            (commit.id(), commit.tree_id())
+        };
+
+        Ok(GitTree { repo, commit, tree })
+    }
+}
+
+/// Files of one git tree object.
+struct GitFiles<'a> {
+    repo: &'a Repository,
+    tree: git2::Tree<'a>,
+}
+
+impl GitFiles<'_> {
+    fn kind(&self, path: &Path) -> Option<ObjectType> {
+        self.tree.get_path(path).ok()?.kind()
+    }
+}
+
+impl PackageFiles for GitFiles<'_> {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "No such file in revision");
+        let entry = self.tree.get_path(path).map_err(|_| not_found())?;
+        if entry.kind() != Some(ObjectType::Blob) {
+            return Err(not_found());
+        }
+        let blob = self.repo.find_blob(entry.id()).map_err(io::Error::other)?;
+        String::from_utf8(blob.content().to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.kind(path) == Some(ObjectType::Blob)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || self.kind(path) == Some(ObjectType::Tree)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let tree = if path.as_os_str().is_empty() {
+            self.tree.clone()
+        } else {
+            let entry = self.tree.get_path(path).map_err(io::Error::other)?;
+            self.repo.find_tree(entry.id()).map_err(io::Error::other)?
+        };
+        Ok(tree
+            .iter()
+            .filter_map(|e| e.name().map(|n| path.join(n)))
+            .collect())
+    }
+}
+
+/// Load the package in `dir` of the tree object `tree` in the repository at
+/// `repo`, for `Loader`.
+pub(crate) fn load_package(
+    repo: &Path,
+    tree: Oid,
+    dir: &Path,
+    scope: &Context,
+    inheritance: &SpecInheritance,
+    parse: &ParseFn,
+) -> Result<Package, PackageError> {
+    let error = |e: git2::Error| PackageError::IOError(dir.to_path_buf(), io::Error::other(e));
+    let repo = Repository::open(repo).map_err(error)?;
+    let files = GitFiles {
+        tree: repo.find_tree(tree).map_err(error)?,
+        repo: &repo,
+    };
+    Package::load_in(dir, scope, inheritance, parse, &files)
+}
+
+impl GitTree {
+    /// The commit `revision` resolved to.
+    pub fn commit_id(&self) -> Oid {
+        self.commit
+    }
+
+    fn files(&self) -> io::Result<GitFiles<'_>> {
+        let tree = self.repo.find_tree(self.tree).map_err(io::Error::other)?;
+        Ok(GitFiles {
+            repo: &self.repo,
+            tree,
+        })
+    }
+
+    fn sorted_dirs(files: &GitFiles, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut dirs: Vec<_> = files
+            .read_dir(dir)?
+            .into_iter()
+            .filter(|p| {
+                let hidden = p.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.'));
+                !hidden && files.is_dir(p)
+            })
+            .collect();
+        dirs.sort();
+        Ok(dirs)
+    }
+
+    /// Directories of all packages, relative to the repository, sorted.
+    pub fn package_dirs(&self) -> io::Result<Vec<PathBuf>> {
+        let files = self.files()?;
+        let mut result = Vec::new();
+        for section in GitTree::sorted_dirs(&files, Path::new(""))? {
+            for dir in GitTree::sorted_dirs(&files, &section)? {
+                if files.is_file(&dir.join("spec")) {
+                    result.push(dir);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// A commit that touched a package, from `GitTree::changelog`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ChangelogEntry {
+    /// Hex commit id.
+    pub commit: String,
+    pub author: String,
+    pub email: String,
+    /// Commit time, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// First line of the commit message.
+    pub summary: String,
+    /// Version after the commit, `None` if the package was removed or could
+    /// not be loaded.
+    pub version: Option<Version>,
+    /// Version before the commit, `None` if the package was added.
+    pub previous_version: Option<Version>,
+}
+
+impl ChangelogEntry {
+    pub fn is_version_change(&self) -> bool {
+        self.version != self.previous_version
+    }
+
+    /// Whether the commit lowered the version without bumping `PKGEPOCH`.
+    pub fn is_downgrade(&self) -> bool {
+        matches!((&self.previous_version, &self.version), (Some(old), Some(new)) if new < old)
+    }
+}
+
+impl GitTree {
+    fn version_at(&self, tree: git2::Tree<'_>, dir: &Path) -> Option<Version> {
+        let files = GitFiles {
+            repo: &self.repo,
+            tree,
+        };
+        let package = Package::load(dir, &SpecInheritance::All, &parse_content, &files).ok()?;
+        Version::from_context(package.fields()).ok()
+    }
+
+    /// Commits up to this revision that changed the package in `dir`, i.e:
+    /// `app-utils/foo`, newest first. Merge commits are compared to their
+    /// first parent.
+    pub fn changelog<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<ChangelogEntry>, git2::Error> {
+        let dir = dir.as_ref();
+        let mut walk = self.repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        walk.push(self.commit)?;
+        walk.simplify_first_parent()?;
+
+        let dir_id = |tree: &git2::Tree| tree.get_path(dir).ok().map(|e| e.id());
+        let mut result = Vec::new();
+        for id in walk {
+            let commit = self.repo.find_commit(id?)?;
+            let tree = commit.tree()?;
+            let parent_tree = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            if dir_id(&tree) == parent_tree.as_ref().and_then(dir_id) {
+                continue;
+            }
+
+            let author = commit.author();
+            result.push(ChangelogEntry {
+                commit: commit.id().to_string(),
+                author: author.name().unwrap_or_default().to_string(),
+                email: author.email().unwrap_or_default().to_string(),
+                timestamp: commit.time().seconds(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+                version: self.version_at(tree, dir),
+                previous_version: parent_tree.and_then(|t| self.version_at(t, dir)),
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+impl TreeSource for GitTree {
+    fn scan(&self) -> io::Result<Scan> {
+        let files = self.files()?;
+        // Packages are evaluated again from the same revision by `resolve_for`.
+        let loader = Loader {
+            files: FileSource::Git {
+                repo: self.repo.path().to_path_buf(),
+                tree: self.tree,
+            },
+            ..Default::default()
+        };
+        let mut scan = Scan::default();
+        for dir in self.package_dirs()? {
+            let result = Package::load(&dir, &SpecInheritance::All, &parse_content, &files);
+            match result.map(|p| p.loaded_by(loader.clone())) {
+                Ok(p) => scan.packages.push(p),
+                Err(e) => scan.errors.push(e),
+            }
+        }
+
+        Ok(scan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::diff_sources;
+    use git2::Signature;
+    use std::fs;
+
+    fn commit(repo: &Repository, message: &str) {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.update_all(["*"].iter(), None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Someone", "someone@example.com").unwrap();
+        let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_open_git() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = Repository::init(root.path()).unwrap();
+        let foo = root.path().join("app-utils").join("foo");
+        fs::create_dir_all(foo.join("autobuild")).unwrap();
+        fs::write(foo.join("spec"), "VER=1.0\n").unwrap();
+        fs::write(foo.join("autobuild").join("defines"), "PKGDES=\"Foo $VER\"\n").unwrap();
+        let bar = root.path().join("core-libs").join("bar");
+        fs::create_dir_all(bar.join("autobuild").join("01-libbar")).unwrap();
+        fs::write(bar.join("spec"), "VER=2.0\n").unwrap();
+        fs::write(bar.join("autobuild").join("01-libbar").join("defines"), "PKGNAME=libbar\n").unwrap();
+        commit(&repo, "Initial");
+
+        fs::write(foo.join("spec"), "VER=1.1\n").unwrap();
+        fs::remove_dir_all(&bar).unwrap();
+        commit(&repo, "foo: update to 1.1");
+
+        let old = Tree::open_git(root.path(), "HEAD~1").unwrap();
+        assert_eq!(
+            old.package_dirs().unwrap(),
+            vec![PathBuf::from("app-utils/foo"), PathBuf::from("core-libs/bar")]
+        );
+        let scan = old.scan().unwrap();
+        assert!(scan.errors.is_empty());
+        assert_eq!(scan.packages[0].fields()["PKGDES"], "Foo 1.0");
+        assert_eq!(scan.packages[1].subpackages()[0].name(), "libbar");
+
+        let new = Tree::open_git(root.path(), "HEAD").unwrap();
+        assert_ne!(old.commit_id(), new.commit_id());
+        let diff = diff_sources(&old, &new).unwrap();
+        assert_eq!(diff.removed, vec!["libbar"]);
+        assert_eq!(diff.version_changes[0].new.as_ref().unwrap().to_string(), "1.1");
+
+        assert!(Tree::open_git(root.path(), "nonexistent").is_err());
+
+        let changelog = new.changelog("app-utils/foo").unwrap();
+        let summary: Vec<_> = changelog
+            .iter()
+            .map(|e| {
+                (
+                    e.summary.as_str(),
+                    e.previous_version.as_ref().map(|v| v.to_string()),
+                    e.version.as_ref().map(|v| v.to_string()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("foo: update to 1.1", Some("1.0".to_string()), Some("1.1".to_string())),
+                ("Initial", None, Some("1.0".to_string())),
+            ]
+        );
+        assert_eq!(changelog[0].author, "Someone");
+        assert!(changelog[0].is_version_change());
+        assert!(!changelog[0].is_downgrade());
+
+        let changelog = new.changelog("core-libs/bar").unwrap();
+        assert_eq!(changelog.len(), 2);
+        assert_eq!(changelog[0].version, None);
+        assert_eq!(old.changelog("core-libs/bar").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_resolve_for_git() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = Repository::init(root.path()).unwrap();
+        let foo = root.path().join("app-utils").join("foo");
+        fs::create_dir_all(foo.join("autobuild")).unwrap();
+        fs::write(foo.join("spec"), "VER=1.0\n").unwrap();
+        fs::write(
+            foo.join("autobuild").join("defines"),
+            "PKGDEP=\"bar\"\nif [[ \"$ARCH\" = riscv64 ]]; then\n    PKGDEP=\"baz\"\nfi\n",
+        )
+        .unwrap();
+        commit(&repo, "Initial");
+        fs::write(foo.join("spec"), "VER=1.1\n").unwrap();
+        commit(&repo, "foo: update to 1.1");
+        // Neither the checkout nor the working directory are read.
+        fs::remove_dir_all(&foo).unwrap();
+
+        let old = Tree::open_git(root.path(), "HEAD~1").unwrap();
+        let package = old.scan().unwrap().packages.remove(0);
+        let resolved = package.resolve_for(crate::arch::Arch::Riscv64).unwrap();
+        assert_eq!(resolved.fields()["VER"], "1.0");
+        assert_eq!(resolved.fields()["PKGDEP"], "baz");
+        assert_eq!(resolved.path(), Some(Path::new("app-utils/foo")));
+    }
+}
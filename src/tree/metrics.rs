@@ -0,0 +1,277 @@
+//! Per-file statistics of a tree scan, to spot spec and defines files slowing
+//! down CI pipelines.
+
+use super::{default_jobs, load_parallel, Scan, Tree};
+use crate::{
+    apf::{self, Context, ParseError},
+    package::{Filesystem, Package, PackageError, PackageFiles, SpecInheritance},
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    fmt, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FileMetrics {
+    pub path: PathBuf,
+    /// Time spent evaluating the file, reading excluded.
+    pub duration: Duration,
+    /// Variable expansions and substitutions, i.e: `$VER` or `${VER%.*}`.
+    pub substitutions: usize,
+    /// Deepest nesting of blocks and substitutions, i.e: 2 for
+    /// `if ...; then A=${B}; fi`.
+    pub max_depth: usize,
+    /// 1 if evaluation failed, 0 otherwise.
+    pub errors: usize,
+}
+
+/// Statistics of every file evaluated by `Tree::scan_with_metrics`, in scan
+/// order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ParseMetrics {
+    pub files: Vec<FileMetrics>,
+}
+
+impl ParseMetrics {
+    pub fn total_duration(&self) -> Duration {
+        self.files.iter().map(|f| f.duration).sum()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.files.iter().map(|f| f.errors).sum()
+    }
+
+    /// The `n` files taking the longest to evaluate, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<&FileMetrics> {
+        let mut files: Vec<_> = self.files.iter().collect();
+        files.sort_by(|a, b| {
+            b.duration
+                .cmp(&a.duration)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        files.truncate(n);
+        files
+    }
+}
+
+impl fmt::Display for ParseMetrics {
+    /// Summary followed by the ten slowest files.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} files in {:?}, {} errors",
+            self.files.len(),
+            self.total_duration(),
+            self.errors()
+        )?;
+        for file in self.slowest(10) {
+            writeln!(
+                f,
+                "{:>12?} {:>5} subst {:>3} deep  {}",
+                file.duration,
+                file.substitutions,
+                file.max_depth,
+                file.path.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+/// Count substitutions and the deepest nesting in `source`, lexically.
+fn measure(source: &str) -> (usize, usize) {
+    let (mut substitutions, mut depth, mut max_depth) = (0, 0usize, 0);
+    // Closing characters of the substitutions currently open.
+    let mut closers = Vec::new();
+    let (mut single, mut double) = (false, false);
+    let mut word = String::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        if single {
+            single = c != '\'';
+            continue;
+        }
+        if !c.is_ascii_alphanumeric() && c != '_' && !word.is_empty() {
+            match word.as_str() {
+                "if" | "case" | "for" | "while" | "until" => depth += 1,
+                "fi" | "esac" | "done" => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            word.clear();
+        }
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' if !double => single = true,
+            '"' => double = !double,
+            '#' if !double && word.is_empty() && closers.is_empty() => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '$' => match chars.peek() {
+                Some('{') | Some('(') => {
+                    substitutions += 1;
+                    depth += 1;
+                    closers.push(if chars.next() == Some('{') { '}' } else { ')' });
+                }
+                Some(c) if is_name_start(*c) => substitutions += 1,
+                _ => (),
+            },
+            c if closers.last() == Some(&c) => {
+                closers.pop();
+                depth = depth.saturating_sub(1);
+            }
+            c if !double && (c.is_ascii_alphanumeric() || c == '_') => word.push(c),
+            _ => (),
+        }
+        max_depth = max_depth.max(depth);
+    }
+    (substitutions, max_depth)
+}
+
+/// `Filesystem`, remembering the last file read so that its evaluation can be
+/// attributed to it.
+struct Recorder {
+    last: RefCell<PathBuf>,
+    files: RefCell<Vec<FileMetrics>>,
+}
+
+impl PackageFiles for Recorder {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        *self.last.borrow_mut() = path.to_path_buf();
+        Filesystem.read(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        Filesystem.is_file(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        Filesystem.is_dir(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        Filesystem.read_dir(path)
+    }
+}
+
+impl Recorder {
+    fn parse(&self, source: &str, context: &mut Context) -> Result<(), ParseError> {
+        let start = Instant::now();
+        let result = apf::parse(source, context);
+        let duration = start.elapsed();
+        let (substitutions, max_depth) = measure(source);
+        self.files.borrow_mut().push(FileMetrics {
+            path: self.last.borrow().clone(),
+            duration,
+            substitutions,
+            max_depth,
+            errors: usize::from(result.is_err()),
+        });
+        result
+    }
+}
+
+fn load_with_metrics(dir: &Path) -> (Result<Package, PackageError>, Vec<FileMetrics>) {
+    let recorder = Recorder {
+        last: RefCell::new(PathBuf::new()),
+        files: RefCell::new(Vec::new()),
+    };
+    let result = Package::load(
+        dir,
+        &SpecInheritance::All,
+        &|_, source, context| recorder.parse(source, context),
+        &recorder,
+    );
+    (result, recorder.files.into_inner())
+}
+
+impl Tree {
+    /// Same as `scan`, also timing the evaluation of every file.
+    pub fn scan_with_metrics(&self) -> io::Result<(Scan, ParseMetrics)> {
+        let dirs = self.package_dirs()?;
+        let mut scan = Scan::default();
+        let mut metrics = ParseMetrics::default();
+        for (result, files) in load_parallel(&dirs, default_jobs(), load_with_metrics) {
+            match result {
+                Ok(p) => scan.packages.push(p),
+                Err(e) => scan.errors.push(e),
+            }
+            metrics.files.extend(files);
+        }
+
+        Ok((scan, metrics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_measure() {
+        assert_eq!(measure("VER=1.0\n"), (0, 0));
+        assert_eq!(
+            measure("A=\"$VER ${VER%.*}\" # $NOT\nB='$NOT' C=\\$NOT\n"),
+            (2, 1)
+        );
+        assert_eq!(
+            measure("if [ \"$ARCH\" = amd64 ]; then\n  A=\"${B/$(echo ${C})/x}\"\nfi\nD=$E\n"),
+            (5, 4)
+        );
+        assert_eq!(measure("A=${#VER}$B\n"), (2, 1));
+        // Keywords only count as whole words.
+        assert_eq!(measure("PKGDEP=\"iffy done-right\"\n"), (0, 0));
+    }
+
+    #[test]
+    fn test_scan_with_metrics() {
+        let root = tempfile::tempdir().unwrap();
+        for (name, spec) in [("foo", "VER=1.0\n"), ("broken", "VER=1.0 | cat\n")] {
+            let dir = root.path().join("app-utils").join(name);
+            fs::create_dir_all(dir.join("autobuild")).unwrap();
+            fs::write(dir.join("spec"), spec).unwrap();
+            fs::write(dir.join("autobuild/defines"), "PKGDES=\"Foo $VER\"\n").unwrap();
+        }
+
+        let (scan, metrics) = Tree::open(root.path()).scan_with_metrics().unwrap();
+        assert_eq!(scan.packages.len(), 1);
+        let files: Vec<_> = metrics
+            .files
+            .iter()
+            .map(|f| {
+                (
+                    f.path.strip_prefix(root.path()).unwrap(),
+                    f.substitutions,
+                    f.errors,
+                )
+            })
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                (Path::new("app-utils/broken/spec"), 0, 1),
+                (Path::new("app-utils/foo/spec"), 0, 0),
+                (Path::new("app-utils/foo/autobuild/defines"), 1, 0),
+            ]
+        );
+        assert_eq!(metrics.errors(), 1);
+        assert_eq!(metrics.slowest(2).len(), 2);
+        assert!(metrics.to_string().starts_with("3 files in "));
+    }
+}
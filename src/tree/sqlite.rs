@@ -0,0 +1,463 @@
+//! Export of tree metadata to SQLite, in the schema used by packages-site.
+//! Each binary package gets a row in `packages`, i.e: every sub-package of a
+//! group, along with its fields in `package_spec`, its relationships in
+//! `package_dependencies` and its sources in `package_sources`.
+//!
+//! Syncing again only rewrites the packages whose fields changed, and drops
+//! the packages no longer in the tree. Where the packages came from is kept in
+//! `export_metadata`, one row per `Provenance` field.
+
+use super::{default_jobs, Tree};
+use crate::{
+    apf::Context,
+    dependency::Dependency,
+    export::Provenance,
+    package::{split_arch_suffix, Package, PackageError, SubPackage},
+    srcs::parse_srcs,
+};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Tables are only created if missing, so an existing database of
+/// packages-site can be synced in place.
+pub const SQLITE_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS packages (
+    name TEXT PRIMARY KEY,
+    tree TEXT NOT NULL,
+    category TEXT NOT NULL,
+    section TEXT NOT NULL,
+    pkg_section TEXT NOT NULL,
+    directory TEXT NOT NULL,
+    description TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS package_versions (
+    package TEXT NOT NULL,
+    branch TEXT NOT NULL,
+    architecture TEXT NOT NULL,
+    version TEXT NOT NULL,
+    release TEXT NOT NULL,
+    epoch TEXT NOT NULL,
+    PRIMARY KEY (package, branch, architecture)
+);
+CREATE TABLE IF NOT EXISTS package_spec (
+    package TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (package, key)
+);
+CREATE TABLE IF NOT EXISTS package_dependencies (
+    package TEXT NOT NULL,
+    dependency TEXT NOT NULL,
+    version TEXT NOT NULL,
+    architecture TEXT NOT NULL,
+    relationship TEXT NOT NULL,
+    PRIMARY KEY (package, dependency, architecture, relationship)
+);
+CREATE TABLE IF NOT EXISTS package_sources (
+    package TEXT NOT NULL,
+    architecture TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (package, architecture, idx)
+);
+CREATE TABLE IF NOT EXISTS export_metadata (
+    tree TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (tree, key)
+);
+";
+
+/// Fields exported to `package_dependencies`, by relationship.
+const RELATIONSHIP_FIELDS: &[&str] = &[
+    "PKGDEP", "BUILDDEP", "PKGRECOM", "PKGSUG", "PKGBREAK", "PKGREP", "PKGPROV", "PKGCONFL",
+];
+
+/// Tables with rows keyed by the `package` column.
+const PACKAGE_TABLES: &[&str] = &[
+    "package_versions",
+    "package_spec",
+    "package_dependencies",
+    "package_sources",
+];
+
+#[derive(Debug)]
+pub enum SqliteError {
+    IOError(io::Error),
+    DatabaseError(rusqlite::Error),
+}
+
+impl fmt::Display for SqliteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqliteError::IOError(e) => write!(f, "Failed to read the tree: {}", e),
+            SqliteError::DatabaseError(e) => write!(f, "Failed to update the database: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SqliteError {}
+
+impl From<io::Error> for SqliteError {
+    fn from(e: io::Error) -> Self {
+        SqliteError::IOError(e)
+    }
+}
+
+impl From<rusqlite::Error> for SqliteError {
+    fn from(e: rusqlite::Error) -> Self {
+        SqliteError::DatabaseError(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SqliteOptions {
+    /// Value of the `tree` column. Syncing only removes packages of this tree.
+    pub tree: String,
+    /// Value of the `branch` column in `package_versions`.
+    pub branch: String,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        SqliteOptions {
+            tree: "aosc-os-abbs".to_string(),
+            branch: "stable".to_string(),
+        }
+    }
+}
+
+/// What `sync_sqlite` changed, counted in binary packages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// Row of the `packages` table.
+#[derive(Debug, PartialEq, Eq)]
+struct PackageRow {
+    category: String,
+    section: String,
+    pkg_section: String,
+    directory: String,
+    description: String,
+}
+
+/// A binary package: a plain package, or a sub-package of a group.
+struct Unit<'a> {
+    name: &'a str,
+    row: PackageRow,
+    fields: &'a Context,
+}
+
+fn units<'a>(package: &'a Package) -> Vec<Unit<'a>> {
+    let path = package.path();
+    let name_of = |p: Option<&Path>| {
+        p.and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let directory = name_of(path);
+    let section_dir = name_of(path.and_then(|p| p.parent()));
+    // `app-utils` is the `utils` section of the `app` category.
+    let (category, section) = match section_dir.split_once('-') {
+        Some((category, section)) => (category.to_string(), section.to_string()),
+        None => (String::new(), section_dir.clone()),
+    };
+    let unit = |name: &'a str, fields: &'a Context| {
+        let field = |key: &str| fields.get(key).cloned().unwrap_or_default();
+        Unit {
+            name,
+            row: PackageRow {
+                category: category.clone(),
+                section: section.clone(),
+                pkg_section: field("PKGSEC"),
+                directory: directory.clone(),
+                description: field("PKGDES"),
+            },
+            fields,
+        }
+    };
+
+    if package.subpackages().is_empty() {
+        vec![unit(package.name(), package.fields())]
+    } else {
+        package
+            .subpackages()
+            .iter()
+            .map(|sub: &'a SubPackage| unit(sub.name(), sub.fields()))
+            .collect()
+    }
+}
+
+fn stored_row(tx: &Transaction, name: &str) -> rusqlite::Result<Option<PackageRow>> {
+    tx.query_row(
+        "SELECT category, section, pkg_section, directory, description FROM packages WHERE name = ?1",
+        params![name],
+        |row| {
+            Ok(PackageRow {
+                category: row.get(0)?,
+                section: row.get(1)?,
+                pkg_section: row.get(2)?,
+                directory: row.get(3)?,
+                description: row.get(4)?,
+            })
+        },
+    )
+    .optional()
+}
+
+fn stored_spec(tx: &Transaction, name: &str) -> rusqlite::Result<BTreeMap<String, String>> {
+    let mut stmt = tx.prepare_cached("SELECT key, value FROM package_spec WHERE package = ?1")?;
+    let rows = stmt.query_map(params![name], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+fn delete_package(tx: &Transaction, name: &str) -> rusqlite::Result<()> {
+    for table in PACKAGE_TABLES {
+        tx.execute(&format!("DELETE FROM {} WHERE package = ?1", table), params![name])?;
+    }
+    tx.execute("DELETE FROM packages WHERE name = ?1", params![name])?;
+    Ok(())
+}
+
+fn insert_unit(tx: &Transaction, unit: &Unit, options: &SqliteOptions) -> rusqlite::Result<()> {
+    let row = &unit.row;
+    tx.execute(
+        "INSERT INTO packages (name, tree, category, section, pkg_section, directory, description)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            unit.name,
+            options.tree,
+            row.category,
+            row.section,
+            row.pkg_section,
+            row.directory,
+            row.description
+        ],
+    )?;
+
+    let field = |key: &str| unit.fields.get(key).map_or("", String::as_str);
+    tx.execute(
+        "INSERT INTO package_versions (package, branch, architecture, version, release, epoch)
+         VALUES (?1, ?2, '', ?3, ?4, ?5)",
+        params![unit.name, options.branch, field("VER"), field("REL"), field("PKGEPOCH")],
+    )?;
+
+    let mut spec = tx.prepare_cached("INSERT INTO package_spec (package, key, value) VALUES (?1, ?2, ?3)")?;
+    let mut dependency = tx.prepare_cached(
+        "INSERT OR IGNORE INTO package_dependencies (package, dependency, version, architecture, relationship)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    let mut source = tx.prepare_cached(
+        "INSERT INTO package_sources (package, architecture, idx, type, url) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for (key, value) in unit.fields {
+        spec.execute(params![unit.name, key, value])?;
+
+        let (field, arch) = match split_arch_suffix(key) {
+            Some((field, arch)) => (field, arch.to_ascii_lowercase()),
+            None => (key.as_str(), String::new()),
+        };
+        if RELATIONSHIP_FIELDS.contains(&field) {
+            // Broken entries are left out; the lints report them.
+            for dep in value.split_whitespace().filter_map(|e| e.parse::<Dependency>().ok()) {
+                let version = dep.version_req.map(|r| r.to_string()).unwrap_or_default();
+                dependency.execute(params![unit.name, dep.name, version, arch, field])?;
+            }
+        } else if field == "SRCS" {
+            for (idx, src) in parse_srcs(value).unwrap_or_default().iter().enumerate() {
+                source.execute(params![unit.name, arch, idx, src.type_name(), src.url()])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Bring the database on `conn` up to date with `packages`, i.e: from
+/// `Tree::scan_iter`, loaded from the tree described by `provenance`. Each
+/// package is written as it arrives, so the packages are never all held in
+/// memory. Packages of `options.tree` in the database but not in `packages`
+/// are removed, unless they failed to load this time.
+pub fn sync_sqlite<I>(
+    conn: &mut Connection,
+    packages: I,
+    provenance: &Provenance,
+    options: &SqliteOptions,
+) -> rusqlite::Result<SyncStats>
+where
+    I: IntoIterator<Item = (PathBuf, Result<Package, PackageError>)>,
+{
+    conn.execute_batch(SQLITE_SCHEMA)?;
+    let tx = conn.transaction()?;
+    let mut stats = SyncStats::default();
+    let mut seen = HashSet::new();
+    let mut kept = HashSet::new();
+    for (dir, result) in packages {
+        let package = match result {
+            Ok(p) => p,
+            Err(_) => {
+                if let Some(name) = dir.file_name() {
+                    kept.insert(name.to_string_lossy().into_owned());
+                }
+                continue;
+            }
+        };
+        for unit in units(&package) {
+            if !seen.insert(unit.name.to_string()) {
+                continue;
+            }
+            let fields: BTreeMap<_, _> = unit.fields.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+            match stored_row(&tx, unit.name)? {
+                Some(row) if row == unit.row && stored_spec(&tx, unit.name)? == fields => {
+                    stats.unchanged += 1;
+                    continue;
+                }
+                Some(_) => stats.updated += 1,
+                None => stats.added += 1,
+            }
+            delete_package(&tx, unit.name)?;
+            insert_unit(&tx, &unit, options)?;
+        }
+    }
+
+    let stale: Vec<String> = {
+        let mut stmt = tx.prepare("SELECT name, directory FROM packages WHERE tree = ?1")?;
+        let rows = stmt.query_map(params![options.tree], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut stale = Vec::new();
+        for row in rows {
+            let (name, directory) = row?;
+            if !seen.contains(&name) && !kept.contains(&directory) {
+                stale.push(name);
+            }
+        }
+        stale
+    };
+    for name in stale {
+        delete_package(&tx, &name)?;
+        stats.removed += 1;
+    }
+
+    for (key, value) in provenance.to_pairs() {
+        tx.execute(
+            "INSERT OR REPLACE INTO export_metadata (tree, key, value) VALUES (?1, ?2, ?3)",
+            params![options.tree, key, value],
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(stats)
+}
+
+impl Tree {
+    /// Load every package and sync them into the SQLite database at `db`,
+    /// creating it if needed, as they are loaded. See `sync_sqlite`.
+    pub fn sync_sqlite<P: AsRef<Path>>(
+        &self,
+        db: P,
+        options: &SqliteOptions,
+    ) -> Result<SyncStats, SqliteError> {
+        let provenance = Provenance::collect(self.root());
+        let scan = self.scan_iter(default_jobs())?;
+        let mut conn = Connection::open(db)?;
+        Ok(sync_sqlite(&mut conn, scan, &provenance, options)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::write_package;
+    use std::fs;
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_sync_sqlite() {
+        let root = tempfile::tempdir().unwrap();
+        let db = root.path().join("abbs.db");
+        let tree = Tree::open(root.path().join("tree"));
+        write_package(
+            tree.root(),
+            "app-utils",
+            "foo",
+            "VER=1.0\nSRCS=\"tbl::https://example.com/foo-$VER.tar.xz\"\n",
+            "PKGDES=\"Foo\"\nPKGSEC=utils\nPKGDEP=\"glibc>=2.31 bar\"\nPKGDEP__AMD64=\"libx\"\n",
+        );
+        write_package(tree.root(), "core-libs", "bar", "VER=2.0\n", "PKGDES=\"Bar\"\n");
+
+        let options = SqliteOptions::default();
+        let stats = tree.sync_sqlite(&db, &options).unwrap();
+        assert_eq!(stats, SyncStats { added: 2, ..Default::default() });
+
+        let conn = Connection::open(&db).unwrap();
+        let (category, section, description): (String, String, String) = conn
+            .query_row(
+                "SELECT category, section, description FROM packages WHERE name = 'foo'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((category.as_str(), section.as_str(), description.as_str()), ("app", "utils", "Foo"));
+        let mut stmt = conn
+            .prepare("SELECT dependency, version, architecture FROM package_dependencies ORDER BY dependency")
+            .unwrap();
+        let deps: Vec<(String, String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            deps,
+            vec![
+                ("bar".to_string(), String::new(), String::new()),
+                ("glibc".to_string(), ">=2.31".to_string(), String::new()),
+                ("libx".to_string(), String::new(), "amd64".to_string()),
+            ]
+        );
+        let url: String = conn
+            .query_row("SELECT url FROM package_sources WHERE package = 'foo'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(url, "https://example.com/foo-1.0.tar.xz");
+        let version: String = conn
+            .query_row(
+                "SELECT value FROM export_metadata WHERE tree = ?1 AND key = 'crate_version'",
+                params![options.tree],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM export_metadata"), 4);
+
+        // Only the changed package is rewritten, the removed one is dropped
+        // and the broken one is kept.
+        write_package(tree.root(), "app-utils", "foo", "VER=1.1\n", "PKGDES=\"Foo\"\n");
+        fs::remove_dir_all(tree.root().join("core-libs")).unwrap();
+        write_package(tree.root(), "core-libs", "baz", "VER=1\n", "PKGDES=\"Baz\"\n");
+        let stats = tree.sync_sqlite(&db, &options).unwrap();
+        assert_eq!(stats, SyncStats { added: 1, updated: 1, removed: 1, unchanged: 0 });
+        write_package(tree.root(), "core-libs", "baz", "VER=$(\n", "");
+        let stats = tree.sync_sqlite(&db, &options).unwrap();
+        assert_eq!(stats, SyncStats { unchanged: 1, ..Default::default() });
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM packages"), 2);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM export_metadata"), 4);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM package_dependencies"), 0);
+        assert_eq!(
+            count(&conn, "SELECT COUNT(*) FROM package_versions WHERE version = '1.1'"),
+            1
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Package counts of a tree, for dashboards.
+
+use crate::{apf::Context, autobuild::REQUIRED_FIELDS, package::Package, section::Section};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Counts of packages, sub-packages of groups counted one by one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TreeStats {
+    pub packages: usize,
+    /// By section directory, i.e: `app-utils`. Packages outside of a section,
+    /// i.e: not loaded from a tree, are not counted.
+    pub sections: BTreeMap<String, usize>,
+    /// By category, i.e: `app`.
+    pub categories: BTreeMap<String, usize>,
+    /// By `MAINTAINER`, as written.
+    pub maintainers: BTreeMap<String, usize>,
+    /// Packages without `MAINTAINER`.
+    pub unmaintained: usize,
+    /// By required field missing, i.e: `PKGDES`.
+    pub missing_fields: BTreeMap<String, usize>,
+}
+
+fn units(package: &Package) -> Vec<&Context> {
+    if package.subpackages().is_empty() {
+        vec![package.fields()]
+    } else {
+        package.subpackages().iter().map(|s| s.fields()).collect()
+    }
+}
+
+/// Count `packages` by section, maintainer and missing required fields.
+pub fn stats(packages: &[Package]) -> TreeStats {
+    let mut stats = TreeStats::default();
+    for package in packages {
+        let section = Section::of(package);
+        for fields in units(package) {
+            stats.packages += 1;
+            if let Some(section) = &section {
+                *stats.sections.entry(section.to_string()).or_default() += 1;
+                *stats
+                    .categories
+                    .entry(section.category.to_string())
+                    .or_default() += 1;
+            }
+            match fields.get("MAINTAINER").filter(|m| !m.is_empty()) {
+                Some(maintainer) => *stats.maintainers.entry(maintainer.clone()).or_default() += 1,
+                None => stats.unmaintained += 1,
+            }
+            for field in REQUIRED_FIELDS {
+                if fields.get(*field).is_none_or(|v| v.is_empty()) {
+                    *stats.missing_fields.entry(field.to_string()).or_default() += 1;
+                }
+            }
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::Tree;
+    use std::fs;
+
+    #[test]
+    fn test_stats() {
+        let root = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        let maintainer = "MAINTAINER=\"Foo <foo@example.com>\"\n";
+        write("app-utils/foo/spec", "VER=1\n");
+        write(
+            "app-utils/foo/autobuild/defines",
+            &format!("PKGNAME=foo\nPKGSEC=utils\nPKGDES=Foo\n{}", maintainer),
+        );
+        write("core-libs/bar/spec", &format!("VER=1\n{}", maintainer));
+        write(
+            "core-libs/bar/autobuild/01-libbar/defines",
+            "PKGNAME=libbar\n",
+        );
+        write(
+            "core-libs/bar/autobuild/02-bar-dev/defines",
+            "PKGNAME=bar-dev\nMAINTAINER=\"\"\n",
+        );
+
+        let scan = Tree::open(root.path()).scan().unwrap();
+        let stats = stats(&scan.packages);
+        assert_eq!(stats.packages, 3);
+        let sections: Vec<_> = stats
+            .sections
+            .iter()
+            .map(|(s, n)| (s.as_str(), *n))
+            .collect();
+        assert_eq!(sections, vec![("app-utils", 1), ("core-libs", 2)]);
+        assert_eq!(stats.categories["core"], 2);
+        assert_eq!(stats.maintainers["Foo <foo@example.com>"], 2);
+        assert_eq!(stats.unmaintained, 1);
+        assert_eq!(stats.missing_fields["PKGDES"], 2);
+        assert!(!stats.missing_fields.contains_key("PKGNAME"));
+    }
+}
@@ -0,0 +1,296 @@
+//! Async variant of `Tree::scan_iter`, for services running on tokio.
+//!
+//! Files are read with `tokio::fs`. Evaluating them still blocks, so each
+//! package is then evaluated in a blocking task of its own, from the files
+//! already in memory.
+
+use super::Tree;
+use crate::package::{parse_content, Package, PackageError, PackageFiles, SpecInheritance};
+use futures_core::Stream;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    vec,
+};
+use tokio::{
+    fs,
+    task::{self, JoinHandle},
+};
+
+/// Files of one package, read ahead so it can be evaluated without blocking
+/// on the filesystem.
+#[derive(Default)]
+struct PackageSnapshot {
+    files: HashMap<PathBuf, io::Result<String>>,
+    dirs: HashSet<PathBuf>,
+    entries: HashMap<PathBuf, io::Result<Vec<PathBuf>>>,
+}
+
+fn copy_error(e: &io::Error) -> io::Error {
+    io::Error::new(e.kind(), e.to_string())
+}
+
+impl PackageSnapshot {
+    /// Read the files `Package::load` needs from `dir`: the spec, and either
+    /// `autobuild/defines` or the defines of every sub-package.
+    async fn read(dir: &Path) -> Self {
+        let mut snapshot = PackageSnapshot::default();
+        snapshot.read_file(dir.join("spec")).await;
+        let autobuild = dir.join("autobuild");
+        if !is_dir(&autobuild).await {
+            return snapshot;
+        }
+        snapshot.dirs.insert(autobuild.clone());
+        let defines = autobuild.join("defines");
+        if is_dir(&defines).await {
+            snapshot.dirs.insert(defines);
+        } else {
+            snapshot.read_file(defines).await;
+        }
+
+        let entries = match list_dir(&autobuild).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                snapshot.entries.insert(autobuild, Err(e));
+                return snapshot;
+            }
+        };
+        for entry in entries.iter() {
+            let defines = entry.join("defines");
+            if fs::metadata(&defines).await.is_ok_and(|m| m.is_file()) {
+                snapshot.read_file(defines).await;
+            }
+        }
+        snapshot.entries.insert(autobuild, Ok(entries));
+
+        snapshot
+    }
+
+    async fn read_file(&mut self, path: PathBuf) {
+        let result = fs::read_to_string(&path).await;
+        self.files.insert(path, result);
+    }
+}
+
+async fn is_dir(path: &Path) -> bool {
+    fs::metadata(path).await.is_ok_and(|m| m.is_dir())
+}
+
+async fn list_dir(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    let mut dir = fs::read_dir(dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        entries.push(entry.path());
+    }
+    Ok(entries)
+}
+
+impl PackageFiles for PackageSnapshot {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        match self.files.get(path) {
+            Some(Ok(content)) => Ok(content.clone()),
+            Some(Err(e)) => Err(copy_error(e)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file")),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        match self.files.get(path) {
+            Some(Err(e)) => e.kind() != io::ErrorKind::NotFound,
+            Some(Ok(_)) => true,
+            None => false,
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.dirs.contains(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        match self.entries.get(path) {
+            Some(Ok(entries)) => Ok(entries.clone()),
+            Some(Err(e)) => Err(copy_error(e)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "No such directory")),
+        }
+    }
+}
+
+async fn load_package(dir: PathBuf) -> Result<Package, PackageError> {
+    let snapshot = PackageSnapshot::read(&dir).await;
+    let loading = dir.clone();
+    task::spawn_blocking(move || {
+        Package::load(&loading, &SpecInheritance::All, &parse_content, &snapshot)
+    })
+    .await
+    .unwrap_or_else(|e| Err(PackageError::IOError(dir, io::Error::other(e))))
+}
+
+/// Directories of `dir` which are not hidden, sorted.
+async fn sorted_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for path in list_dir(dir).await? {
+        let hidden = path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+        if !hidden && is_dir(&path).await {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Packages of a tree as they are loaded, from `Tree::stream_packages`.
+/// Dropping it stops loading the rest.
+pub struct ScanStream {
+    dirs: vec::IntoIter<PathBuf>,
+    jobs: usize,
+    loading: VecDeque<(PathBuf, JoinHandle<Result<Package, PackageError>>)>,
+}
+
+impl ScanStream {
+    /// Start loading packages until `jobs` of them are on their way.
+    fn start(&mut self) {
+        while self.loading.len() < self.jobs {
+            match self.dirs.next() {
+                Some(dir) => {
+                    let task = tokio::spawn(load_package(dir.clone()));
+                    self.loading.push_back((dir, task));
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl Stream for ScanStream {
+    type Item = (PathBuf, Result<Package, PackageError>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.start();
+        let result = match this.loading.front_mut() {
+            Some((_, task)) => match Pin::new(task).poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            },
+            None => return Poll::Ready(None),
+        };
+        let (dir, _) = this.loading.pop_front().expect("Package being loaded");
+        let result = result.unwrap_or_else(|e| Err(PackageError::IOError(dir.clone(), io::Error::other(e))));
+        // Keep loading while the caller handles this one.
+        this.start();
+
+        Poll::Ready(Some((dir, result)))
+    }
+}
+
+impl Drop for ScanStream {
+    fn drop(&mut self) {
+        for (_, task) in self.loading.iter() {
+            task.abort();
+        }
+    }
+}
+
+impl Tree {
+    /// Same as `scan_iter`, as a `Stream` on the current tokio runtime, which
+    /// it must be polled from. Files are read with `tokio::fs`, and at most
+    /// `jobs` packages are loaded at once, each evaluated in a blocking task
+    /// of its own. `TreeOptions::mmap` does not apply. Packages are yielded
+    /// in the order of `package_dirs`, i.e:
+    /// `while let Some((dir, result)) = stream.next().await`.
+    pub async fn stream_packages(&self, jobs: usize) -> io::Result<ScanStream> {
+        let mut dirs = Vec::new();
+        for section in sorted_dirs(self.root()).await? {
+            for dir in sorted_dirs(&section).await? {
+                if fs::metadata(dir.join("spec")).await.is_ok_and(|m| m.is_file()) {
+                    dirs.push(dir);
+                }
+            }
+        }
+        self.sort_dirs(&mut dirs);
+
+        Ok(ScanStream {
+            dirs: dirs.into_iter(),
+            jobs: jobs.max(1),
+            loading: VecDeque::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{write_file, write_package},
+        tree::{PackageOrder, TreeOptions},
+    };
+    use std::{fs::remove_file, future};
+
+    fn collect(tree: &Tree, jobs: usize) -> Vec<(PathBuf, Result<Package, PackageError>)> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut stream = tree.stream_packages(jobs).await.unwrap();
+            let mut result = Vec::new();
+            while let Some(item) = future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+                result.push(item);
+            }
+            result
+        })
+    }
+
+    #[test]
+    fn test_stream_packages() {
+        let root = tempfile::tempdir().unwrap();
+        let mut expected = Vec::new();
+        for i in 0..40 {
+            let name = format!("pkg{:02}", i);
+            write_package(root.path(), "section", &name, "VER=1\n", "PKGDES=\"$VER\"\n");
+            expected.push(name);
+        }
+
+        let names: Vec<_> = collect(&Tree::open(root.path()), 3)
+            .into_iter()
+            .map(|(_, p)| p.unwrap().name().to_string())
+            .collect();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_stream_same_as_scan() {
+        let root = tempfile::tempdir().unwrap();
+        write_package(root.path(), "core-libs", "foo", "VER=1.0\n", "PKGDES=\"Foo $VER\"\n");
+        write_package(root.path(), "app-utils", "foo", "VER=2.0\n", "");
+        write_package(root.path(), "app-utils", "broken", "VER=1.0 | cat\n", "");
+        let group = write_package(root.path(), "app-utils", "group", "VER=3\n", "");
+        remove_file(group.join("autobuild/defines")).unwrap();
+        write_file(&group, "autobuild/01-libgroup/defines", "PKGNAME=libgroup\n");
+        write_file(&group, "autobuild/02-group/defines", "PKGDES=\"$VER\"\n");
+        let missing = write_package(root.path(), "app-utils", "missing", "VER=1\n", "");
+        remove_file(missing.join("autobuild/defines")).unwrap();
+
+        let tree = Tree::open_with(root.path(), TreeOptions::default().order(PackageOrder::Name));
+        let streamed = collect(&tree, 2);
+        let scanned: Vec<_> = tree.scan_iter(2).unwrap().collect();
+        assert_eq!(streamed.len(), 5);
+        assert_eq!(streamed.len(), scanned.len());
+        for ((dir, streamed), (scanned_dir, scanned)) in streamed.into_iter().zip(scanned) {
+            assert_eq!(dir, scanned_dir);
+            match (streamed, scanned) {
+                (Ok(a), Ok(b)) => {
+                    assert_eq!(a.name(), b.name());
+                    assert_eq!(a.fingerprint(), b.fingerprint());
+                }
+                (Err(a), Err(b)) => assert_eq!(a.to_string(), b.to_string()),
+                (a, b) => panic!("{:?} != {:?}", a, b),
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Keeping the packages of a tree loaded while it changes on disk, i.e: for
+//! daemons answering queries about the tree without scanning it every time.
+
+use super::{default_jobs, load_package, Tree};
+use crate::package::{Package, PackageError};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+#[derive(Debug)]
+pub enum WatchError {
+    IOError(io::Error),
+    NotifyError(notify::Error),
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchError::IOError(e) => write!(f, "Failed to read the tree: {}", e),
+            WatchError::NotifyError(e) => write!(f, "Failed to watch the tree: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+impl From<io::Error> for WatchError {
+    fn from(e: io::Error) -> Self {
+        WatchError::IOError(e)
+    }
+}
+
+impl From<notify::Error> for WatchError {
+    fn from(e: notify::Error) -> Self {
+        WatchError::NotifyError(e)
+    }
+}
+
+/// A field of a package, or sub-package, changed by an update.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub package: String,
+    pub field: String,
+    /// `None` where the field is not set.
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// A change of the packages in a `TreeIndex`.
+#[derive(Debug)]
+pub enum IndexEvent {
+    /// A package appeared, or loads again after failing to.
+    Added { dir: PathBuf },
+    /// Sorted by package, then field.
+    Updated {
+        dir: PathBuf,
+        changes: Vec<FieldChange>,
+    },
+    /// The package, as it was before its directory or `spec` file was
+    /// removed.
+    Removed { dir: PathBuf, package: Package },
+    /// A package changed, but could not be loaded, i.e: while being edited.
+    /// The index keeps it as it was before.
+    Failed { dir: PathBuf, error: PackageError },
+}
+
+impl IndexEvent {
+    pub fn dir(&self) -> &Path {
+        match self {
+            IndexEvent::Added { dir }
+            | IndexEvent::Updated { dir, .. }
+            | IndexEvent::Removed { dir, .. }
+            | IndexEvent::Failed { dir, .. } => dir,
+        }
+    }
+}
+
+/// Fields which differ between two versions of a package. Sub-packages are
+/// compared by name.
+fn field_changes(old: &Package, new: &Package) -> Vec<FieldChange> {
+    // Package name -> field -> (old, new)
+    type Values<'a> = (Option<&'a str>, Option<&'a str>);
+    let mut fields: BTreeMap<&str, BTreeMap<&str, Values>> = BTreeMap::new();
+    for (is_new, package) in [(false, old), (true, new)] {
+        let mut units = vec![(package.name(), package.fields())];
+        units.extend(package.subpackages().iter().map(|s| (s.name(), s.fields())));
+        for (name, context) in units {
+            let unit = fields.entry(name).or_default();
+            for (key, value) in context.iter() {
+                let values = unit.entry(key).or_default();
+                if is_new {
+                    values.1 = Some(value);
+                } else {
+                    values.0 = Some(value);
+                }
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+    for (name, unit) in fields {
+        for (field, (old, new)) in unit {
+            if old != new {
+                result.push(FieldChange {
+                    package: name.to_string(),
+                    field: field.to_string(),
+                    old: old.map(|v| v.to_string()),
+                    new: new.map(|v| v.to_string()),
+                });
+            }
+        }
+    }
+    result
+}
+
+/// Packages of a tree by directory, updated from the files changed since
+/// they were loaded.
+#[derive(Debug, Clone)]
+pub struct TreeIndex {
+    tree: Tree,
+    packages: BTreeMap<PathBuf, Package>,
+}
+
+impl TreeIndex {
+    /// Load every package of `tree`. Packages which fail to load are left out
+    /// until a change makes them load.
+    pub fn new(tree: Tree) -> io::Result<Self> {
+        let packages = tree
+            .scan_iter(default_jobs())?
+            .filter_map(|(dir, package)| Some((dir, package.ok()?)))
+            .collect();
+        Ok(TreeIndex { tree, packages })
+    }
+
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    /// Package in `dir`, i.e: `TREE/SECTION/NAME`.
+    pub fn get<P: AsRef<Path>>(&self, dir: P) -> Option<&Package> {
+        self.packages.get(dir.as_ref())
+    }
+
+    /// Packages, sorted by directory.
+    pub fn packages(&self) -> impl Iterator<Item = (&Path, &Package)> {
+        self.packages.iter().map(|(dir, p)| (dir.as_path(), p))
+    }
+
+    pub fn len(&self) -> usize {
+        self.packages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty()
+    }
+
+    /// Reload the packages containing `paths`, files or directories which
+    /// changed, i.e: `index.apply(["TREE/core/bash/spec"])`. A section
+    /// reloads every package in it. Paths outside the tree, or in hidden
+    /// directories such as `.git`, are ignored.
+    ///
+    /// Sorted by directory.
+    pub fn apply<I, P>(&mut self, paths: I) -> Vec<IndexEvent>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut dirs = BTreeSet::new();
+        for path in paths {
+            self.affected_dirs(path.as_ref(), &mut dirs);
+        }
+        dirs.into_iter()
+            .filter_map(|dir| self.reload(dir))
+            .collect()
+    }
+
+    /// Package directories which `path` is in, or which are in `path`.
+    fn affected_dirs(&self, path: &Path, dirs: &mut BTreeSet<PathBuf>) {
+        let root = self.tree.root();
+        // Events name files through the path the tree was opened with, or
+        // the canonical one, depending on the platform.
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => match fs::canonicalize(root) {
+                Ok(canonical) => match path.strip_prefix(&canonical) {
+                    Ok(relative) => relative,
+                    Err(_) => return,
+                },
+                Err(_) => return,
+            },
+        };
+        let mut components = relative.components().map(|c| match c {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        });
+        let section = match components.next().flatten() {
+            Some(section) if !section.to_string_lossy().starts_with('.') => root.join(section),
+            _ => return,
+        };
+        match components.next() {
+            Some(Some(name)) => {
+                dirs.insert(section.join(name));
+            }
+            Some(None) => (),
+            None => {
+                dirs.extend(
+                    self.packages
+                        .keys()
+                        .filter(|dir| dir.parent() == Some(section.as_path()))
+                        .cloned(),
+                );
+                if let Ok(entries) = fs::read_dir(&section) {
+                    dirs.extend(entries.filter_map(|e| Some(e.ok()?.path())));
+                }
+            }
+        }
+    }
+
+    fn reload(&mut self, dir: PathBuf) -> Option<IndexEvent> {
+        if !dir.join("spec").is_file() {
+            let package = self.packages.remove(&dir)?;
+            return Some(IndexEvent::Removed { dir, package });
+        }
+        let package = match load_package(&dir, self.tree.options()) {
+            Ok(package) => package,
+            Err(error) => return Some(IndexEvent::Failed { dir, error }),
+        };
+        match self.packages.insert(dir.clone(), package) {
+            None => Some(IndexEvent::Added { dir }),
+            Some(old) => {
+                let changes = field_changes(&old, &self.packages[&dir]);
+                if changes.is_empty() {
+                    None
+                } else {
+                    Some(IndexEvent::Updated { dir, changes })
+                }
+            }
+        }
+    }
+}
+
+/// A `TreeIndex` kept up to date by watching the files of the tree, i.e:
+/// `loop { for event in watcher.next_events(None)? { ... } }`.
+pub struct TreeWatcher {
+    index: TreeIndex,
+    receiver: mpsc::Receiver<notify::Result<notify::Event>>,
+    // Stops watching once dropped.
+    _watcher: RecommendedWatcher,
+}
+
+impl TreeWatcher {
+    /// Start watching `tree`, then load it.
+    pub fn new(tree: Tree) -> Result<Self, WatchError> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(tree.root(), RecursiveMode::Recursive)?;
+        Ok(TreeWatcher {
+            index: TreeIndex::new(tree)?,
+            receiver,
+            _watcher: watcher,
+        })
+    }
+
+    pub fn index(&self) -> &TreeIndex {
+        &self.index
+    }
+
+    /// Wait for files to change, up to `timeout` if any, then apply every
+    /// change received so far to the index. Empty if nothing changed in
+    /// time, or the changes left the packages as they were.
+    pub fn next_events(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<IndexEvent>, WatchError> {
+        let first = match timeout {
+            Some(timeout) => match self.receiver.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => return Ok(Vec::new()),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(stopped()),
+            },
+            None => self.receiver.recv().map_err(|_| stopped())?,
+        };
+        let mut paths = Vec::new();
+        for event in std::iter::once(first).chain(self.receiver.try_iter()) {
+            let event = event?;
+            // Loading packages reads their files, which must not trigger a
+            // reload in turn.
+            if !matches!(event.kind, EventKind::Access(_)) {
+                paths.extend(event.paths);
+            }
+        }
+
+        Ok(self.index.apply(paths))
+    }
+}
+
+fn stopped() -> WatchError {
+    WatchError::NotifyError(notify::Error::generic("Watcher stopped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::write_package;
+
+    #[test]
+    fn test_apply() {
+        let root = tempfile::tempdir().unwrap();
+        let bash = write_package(root.path(), "core", "bash", "VER=1\n", "PKGDES=foo\n");
+        let curl = write_package(root.path(), "net", "curl", "VER=1\n", "PKGDES=foo\n");
+        let mut index = TreeIndex::new(Tree::open(root.path())).unwrap();
+        assert_eq!(index.len(), 2);
+
+        fs::write(bash.join("spec"), "VER=2\nREL=1\n").unwrap();
+        let events = index.apply([bash.join("spec"), root.path().join(".git/index")]);
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            IndexEvent::Updated { dir, changes } => {
+                assert_eq!(dir, &bash);
+                let changes: Vec<_> = changes
+                    .iter()
+                    .map(|c| (c.field.as_str(), c.old.as_deref(), c.new.as_deref()))
+                    .collect();
+                assert_eq!(
+                    changes,
+                    vec![("REL", None, Some("1")), ("VER", Some("1"), Some("2"))]
+                );
+            }
+            event => panic!("Unexpected event: {:?}", event),
+        }
+        assert_eq!(index.get(&bash).unwrap().fields()["VER"], "2");
+        // Nothing changed since.
+        assert!(index.apply([&bash]).is_empty());
+
+        fs::write(bash.join("spec"), "VER=\"\n").unwrap();
+        let events = index.apply([bash.join("spec")]);
+        assert!(matches!(events[0], IndexEvent::Failed { .. }));
+        assert_eq!(index.get(&bash).unwrap().fields()["VER"], "2");
+
+        let zlib = write_package(root.path(), "net", "zlib", "VER=1\n", "PKGDES=foo\n");
+        fs::remove_dir_all(&curl).unwrap();
+        let events = index.apply([root.path().join("net")]);
+        let events: Vec<_> = events
+            .iter()
+            .map(|e| match e {
+                IndexEvent::Added { dir } => ("added", dir.clone()),
+                IndexEvent::Removed { dir, package } => {
+                    assert_eq!(package.name(), "curl");
+                    ("removed", dir.clone())
+                }
+                event => panic!("Unexpected event: {:?}", event),
+            })
+            .collect();
+        assert_eq!(events, vec![("removed", curl), ("added", zlib)]);
+        assert_eq!(index.len(), 2);
+    }
+}
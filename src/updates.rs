@@ -0,0 +1,596 @@
+//! Checking packages against their latest upstream release.
+//! An `UpdateChecker` asks each registered `UpstreamProvider` in turn for the
+//! latest version of a package, and reports it if it sorts higher than `VER`.
+//!
+//! With the `http` feature, `UpdateChecker::with_builtin` registers providers
+//! guessing the upstream project from the source URLs: GitHub releases, PyPI,
+//! the directory listing the tarball was downloaded from, and finally
+//! release-monitoring.org (anitya) by package name.
+
+use crate::{apf::Context, package::Package, srcs::get_rendered_urls, version::Version};
+use regex::Regex;
+#[cfg(feature = "http")]
+use serde_json::Value;
+use std::{fmt, sync::OnceLock};
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateError {
+    /// The request to the URL failed, and why.
+    RequestFailed(String, String),
+    /// The URL answered with something unexpected.
+    BadResponse(String),
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::RequestFailed(url, e) => write!(f, "Request to {} failed: {}", url, e),
+            UpdateError::BadResponse(url) => write!(f, "Unexpected response from {}.", url),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
+/// What a provider knows about a package.
+#[derive(Debug, Clone)]
+pub struct UpstreamQuery<'a> {
+    pub name: &'a str,
+    pub fields: &'a Context,
+    /// Source URLs with variables substituted.
+    pub urls: Vec<String>,
+}
+
+impl UpstreamQuery<'_> {
+    pub fn version(&self) -> Option<&str> {
+        self.fields.get("VER").map(|v| v.as_str())
+    }
+}
+
+pub trait UpstreamProvider {
+    /// Name for reports, i.e: `github`.
+    fn name(&self) -> &str;
+
+    /// Latest upstream version, or `None` if the provider does not know the
+    /// package.
+    fn latest_version(&self, query: &UpstreamQuery) -> Result<Option<String>, UpdateError>;
+}
+
+/// A package with a newer upstream version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateReport {
+    pub name: String,
+    pub current: String,
+    pub latest: String,
+    /// Name of the provider that found `latest`.
+    pub provider: String,
+}
+
+impl fmt::Display for UpdateReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {} ({})", self.name, self.current, self.latest, self.provider)
+    }
+}
+
+/// Pre-releases are not offered as updates. Letters right after a number
+/// are only a pre-release when followed by another number, i.e: `2.0b1` but
+/// not `1.1.1a`.
+fn is_prerelease(version: &str) -> bool {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?i)alpha|beta|pre|dev|rc|\d[ab]\d").expect("Bad pre-release pattern")
+    })
+    .is_match(version)
+}
+
+/// `value` with everything but unreserved characters percent-encoded, for use
+/// in a query string, i.e: `gtk+` is `gtk%2B`.
+#[cfg(feature = "http")]
+fn encode_query(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                result.push(b as char)
+            }
+            _ => result.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    result
+}
+
+/// Version in a release tag, i.e: `v1.2`, `foo-1.2` or `release-1.2`.
+pub fn version_from_tag(tag: &str, name: &str) -> Option<String> {
+    let mut version = tag;
+    for prefix in [name, "release", "version", "ver"] {
+        if let Some(rest) = version.strip_prefix(prefix) {
+            version = rest.trim_start_matches(['-', '_', '.']);
+        }
+    }
+    version = version.trim_start_matches(['v', 'V']);
+    if version.starts_with(|c: char| c.is_ascii_digit()) && version.parse::<Version>().is_ok() {
+        Some(version.to_string())
+    } else {
+        None
+    }
+}
+
+/// The highest of `candidates` that is not a pre-release.
+pub fn latest_stable<I: IntoIterator<Item = String>>(candidates: I) -> Option<String> {
+    candidates
+        .into_iter()
+        .filter(|v| !is_prerelease(v))
+        .filter_map(|v| v.parse::<Version>().ok().map(|parsed| (parsed, v)))
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, v)| v)
+}
+
+#[derive(Default)]
+pub struct UpdateChecker {
+    providers: Vec<Box<dyn UpstreamProvider>>,
+}
+
+impl UpdateChecker {
+    /// A checker without providers.
+    pub fn new() -> Self {
+        UpdateChecker::default()
+    }
+
+    /// A checker with the built-in providers, in order of precision.
+    #[cfg(feature = "http")]
+    pub fn with_builtin() -> Self {
+        let mut checker = UpdateChecker::new();
+        checker.register(GitHubReleases::new(HttpClient::default()));
+        checker.register(PyPI::new(HttpClient::default()));
+        checker.register(DirectoryListing::new(HttpClient::default()));
+        checker.register(Anitya::new(HttpClient::default()));
+        checker
+    }
+
+    /// Add a provider, asked after the ones already registered.
+    pub fn register<P: UpstreamProvider + 'static>(&mut self, provider: P) {
+        self.providers.push(Box::new(provider));
+    }
+
+    /// Compare the latest upstream version of `package`, from the first
+    /// provider knowing it, against its `VER`. Returns `None` if the package is
+    /// up to date, has no `VER`, or no provider knows it.
+    pub fn check(&self, package: &Package) -> Result<Option<UpdateReport>, UpdateError> {
+        let fields = package.fields();
+        let current = match fields.get("VER").and_then(|v| v.parse::<Version>().ok()) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let query = UpstreamQuery {
+            name: package.name(),
+            fields,
+            urls: get_rendered_urls(fields).unwrap_or_default(),
+        };
+        for provider in self.providers.iter() {
+            let latest = match provider.latest_version(&query)? {
+                Some(latest) => latest,
+                None => continue,
+            };
+            let newer = latest.parse::<Version>().is_ok_and(|v| v > current);
+            return Ok(newer.then(|| UpdateReport {
+                name: package.name().to_string(),
+                current: fields["VER"].clone(),
+                latest,
+                provider: provider.name().to_string(),
+            }));
+        }
+
+        Ok(None)
+    }
+
+    /// Check every package. Errors do not stop the run; they are returned
+    /// along with the name of the package.
+    pub fn check_all(&self, packages: &[Package]) -> (Vec<UpdateReport>, Vec<(String, UpdateError)>) {
+        let mut reports = Vec::new();
+        let mut errors = Vec::new();
+        for package in packages {
+            match self.check(package) {
+                Ok(Some(report)) => reports.push(report),
+                Ok(None) => (),
+                Err(e) => errors.push((package.name().to_string(), e)),
+            }
+        }
+        (reports, errors)
+    }
+}
+
+/// How built-in providers fetch documents.
+#[cfg(feature = "http")]
+pub trait HttpGet {
+    /// Body of `url`, or `None` if it does not exist.
+    fn get(&self, url: &str) -> Result<Option<String>, UpdateError>;
+}
+
+#[cfg(feature = "http")]
+pub struct HttpClient {
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "http")]
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .user_agent(concat!("abbs-rs/", env!("CARGO_PKG_VERSION")))
+                .build(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl HttpGet for HttpClient {
+    fn get(&self, url: &str) -> Result<Option<String>, UpdateError> {
+        match self.agent.get(url).call() {
+            Ok(response) => response
+                .into_string()
+                .map(Some)
+                .map_err(|e| UpdateError::RequestFailed(url.to_string(), e.to_string())),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(UpdateError::RequestFailed(url.to_string(), e.to_string())),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+fn get_json<C: HttpGet>(client: &C, url: &str) -> Result<Option<Value>, UpdateError> {
+    match client.get(url)? {
+        Some(body) => serde_json::from_str(&body)
+            .map(Some)
+            .map_err(|_| UpdateError::BadResponse(url.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// `OWNER/REPO` of a GitHub URL, i.e: `https://github.com/OWNER/REPO/archive/v1.0.tar.gz`.
+pub fn github_project(url: &str) -> Option<(&str, &str)> {
+    let rest = url
+        .strip_prefix("https://github.com/")
+        .or_else(|| url.strip_prefix("https://codeload.github.com/"))?;
+    let mut parts = rest.split('/');
+    let owner = parts.next().filter(|s| !s.is_empty())?;
+    let repo = parts.next().filter(|s| !s.is_empty())?;
+    Some((owner, repo.trim_end_matches(".git")))
+}
+
+/// Project name of a PyPI source URL, i.e:
+/// `https://files.pythonhosted.org/packages/source/r/requests/requests-2.0.tar.gz`.
+pub fn pypi_project(url: &str) -> Option<&str> {
+    let hosts = [
+        "https://files.pythonhosted.org/packages/source/",
+        "https://pypi.io/packages/source/",
+        "https://pypi.python.org/packages/source/",
+    ];
+    let rest = hosts.iter().find_map(|h| url.strip_prefix(h))?;
+    rest.split('/').nth(1).filter(|s| !s.is_empty())
+}
+
+/// Latest GitHub release, falling back to tags for projects without releases.
+#[cfg(feature = "http")]
+pub struct GitHubReleases<C> {
+    client: C,
+}
+
+#[cfg(feature = "http")]
+impl<C: HttpGet> GitHubReleases<C> {
+    pub fn new(client: C) -> Self {
+        GitHubReleases { client }
+    }
+}
+
+#[cfg(feature = "http")]
+impl<C: HttpGet> UpstreamProvider for GitHubReleases<C> {
+    fn name(&self) -> &str {
+        "github"
+    }
+
+    fn latest_version(&self, query: &UpstreamQuery) -> Result<Option<String>, UpdateError> {
+        let (owner, repo) = match query.urls.iter().find_map(|u| github_project(u)) {
+            Some(project) => project,
+            None => return Ok(None),
+        };
+        for (kind, key) in [("releases", "tag_name"), ("tags", "name")] {
+            let url = format!("https://api.github.com/repos/{}/{}/{}", owner, repo, kind);
+            let entries = match get_json(&self.client, &url)? {
+                Some(Value::Array(entries)) => entries,
+                Some(_) => return Err(UpdateError::BadResponse(url)),
+                None => return Ok(None),
+            };
+            let tags = entries
+                .iter()
+                .filter(|e| e["draft"] != true && e["prerelease"] != true)
+                .filter_map(|e| e[key].as_str())
+                .filter_map(|tag| version_from_tag(tag, repo));
+            if let Some(latest) = latest_stable(tags) {
+                return Ok(Some(latest));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Latest release on PyPI.
+#[cfg(feature = "http")]
+pub struct PyPI<C> {
+    client: C,
+}
+
+#[cfg(feature = "http")]
+impl<C: HttpGet> PyPI<C> {
+    pub fn new(client: C) -> Self {
+        PyPI { client }
+    }
+}
+
+#[cfg(feature = "http")]
+impl<C: HttpGet> UpstreamProvider for PyPI<C> {
+    fn name(&self) -> &str {
+        "pypi"
+    }
+
+    fn latest_version(&self, query: &UpstreamQuery) -> Result<Option<String>, UpdateError> {
+        let project = match query.urls.iter().find_map(|u| pypi_project(u)) {
+            Some(project) => project,
+            None => return Ok(None),
+        };
+        let url = format!("https://pypi.org/pypi/{}/json", project);
+        let json = match get_json(&self.client, &url)? {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        let releases = match json["releases"].as_object() {
+            Some(releases) => releases,
+            None => return Err(UpdateError::BadResponse(url)),
+        };
+        Ok(latest_stable(releases.keys().cloned()))
+    }
+}
+
+/// Latest tarball in the directory listing next to the current one, i.e:
+/// `foo-1.3.tar.xz` in `https://example.com/pub/foo/` for
+/// `https://example.com/pub/foo/foo-1.2.tar.xz`.
+#[cfg(feature = "http")]
+pub struct DirectoryListing<C> {
+    client: C,
+}
+
+#[cfg(feature = "http")]
+impl<C: HttpGet> DirectoryListing<C> {
+    pub fn new(client: C) -> Self {
+        DirectoryListing { client }
+    }
+}
+
+#[cfg(feature = "http")]
+impl<C: HttpGet> UpstreamProvider for DirectoryListing<C> {
+    fn name(&self) -> &str {
+        "listing"
+    }
+
+    fn latest_version(&self, query: &UpstreamQuery) -> Result<Option<String>, UpdateError> {
+        let version = match query.version() {
+            Some(v) if !v.is_empty() => v,
+            _ => return Ok(None),
+        };
+        // The first tarball named after the version.
+        let found = query.urls.iter().find_map(|url| {
+            let (dir, file) = url.rsplit_once('/')?;
+            let start = file.find(version)?;
+            (url.starts_with("http") && file.contains(".tar")).then(|| (dir, &file[..start]))
+        });
+        let (dir, prefix) = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let url = format!("{}/", dir);
+        let listing = match self.client.get(&url)? {
+            Some(listing) => listing,
+            None => return Ok(None),
+        };
+        let re = Regex::new(&format!(
+            r"{}([0-9][0-9A-Za-z.+~]*?)\.(?:tar|tgz|tbz|txz|zip)",
+            regex::escape(prefix)
+        ))
+        .expect("Bad listing pattern");
+        Ok(latest_stable(re.captures_iter(&listing).map(|c| c[1].to_string())))
+    }
+}
+
+/// Latest version known to release-monitoring.org, by package name.
+#[cfg(feature = "http")]
+pub struct Anitya<C> {
+    client: C,
+}
+
+#[cfg(feature = "http")]
+impl<C: HttpGet> Anitya<C> {
+    pub fn new(client: C) -> Self {
+        Anitya { client }
+    }
+}
+
+#[cfg(feature = "http")]
+impl<C: HttpGet> UpstreamProvider for Anitya<C> {
+    fn name(&self) -> &str {
+        "anitya"
+    }
+
+    fn latest_version(&self, query: &UpstreamQuery) -> Result<Option<String>, UpdateError> {
+        let url = format!(
+            "https://release-monitoring.org/api/v2/projects/?name={}",
+            encode_query(query.name)
+        );
+        let json = match get_json(&self.client, &url)? {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        let items = match json["items"].as_array() {
+            Some(items) => items,
+            None => return Err(UpdateError::BadResponse(url)),
+        };
+        // Several ecosystems may have a project of that name; only trust a
+        // single match.
+        match items.as_slice() {
+            [item] => Ok(item["stable_versions"]
+                .as_array()
+                .and_then(|v| v.first())
+                .or(item.get("version"))
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string())),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, ver: &str, srcs: &str) -> Package {
+        let mut fields = Context::new();
+        fields.insert("VER".to_string(), ver.to_string());
+        fields.insert("SRCS".to_string(), srcs.to_string());
+        Package::new(name, fields)
+    }
+
+    struct Fixed(&'static str);
+
+    impl UpstreamProvider for Fixed {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        fn latest_version(&self, query: &UpstreamQuery) -> Result<Option<String>, UpdateError> {
+            match query.name {
+                "broken" => Err(UpdateError::BadResponse("https://example.com".to_string())),
+                "unknown" => Ok(None),
+                _ => Ok(Some(self.0.to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let mut checker = UpdateChecker::new();
+        checker.register(Fixed("1.10"));
+        let packages = [
+            package("old", "1.9", ""),
+            package("new", "1.10", ""),
+            package("unknown", "1.0", ""),
+            package("broken", "1.0", ""),
+        ];
+        let (reports, errors) = checker.check_all(&packages);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].to_string(), "old: 1.9 -> 1.10 (fixed)");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "broken");
+    }
+
+    #[test]
+    fn test_heuristics() {
+        assert_eq!(version_from_tag("v1.2.3", "foo").as_deref(), Some("1.2.3"));
+        assert_eq!(version_from_tag("foo-1.2", "foo").as_deref(), Some("1.2"));
+        assert_eq!(version_from_tag("release_2.0", "foo").as_deref(), Some("2.0"));
+        assert_eq!(version_from_tag("nightly", "foo"), None);
+        assert_eq!(
+            latest_stable(vec!["1.9".to_string(), "1.10".to_string(), "2.0rc1".to_string()]).as_deref(),
+            Some("1.10")
+        );
+        assert_eq!(
+            latest_stable(vec!["1.1.1".to_string(), "1.1.1a".to_string(), "1.2a1".to_string()]).as_deref(),
+            Some("1.1.1a")
+        );
+
+        assert_eq!(
+            github_project("https://github.com/owner/repo/archive/v1.0.tar.gz"),
+            Some(("owner", "repo"))
+        );
+        assert_eq!(github_project("https://github.com/owner/repo.git"), Some(("owner", "repo")));
+        assert_eq!(github_project("https://example.com/owner/repo"), None);
+        assert_eq!(
+            pypi_project("https://files.pythonhosted.org/packages/source/r/requests/requests-2.0.tar.gz"),
+            Some("requests")
+        );
+    }
+
+    #[cfg(feature = "http")]
+    struct FakeClient(Vec<(&'static str, &'static str)>);
+
+    #[cfg(feature = "http")]
+    impl HttpGet for FakeClient {
+        fn get(&self, url: &str) -> Result<Option<String>, UpdateError> {
+            Ok(self.0.iter().find(|(u, _)| *u == url).map(|(_, body)| body.to_string()))
+        }
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_providers() {
+        let check = |provider: &dyn UpstreamProvider, srcs: &str| {
+            let package = package("foo", "1.0", srcs);
+            let query = UpstreamQuery {
+                name: "foo",
+                fields: package.fields(),
+                urls: get_rendered_urls(package.fields()).unwrap(),
+            };
+            provider.latest_version(&query).unwrap()
+        };
+
+        let github = GitHubReleases::new(FakeClient(vec![
+            (
+                "https://api.github.com/repos/owner/foo/releases",
+                r#"[{"tag_name": "v2.0-rc1", "prerelease": true}, {"tag_name": "v1.1"}]"#,
+            ),
+            ("https://api.github.com/repos/owner/bar/releases", "[]"),
+            ("https://api.github.com/repos/owner/bar/tags", r#"[{"name": "bar-0.9"}]"#),
+        ]));
+        assert_eq!(check(&github, "tbl::https://github.com/owner/foo/archive/v$VER.tar.gz").as_deref(), Some("1.1"));
+        assert_eq!(check(&github, "git::https://github.com/owner/bar").as_deref(), Some("0.9"));
+        assert_eq!(check(&github, "tbl::https://example.com/foo.tar.gz"), None);
+
+        let pypi = PyPI::new(FakeClient(vec![(
+            "https://pypi.org/pypi/foo/json",
+            r#"{"releases": {"1.0": [], "1.2": [], "2.0b1": []}}"#,
+        )]));
+        assert_eq!(
+            check(&pypi, "tbl::https://files.pythonhosted.org/packages/source/f/foo/foo-$VER.tar.gz").as_deref(),
+            Some("1.2")
+        );
+
+        let listing = DirectoryListing::new(FakeClient(vec![(
+            "https://example.com/pub/foo/",
+            r#"<a href="foo-1.0.tar.xz">foo-1.0.tar.xz</a> <a href="foo-1.10.tar.xz">
+               <a href="foo-1.9.tar.gz"> <a href="foobar-3.0.tar.xz"> <a href="foo-2.0rc1.tar.xz">"#,
+        )]));
+        assert_eq!(
+            check(&listing, "tbl::https://example.com/pub/foo/foo-$VER.tar.xz").as_deref(),
+            Some("1.10")
+        );
+
+        let anitya = Anitya::new(FakeClient(vec![(
+            "https://release-monitoring.org/api/v2/projects/?name=foo",
+            r#"{"items": [{"version": "1.3rc1", "stable_versions": ["1.2", "1.1"]}]}"#,
+        )]));
+        assert_eq!(check(&anitya, "").as_deref(), Some("1.2"));
+
+        let anitya = Anitya::new(FakeClient(vec![(
+            "https://release-monitoring.org/api/v2/projects/?name=gtk%2B%20x",
+            r#"{"items": [{"version": "3.24"}]}"#,
+        )]));
+        let package = package("gtk+ x", "3.0", "");
+        let query = UpstreamQuery {
+            name: "gtk+ x",
+            fields: package.fields(),
+            urls: Vec::new(),
+        };
+        assert_eq!(anitya.latest_version(&query).unwrap().as_deref(), Some("3.24"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Value validators for known keys.
+//! The default registry checks the usual suspects (`VER`, `REL`, `PKGSEC`,
+//! `MAINTAINER`); downstream crates may register their own checks on top.
+
+use crate::{apf::Context, package::split_arch_suffix};
+use regex::Regex;
+use std::{collections::HashMap, fmt};
+
+/// A validator returns the reason of rejection on failure.
+pub type Validator = Box<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// Sections accepted in `PKGSEC`.
+pub const KNOWN_SECTIONS: &[&str] = &[
+    "admin", "cli-mono", "comm", "database", "debug", "devel", "doc", "editors", "education",
+    "electronics", "embedded", "fonts", "games", "gnome", "gnu-r", "gnustep", "graphics",
+    "hamradio", "haskell", "httpd", "interpreters", "introspection", "java", "javascript", "kde",
+    "kernel", "libdevel", "libs", "lisp", "localization", "mail", "math", "metapackages", "misc",
+    "net", "news", "ocaml", "oldlibs", "otherosfs", "perl", "php", "python", "ruby", "rust",
+    "science", "shells", "sound", "tasks", "tex", "text", "utils", "vcs", "video", "web", "x11",
+    "xfce", "zope",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub key: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {} `{}`: {}", self.key, self.value, self.reason)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+fn regex_validator(pattern: &str, reason: &'static str) -> Validator {
+    let re = Regex::new(pattern).expect("Bad built-in validator pattern");
+    Box::new(move |value| {
+        if re.is_match(value) {
+            Ok(())
+        } else {
+            Err(reason.to_string())
+        }
+    })
+}
+
+pub struct ValidatorRegistry {
+    validators: HashMap<String, Vec<Validator>>,
+}
+
+impl Default for ValidatorRegistry {
+    /// Registry with the built-in validators.
+    fn default() -> Self {
+        let mut registry = ValidatorRegistry::empty();
+        registry.register_boxed(
+            "VER",
+            regex_validator(
+                r"^[0-9][A-Za-z0-9.+~]*$",
+                "must start with a digit and contain only alphanumerics and `.+~`",
+            ),
+        );
+        registry.register_boxed("REL", regex_validator(r"^[0-9]+$", "must be a number"));
+        registry.register("PKGSEC", |value| {
+            if KNOWN_SECTIONS.contains(&value) {
+                Ok(())
+            } else {
+                Err("unknown section".to_string())
+            }
+        });
+        registry.register_boxed(
+            "MAINTAINER",
+            regex_validator(
+                r"^[^<>]+ <[^<>@\s]+@[^<>@\s]+\.[^<>@\s]+>$",
+                "must be in the form of `Name <user@example.com>`",
+            ),
+        );
+        registry
+    }
+}
+
+impl ValidatorRegistry {
+    /// Registry without any validator.
+    pub fn empty() -> Self {
+        ValidatorRegistry {
+            validators: HashMap::new(),
+        }
+    }
+
+    /// Add a validator for `key`. Validators of the same key run in the order
+    /// of registration.
+    pub fn register<F>(&mut self, key: &str, validator: F)
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.register_boxed(key, Box::new(validator));
+    }
+
+    pub fn register_boxed(&mut self, key: &str, validator: Validator) {
+        self.validators
+            .entry(key.to_string())
+            .or_default()
+            .push(validator);
+    }
+
+    /// Remove all validators of `key`.
+    pub fn unregister(&mut self, key: &str) {
+        self.validators.remove(key);
+    }
+
+    pub fn is_known(&self, key: &str) -> bool {
+        self.validators.contains_key(key)
+    }
+
+    /// Check a single value. Keys without validators always pass.
+    pub fn validate(&self, key: &str, value: &str) -> Result<(), ValidationError> {
+        for validator in self.validators.get(key).into_iter().flatten() {
+            validator(value).map_err(|reason| ValidationError {
+                key: key.to_string(),
+                value: value.to_string(),
+                reason,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check every known key in `context`, including architecture-specific
+    /// overrides like `VER__AMD64`. Errors are sorted by key.
+    pub fn validate_context(&self, context: &Context) -> Vec<ValidationError> {
+        let mut errors: Vec<_> = context
+            .iter()
+            .filter_map(|(k, v)| {
+                let field = split_arch_suffix(k).map_or(k.as_str(), |(field, _)| field);
+                self.validate(field, v)
+                    .err()
+                    .map(|e| ValidationError { key: k.clone(), ..e })
+            })
+            .collect();
+        errors.sort_by(|a, b| a.key.cmp(&b.key));
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let registry = ValidatorRegistry::default();
+        assert!(registry.validate("VER", "1.2.3+git20200101").is_ok());
+        assert!(registry.validate("VER", "1.0~rc1").is_ok());
+        assert!(registry.validate("VER", "v1.0").is_err());
+        assert!(registry.validate("VER", "1.0-1").is_err());
+        assert!(registry.validate("REL", "2").is_ok());
+        assert!(registry.validate("REL", "2a").is_err());
+        assert!(registry.validate("PKGSEC", "utils").is_ok());
+        assert!(registry.validate("PKGSEC", "utilities").is_err());
+        assert!(registry.validate("MAINTAINER", "Foo Bar <foo@example.com>").is_ok());
+        assert!(registry.validate("MAINTAINER", "foo@example.com").is_err());
+        assert!(registry.validate("PKGDES", "anything").is_ok());
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut registry = ValidatorRegistry::default();
+        registry.register("PKGDES", |v| {
+            if v.ends_with('.') {
+                Err("must not end with a period".to_string())
+            } else {
+                Ok(())
+            }
+        });
+        let mut context = Context::new();
+        context.insert("PKGDES".to_string(), "A tool.".to_string());
+        context.insert("VER".to_string(), "x".to_string());
+        context.insert("REL".to_string(), "1".to_string());
+        context.insert("REL__AMD64".to_string(), "b".to_string());
+        let errors = registry.validate_context(&context);
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].key, "PKGDES");
+        assert_eq!(errors[1].key, "REL__AMD64");
+        assert_eq!(errors[2].key, "VER");
+
+        registry.unregister("VER");
+        assert_eq!(registry.validate_context(&context).len(), 2);
+    }
+}
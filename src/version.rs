@@ -0,0 +1,365 @@
+//! Package versions with dpkg comparison semantics.
+//! i.e: `1:2.30~rc1-3` is epoch 1, upstream version `2.30~rc1` and revision 3.
+
+use crate::apf::Context;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub epoch: u32,
+    pub upstream: String,
+    pub revision: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VersionError {
+    BadEpoch(String),
+    BadUpstream(String),
+    BadRevision(String),
+    MissingVersion,
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionError::BadEpoch(v) => write!(f, "Bad epoch in version `{}`.", v),
+            VersionError::BadUpstream(v) => write!(f, "Bad upstream version in `{}`.", v),
+            VersionError::BadRevision(v) => write!(f, "Bad revision in version `{}`.", v),
+            VersionError::MissingVersion => write!(f, "VER is not defined."),
+        }
+    }
+}
+
+impl std::error::Error for VersionError {}
+
+impl FromStr for Version {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, rest) = match s.split_once(':') {
+            Some((epoch, rest)) => (
+                epoch
+                    .parse()
+                    .map_err(|_| VersionError::BadEpoch(s.to_string()))?,
+                rest,
+            ),
+            None => (0, s),
+        };
+        let (upstream, revision) = match rest.rsplit_once('-') {
+            Some((upstream, revision)) => {
+                if revision.is_empty()
+                    || !revision
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+.~".contains(c))
+                {
+                    return Err(VersionError::BadRevision(s.to_string()));
+                }
+                (upstream, Some(revision.to_string()))
+            }
+            None => (rest, None),
+        };
+        if !upstream.starts_with(|c: char| c.is_ascii_digit())
+            || !upstream
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+.~-:".contains(c))
+        {
+            return Err(VersionError::BadUpstream(s.to_string()));
+        }
+
+        Ok(Version {
+            epoch,
+            upstream: upstream.to_string(),
+            revision,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.epoch > 0 {
+            write!(f, "{}:", self.epoch)?;
+        }
+        f.write_str(&self.upstream)?;
+        if let Some(rev) = &self.revision {
+            write!(f, "-{}", rev)?;
+        }
+        Ok(())
+    }
+}
+
+/// Versions are (de)serialized in their textual form, i.e: `"1:2.30-3"`.
+#[cfg(feature = "serde")]
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Version {
+    /// Version of a package from its `VER`, `REL` and `PKGEPOCH` fields.
+    /// A missing or zero `REL` means no revision, a missing `PKGEPOCH` means
+    /// epoch 0. `VER` itself may carry neither.
+    pub fn from_context(context: &Context) -> Result<Self, VersionError> {
+        let ver = context.get("VER").ok_or(VersionError::MissingVersion)?;
+        let mut version: Version = ver.parse()?;
+        if version.revision.is_some() || ver.contains(':') {
+            return Err(VersionError::BadUpstream(ver.clone()));
+        }
+        match context.get("PKGEPOCH").map(|e| e.as_str()) {
+            None | Some("") => (),
+            Some(epoch) => {
+                version.epoch = epoch
+                    .parse()
+                    .map_err(|_| VersionError::BadEpoch(epoch.to_string()))?;
+            }
+        }
+        match context.get("REL").map(|r| r.as_str()) {
+            None | Some("") | Some("0") => (),
+            Some(rel) => {
+                if !rel.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(VersionError::BadRevision(rel.to_string()));
+                }
+                version.revision = Some(rel.to_string());
+            }
+        }
+
+        Ok(version)
+    }
+}
+
+/// Sorting weight of a non-digit character, as in dpkg.
+fn order(c: Option<u8>) -> i32 {
+    match c {
+        None => 0,
+        Some(c) if c.is_ascii_digit() => 0,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(b'~') => -1,
+        Some(c) => c as i32 + 256,
+    }
+}
+
+/// dpkg's `verrevcmp`: compare alternating non-digit and digit parts.
+fn compare_part(a: &str, b: &str) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    let is_digit = |s: &[u8], k: usize| s.get(k).is_some_and(|c| c.is_ascii_digit());
+
+    while i < a.len() || j < b.len() {
+        while (i < a.len() && !is_digit(a, i)) || (j < b.len() && !is_digit(b, j)) {
+            let ac = order(a.get(i).copied());
+            let bc = order(b.get(j).copied());
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+            i += 1;
+            j += 1;
+        }
+        while a.get(i) == Some(&b'0') {
+            i += 1;
+        }
+        while b.get(j) == Some(&b'0') {
+            j += 1;
+        }
+        let mut first_diff = Ordering::Equal;
+        while is_digit(a, i) && is_digit(b, j) {
+            if first_diff == Ordering::Equal {
+                first_diff = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+        if is_digit(a, i) {
+            return Ordering::Greater;
+        }
+        if is_digit(b, j) {
+            return Ordering::Less;
+        }
+        if first_diff != Ordering::Equal {
+            return first_diff;
+        }
+    }
+
+    Ordering::Equal
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| compare_part(&self.upstream, &other.upstream))
+            .then_with(|| {
+                compare_part(
+                    self.revision.as_deref().unwrap_or(""),
+                    other.revision.as_deref().unwrap_or(""),
+                )
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    /// Versions are equal if dpkg considers them equal, i.e: `1.0` == `1.00`.
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// Version comparator allowed in a relationship entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Comparator {
+    /// `pkg>=1.2`
+    #[cfg_attr(feature = "serde", serde(rename = ">="))]
+    GreaterOrEqual,
+    /// `pkg<=1.2`
+    #[cfg_attr(feature = "serde", serde(rename = "<="))]
+    LessOrEqual,
+    /// `pkg==1.2`
+    #[cfg_attr(feature = "serde", serde(rename = "=="))]
+    Equal,
+}
+
+impl Comparator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparator::GreaterOrEqual => ">=",
+            Comparator::LessOrEqual => "<=",
+            Comparator::Equal => "==",
+        }
+    }
+}
+
+impl fmt::Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Version constraint attached to a dependency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VersionReq {
+    pub op: Comparator,
+    pub version: Version,
+}
+
+impl VersionReq {
+    pub fn matches(&self, version: &Version) -> bool {
+        match self.op {
+            Comparator::GreaterOrEqual => version >= &self.version,
+            Comparator::LessOrEqual => version <= &self.version,
+            Comparator::Equal => version == &self.version,
+        }
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op, self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let ver = v("1:2.30~rc1-3");
+        assert_eq!(ver.epoch, 1);
+        assert_eq!(ver.upstream, "2.30~rc1");
+        assert_eq!(ver.revision.as_deref(), Some("3"));
+        assert_eq!(ver.to_string(), "1:2.30~rc1-3");
+        assert_eq!(v("0:1.0").to_string(), "1.0");
+        assert_eq!(v("1.0-2-3").upstream, "1.0-2");
+
+        let bad = vec!["", "a1.0", "x:1.0", "1.0-", "1.0_1", "1.0-r_1"];
+        for b in bad {
+            assert!(b.parse::<Version>().is_err(), "{}", b);
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let ascending = vec![
+            "1.0~~", "1.0~~a", "1.0~", "1.0", "1.0-1", "1.0-2", "1.0a", "1.0+", "1.00.1", "1.1",
+            "1.10", "2.0", "2.0.0", "1:0.1",
+        ];
+        for pair in ascending.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+            assert!(v(pair[1]) > v(pair[0]));
+        }
+        assert_eq!(v("1.0"), v("1.00"));
+        assert_eq!(v("1.0"), v("0:1.0"));
+        assert_eq!(v("1.0"), v("1.0-0"));
+    }
+
+    #[test]
+    fn test_from_context() {
+        let mut context = Context::new();
+        assert_eq!(Version::from_context(&context), Err(VersionError::MissingVersion));
+        context.insert("VER".to_string(), "5.8".to_string());
+        assert_eq!(Version::from_context(&context).unwrap().to_string(), "5.8");
+        context.insert("REL".to_string(), "0".to_string());
+        assert_eq!(Version::from_context(&context).unwrap().to_string(), "5.8");
+        context.insert("REL".to_string(), "2".to_string());
+        assert_eq!(Version::from_context(&context).unwrap().to_string(), "5.8-2");
+        context.insert("PKGEPOCH".to_string(), "1".to_string());
+        assert_eq!(Version::from_context(&context).unwrap().to_string(), "1:5.8-2");
+        context.insert("PKGEPOCH".to_string(), "one".to_string());
+        assert_eq!(
+            Version::from_context(&context),
+            Err(VersionError::BadEpoch("one".to_string()))
+        );
+        context.insert("PKGEPOCH".to_string(), "0".to_string());
+        assert_eq!(Version::from_context(&context).unwrap().to_string(), "5.8-2");
+        context.insert("VER".to_string(), "5.8-1".to_string());
+        assert!(Version::from_context(&context).is_err());
+        context.insert("VER".to_string(), "1:5.8".to_string());
+        assert!(Version::from_context(&context).is_err());
+    }
+
+    #[test]
+    fn test_req() {
+        let req = VersionReq {
+            op: Comparator::GreaterOrEqual,
+            version: v("1.2"),
+        };
+        assert!(req.matches(&v("1.2")));
+        assert!(req.matches(&v("1.10")));
+        assert!(!req.matches(&v("1.2~rc1")));
+        let req = VersionReq {
+            op: Comparator::LessOrEqual,
+            version: v("1.2"),
+        };
+        assert!(req.matches(&v("1.1")));
+        assert!(!req.matches(&v("1.2-1")));
+        let req = VersionReq {
+            op: Comparator::Equal,
+            version: v("1.2"),
+        };
+        assert!(req.matches(&v("1.02")));
+        assert!(!req.matches(&v("1.2.1")));
+    }
+}